@@ -1,8 +1,22 @@
 pub(crate) mod bitmask;
 pub(crate) mod board;
 pub(crate) mod moves;
+#[cfg(feature = "cli")]
+pub(crate) mod pgn;
+#[cfg(feature = "cli")]
+pub(crate) mod polyglot;
 pub(crate) mod prng;
+#[cfg(feature = "serde")]
+mod serialize;
 pub(crate) mod state;
+#[cfg(feature = "cli")]
+pub(crate) mod symmetry;
+#[cfg(feature = "cli")]
+pub(crate) mod syzygy;
 pub(crate) mod types;
 
+pub use bitmask::Bitmask;
+pub use board::{Board, CastlingRights};
+pub use moves::{Move, MoveType};
 pub use state::State;
+pub use types::{Color, FenError, Piece, Position};
@@ -4,13 +4,15 @@ use std::ops::{
     ShrAssign,
 };
 
+/// A set of squares, bit `i` standing for `Position` index `i`. Iterating yields the squares
+/// from a1 to h8.
 #[repr(transparent)]
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub(crate) struct Bitmask(pub(crate) u64);
+pub struct Bitmask(pub(crate) u64);
 
 impl Bitmask {
-    pub(crate) const EMPTY: Self = Self(0);
-    pub(crate) const FULL: Self = Self(!0);
+    pub const EMPTY: Self = Self(0);
+    pub const FULL: Self = Self(!0);
 
     pub(crate) const QS_CASTLE_PATH: [Self; 2] =
         [Self(0x000000000000001C), Self(0x1C00000000000000)];
@@ -35,6 +37,18 @@ impl Bitmask {
         Self(0xFF00000000000000),
     ];
 
+    #[cfg(feature = "cli")]
+    pub(crate) const FILES: [Self; 8] = [
+        Self(0x0101010101010101),
+        Self(0x0202020202020202),
+        Self(0x0404040404040404),
+        Self(0x0808080808080808),
+        Self(0x1010101010101010),
+        Self(0x2020202020202020),
+        Self(0x4040404040404040),
+        Self(0x8080808080808080),
+    ];
+
     pub(crate) const KING_ATTACK_MASKS: [Self; 64] = {
         let mut masks = [Self::EMPTY; 64];
         let mut i = 0;
@@ -187,7 +201,19 @@ impl Bitmask {
         masks
     };
 
-    pub(crate) const fn contains(self, position: Position) -> bool {
+    pub const fn new(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, position: Position) -> bool {
         self.0 & position.mask().0 != 0
     }
 
@@ -195,6 +221,10 @@ impl Bitmask {
         self.0.is_power_of_two()
     }
 
+    pub const fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+
     pub(crate) const fn set_mut(&mut self, position: Position) {
         self.0 |= position.mask().0
     }
@@ -1,12 +1,12 @@
 use crate::chess::{
     bitmask::Bitmask,
-    types::{Color, Direction, Piece, Position},
+    types::{Color, Direction, FenError, Piece, Position},
 };
 use std::ops::{Index, IndexMut};
 
 #[repr(transparent)]
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub(crate) struct CastlingRights(pub(crate) u8);
+pub struct CastlingRights(pub(crate) u8);
 
 impl CastlingRights {
     // Bit layout (4 bits total, LSB first):
@@ -14,11 +14,11 @@ impl CastlingRights {
     //   Bit 1 (0b0010): Black king side   — cleared when H8 rook or E8 king moves
     //   Bit 2 (0b0100): White queen side  — cleared when A1 rook or E1 king moves
     //   Bit 3 (0b1000): White king side   — cleared when H1 rook or E1 king moves
-    pub(crate) const WHITE_QUEEN_SIDE: Self = Self(0b0100);
-    pub(crate) const BLACK_QUEEN_SIDE: Self = Self(0b0001);
+    pub const WHITE_QUEEN_SIDE: Self = Self(0b0100);
+    pub const BLACK_QUEEN_SIDE: Self = Self(0b0001);
     pub(crate) const QUEEN_SIDE: [Self; 2] = [Self::WHITE_QUEEN_SIDE, Self::BLACK_QUEEN_SIDE];
-    pub(crate) const WHITE_KING_SIDE: Self = Self(0b1000);
-    pub(crate) const BLACK_KING_SIDE: Self = Self(0b0010);
+    pub const WHITE_KING_SIDE: Self = Self(0b1000);
+    pub const BLACK_KING_SIDE: Self = Self(0b0010);
     pub(crate) const KING_SIDE: [Self; 2] = [Self::WHITE_KING_SIDE, Self::BLACK_KING_SIDE];
 
     const MASKS: [u8; 64] = [
@@ -36,25 +36,56 @@ impl CastlingRights {
         Self(0b1111)
     }
 
-    pub(crate) fn from_fen(fen: &str) -> Self {
+    pub(crate) fn from_fen(fen: &str) -> Result<Self, FenError> {
+        if fen == "-" {
+            return Ok(Self(0));
+        }
         let mut bits = 0u8;
-        fen.chars().for_each(|c| match c {
-            'K' => bits |= Self::KING_SIDE[Color::White].0,
-            'Q' => bits |= Self::QUEEN_SIDE[Color::White].0,
-            'k' => bits |= Self::KING_SIDE[Color::Black].0,
-            'q' => bits |= Self::QUEEN_SIDE[Color::Black].0,
-            _ => (),
-        });
-        Self(bits)
+        for c in fen.chars() {
+            bits |= match c {
+                'K' => Self::KING_SIDE[Color::White].0,
+                'Q' => Self::QUEEN_SIDE[Color::White].0,
+                'k' => Self::KING_SIDE[Color::Black].0,
+                'q' => Self::QUEEN_SIDE[Color::Black].0,
+                _ => return Err(FenError::CastlingRights(fen.to_string())),
+            };
+        }
+        match bits {
+            0 => Err(FenError::CastlingRights(fen.to_string())),
+            _ => Ok(Self(bits)),
+        }
     }
 
-    pub(crate) const fn has(self, right: Self) -> bool {
+    pub const fn has(self, right: Self) -> bool {
         (self.0 & right.0) != 0
     }
 
     pub(crate) fn update(self, from: Position, to: Position) -> Self {
         Self(self.0 & Self::MASKS[from] & Self::MASKS[to])
     }
+
+    // Swaps the white and black rights
+    pub(crate) const fn mirrored(self) -> Self {
+        Self(((self.0 & 0b0011) << 2) | ((self.0 & 0b1100) >> 2))
+    }
+
+    pub(crate) fn to_fen(self) -> String {
+        let fen: String = [
+            (Self::WHITE_KING_SIDE, 'K'),
+            (Self::WHITE_QUEEN_SIDE, 'Q'),
+            (Self::BLACK_KING_SIDE, 'k'),
+            (Self::BLACK_QUEEN_SIDE, 'q'),
+        ]
+        .into_iter()
+        .filter(|&(right, _)| self.has(right))
+        .map(|(_, c)| c)
+        .collect();
+        if fen.is_empty() {
+            "-".to_string()
+        } else {
+            fen
+        }
+    }
 }
 
 impl<T> Index<CastlingRights> for [T; 16] {
@@ -118,7 +149,9 @@ impl MoveGenMasks {
     }
 }
 
-pub(crate) struct Board {
+/// Piece placement, by piece kind, by color and square by square.
+#[derive(Clone)]
+pub struct Board {
     pub(crate) pieces: [Bitmask; 12],
     pub(crate) colors: [Bitmask; 2],
     pub(crate) occupancy: Bitmask,
@@ -213,46 +246,122 @@ impl Board {
         }
     }
 
-    pub(crate) fn from_fen(fen: &str) -> Self {
+    pub(crate) fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.mailbox[rank * 8 + file] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push_str(&piece.to_string());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+        fen
+    }
+
+    // Piece placement field of a FEN. Both sides need exactly one king and material a game can
+    // reach, which keeps the move count within what move generation has room for.
+    pub(crate) fn from_fen(fen: &str) -> Result<Self, FenError> {
         let mut board = Self {
             pieces: [Bitmask::EMPTY; 12],
             colors: [Bitmask::EMPTY; 2],
             occupancy: Bitmask::EMPTY,
             mailbox: [None; 64],
         };
+        let invalid = || FenError::Board(fen.to_string());
 
-        fen.split('/').rev().enumerate().for_each(|(i, rank)| {
+        let ranks: Vec<&str> = fen.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(invalid());
+        }
+        for (i, rank) in ranks.into_iter().rev().enumerate() {
+            let too_wide = || FenError::RankWidth(rank.to_string());
             let mut file = 0u8;
-            rank.chars().for_each(|c| {
-                if c.is_ascii_digit() {
-                    file += c.to_digit(10).unwrap() as u8;
-                    return;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    if empty == 0 || file as u32 + empty > 8 {
+                        return Err(too_wide());
+                    }
+                    file += empty as u8;
+                    continue;
                 }
 
-                let pos = Position((i * 8) as u8 + file);
-                let (piece, color) = match c {
-                    'P' => (Piece::WhitePawn, Color::White),
-                    'R' => (Piece::WhiteRook, Color::White),
-                    'N' => (Piece::WhiteKnight, Color::White),
-                    'B' => (Piece::WhiteBishop, Color::White),
-                    'Q' => (Piece::WhiteQueen, Color::White),
-                    'K' => (Piece::WhiteKing, Color::White),
-                    'p' => (Piece::BlackPawn, Color::Black),
-                    'r' => (Piece::BlackRook, Color::Black),
-                    'n' => (Piece::BlackKnight, Color::Black),
-                    'b' => (Piece::BlackBishop, Color::Black),
-                    'q' => (Piece::BlackQueen, Color::Black),
-                    'k' => (Piece::BlackKing, Color::Black),
-                    _ => return,
+                let piece = match c {
+                    'P' => Piece::WhitePawn,
+                    'R' => Piece::WhiteRook,
+                    'N' => Piece::WhiteKnight,
+                    'B' => Piece::WhiteBishop,
+                    'Q' => Piece::WhiteQueen,
+                    'K' => Piece::WhiteKing,
+                    'p' => Piece::BlackPawn,
+                    'r' => Piece::BlackRook,
+                    'n' => Piece::BlackKnight,
+                    'b' => Piece::BlackBishop,
+                    'q' => Piece::BlackQueen,
+                    'k' => Piece::BlackKing,
+                    _ => return Err(invalid()),
                 };
-                board.pieces[piece].set_mut(pos);
-                board.colors[color].set_mut(pos);
-                board.occupancy.set_mut(pos);
-                board.mailbox[pos] = Some(piece);
+                let position = Position::new(file, i as u8).ok_or_else(too_wide)?;
+                board.set_piece(position, piece);
                 file += 1;
-            })
-        });
+            }
+            if file != 8 {
+                return Err(too_wide());
+            }
+        }
 
+        for color in [Color::White, Color::Black] {
+            if board.pieces[Piece::king(color)].count() != 1 {
+                return Err(invalid());
+            }
+            let pawns = board.pieces[Piece::pawn(color)].count();
+            let promoted: u32 = [
+                (Piece::queen(color), 1),
+                (Piece::rook(color), 2),
+                (Piece::bishop(color), 2),
+                (Piece::knight(color), 2),
+            ]
+            .into_iter()
+            .map(|(piece, initial)| board.pieces[piece].count().saturating_sub(initial))
+            .sum();
+            if board.colors[color].count() > 16 || pawns > 8 || promoted > 8 - pawns {
+                return Err(FenError::Material(color));
+            }
+        }
+
+        let pawns = board.pieces[Piece::WhitePawn] | board.pieces[Piece::BlackPawn];
+        match (pawns & (Bitmask::RANKS[0] | Bitmask::RANKS[7])).next() {
+            Some(position) => Err(FenError::PawnOnBackRank(position)),
+            None => Ok(board),
+        }
+    }
+
+    // Vertical flip with colors swapped, the same position seen from the other side
+    pub(crate) fn mirrored(&self) -> Self {
+        let mut board = Self {
+            pieces: [Bitmask::EMPTY; 12],
+            colors: [Bitmask::EMPTY; 2],
+            occupancy: Bitmask::EMPTY,
+            mailbox: [None; 64],
+        };
+        for (i, square) in self.mailbox.iter().enumerate() {
+            if let Some(piece) = *square {
+                board.set_piece(Position(i as u8).mirrored(), piece.flip_color());
+            }
+        }
         board
     }
 
@@ -260,6 +369,47 @@ impl Board {
         self.occupancy.contains(position)
     }
 
+    pub const fn piece_at(&self, position: Position) -> Option<Piece> {
+        self.mailbox[position.0 as usize]
+    }
+
+    pub const fn pieces(&self, piece: Piece) -> Bitmask {
+        self.pieces[piece as usize]
+    }
+
+    pub const fn color(&self, color: Color) -> Bitmask {
+        self.colors[color as usize]
+    }
+
+    pub const fn occupancy(&self) -> Bitmask {
+        self.occupancy
+    }
+
+    /// Squares attacked by `color`, whether or not a piece of its own stands there.
+    pub fn attacks(&self, color: Color) -> Bitmask {
+        self.color_attack_mask(color, Bitmask::EMPTY)
+    }
+
+    /// Pieces of `color` attacking `position`.
+    ///
+    /// ```
+    /// use chess_ai::{Color, Position, State};
+    ///
+    /// let state = State::from_fen("4k3/8/8/3q4/4P3/8/8/4K3 b - - 0 1").unwrap();
+    /// let e4 = Position::new(4, 3).unwrap();
+    /// let attackers: Vec<Position> = state.board().attackers(e4, Color::Black).collect();
+    /// assert_eq!(attackers, [Position::new(3, 4).unwrap()]);
+    /// ```
+    pub fn attackers(&self, position: Position, color: Color) -> Bitmask {
+        let straight = self.pieces[Piece::rook(color)] | self.pieces[Piece::queen(color)];
+        let diagonal = self.pieces[Piece::bishop(color)] | self.pieces[Piece::queen(color)];
+        (Bitmask::PAWN_ATTACK_MASKS[color.flip()][position] & self.pieces[Piece::pawn(color)])
+            | (Bitmask::KNIGHT_ATTACK_MASKS[position] & self.pieces[Piece::knight(color)])
+            | (Bitmask::KING_ATTACK_MASKS[position] & self.pieces[Piece::king(color)])
+            | (Bitmask::rook_attack_mask(position, self.occupancy) & straight)
+            | (Bitmask::bishop_attack_mask(position, self.occupancy) & diagonal)
+    }
+
     pub(crate) fn color_attack_mask(&self, color: Color, ignore: Bitmask) -> Bitmask {
         let mut mask = Bitmask::EMPTY;
         let occupancy_with_ignore = self.occupancy & !ignore;
@@ -365,10 +515,10 @@ impl Board {
             self.pieces[idx] = self.pieces[idx].unset(position);
             self.colors[idx.color()] = self.colors[idx.color()].unset(position);
         }
-        self.pieces[piece] = self.pieces[piece].set(position);
+        self.pieces[piece].set_mut(position);
         self.mailbox[position] = Some(piece);
-        self.occupancy = self.occupancy.set(position);
-        self.colors[piece.color()] = self.colors[piece.color()].set(position);
+        self.occupancy.set_mut(position);
+        self.colors[piece.color()].set_mut(position);
     }
 
     pub(crate) fn unset_piece(&mut self, position: Position) {
@@ -2,7 +2,8 @@ use crate::chess::types::Position;
 
 #[repr(u8)]
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub(crate) enum MoveType {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MoveType {
     Standard,
     DoublePush,
     EnPassant,
@@ -30,9 +31,13 @@ impl MoveType {
     }
 }
 
+/// Origin, destination and kind of a move packed into 16 bits. With the `serde` feature,
+/// human-readable formats hold its squares and kind as separate fields and binary formats the
+/// packed bits. A UCI string leaves the kind out and is resolved against its position with
+/// `State::parse_uci` instead.
 #[repr(transparent)]
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub(crate) struct Move(pub(crate) u16);
+pub struct Move(pub(crate) u16);
 
 impl Move {
     pub(crate) const QUEEN_SIDE_CASTLING: [Self; 2] = [
@@ -53,21 +58,41 @@ impl Move {
         Self(mv)
     }
 
-    pub(crate) const fn unpack(self) -> (Position, Position, MoveType) {
+    pub const fn unpack(self) -> (Position, Position, MoveType) {
         (self.from(), self.to(), self.move_type())
     }
 
-    pub(crate) const fn from(self) -> Position {
+    pub const fn from(self) -> Position {
         Position(((self.0 >> 10) & 0x3F) as u8)
     }
 
-    pub(crate) const fn to(self) -> Position {
+    pub const fn to(self) -> Position {
         Position(((self.0 >> 4) & 0x3F) as u8)
     }
 
-    pub(crate) const fn move_type(self) -> MoveType {
+    pub const fn move_type(self) -> MoveType {
         MoveType::new(self.0 & 0x0F)
     }
+
+    pub(crate) const fn mirrored(self) -> Self {
+        Self::new(
+            self.from().mirrored(),
+            self.to().mirrored(),
+            self.move_type(),
+        )
+    }
+
+    pub fn to_uci(self) -> String {
+        let (from, to, move_type) = self.unpack();
+        let promotion = match move_type {
+            MoveType::PromotionQueen => "q",
+            MoveType::PromotionRook => "r",
+            MoveType::PromotionBishop => "b",
+            MoveType::PromotionKnight => "n",
+            _ => "",
+        };
+        format!("{}{}{}", from, to, promotion)
+    }
 }
 
 impl std::fmt::Display for Move {
@@ -0,0 +1,320 @@
+use crate::chess::{
+    moves::{Move, MoveType},
+    types::{FenError, GameResult, Piece, Position},
+    State,
+};
+use std::io::{self, BufRead};
+
+pub(crate) struct PgnGame {
+    pub(crate) tags: Vec<(String, String)>,
+    pub(crate) moves: Vec<Move>,
+    pub(crate) result: Option<GameResult>,
+}
+
+impl PgnGame {
+    pub(crate) fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn initial_state(&self) -> Result<State, FenError> {
+        match self.tag("FEN") {
+            Some(fen) => State::from_fen(fen),
+            None => Ok(State::new()),
+        }
+    }
+
+    fn from_text(tags: Vec<(String, String)>, movetext: &str) -> Self {
+        let mut game = Self {
+            tags,
+            moves: Vec::new(),
+            result: None,
+        };
+        // Moves after the first unparseable token are dropped, the prefix is still a valid game. A
+        // game set up from an invalid FEN keeps its tags and result but no moves.
+        let (mut state, mut legal) = match game.initial_state() {
+            Ok(state) => (state, true),
+            Err(_) => (State::new(), false),
+        };
+        for token in tokenize_movetext(movetext) {
+            if let Some(result) = GameResult::from_pgn(token) {
+                game.result = Some(result);
+                continue;
+            }
+            if token == "*" || !legal {
+                continue;
+            }
+            match state.parse_san(token) {
+                Some(mv) => {
+                    state.make_move(mv);
+                    game.moves.push(mv);
+                }
+                None => legal = false,
+            }
+        }
+
+        if game.result.is_none() {
+            game.result = game.tag("Result").and_then(GameResult::from_pgn);
+        }
+        game
+    }
+}
+
+pub(crate) struct PgnReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    pending_tag: Option<String>,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            pending_tag: None,
+        }
+    }
+}
+
+// Games are yielded as they are read. A read error is yielded in place of the game it cut short,
+// so a truncated database is never mistaken for a complete one.
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = io::Result<PgnGame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tags = Vec::new();
+        let mut movetext = String::new();
+
+        if let Some(line) = self.pending_tag.take() {
+            tags.extend(parse_tag(&line));
+        }
+
+        for line in self.lines.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+            let line = line.trim();
+
+            if line.starts_with('[') {
+                // A tag after movetext starts the next game
+                if !movetext.trim().is_empty() {
+                    self.pending_tag = Some(line.to_string());
+                    return Some(Ok(PgnGame::from_text(tags, &movetext)));
+                }
+                tags.extend(parse_tag(line));
+            } else if !line.starts_with('%') {
+                movetext.push_str(line);
+                movetext.push('\n');
+            }
+        }
+
+        if tags.is_empty() && movetext.trim().is_empty() {
+            None
+        } else {
+            Some(Ok(PgnGame::from_text(tags, &movetext)))
+        }
+    }
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (key, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((key.to_string(), value.replace("\\\"", "\"")))
+}
+
+fn tokenize_movetext(movetext: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut variation_depth = 0usize;
+    let mut in_comment = false;
+    let mut in_line_comment = false;
+    let mut token_start = None;
+
+    for (i, c) in movetext.char_indices() {
+        let is_separator = match c {
+            '{' if !in_line_comment => {
+                in_comment = true;
+                true
+            }
+            '}' if in_comment => {
+                in_comment = false;
+                true
+            }
+            ';' if !in_comment => {
+                in_line_comment = true;
+                true
+            }
+            '\n' if in_line_comment => {
+                in_line_comment = false;
+                true
+            }
+            '(' if !in_comment && !in_line_comment => {
+                variation_depth += 1;
+                true
+            }
+            ')' if !in_comment && !in_line_comment => {
+                variation_depth = variation_depth.saturating_sub(1);
+                true
+            }
+            _ => c.is_whitespace() || in_comment || in_line_comment,
+        };
+
+        if is_separator {
+            if let Some(start) = token_start.take() {
+                tokens.push(&movetext[start..i]);
+            }
+        } else if token_start.is_none() && variation_depth == 0 {
+            token_start = Some(i);
+        }
+    }
+    if let Some(start) = token_start {
+        tokens.push(&movetext[start..]);
+    }
+
+    tokens
+        .into_iter()
+        .map(strip_move_number)
+        .filter(|token| !token.is_empty() && !token.starts_with('$'))
+        .collect()
+}
+
+fn strip_move_number(token: &str) -> &str {
+    match token.split_once('.') {
+        Some((number, rest)) if number.bytes().all(|b| b.is_ascii_digit()) => {
+            rest.trim_start_matches('.')
+        }
+        _ => token,
+    }
+}
+
+impl State {
+    pub(crate) fn parse_san(&self, san: &str) -> Option<Move> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        let moves = self.generate_moves();
+
+        match san {
+            "O-O" | "0-0" => {
+                return moves
+                    .into_iter()
+                    .find(|mv| mv.move_type() == MoveType::KingSideCastling)
+            }
+            "O-O-O" | "0-0-0" => {
+                return moves
+                    .into_iter()
+                    .find(|mv| mv.move_type() == MoveType::QueenSideCastling)
+            }
+            _ => (),
+        }
+
+        let (san, promotion) = match san.char_indices().last()? {
+            (i, c @ ('Q' | 'R' | 'B' | 'N')) if i > 0 => {
+                let promotion = match c {
+                    'Q' => MoveType::PromotionQueen,
+                    'R' => MoveType::PromotionRook,
+                    'B' => MoveType::PromotionBishop,
+                    _ => MoveType::PromotionKnight,
+                };
+                (san[..i].trim_end_matches('='), Some(promotion))
+            }
+            _ => (san, None),
+        };
+
+        let (piece, san) = match san.chars().next()? {
+            'K' => (Piece::king(self.turn), &san[1..]),
+            'Q' => (Piece::queen(self.turn), &san[1..]),
+            'R' => (Piece::rook(self.turn), &san[1..]),
+            'B' => (Piece::bishop(self.turn), &san[1..]),
+            'N' => (Piece::knight(self.turn), &san[1..]),
+            _ => (Piece::pawn(self.turn), san),
+        };
+
+        if san.len() < 2 || !san.is_char_boundary(san.len() - 2) {
+            return None;
+        }
+        let (disambiguation, to) = san.split_at(san.len() - 2);
+        let to = Position::from_fen(to)?;
+        let from_file = disambiguation
+            .bytes()
+            .find(|b| (b'a'..=b'h').contains(b))
+            .map(|b| b - b'a');
+        let from_rank = disambiguation
+            .bytes()
+            .find(|b| (b'1'..=b'8').contains(b))
+            .map(|b| b - b'1');
+
+        moves.into_iter().find(|mv| {
+            let (from, mv_to, move_type) = mv.unpack();
+            let is_promotion = matches!(
+                move_type,
+                MoveType::PromotionQueen
+                    | MoveType::PromotionRook
+                    | MoveType::PromotionBishop
+                    | MoveType::PromotionKnight
+            );
+            mv_to == to
+                && self.board.mailbox[from] == Some(piece)
+                && from_file.is_none_or(|file| from.file() == file)
+                && from_rank.is_none_or(|rank| from.rank() == rank)
+                && match promotion {
+                    Some(promotion) => move_type == promotion,
+                    None => !is_promotion,
+                }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_games_with_comments_and_variations() {
+        let pgn = r#"[Event "Test"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2. Nf3 (2. f4 exf4) Nc6 3. Bb5 a6 $1 4. Ba4 Nf6 5. O-O Be7 1-0
+
+[Event "Second"]
+[FEN "4k3/P7/8/8/8/8/8/4K3 w - - 0 1"]
+
+1. a8=Q+ Kd7 *
+"#;
+        let games: Vec<PgnGame> = PgnReader::new(pgn.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(games.len(), 2);
+
+        assert_eq!(games[0].tag("Event"), Some("Test"));
+        assert_eq!(games[0].result, Some(GameResult::WhiteWin));
+        assert_eq!(games[0].moves.len(), 10);
+        assert_eq!(games[0].moves[8], Move::KING_SIDE_CASTLING[0]);
+
+        assert_eq!(games[1].result, None);
+        assert_eq!(games[1].moves.len(), 2);
+        assert_eq!(games[1].moves[0].to(), Position::A8);
+
+        // A read error ends the stream with the error instead of a truncated game
+        let mut broken = pgn.as_bytes().to_vec();
+        broken.extend(b"[Event \"Third\"]\n\n1. e4 \xff e5 *\n");
+        let games: Vec<_> = PgnReader::new(broken.as_slice()).collect();
+        assert_eq!(games.len(), 3);
+        assert!(games[1].is_ok());
+        assert!(matches!(&games[2], Err(err) if err.kind() == io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_san_disambiguation() {
+        let state = State::from_fen("4k3/8/8/8/8/8/8/R3K2R w - - 0 1").unwrap();
+        let mv = state.parse_san("Rad1").unwrap();
+        assert_eq!(mv.from(), Position::A1);
+        assert_eq!(mv.to(), Position::D1);
+        assert!(state.parse_san("Rd1").is_some());
+        assert!(state.parse_san("Ra8+").is_some());
+        assert!(state.parse_san("Rc7").is_none());
+        // Non-ASCII squares are refused rather than indexed into
+        for san in ["é", "Ré", "Raé", "Rdé1"] {
+            assert!(state.parse_san(san).is_none(), "{}", san);
+        }
+    }
+}
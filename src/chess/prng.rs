@@ -1,19 +1,25 @@
-struct PseudoRng {
+pub(crate) struct PseudoRng {
     state: u64,
 }
 
 impl PseudoRng {
-    const fn new(seed: u64) -> Self {
+    pub(crate) const fn new(seed: u64) -> Self {
         Self { state: seed }
     }
 
-    const fn next(&mut self) -> u64 {
+    pub(crate) const fn next(&mut self) -> u64 {
         self.state ^= self.state >> 12;
         self.state ^= self.state << 25;
         self.state ^= self.state >> 27;
         self.state.wrapping_mul(0x2545F4914F6CDD1D)
     }
 
+    // Resuming from `PseudoRng::new(state())` continues the same sequence
+    #[cfg(feature = "cli")]
+    pub(crate) const fn state(&self) -> u64 {
+        self.state
+    }
+
     const fn next_n<const N: usize>(&mut self) -> [u64; N] {
         let mut rands = [0u64; N];
         let mut i = 0;
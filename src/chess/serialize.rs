@@ -0,0 +1,238 @@
+// Serde support behind the `serde` feature. Human-readable formats get the notation a person would
+// write: square names, the castling field of a FEN and UCI moves in a game. Binary formats get the
+// packed integers the types are made of. A UCI move leaves out whether it castles, captures en
+// passant or pushes a pawn two squares, so a lone `Move` is written with its kind spelled out next
+// to its squares, while the moves of a `State` are UCI strings resolved against the position.
+use crate::chess::{
+    board::CastlingRights,
+    moves::{Move, MoveType},
+    types::Position,
+    State,
+};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match serializer.is_human_readable() {
+            true => serializer.collect_str(self),
+            false => serializer.serialize_u8(self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.is_human_readable() {
+            true => {
+                let name = String::deserialize(deserializer)?;
+                Position::from_fen(&name)
+                    .ok_or_else(|| D::Error::custom(format!("invalid square '{}'", name)))
+            }
+            false => {
+                let index = u8::deserialize(deserializer)?;
+                Position::from_index(index)
+                    .ok_or_else(|| D::Error::custom(format!("invalid square index {}", index)))
+            }
+        }
+    }
+}
+
+// Human-readable form of a `Move`, e.g. {"from":"e1","to":"g1","move_type":"KingSideCastling"}
+#[derive(Serialize, Deserialize)]
+struct MoveFields {
+    from: Position,
+    to: Position,
+    move_type: MoveType,
+}
+
+impl Serialize for Move {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match serializer.is_human_readable() {
+            true => {
+                let (from, to, move_type) = self.unpack();
+                MoveFields {
+                    from,
+                    to,
+                    move_type,
+                }
+                .serialize(serializer)
+            }
+            false => serializer.serialize_u16(self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Move {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let MoveFields {
+                from,
+                to,
+                move_type,
+            } = MoveFields::deserialize(deserializer)?;
+            return Ok(Move::new(from, to, move_type));
+        }
+        let bits = u16::deserialize(deserializer)?;
+        match bits & 0x0F <= MoveType::QueenSideCastling as u16 {
+            true => Ok(Move(bits)),
+            false => Err(D::Error::custom(format!("invalid move bits {:#06x}", bits))),
+        }
+    }
+}
+
+impl Serialize for CastlingRights {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match serializer.is_human_readable() {
+            true => serializer.serialize_str(&self.to_fen()),
+            false => serializer.serialize_u8(self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CastlingRights {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.is_human_readable() {
+            true => {
+                let fen = String::deserialize(deserializer)?;
+                CastlingRights::from_fen(&fen).map_err(D::Error::custom)
+            }
+            false => match u8::deserialize(deserializer)? {
+                bits @ 0..=0b1111 => Ok(CastlingRights(bits)),
+                bits => Err(D::Error::custom(format!(
+                    "invalid castling rights {:#06b}",
+                    bits
+                ))),
+            },
+        }
+    }
+}
+
+// What a `State` is stored as: the FEN the game started from or was set up with, and the moves
+// played since, so repetitions and `unmake_move` work the same after a round trip. The moves are
+// UCI strings in human-readable formats and packed moves in binary ones.
+#[derive(Serialize, Deserialize)]
+struct Game<M> {
+    fen: String,
+    moves: Vec<M>,
+}
+
+impl Serialize for State {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (root, moves) = self.root_and_moves();
+        let fen = root.to_fen();
+        match serializer.is_human_readable() {
+            true => Game {
+                fen,
+                moves: moves.into_iter().map(Move::to_uci).collect::<Vec<_>>(),
+            }
+            .serialize(serializer),
+            false => Game { fen, moves }.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for State {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Game { fen, moves } = match deserializer.is_human_readable() {
+            true => Game::<String>::deserialize(deserializer)?,
+            false => {
+                let game = Game::<Move>::deserialize(deserializer)?;
+                let moves = game.moves.into_iter().map(Move::to_uci).collect();
+                Game {
+                    fen: game.fen,
+                    moves,
+                }
+            }
+        };
+        let mut state = State::from_fen(&fen).map_err(D::Error::custom)?;
+        for uci in moves {
+            // The legal move with the same squares, which has the kind a UCI string leaves out
+            let legal = state.parse_uci(&uci).ok_or_else(|| {
+                D::Error::custom(format!("illegal move {} in {}", uci, state.to_fen()))
+            })?;
+            state.make_move(legal);
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::types::{Color, Piece};
+
+    fn play(fen: &str, moves: &[&str]) -> State {
+        let mut state = State::from_fen(fen).unwrap();
+        for uci in moves {
+            state.make_move(state.parse_uci(uci).unwrap());
+        }
+        state
+    }
+
+    #[test]
+    fn test_json_encodings() {
+        let e4 = Position::new(4, 3).unwrap();
+        assert_eq!(serde_json::to_string(&e4).unwrap(), "\"e4\"");
+        assert_eq!(serde_json::from_str::<Position>("\"e4\"").unwrap(), e4);
+        assert!(serde_json::from_str::<Position>("\"i9\"").is_err());
+        assert!(serde_json::from_str::<Position>("\"é\"").is_err());
+
+        let promotion = Move::new(Position(52), Position(60), MoveType::PromotionQueen);
+        assert_eq!(
+            serde_json::to_string(&promotion).unwrap(),
+            "{\"from\":\"e7\",\"to\":\"e8\",\"move_type\":\"PromotionQueen\"}"
+        );
+        // Without a position "e1g1" could be a castle or a rook move, so UCI strings are refused
+        assert!(serde_json::from_str::<Move>("\"e7e8q\"").is_err());
+        let castle = Move::new(Position::E1, Position::G1, MoveType::KingSideCastling);
+        let json = serde_json::to_string(&castle).unwrap();
+        assert_eq!(serde_json::from_str::<Move>(&json).unwrap(), castle);
+        let binary = rmp_serde::to_vec(&castle).unwrap();
+        assert_eq!(rmp_serde::from_slice::<Move>(&binary).unwrap(), castle);
+
+        let rights =
+            CastlingRights(CastlingRights::WHITE_KING_SIDE.0 | CastlingRights::BLACK_QUEEN_SIDE.0);
+        assert_eq!(serde_json::to_string(&rights).unwrap(), "\"Kq\"");
+        assert_eq!(
+            serde_json::from_str::<CastlingRights>("\"Kq\"").unwrap(),
+            rights
+        );
+        assert_eq!(serde_json::to_string(&Color::Black).unwrap(), "\"Black\"");
+        assert_eq!(
+            serde_json::from_str::<Piece>("\"WhiteKnight\"").unwrap(),
+            Piece::knight(Color::White)
+        );
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        // Castling, a double push answered en passant and a promotion
+        let state = play(
+            "r3k3/6P1/8/8/5p2/8/4P3/4K2R w Kq - 0 1",
+            &["e1g1", "e8c8", "e2e4", "f4e3", "g7g8q"],
+        );
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(
+            json,
+            "{\"fen\":\"r3k3/6P1/8/8/5p2/8/4P3/4K2R w Kq - 0 1\",\
+             \"moves\":[\"e1g1\",\"e8c8\",\"e2e4\",\"f4e3\",\"g7g8q\"]}"
+        );
+
+        let binary = rmp_serde::to_vec(&state).unwrap();
+        for decoded in [
+            serde_json::from_str::<State>(&json).unwrap(),
+            rmp_serde::from_slice::<State>(&binary).unwrap(),
+        ] {
+            assert_eq!(decoded.to_fen(), state.to_fen());
+            assert_eq!(decoded.hash(), state.hash());
+            assert_eq!(decoded.root_and_moves().1, state.root_and_moves().1);
+        }
+        assert!(binary.len() < json.len());
+
+        // The moves are checked against the position
+        let illegal = "{\"fen\":\"4k3/8/8/8/8/8/8/4K3 w - - 0 1\",\"moves\":[\"e1e3\"]}";
+        let err = serde_json::from_str::<State>(illegal).unwrap_err();
+        assert!(err.to_string().contains("illegal move e1e3"), "{}", err);
+        assert!(serde_json::from_str::<State>("{\"fen\":\"8/8 w\",\"moves\":[]}").is_err());
+    }
+}
@@ -1,15 +1,19 @@
+#[cfg(feature = "cli")]
+use crate::chess::symmetry::Symmetry;
 use crate::chess::{
     bitmask::Bitmask,
     board::{Board, CastlingRights, MoveGenMasks},
     moves::{Move, MoveType},
     prng::{RAND_CASTLING, RAND_COLOR, RAND_EN_PASSANT, RAND_PLACEMENT},
-    types::{Color, Direction, Piece, Position},
+    types::{Color, Direction, FenError, Piece, Position},
 };
 use arrayvec::ArrayVec;
+use std::str::FromStr;
 
 #[derive(Clone)]
 struct UndoRecord {
     mv: Move,
+    moved: Option<Piece>,
     captured: Option<Piece>,
     en_passant: Option<Position>,
     castling_rights: CastlingRights,
@@ -17,6 +21,63 @@ struct UndoRecord {
     hash: u64,
 }
 
+impl UndoRecord {
+    // Zobrist keys the move toggles, given the en passant square and castling rights after it
+    fn keys(&self, en_passant: Option<Position>, castling_rights: CastlingRights) -> u64 {
+        let mut keys = RAND_COLOR[Color::White]
+            ^ RAND_COLOR[Color::Black]
+            ^ RAND_CASTLING[self.castling_rights]
+            ^ RAND_CASTLING[castling_rights];
+        for position in [self.en_passant, en_passant].into_iter().flatten() {
+            keys ^= RAND_EN_PASSANT[position.file() as usize];
+        }
+
+        let (from, to, move_type) = self.mv.unpack();
+        let Some(moved) = self.moved else {
+            return keys;
+        };
+        let color = moved.color();
+        keys ^= RAND_PLACEMENT[moved][from];
+        if let Some(captured) = self.captured {
+            keys ^= RAND_PLACEMENT[captured][to];
+        }
+        let (landed, rook) = match move_type {
+            MoveType::EnPassant => {
+                let captured = Position::en_passant_captured(from, to);
+                keys ^= RAND_PLACEMENT[Piece::pawn(color.flip())][captured];
+                (moved, None)
+            }
+            MoveType::PromotionRook => (Piece::rook(color), None),
+            MoveType::PromotionKnight => (Piece::knight(color), None),
+            MoveType::PromotionBishop => (Piece::bishop(color), None),
+            MoveType::PromotionQueen => (Piece::queen(color), None),
+            MoveType::KingSideCastling => (moved, Some(Position::KS_CASTLE_ROOK[color])),
+            MoveType::QueenSideCastling => (moved, Some(Position::QS_CASTLE_ROOK[color])),
+            MoveType::Standard | MoveType::DoublePush => (moved, None),
+        };
+        keys ^= RAND_PLACEMENT[landed][to];
+        if let Some((rook_from, rook_to)) = rook {
+            keys ^= RAND_PLACEMENT[Piece::rook(color)][rook_from];
+            keys ^= RAND_PLACEMENT[Piece::rook(color)][rook_to];
+        }
+        keys
+    }
+
+    // Whether the position before the move had castling rights or pawns
+    #[cfg(feature = "cli")]
+    fn had_castling_rights_or_pawns(&self) -> bool {
+        let is_pawn = |piece| matches!(piece, Some(Piece::WhitePawn | Piece::BlackPawn));
+        self.castling_rights.0 != 0 || is_pawn(self.moved) || is_pawn(self.captured)
+    }
+}
+
+// Board of an earlier position and how often it had been repeated at that point
+#[cfg(any(feature = "cli", test))]
+pub(crate) type PreviousPosition = ([Option<Piece>; 64], usize);
+
+/// A position and the moves that led to it since the start of the game or the FEN it was set up
+/// with, which repetitions and `unmake_move` need.
+#[derive(Clone)]
 pub struct State {
     pub(crate) board: Board,
     pub(crate) turn: Color,
@@ -44,50 +105,310 @@ impl State {
         state
     }
 
-    pub fn from_fen(fen: &str) -> Self {
-        let parts: ArrayVec<&str, 6> = fen.split_whitespace().collect();
+    /// Positions in EPD style without the two clocks start them at 0 and 1.
+    ///
+    /// ```
+    /// use chess_ai::{FenError, State};
+    ///
+    /// let state = State::from_fen("4k3/8/8/8/8/8/8/4K2R w K -").unwrap();
+    /// assert_eq!(state.to_fen(), "4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+    /// assert_eq!(
+    ///     State::from_fen("4k3/8/8/8/8/8/8/4K2R x K - 0 1").unwrap_err(),
+    ///     FenError::Turn("x".to_string())
+    /// );
+    /// ```
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        if parts.len() != 4 && parts.len() != 6 {
+            return Err(FenError::FieldCount(parts.len()));
+        }
+        let board = Board::from_fen(parts[0])?;
+        let turn = Color::from_fen(parts[1])?;
+        let opponent_king = board.pieces[Piece::king(turn.flip())].lsb();
+        if !board.attackers(opponent_king, turn).is_empty() {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        let castling_rights = CastlingRights::from_fen(parts[2])?;
+        for (color, king, rooks) in [
+            (Color::White, Position::E1, [Position::A1, Position::H1]),
+            (Color::Black, Position::E8, [Position::A8, Position::H8]),
+        ] {
+            let rights = [
+                CastlingRights::QUEEN_SIDE[color],
+                CastlingRights::KING_SIDE[color],
+            ];
+            for (right, rook) in rights.into_iter().zip(rooks) {
+                if castling_rights.has(right)
+                    && (board.mailbox[king] != Some(Piece::king(color))
+                        || board.mailbox[rook] != Some(Piece::rook(color)))
+                {
+                    return Err(FenError::CastlingWithoutPieces(parts[2].to_string()));
+                }
+            }
+        }
+
+        let en_passant = match parts[3] {
+            "-" => None,
+            square => {
+                // Behind a pawn of the side not to move that just moved two squares, on its third
+                // rank, with the square it came from empty
+                let (rank, forward) = [(5, -8), (2, 8)][turn];
+                let pushed = Position::from_fen(square).filter(|&position| {
+                    position.rank() == rank
+                        && !board.is_occupied(position)
+                        && !board.is_occupied(position.offset_unchecked(-forward))
+                        && board.mailbox[position.offset_unchecked(forward)]
+                            == Some(Piece::pawn(turn.flip()))
+                });
+                Some(pushed.ok_or_else(|| FenError::EnPassant(square.to_string()))?)
+            }
+        };
+        let (halfmove_clock, fullmove_number) = match parts.get(4..6) {
+            Some(&[halfmove, fullmove]) => (
+                halfmove
+                    .parse()
+                    .map_err(|_| FenError::HalfmoveClock(halfmove.to_string()))?,
+                fullmove
+                    .parse()
+                    .ok()
+                    .filter(|&number| number > 0)
+                    .ok_or_else(|| FenError::FullmoveNumber(fullmove.to_string()))?,
+            ),
+            _ => (0, 1),
+        };
+
         let mut state = Self {
-            board: Board::from_fen(parts[0]),
-            turn: Color::from_fen(parts[1]),
-            castling_rights: CastlingRights::from_fen(parts[2]),
-            en_passant: Position::from_fen(parts[3]),
-            halfmove_clock: parts[4].parse().unwrap(),
-            fullmove_number: parts[5].parse().unwrap(),
+            board,
+            turn,
+            castling_rights,
+            en_passant,
+            halfmove_clock,
+            fullmove_number,
             hash: 0,
             history: Vec::with_capacity(64),
         };
         state.generate_hash();
+        Ok(state)
+    }
+
+    pub const fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub const fn turn(&self) -> Color {
+        self.turn
+    }
+
+    pub const fn en_passant(&self) -> Option<Position> {
+        self.en_passant
+    }
+
+    pub const fn castling_rights(&self) -> CastlingRights {
+        self.castling_rights
+    }
+
+    pub const fn halfmove_clock(&self) -> usize {
+        self.halfmove_clock
+    }
+
+    pub const fn fullmove_number(&self) -> usize {
+        self.fullmove_number
+    }
+
+    /// Same position with colors swapped and the board flipped, so the side to move changes while
+    /// every line of play maps onto a mirrored one. The game history is mirrored as well.
+    pub fn mirrored(&self) -> Self {
+        self.mapped(
+            self.board.mirrored(),
+            &self.history,
+            Move::mirrored,
+            Position::mirrored,
+            Piece::flip_color,
+            CastlingRights::mirrored,
+        )
+    }
+
+    // Whether the position is unchanged by `symmetry` as far as the rules go: castling depends on
+    // the exact squares of king and rooks, and pawns on the direction of the ranks
+    #[cfg(feature = "cli")]
+    pub(crate) fn allows(&self, symmetry: Symmetry) -> bool {
+        let pawns = self.board.pieces[Piece::pawn(Color::White)]
+            | self.board.pieces[Piece::pawn(Color::Black)];
+        symmetry == Symmetry::Identity
+            || (self.castling_rights.0 == 0
+                && (symmetry.preserves_ranks() || pawns == Bitmask::EMPTY))
+    }
+
+    // The position under `symmetry`, which it has to allow. The history is kept back to the first
+    // position that allows it, castling rights and pawns never come back once gone. A pawn moved
+    // or captured in the history of a pawnless position was still on the board before that move.
+    #[cfg(feature = "cli")]
+    pub(crate) fn transformed(&self, symmetry: Symmetry) -> Self {
+        assert!(
+            self.allows(symmetry),
+            "{:?} does not apply to {}",
+            symmetry,
+            self.to_fen()
+        );
+        let start = match symmetry {
+            Symmetry::Identity => None,
+            Symmetry::FlipFiles => self
+                .history
+                .iter()
+                .rposition(|record| record.castling_rights.0 != 0),
+            _ => self
+                .history
+                .iter()
+                .rposition(UndoRecord::had_castling_rights_or_pawns),
+        }
+        .map_or(0, |last| last + 1);
+        self.mapped(
+            self.board.transformed(symmetry),
+            &self.history[start..],
+            |mv| mv.transformed(symmetry),
+            |position| position.transformed(symmetry),
+            |piece| piece,
+            |castling_rights| castling_rights,
+        )
+    }
+
+    // The position with `board` and `history`, whose moves, squares, pieces and castling rights
+    // are mapped from this one's. The side to move goes with its pieces. The hashes of the
+    // history are worked out backwards from the mapped position's own, one move's keys at a time.
+    fn mapped(
+        &self,
+        board: Board,
+        history: &[UndoRecord],
+        mv: impl Fn(Move) -> Move,
+        square: impl Fn(Position) -> Position,
+        piece: impl Fn(Piece) -> Piece,
+        castling: impl Fn(CastlingRights) -> CastlingRights,
+    ) -> Self {
+        let mut state = Self {
+            board,
+            turn: piece(Piece::king(self.turn)).color(),
+            en_passant: self.en_passant.map(&square),
+            castling_rights: castling(self.castling_rights),
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            hash: 0,
+            history: history
+                .iter()
+                .map(|record| UndoRecord {
+                    mv: mv(record.mv),
+                    moved: record.moved.map(&piece),
+                    captured: record.captured.map(&piece),
+                    en_passant: record.en_passant.map(&square),
+                    castling_rights: castling(record.castling_rights),
+                    halfmove_clock: record.halfmove_clock,
+                    hash: 0,
+                })
+                .collect(),
+        };
+        state.generate_hash();
+
+        let (mut hash, mut en_passant, mut castling_rights) =
+            (state.hash, state.en_passant, state.castling_rights);
+        for record in state.history.iter_mut().rev() {
+            hash ^= record.keys(en_passant, castling_rights);
+            record.hash = hash;
+            (en_passant, castling_rights) = (record.en_passant, record.castling_rights);
+        }
         state
     }
 
+    // Position the game started from, or the FEN it was set up with, and the moves played since
+    #[cfg(any(feature = "cli", feature = "serde", test))]
+    pub(crate) fn root_and_moves(&self) -> (Self, Vec<Move>) {
+        let mut root = self.clone();
+        let mut moves = Vec::with_capacity(self.history.len());
+        while let Some(record) = root.history.last() {
+            moves.push(record.mv);
+            root.unmake_move();
+        }
+        moves.reverse();
+        (root, moves)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let en_passant = self
+            .en_passant
+            .map_or("-".to_string(), |position| position.to_string());
+        format!(
+            "{} {} {} {} {} {}",
+            self.board.to_fen(),
+            if self.turn == Color::White { "w" } else { "b" },
+            self.castling_rights.to_fen(),
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+
+    /// Long algebraic notation as used by UCI, e.g. "e2e4" or "e7e8q". `None` unless the move is
+    /// legal in the position.
+    pub fn parse_uci(&self, uci: &str) -> Option<Move> {
+        self.generate_moves()
+            .into_iter()
+            .find(|mv| mv.to_uci() == uci)
+    }
+
+    // Move that led to the position, None at the start of the game or the FEN root
+    #[cfg(feature = "cli")]
+    pub(crate) fn last_move(&self) -> Option<Move> {
+        self.history.last().map(|record| record.mv)
+    }
+
+    // Zobrist hash of the position, the same for transpositions regardless of the moves before
+    #[cfg(any(feature = "cli", all(feature = "serde", test)))]
+    pub(crate) const fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    // Earlier occurrences of the current position since the last irreversible move
+    #[cfg(any(feature = "cli", test))]
+    pub(crate) fn repetition_count(&self) -> usize {
+        self.history
+            .iter()
+            .rev()
+            .take(self.halfmove_clock)
+            .skip(1)
+            .step_by(2)
+            .filter(|record| record.hash == self.hash)
+            .count()
+    }
+
+    // Up to `n` previous positions, most recent first, with their own repetition counts. Stops
+    // early at the start of the game or the FEN root.
+    #[cfg(any(feature = "cli", test))]
+    pub(crate) fn previous_positions(&self, n: usize) -> Vec<PreviousPosition> {
+        let mut state = self.clone();
+        let mut positions = Vec::with_capacity(n);
+        while positions.len() < n && !state.history.is_empty() {
+            state.unmake_move();
+            positions.push((state.board.mailbox, state.repetition_count()));
+        }
+        positions
+    }
+
     // ------------------------------------------------------------------------
     // Move Making
     // ------------------------------------------------------------------------
 
+    /// Plays `mv`, which has to be legal here: one of `legal_moves` or from `parse_uci`.
     pub fn make_move(&mut self, mv: Move) {
         let (from, to, move_type) = mv.unpack();
         let (moved, captured) = self.board.move_piece(from, to);
-
-        self.history.push(UndoRecord {
+        let record = UndoRecord {
             mv,
+            moved,
             captured,
             en_passant: self.en_passant,
             castling_rights: self.castling_rights,
             halfmove_clock: self.halfmove_clock,
             hash: self.hash,
-        });
-
-        if let Some(piece) = moved {
-            self.hash ^= RAND_PLACEMENT[piece][from];
-            self.hash ^= RAND_PLACEMENT[piece][to];
-        }
-        if let Some(piece) = captured {
-            self.hash ^= RAND_PLACEMENT[piece][to];
-        }
-        if let Some(pos) = self.en_passant {
-            self.hash ^= RAND_EN_PASSANT[pos.file() as usize];
-        }
-        self.hash ^= RAND_CASTLING[self.castling_rights];
+        };
 
         match move_type {
             MoveType::DoublePush => self.make_move_double_push(from, to),
@@ -104,11 +425,11 @@ impl State {
         self.castling_rights = self.castling_rights.update(from, to);
         self.fullmove_number += self.turn as usize;
         self.turn = self.turn.flip();
-        self.hash ^= RAND_COLOR[Color::White];
-        self.hash ^= RAND_COLOR[Color::Black];
-        self.hash ^= RAND_CASTLING[self.castling_rights];
+        self.hash ^= record.keys(self.en_passant, self.castling_rights);
+        self.history.push(record);
     }
 
+    /// Takes back the last move, nothing at the start of the game or the FEN root.
     pub fn unmake_move(&mut self) {
         let Some(history) = self.history.pop() else {
             return;
@@ -159,7 +480,6 @@ impl State {
         let en_passant = Position::middle_of(from, to);
         self.en_passant = Some(en_passant);
         self.halfmove_clock = 0;
-        self.hash ^= RAND_EN_PASSANT[en_passant.file() as usize];
     }
 
     fn make_move_en_passant(&mut self, from: Position, to: Position) {
@@ -167,39 +487,30 @@ impl State {
         self.board.unset_piece(captured);
         self.en_passant = None;
         self.halfmove_clock = 0;
-        self.hash ^= RAND_PLACEMENT[Piece::pawn(self.turn.flip())][captured];
     }
 
     fn make_move_promotion_rook(&mut self, to: Position) {
         self.board.set_piece(to, Piece::rook(self.turn));
         self.en_passant = None;
         self.halfmove_clock = 0;
-        self.hash ^= RAND_PLACEMENT[Piece::pawn(self.turn)][to];
-        self.hash ^= RAND_PLACEMENT[Piece::rook(self.turn)][to];
     }
 
     fn make_move_promotion_knight(&mut self, to: Position) {
         self.board.set_piece(to, Piece::knight(self.turn));
         self.en_passant = None;
         self.halfmove_clock = 0;
-        self.hash ^= RAND_PLACEMENT[Piece::pawn(self.turn)][to];
-        self.hash ^= RAND_PLACEMENT[Piece::knight(self.turn)][to];
     }
 
     fn make_move_promotion_bishop(&mut self, to: Position) {
         self.board.set_piece(to, Piece::bishop(self.turn));
         self.en_passant = None;
         self.halfmove_clock = 0;
-        self.hash ^= RAND_PLACEMENT[Piece::pawn(self.turn)][to];
-        self.hash ^= RAND_PLACEMENT[Piece::bishop(self.turn)][to];
     }
 
     fn make_move_promotion_queen(&mut self, to: Position) {
         self.board.set_piece(to, Piece::queen(self.turn));
         self.en_passant = None;
         self.halfmove_clock = 0;
-        self.hash ^= RAND_PLACEMENT[Piece::pawn(self.turn)][to];
-        self.hash ^= RAND_PLACEMENT[Piece::queen(self.turn)][to];
     }
 
     fn make_move_king_side_castling(&mut self) {
@@ -207,8 +518,6 @@ impl State {
         self.board.move_piece(rook_from, rook_to);
         self.en_passant = None;
         self.halfmove_clock += 1;
-        self.hash ^= RAND_PLACEMENT[Piece::rook(self.turn)][rook_from];
-        self.hash ^= RAND_PLACEMENT[Piece::rook(self.turn)][rook_to];
     }
 
     fn make_move_queen_side_castling(&mut self) {
@@ -216,8 +525,6 @@ impl State {
         self.board.move_piece(rook_from, rook_to);
         self.en_passant = None;
         self.halfmove_clock += 1;
-        self.hash ^= RAND_PLACEMENT[Piece::rook(self.turn)][rook_from];
-        self.hash ^= RAND_PLACEMENT[Piece::rook(self.turn)][rook_to];
     }
 
     fn make_move_standard(&mut self, moved: Option<Piece>, captured: Option<Piece>) {
@@ -234,7 +541,29 @@ impl State {
     // Move Generation
     // ------------------------------------------------------------------------
 
-    pub fn generate_moves(&self) -> ArrayVec<Move, 218> {
+    pub fn is_check(&self) -> bool {
+        self.board.move_gen_masks(self.turn).check_mask != Bitmask::FULL
+    }
+
+    /// Whether a piece of `color` attacks `position`.
+    pub fn is_attacked(&self, position: Position, color: Color) -> bool {
+        !self.board.attackers(position, color).is_empty()
+    }
+
+    /// Moves the side to move can play, in no particular order.
+    ///
+    /// ```
+    /// use chess_ai::State;
+    ///
+    /// let state = State::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+    /// assert_eq!(state.legal_moves().count(), 0);
+    /// assert!(!state.is_check());
+    /// ```
+    pub fn legal_moves(&self) -> impl Iterator<Item = Move> {
+        self.generate_moves().into_iter()
+    }
+
+    pub(crate) fn generate_moves(&self) -> ArrayVec<Move, 218> {
         let mut moves = ArrayVec::<Move, 218>::new();
         let masks = self.board.move_gen_masks(self.turn);
 
@@ -507,7 +836,8 @@ impl State {
         nodes
     }
 
-    pub fn divide(&mut self, depth: u8) {
+    #[cfg(feature = "cli")]
+    pub(crate) fn divide(&mut self, depth: u8) {
         if depth == 0 {
             return;
         }
@@ -529,6 +859,20 @@ impl State {
     }
 }
 
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromStr for State {
+    type Err = FenError;
+
+    fn from_str(fen: &str) -> Result<Self, FenError> {
+        Self::from_fen(fen)
+    }
+}
+
 impl std::fmt::Display for State {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{}", self.board)?;
@@ -582,4 +926,218 @@ mod tests {
         let mut state = State::new();
         assert_eq!(state.perft(5), 4_865_609);
     }
+
+    #[test]
+    fn perft_mirrored() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ];
+
+        for fen in fens {
+            let mut state = State::from_fen(fen).unwrap();
+            let mut mirrored = state.mirrored();
+            assert!(mirrored.turn != state.turn);
+            assert_eq!(mirrored.perft(3), state.perft(3), "{}", fen);
+
+            let moves: Vec<Move> = state
+                .generate_moves()
+                .iter()
+                .map(|mv| mv.mirrored())
+                .collect();
+            let mirrored_moves = mirrored.generate_moves();
+            assert_eq!(moves.len(), mirrored_moves.len());
+            assert!(
+                moves.iter().all(|mv| mirrored_moves.contains(mv)),
+                "{}",
+                fen
+            );
+            assert!(mirrored.mirrored().hash == state.hash);
+        }
+    }
+
+    #[test]
+    fn repetition_history() {
+        let mut state = State::new();
+        let shuffle = [
+            Move::new(Position(6), Position(21), MoveType::Standard),
+            Move::new(Position(62), Position(45), MoveType::Standard),
+            Move::new(Position(21), Position(6), MoveType::Standard),
+            Move::new(Position(45), Position(62), MoveType::Standard),
+        ];
+        for mv in shuffle.iter().chain(&shuffle) {
+            state.make_move(*mv);
+        }
+        assert_eq!(state.repetition_count(), 2);
+
+        let previous = state.previous_positions(3);
+        assert_eq!(previous.len(), 3);
+        assert!(previous[0].0 != state.board.mailbox);
+        assert!(
+            previous[2].0
+                == State::from_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1")
+                    .unwrap()
+                    .board
+                    .mailbox
+        );
+        assert_eq!(previous[2].1, 1);
+        assert_eq!(State::new().previous_positions(4).len(), 0);
+
+        let mirrored = state.mirrored();
+        assert_eq!(mirrored.repetition_count(), 2);
+        assert_eq!(mirrored.previous_positions(8).len(), 8);
+    }
+
+    #[test]
+    fn mirrored_history() {
+        // Castling, a double push taken en passant and a promotion, mirrored without a replay
+        let fen = "r3k3/6P1/8/8/5p2/8/4P3/4K2R w Kq - 0 1";
+        let mut state = State::from_fen(fen).unwrap();
+        let mut replayed = State::from_fen(fen).unwrap().mirrored();
+        for uci in ["e1g1", "e8c8", "e2e4", "f4e3", "g7g8q", "c8b7"] {
+            let mv = state.parse_uci(uci).unwrap();
+            state.make_move(mv);
+            replayed.make_move(mv.mirrored());
+        }
+
+        let mut mirrored = state.mirrored();
+        while !replayed.history.is_empty() {
+            assert_eq!(mirrored.to_fen(), replayed.to_fen());
+            assert_eq!(mirrored.hash, replayed.hash);
+            mirrored.unmake_move();
+            replayed.unmake_move();
+        }
+        assert_eq!(mirrored.to_fen(), replayed.to_fen());
+        assert!(mirrored.history.is_empty());
+    }
+
+    #[test]
+    fn fen_and_uci_round_trip() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12 40",
+        ];
+        for fen in fens {
+            let state = State::from_fen(fen).unwrap();
+            assert_eq!(state.to_fen(), fen);
+            for mv in state.generate_moves() {
+                assert_eq!(state.parse_uci(&mv.to_uci()), Some(mv));
+            }
+        }
+
+        let mut state = State::new();
+        for uci in ["e2e4", "c7c5", "g1f3"] {
+            let mv = state.parse_uci(uci).unwrap();
+            state.make_move(mv);
+        }
+        assert!(state.parse_uci("e7e8q").is_none());
+        let (root, moves) = state.root_and_moves();
+        assert_eq!(root.to_fen(), State::new().to_fen());
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[2].to_uci(), "g1f3");
+    }
+
+    #[test]
+    fn invalid_fens() {
+        for (fen, err) in [
+            (
+                "8/8/8/8/8/8/8/8 w - - 0 1",
+                FenError::Board("8/8/8/8/8/8/8/8".to_string()),
+            ),
+            (
+                "4k3/9/8/8/8/8/8/4K3 w - -",
+                FenError::RankWidth("9".to_string()),
+            ),
+            (
+                "99999999999999999999999999999999/8/8/8/8/8/8/8 w - -",
+                FenError::RankWidth("99999999999999999999999999999999".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3p w - -",
+                FenError::RankWidth("4K3p".to_string()),
+            ),
+            (
+                "P3k3/8/8/8/8/8/8/4K3 w - - 0 1",
+                FenError::PawnOnBackRank(Position::A8),
+            ),
+            (
+                "4k3/8/8/8/8/8/PPPPPPPP/QQ2K3 w - - 0 1",
+                FenError::Material(Color::White),
+            ),
+            (
+                "4k3/pppppppp/p7/8/8/8/8/4K3 b - - 0 1",
+                FenError::Material(Color::Black),
+            ),
+            ("4k3/4Q3/8/8/8/8/8/4K3 w - - 0 1", FenError::OpponentInCheck),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w KQkq - 0 1",
+                FenError::CastlingWithoutPieces("KQkq".to_string()),
+            ),
+            (
+                "r3k2r/8/8/8/8/8/8/R4K1R w K - 0 1",
+                FenError::CastlingWithoutPieces("K".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/4K3 w - -",
+                FenError::Board("4k3/8/8/8/8/8/4K3".to_string()),
+            ),
+            ("4k3/8/8/8/8/8/8/4K3 w", FenError::FieldCount(2)),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w KX -",
+                FenError::CastlingRights("KX".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - e3",
+                FenError::EnPassant("e3".to_string()),
+            ),
+            // Two bytes but a single character
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - é",
+                FenError::EnPassant("é".to_string()),
+            ),
+            (
+                "4k3/8/8/8/4p3/8/8/4K3 w - e6",
+                FenError::EnPassant("e6".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - x 1",
+                FenError::HalfmoveClock("x".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - 0 0",
+                FenError::FullmoveNumber("0".to_string()),
+            ),
+        ] {
+            assert_eq!(State::from_fen(fen).unwrap_err(), err, "{}", fen);
+        }
+        let state: State = "4k3/8/8/8/8/8/8/4K3 b - - 3 20".parse().unwrap();
+        assert_eq!(state.halfmove_clock(), 3);
+        assert_eq!(state.fullmove_number(), 20);
+        assert_eq!(state.turn(), Color::Black);
+        assert!(State::from_fen("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 2").is_ok());
+    }
+
+    #[test]
+    fn attack_queries() {
+        let state = State::from_fen("r3k3/8/8/3n4/8/2P5/8/R3K3 w - - 0 1").unwrap();
+        let d1 = Position::new(3, 0).unwrap();
+        let b4 = Position::new(1, 3).unwrap();
+        assert!(state.is_attacked(d1, Color::White));
+        assert!(!state.is_attacked(d1, Color::Black));
+        let attackers: Vec<Position> = state.board().attackers(b4, Color::White).collect();
+        assert_eq!(attackers, [Position::new(2, 2).unwrap()]);
+        let attackers: Vec<Position> = state.board().attackers(b4, Color::Black).collect();
+        assert_eq!(attackers, [Position::new(3, 4).unwrap()]);
+        assert!(state.board().attacks(Color::Black).contains(Position::A1));
+        assert!(state.board().attacks(Color::White).contains(Position::A8));
+        assert!(!state.board().attacks(Color::White).contains(Position::H8));
+        assert_eq!(
+            state.board().piece_at(Position::A8),
+            Some(Piece::rook(Color::Black))
+        );
+    }
 }
@@ -0,0 +1,175 @@
+use crate::chess::{bitmask::Bitmask, board::Board, moves::Move, types::Position};
+
+// The eight symmetries of the square board. Colors and the side to move are left alone, so unlike
+// `State::mirrored` none of them is a symmetry of chess in general, see `State::allows`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Symmetry {
+    Identity,
+    // a1 <-> h1, valid without castling rights
+    FlipFiles,
+    // The rest also need a board without pawns
+    // a1 <-> a8
+    FlipRanks,
+    // a1 <-> h8
+    Rotate180,
+    // a1 -> a8 -> h8 -> h1, clockwise with White at the bottom
+    Rotate90,
+    Rotate270,
+    // Reflection in the a1-h8 diagonal
+    Transpose,
+    // Reflection in the a8-h1 diagonal
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub(crate) const ALL: [Self; 8] = [
+        Self::Identity,
+        Self::FlipFiles,
+        Self::FlipRanks,
+        Self::Rotate180,
+        Self::Rotate90,
+        Self::Rotate270,
+        Self::Transpose,
+        Self::AntiTranspose,
+    ];
+
+    // Symmetries that keep pawns moving along their files
+    pub(crate) const fn preserves_ranks(self) -> bool {
+        matches!(self, Self::Identity | Self::FlipFiles)
+    }
+
+    // Maps a (rank, file) offset between two squares, e.g. a move's direction
+    pub(crate) const fn transform_delta(self, (rank, file): (i8, i8)) -> (i8, i8) {
+        match self {
+            Self::Identity => (rank, file),
+            Self::FlipFiles => (rank, -file),
+            Self::FlipRanks => (-rank, file),
+            Self::Rotate180 => (-rank, -file),
+            Self::Rotate90 => (-file, rank),
+            Self::Rotate270 => (file, -rank),
+            Self::Transpose => (file, rank),
+            Self::AntiTranspose => (-file, -rank),
+        }
+    }
+}
+
+impl Position {
+    pub(crate) const fn transformed(self, symmetry: Symmetry) -> Self {
+        let (rank, file) = (self.rank(), self.file());
+        let (rank, file) = match symmetry {
+            Symmetry::Identity => (rank, file),
+            Symmetry::FlipFiles => (rank, 7 - file),
+            Symmetry::FlipRanks => (7 - rank, file),
+            Symmetry::Rotate180 => (7 - rank, 7 - file),
+            Symmetry::Rotate90 => (7 - file, rank),
+            Symmetry::Rotate270 => (file, 7 - rank),
+            Symmetry::Transpose => (file, rank),
+            Symmetry::AntiTranspose => (7 - file, 7 - rank),
+        };
+        Self(rank * 8 + file)
+    }
+}
+
+impl Move {
+    pub(crate) const fn transformed(self, symmetry: Symmetry) -> Self {
+        Self::new(
+            self.from().transformed(symmetry),
+            self.to().transformed(symmetry),
+            self.move_type(),
+        )
+    }
+}
+
+impl Board {
+    pub(crate) fn transformed(&self, symmetry: Symmetry) -> Self {
+        let mut board = Self {
+            pieces: [Bitmask::EMPTY; 12],
+            colors: [Bitmask::EMPTY; 2],
+            occupancy: Bitmask::EMPTY,
+            mailbox: [None; 64],
+        };
+        for (i, square) in self.mailbox.iter().enumerate() {
+            if let Some(piece) = *square {
+                board.set_piece(Position(i as u8).transformed(symmetry), piece);
+            }
+        }
+        board
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::State;
+
+    #[test]
+    fn test_symmetries_keep_move_generation() {
+        // Pawnless and without castling rights, so every symmetry applies
+        let mut state = State::from_fen("1k6/8/3q4/8/4N3/2B5/6R1/4K3 w - - 0 1").unwrap();
+        let expected = state.perft(3);
+        let moves = state.generate_moves();
+
+        for symmetry in Symmetry::ALL {
+            assert!(state.allows(symmetry));
+            let mut transformed = state.transformed(symmetry);
+            assert_eq!(transformed.perft(3), expected, "{:?}", symmetry);
+
+            let mut mapped: Vec<u16> = moves.iter().map(|mv| mv.transformed(symmetry).0).collect();
+            let mut generated: Vec<u16> =
+                transformed.generate_moves().iter().map(|mv| mv.0).collect();
+            mapped.sort();
+            generated.sort();
+            assert_eq!(mapped, generated, "{:?}", symmetry);
+
+            // Every symmetry undoes itself but the rotations, which undo each other
+            let inverse = match symmetry {
+                Symmetry::Rotate90 => Symmetry::Rotate270,
+                Symmetry::Rotate270 => Symmetry::Rotate90,
+                other => other,
+            };
+            let back = transformed.transformed(inverse);
+            assert_eq!(back.to_fen(), state.to_fen());
+            // A knight hop from a1 to b3 keeps its offset consistent with the squares
+            let (from, to) = (
+                Position::A1.transformed(symmetry),
+                Position(17).transformed(symmetry),
+            );
+            let offset = (
+                to.rank() as i8 - from.rank() as i8,
+                to.file() as i8 - from.file() as i8,
+            );
+            assert_eq!(symmetry.transform_delta((2, 1)), offset, "{:?}", symmetry);
+        }
+
+        // With pawns only the file flip is left, castling rights rule out everything
+        let mut pawns = State::from_fen("4k3/1p6/8/2P5/8/8/5P2/4K3 b - - 0 1").unwrap();
+        let allowed: Vec<Symmetry> = Symmetry::ALL
+            .into_iter()
+            .filter(|&symmetry| pawns.allows(symmetry))
+            .collect();
+        assert_eq!(allowed, vec![Symmetry::Identity, Symmetry::FlipFiles]);
+        let expected = pawns.perft(3);
+        assert_eq!(pawns.transformed(Symmetry::FlipFiles).perft(3), expected);
+        assert!(!State::new().allows(Symmetry::FlipFiles));
+        assert!(State::new().allows(Symmetry::Identity));
+    }
+
+    #[test]
+    fn test_transformed_history() {
+        // Castling rights are lost along the way, the history is kept from that point on
+        let mut state = State::from_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+        for uci in ["a1b1", "e8d8", "b1a1", "d8e8", "a1b1"] {
+            state.make_move(state.parse_uci(uci).unwrap());
+        }
+        assert!(state.allows(Symmetry::Rotate90));
+        let transformed = state.transformed(Symmetry::Rotate90);
+        let (root, moves) = transformed.root_and_moves();
+        assert_eq!(root.castling_rights.0, 0);
+        assert_eq!(moves.len(), 4);
+        assert_eq!(transformed.repetition_count(), state.repetition_count());
+        assert_eq!(
+            transformed.transformed(Symmetry::Rotate270).to_fen(),
+            state.to_fen()
+        );
+    }
+}
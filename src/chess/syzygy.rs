@@ -0,0 +1,2000 @@
+use crate::chess::{
+    moves::{Move, MoveType},
+    types::{Color, GameResult, Piece},
+    State,
+};
+use arrayvec::ArrayVec;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const MAX_PIECES: usize = 7;
+
+type PieceOf = fn(Color) -> Piece;
+
+// Piece letters in the order Syzygy file names list them
+const PIECE_LETTERS: [(char, PieceOf); 6] = [
+    ('K', Piece::king),
+    ('Q', Piece::queen),
+    ('R', Piece::rook),
+    ('B', Piece::bishop),
+    ('N', Piece::knight),
+    ('P', Piece::pawn),
+];
+
+#[rustfmt::skip]
+const TRIANGLE: [u8; 64] = [
+    6, 0, 1, 2, 2, 1, 0, 6,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    6, 0, 1, 2, 2, 1, 0, 6,
+];
+
+#[rustfmt::skip]
+const FLAP: [u8; 64] = [
+    0,  0,  0,  0,  0,  0,  0, 0,
+    0,  6, 12, 18, 18, 12,  6, 0,
+    1,  7, 13, 19, 19, 13,  7, 1,
+    2,  8, 14, 20, 20, 14,  8, 2,
+    3,  9, 15, 21, 21, 15,  9, 3,
+    4, 10, 16, 22, 22, 16, 10, 4,
+    5, 11, 17, 23, 23, 17, 11, 5,
+    0,  0,  0,  0,  0,  0,  0, 0,
+];
+
+#[rustfmt::skip]
+const PAWN_TWIST: [u8; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    47, 35, 23, 11, 10, 22, 34, 46,
+    45, 33, 21,  9,  8, 20, 32, 44,
+    43, 31, 19,  7,  6, 18, 30, 42,
+    41, 29, 17,  5,  4, 16, 28, 40,
+    39, 27, 15,  3,  2, 14, 26, 38,
+    37, 25, 13,  1,  0, 12, 24, 36,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+const FILE_TO_FILE: [usize; 8] = [0, 1, 2, 3, 3, 2, 1, 0];
+
+// Sign of rank - file: 0 on the a1-h8 diagonal, -1 below it, 1 above it
+const OFF_DIAG: [i8; 64] = {
+    let mut table = [0i8; 64];
+    let mut i = 0;
+    while i < 64 {
+        let (rank, file) = ((i / 8) as i8, (i % 8) as i8);
+        table[i] = if rank > file {
+            1
+        } else if rank < file {
+            -1
+        } else {
+            0
+        };
+        i += 1;
+    }
+    table
+};
+
+const FLIP_DIAG: [u8; 64] = {
+    let mut table = [0u8; 64];
+    let mut i = 0;
+    while i < 64 {
+        table[i] = ((i % 8) * 8 + i / 8) as u8;
+        i += 1;
+    }
+    table
+};
+
+// Squares below the diagonal are numbered 0..28, the diagonal itself 28..36
+const LOWER: [u8; 64] = {
+    let mut table = [0u8; 64];
+    let mut n = 0u8;
+    let mut i = 0;
+    while i < 64 {
+        let (rank, file) = (i / 8, i % 8);
+        if file > rank {
+            table[i] = n;
+            table[file * 8 + rank] = n;
+            n += 1;
+        } else if file == rank {
+            table[i] = 28 + rank as u8;
+        }
+        i += 1;
+    }
+    table
+};
+
+const DIAG: [u8; 64] = {
+    let mut table = [0u8; 64];
+    let mut i = 0;
+    while i < 8 {
+        table[i * 9] = i as u8;
+        table[(i + 1) * 7] = 8 + i as u8;
+        i += 1;
+    }
+    table
+};
+
+// Index of the second king given the first king's triangle index. When the first king sits on
+// the diagonal the second one is mirrored onto or below it, which leaves 462 king pairs. As in the
+// tables, the 21 pairs with both kings on the a1-h8 diagonal come last.
+const KK_IDX: [[i16; 64]; 10] = {
+    const INV_TRIANGLE: [usize; 10] = [1, 2, 3, 10, 11, 19, 0, 9, 18, 27];
+    let mut table = [[-1i16; 64]; 10];
+    let mut both_on_diagonal = [(0usize, 0usize); 21];
+    let mut deferred = 0;
+    let mut n = 0i16;
+    let mut t = 0;
+    while t < 10 {
+        let k1 = INV_TRIANGLE[t];
+        let mut k2 = 0;
+        while k2 < 64 {
+            let rank_dist = (k1 / 8).abs_diff(k2 / 8);
+            let file_dist = (k1 % 8).abs_diff(k2 % 8);
+            let adjacent = rank_dist <= 1 && file_dist <= 1;
+            if adjacent || (t >= 6 && k2 % 8 < k2 / 8) {
+                k2 += 1;
+                continue;
+            }
+            if t >= 6 && k2 % 8 == k2 / 8 {
+                both_on_diagonal[deferred] = (t, k2);
+                deferred += 1;
+            } else {
+                table[t][k2] = n;
+                n += 1;
+            }
+            k2 += 1;
+        }
+        t += 1;
+    }
+
+    let mut i = 0;
+    while i < deferred {
+        let (t, k2) = both_on_diagonal[i];
+        table[t][k2] = n;
+        n += 1;
+        i += 1;
+    }
+    table
+};
+
+const fn binomial(n: u64, k: u64) -> u64 {
+    if k > n {
+        return 0;
+    }
+    let mut result = 1u64;
+    let mut i = 0;
+    while i < k {
+        result = result * (n - i) / (i + 1);
+        i += 1;
+    }
+    result
+}
+
+// PAWN_IDX[pawns - 1][j] and PAWN_FACTOR[pawns - 1][file] for the leading pawn encoding
+const PAWN_IDX: [[u64; 24]; 6] = {
+    let mut table = [[0u64; 24]; 6];
+    let mut i = 0;
+    while i < 6 {
+        let mut s = 0;
+        let mut j = 0;
+        while j < 24 {
+            table[i][j] = s;
+            s += binomial(PAWN_TWIST[(1 + j % 6) * 8 + j / 6] as u64, i as u64);
+            if (j + 1) % 6 == 0 {
+                s = 0;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    table
+};
+
+const PAWN_FACTOR: [[u64; 4]; 6] = {
+    let mut table = [[0u64; 4]; 6];
+    let mut i = 0;
+    while i < 6 {
+        let mut j = 0;
+        while j < 24 {
+            table[i][j / 6] += binomial(PAWN_TWIST[(1 + j % 6) * 8 + j / 6] as u64, i as u64);
+            j += 1;
+        }
+        i += 1;
+    }
+    table
+};
+
+#[repr(i8)]
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub(crate) enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Self {
+        match value {
+            -2 => Self::Loss,
+            -1 => Self::BlessedLoss,
+            1 => Self::CursedWin,
+            2 => Self::Win,
+            _ => Self::Draw,
+        }
+    }
+}
+
+impl std::fmt::Display for Wdl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let w = match self {
+            Self::Loss => "Loss",
+            Self::BlessedLoss => "Blessed Loss",
+            Self::Draw => "Draw",
+            Self::CursedWin => "Cursed Win",
+            Self::Win => "Win",
+        };
+        write!(f, "{}", w)
+    }
+}
+
+impl std::fmt::Debug for Wdl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+struct TableFile {
+    file: File,
+}
+
+impl TableFile {
+    #[cfg(unix)]
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(&self.file, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match std::os::windows::fs::FileExt::seek_read(&self.file, buf, offset)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_vec(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        self.read_exact_at(&mut buf, offset)?;
+        Ok(buf)
+    }
+
+    fn read_u8(&self, offset: u64) -> io::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.read_exact_at(&mut buf, offset)?;
+        Ok(buf[0])
+    }
+
+    fn read_u16(&self, offset: u64) -> io::Result<u16> {
+        let mut buf = [0u8; 2];
+        self.read_exact_at(&mut buf, offset)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+}
+
+// Canonical Huffman coded symbol stream, one per (file, side) of a table
+struct PairsData {
+    flags: u8,
+    single_value: Option<u8>,
+    block_size: u32,
+    idx_bits: u32,
+    min_len: u32,
+    offsets: Vec<u16>,
+    base: Vec<u64>,
+    symlen: Vec<u8>,
+    sympat: Vec<u8>,
+    index_table: u64,
+    size_table: u64,
+    data: u64,
+}
+
+impl PairsData {
+    // Returns the pairs data and the sizes of its index table, size table and block data
+    fn parse(
+        file: &TableFile,
+        pos: &mut u64,
+        tb_size: u64,
+        is_wdl: bool,
+    ) -> io::Result<(Self, [u64; 3])> {
+        let flags = file.read_u8(*pos)?;
+        if flags & 0x80 != 0 {
+            let value = if is_wdl { file.read_u8(*pos + 1)? } else { 0 };
+            *pos += 2;
+            let pairs = Self {
+                flags,
+                single_value: Some(value),
+                block_size: 0,
+                idx_bits: 0,
+                min_len: 0,
+                offsets: Vec::new(),
+                base: Vec::new(),
+                symlen: Vec::new(),
+                sympat: Vec::new(),
+                index_table: 0,
+                size_table: 0,
+                data: 0,
+            };
+            return Ok((pairs, [0; 3]));
+        }
+
+        let header = file.read_vec(*pos, 10)?;
+        let block_size = header[1] as u32;
+        let idx_bits = header[2] as u32;
+        let real_num_blocks = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+        let num_blocks = real_num_blocks + header[3] as u64;
+        let max_len = header[8] as u32;
+        let min_len = header[9] as u32;
+        if max_len < min_len || max_len > 64 || min_len == 0 {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let h = (max_len - min_len + 1) as usize;
+
+        let offset_bytes = file.read_vec(*pos + 10, 2 * h + 2)?;
+        let offsets: Vec<u16> = offset_bytes[..2 * h]
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        let num_syms = u16::from_le_bytes([offset_bytes[2 * h], offset_bytes[2 * h + 1]]) as usize;
+        let sympat = file.read_vec(*pos + 12 + 2 * h as u64, 3 * num_syms)?;
+        *pos += (12 + 2 * h + 3 * num_syms + (num_syms & 1)) as u64;
+
+        let mut symlen = vec![0u8; num_syms];
+        let mut visited = vec![false; num_syms];
+        for s in 0..num_syms {
+            calc_symlen(&sympat, &mut symlen, &mut visited, s)?;
+        }
+
+        let mut base = vec![0u64; h];
+        for i in (0..h - 1).rev() {
+            base[i] = base[i + 1]
+                .wrapping_add(offsets[i] as u64)
+                .wrapping_sub(offsets[i + 1] as u64)
+                / 2;
+        }
+        for (i, b) in base.iter_mut().enumerate() {
+            *b = b.checked_shl(64 - (min_len + i as u32)).unwrap_or(0);
+        }
+
+        let num_indices = (tb_size + (1u64 << idx_bits) - 1) >> idx_bits;
+        let sizes = [
+            6 * num_indices,
+            2 * num_blocks,
+            (1u64 << block_size) * real_num_blocks,
+        ];
+
+        let pairs = Self {
+            flags,
+            single_value: None,
+            block_size,
+            idx_bits,
+            min_len,
+            offsets,
+            base,
+            symlen,
+            sympat,
+            index_table: 0,
+            size_table: 0,
+            data: 0,
+        };
+        Ok((pairs, sizes))
+    }
+
+    fn decompress(&self, file: &TableFile, idx: u64) -> io::Result<u8> {
+        if let Some(value) = self.single_value {
+            return Ok(value);
+        }
+
+        let main_idx = idx >> self.idx_bits;
+        let mut lit_idx =
+            (idx & ((1u64 << self.idx_bits) - 1)) as i64 - (1i64 << (self.idx_bits - 1));
+
+        let entry = file.read_vec(self.index_table + 6 * main_idx, 6)?;
+        let mut block = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as u64;
+        lit_idx += u16::from_le_bytes([entry[4], entry[5]]) as i64;
+
+        if lit_idx < 0 {
+            while lit_idx < 0 {
+                block -= 1;
+                lit_idx += file.read_u16(self.size_table + 2 * block)? as i64 + 1;
+            }
+        } else {
+            loop {
+                let size = file.read_u16(self.size_table + 2 * block)? as i64;
+                if lit_idx <= size {
+                    break;
+                }
+                lit_idx -= size + 1;
+                block += 1;
+            }
+        }
+
+        let block_len = 1usize << self.block_size;
+        let data = file.read_vec(self.data + (block << self.block_size), block_len)?;
+        let read_u32 = |i: usize| -> u64 {
+            data.get(i..i + 4)
+                .map_or(0, |b| u32::from_be_bytes(b.try_into().unwrap()) as u64)
+        };
+
+        let mut code = (read_u32(0) << 32) | read_u32(4);
+        let mut ptr = 8;
+        let mut bit_count = 0;
+        let mut sym;
+
+        loop {
+            let mut l = self.min_len;
+            while code < self.base[(l - self.min_len) as usize] {
+                l += 1;
+            }
+            let i = (l - self.min_len) as usize;
+            sym = self.offsets[i] as usize + ((code - self.base[i]) >> (64 - l)) as usize;
+            let len = *self.symlen.get(sym).ok_or(io::ErrorKind::InvalidData)? as i64;
+            if lit_idx < len + 1 {
+                break;
+            }
+            lit_idx -= len + 1;
+            code <<= l;
+            bit_count += l;
+            if bit_count >= 32 {
+                bit_count -= 32;
+                code |= read_u32(ptr) << bit_count;
+                ptr += 4;
+            }
+        }
+
+        while self.symlen[sym] != 0 {
+            let w = &self.sympat[3 * sym..3 * sym + 3];
+            let left = ((w[1] as usize & 0x0F) << 8) | w[0] as usize;
+            if lit_idx < self.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                lit_idx -= self.symlen[left] as i64 + 1;
+                sym = ((w[2] as usize) << 4) | (w[1] as usize >> 4);
+            }
+        }
+
+        Ok(self.sympat[3 * sym])
+    }
+}
+
+fn calc_symlen(sympat: &[u8], symlen: &mut [u8], visited: &mut [bool], s: usize) -> io::Result<()> {
+    if visited[s] {
+        return Ok(());
+    }
+    let w = sympat
+        .get(3 * s..3 * s + 3)
+        .ok_or(io::ErrorKind::InvalidData)?;
+    let right = ((w[2] as usize) << 4) | (w[1] as usize >> 4);
+    if right == 0x0FFF {
+        symlen[s] = 0;
+    } else {
+        let left = ((w[1] as usize & 0x0F) << 8) | w[0] as usize;
+        if left >= symlen.len() || right >= symlen.len() {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        visited[s] = true;
+        calc_symlen(sympat, symlen, visited, left)?;
+        calc_symlen(sympat, symlen, visited, right)?;
+        symlen[s] = symlen[left].wrapping_add(symlen[right]).wrapping_add(1);
+    }
+    visited[s] = true;
+    Ok(())
+}
+
+struct EncInfo {
+    pieces: [u8; MAX_PIECES],
+    norm: [u8; MAX_PIECES],
+    factor: [u64; MAX_PIECES],
+    pairs: PairsData,
+}
+
+// Material of a table as its file name spells it, the first side is "white"
+#[derive(Clone, Eq, PartialEq, Hash)]
+struct Material {
+    counts: [[u8; 6]; 2],
+}
+
+impl Material {
+    fn from_name(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let mut counts = [[0u8; 6]; 2];
+        for (side, part) in [white, black].into_iter().enumerate() {
+            for c in part.chars() {
+                let i = PIECE_LETTERS.iter().position(|&(l, _)| l == c)?;
+                counts[side][i] += 1;
+            }
+            if counts[side][0] != 1 {
+                return None;
+            }
+        }
+        Some(Self { counts })
+    }
+
+    fn from_state(state: &State) -> Self {
+        let mut counts = [[0u8; 6]; 2];
+        for (side, color) in [Color::White, Color::Black].into_iter().enumerate() {
+            for (i, &(_, piece)) in PIECE_LETTERS.iter().enumerate() {
+                counts[side][i] = state.board.pieces[piece(color)].count() as u8;
+            }
+        }
+        Self { counts }
+    }
+
+    fn flipped(&self) -> Self {
+        Self {
+            counts: [self.counts[1], self.counts[0]],
+        }
+    }
+
+    fn name(&self) -> String {
+        let mut name = String::with_capacity(MAX_PIECES + 1);
+        for side in 0..2 {
+            if side == 1 {
+                name.push('v');
+            }
+            for (i, &(letter, _)) in PIECE_LETTERS.iter().enumerate() {
+                (0..self.counts[side][i]).for_each(|_| name.push(letter));
+            }
+        }
+        name
+    }
+
+    fn count(&self) -> usize {
+        self.counts.iter().flatten().map(|&c| c as usize).sum()
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.counts[0] == self.counts[1]
+    }
+
+    fn pawns(&self) -> [u8; 2] {
+        [self.counts[0][5], self.counts[1][5]]
+    }
+}
+
+struct Table {
+    file: TableFile,
+    num: usize,
+    symmetric: bool,
+    has_pawns: bool,
+    pawns: [u8; 2],
+    kk_enc: bool,
+    // Indexed by leading pawn file (a single entry without pawns), then by side to move
+    enc: Vec<[Option<EncInfo>; 2]>,
+    dtz_map: u64,
+    dtz_map_idx: Vec<[u64; 4]>,
+}
+
+impl Table {
+    fn open(path: &Path, material: &Material, is_wdl: bool) -> io::Result<Self> {
+        let file = TableFile {
+            file: File::open(path)?,
+        };
+
+        let header = file.read_vec(0, 5)?;
+        let magic = if is_wdl { WDL_MAGIC } else { DTZ_MAGIC };
+        if header[0..4] != magic {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let split = is_wdl && header[4] & 0x01 != 0;
+        let has_pawns = header[4] & 0x02 != 0;
+        let table_pawns = material.pawns();
+        if has_pawns != (table_pawns[0] + table_pawns[1] > 0) {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        // The leading pawns are the side with fewer pawns, white on a tie
+        let pawns =
+            if table_pawns[1] > 0 && (table_pawns[0] == 0 || table_pawns[1] < table_pawns[0]) {
+                [table_pawns[1], table_pawns[0]]
+            } else {
+                table_pawns
+            };
+        let unique_pieces = material
+            .counts
+            .iter()
+            .flatten()
+            .filter(|&&c| c == 1)
+            .count();
+
+        let mut table = Self {
+            file,
+            num: material.count(),
+            symmetric: material.is_symmetric(),
+            has_pawns,
+            pawns,
+            kk_enc: unique_pieces == 2,
+            enc: Vec::new(),
+            dtz_map: 0,
+            dtz_map_idx: Vec::new(),
+        };
+        table.parse(split, is_wdl)?;
+        Ok(table)
+    }
+
+    fn parse(&mut self, split: bool, is_wdl: bool) -> io::Result<()> {
+        let num_files = if self.has_pawns { 4 } else { 1 };
+        let more_pawns = self.has_pawns && self.pawns[1] > 0;
+        let sides = if split { 2 } else { 1 };
+
+        let mut pos = 5u64;
+        let mut layouts = Vec::with_capacity(num_files);
+        for t in 0..num_files {
+            let bytes = self
+                .file
+                .read_vec(pos, self.num + 1 + more_pawns as usize)?;
+            let mut sides_layout = [None, None];
+            for (side, layout) in sides_layout.iter_mut().enumerate().take(sides) {
+                *layout = Some(self.enc_layout(&bytes, 4 * side as u32, t, more_pawns));
+            }
+            layouts.push(sides_layout);
+            pos += bytes.len() as u64;
+        }
+        pos += pos & 1;
+
+        let mut sizes = Vec::with_capacity(num_files);
+        for layout in layouts {
+            let mut enc: [Option<EncInfo>; 2] = [None, None];
+            let mut size = [[0u64; 3]; 2];
+            for (side, layout) in layout.into_iter().enumerate() {
+                let Some((pieces, norm, factor, tb_size)) = layout else {
+                    continue;
+                };
+                let (pairs, pairs_size) = PairsData::parse(&self.file, &mut pos, tb_size, is_wdl)?;
+                size[side] = pairs_size;
+                enc[side] = Some(EncInfo {
+                    pieces,
+                    norm,
+                    factor,
+                    pairs,
+                });
+            }
+            self.enc.push(enc);
+            sizes.push(size);
+        }
+
+        if !is_wdl {
+            self.dtz_map = pos;
+            for t in 0..num_files {
+                let flags = self.dtz_flags(t);
+                let mut map_idx = [0u64; 4];
+                if flags & 2 != 0 {
+                    if flags & 16 == 0 {
+                        for idx in map_idx.iter_mut() {
+                            *idx = pos + 1 - self.dtz_map;
+                            pos += 1 + self.file.read_u8(pos)? as u64;
+                        }
+                    } else {
+                        pos += pos & 1;
+                        for idx in map_idx.iter_mut() {
+                            *idx = pos + 2 - self.dtz_map;
+                            pos += 2 + 2 * self.file.read_u16(pos)? as u64;
+                        }
+                    }
+                }
+                self.dtz_map_idx.push(map_idx);
+            }
+            pos += pos & 1;
+        }
+
+        for (enc, size) in self.enc.iter_mut().zip(&sizes) {
+            for (side, info) in enc.iter_mut().enumerate() {
+                if let Some(info) = info {
+                    info.pairs.index_table = pos;
+                    pos += size[side][0];
+                }
+            }
+        }
+        for (enc, size) in self.enc.iter_mut().zip(&sizes) {
+            for (side, info) in enc.iter_mut().enumerate() {
+                if let Some(info) = info {
+                    pos += pos & 1;
+                    info.pairs.size_table = pos;
+                    pos += size[side][1];
+                }
+            }
+        }
+        for (enc, size) in self.enc.iter_mut().zip(&sizes) {
+            for (side, info) in enc.iter_mut().enumerate() {
+                if let Some(info) = info {
+                    pos = (pos + 0x3F) & !0x3F;
+                    info.pairs.data = pos;
+                    pos += size[side][2];
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Reads the piece order of one side and derives the normalization groups and index factors
+    #[allow(clippy::type_complexity)]
+    fn enc_layout(
+        &self,
+        bytes: &[u8],
+        shift: u32,
+        t: usize,
+        more_pawns: bool,
+    ) -> ([u8; MAX_PIECES], [u8; MAX_PIECES], [u64; MAX_PIECES], u64) {
+        let num = self.num;
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut norm = [0u8; MAX_PIECES];
+        let mut factor = [0u64; MAX_PIECES];
+
+        for (i, piece) in pieces.iter_mut().enumerate().take(num) {
+            *piece = (bytes[i + 1 + more_pawns as usize] >> shift) & 0x0F;
+        }
+        let order = ((bytes[0] >> shift) & 0x0F) as usize;
+        let order2 = if more_pawns {
+            ((bytes[1] >> shift) & 0x0F) as usize
+        } else {
+            0x0F
+        };
+
+        let mut k = if self.has_pawns {
+            self.pawns[0] as usize
+        } else if self.kk_enc {
+            2
+        } else {
+            3
+        };
+        norm[0] = k as u8;
+        if more_pawns {
+            norm[k] = self.pawns[1];
+            k += norm[k] as usize;
+        }
+
+        let mut i = k;
+        while i < num {
+            let mut j = i;
+            while j < num && pieces[j] == pieces[i] {
+                norm[i] += 1;
+                j += 1;
+            }
+            i += norm[i] as usize;
+        }
+
+        let mut n = 64 - k as u64;
+        let mut f = 1u64;
+        let mut i = 0;
+        while k < num || i == order || i == order2 {
+            if i == order {
+                factor[0] = f;
+                f *= if self.has_pawns {
+                    PAWN_FACTOR[norm[0] as usize - 1][t]
+                } else if self.kk_enc {
+                    462
+                } else {
+                    31332
+                };
+            } else if i == order2 {
+                factor[norm[0] as usize] = f;
+                f *= binomial(48 - norm[0] as u64, norm[norm[0] as usize] as u64);
+            } else {
+                factor[k] = f;
+                f *= binomial(n, norm[k] as u64);
+                n -= norm[k] as u64;
+                k += norm[k] as usize;
+            }
+            i += 1;
+        }
+
+        (pieces, norm, factor, f)
+    }
+
+    fn dtz_flags(&self, t: usize) -> u8 {
+        self.enc[t][0].as_ref().map_or(0, |info| info.pairs.flags)
+    }
+
+    // Collects piece squares in table order and returns them with the leading pawn file
+    fn squares(
+        &self,
+        state: &State,
+        side: usize,
+        flip_colors: bool,
+        mirror: u8,
+    ) -> Option<(usize, ArrayVec<u8, MAX_PIECES>)> {
+        let piece_of = |code: u8| -> Option<Piece> {
+            let color = if (code >> 3 != 0) != flip_colors {
+                Color::Black
+            } else {
+                Color::White
+            };
+            let piece = match code & 0x07 {
+                1 => Piece::pawn(color),
+                2 => Piece::knight(color),
+                3 => Piece::bishop(color),
+                4 => Piece::rook(color),
+                5 => Piece::queen(color),
+                6 => Piece::king(color),
+                _ => return None,
+            };
+            Some(piece)
+        };
+
+        let mut squares = ArrayVec::<u8, MAX_PIECES>::new();
+        let mut t = 0;
+
+        if self.has_pawns {
+            let leading = piece_of(self.enc[0][0].as_ref()?.pieces[0])?;
+            for position in state.board.pieces[leading] {
+                squares.try_push(position.0 ^ mirror).ok()?;
+            }
+            for i in 1..self.pawns[0] as usize {
+                if FLAP[squares[0] as usize] > FLAP[squares[i] as usize] {
+                    squares.swap(0, i);
+                }
+            }
+            t = FILE_TO_FILE[(squares[0] & 0x07) as usize];
+        }
+
+        let pieces = self.enc[t][side].as_ref()?.pieces;
+        while squares.len() < self.num {
+            let piece = piece_of(pieces[squares.len()])?;
+            let bitmask = state.board.pieces[piece];
+            if bitmask.count() == 0 {
+                return None;
+            }
+            for position in bitmask {
+                squares.try_push(position.0 ^ mirror).ok()?;
+            }
+        }
+
+        Some((t, squares))
+    }
+
+    fn encode(&self, info: &EncInfo, p: &mut [u8]) -> u64 {
+        let n = self.num;
+        let norm = &info.norm;
+
+        if p[0] & 0x04 != 0 {
+            p.iter_mut().for_each(|sq| *sq ^= 0x07);
+        }
+
+        let mut idx;
+        let mut k;
+
+        if !self.has_pawns {
+            if p[0] & 0x20 != 0 {
+                p.iter_mut().for_each(|sq| *sq ^= 0x38);
+            }
+
+            for i in 0..n {
+                let off_diag = OFF_DIAG[p[i] as usize];
+                if off_diag != 0 {
+                    if off_diag > 0 && i < if self.kk_enc { 2 } else { 3 } {
+                        p.iter_mut().for_each(|sq| *sq = FLIP_DIAG[*sq as usize]);
+                    }
+                    break;
+                }
+            }
+
+            let sq = |i: usize| p[i] as u64;
+            if self.kk_enc {
+                idx = KK_IDX[TRIANGLE[p[0] as usize] as usize][p[1] as usize] as u64;
+                k = 2;
+            } else {
+                let s1 = (p[1] > p[0]) as u64;
+                let s2 = (p[2] > p[0]) as u64 + (p[2] > p[1]) as u64;
+                let diag = |i: usize| DIAG[p[i] as usize] as u64;
+                let lower = |i: usize| LOWER[p[i] as usize] as u64;
+
+                idx = if OFF_DIAG[p[0] as usize] != 0 {
+                    TRIANGLE[p[0] as usize] as u64 * 63 * 62 + (sq(1) - s1) * 62 + (sq(2) - s2)
+                } else if OFF_DIAG[p[1] as usize] != 0 {
+                    6 * 63 * 62 + diag(0) * 28 * 62 + lower(1) * 62 + sq(2) - s2
+                } else if OFF_DIAG[p[2] as usize] != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + diag(0) * 7 * 28 + (diag(1) - s1) * 28 + lower(2)
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + diag(0) * 7 * 6
+                        + (diag(1) - s1) * 6
+                        + (diag(2) - s2)
+                };
+                k = 3;
+            }
+            idx *= info.factor[0];
+        } else {
+            k = norm[0] as usize;
+            p[1..k].sort_unstable_by(|a, b| PAWN_TWIST[*b as usize].cmp(&PAWN_TWIST[*a as usize]));
+
+            idx = PAWN_IDX[k - 1][FLAP[p[0] as usize] as usize];
+            for i in 1..k {
+                idx += binomial(PAWN_TWIST[p[i] as usize] as u64, (k - i) as u64);
+            }
+            idx *= info.factor[0];
+
+            if self.pawns[1] > 0 {
+                let t = k + norm[k] as usize;
+                p[k..t].sort_unstable();
+                let mut s = 0;
+                for i in k..t {
+                    let skips = p[..k].iter().filter(|&&other| p[i] > other).count() as u64;
+                    s += binomial(p[i] as u64 - skips - 8, (i - k + 1) as u64);
+                }
+                idx += s * info.factor[k];
+                k = t;
+            }
+        }
+
+        while k < n {
+            let t = k + norm[k] as usize;
+            p[k..t].sort_unstable();
+            let mut s = 0;
+            for i in k..t {
+                let skips = p[..k].iter().filter(|&&other| p[i] > other).count() as u64;
+                s += binomial(p[i] as u64 - skips, (i - k + 1) as u64);
+            }
+            idx += s * info.factor[k];
+            k = t;
+        }
+
+        idx
+    }
+}
+
+struct TableEntry {
+    material: Material,
+    wdl_path: Option<PathBuf>,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+impl TableEntry {
+    fn wdl(&self) -> Option<&Table> {
+        self.wdl
+            .get_or_init(|| {
+                let path = self.wdl_path.as_ref()?;
+                Table::open(path, &self.material, true).ok()
+            })
+            .as_ref()
+    }
+
+    fn dtz(&self) -> Option<&Table> {
+        self.dtz
+            .get_or_init(|| {
+                let path = self.dtz_path.as_ref()?;
+                Table::open(path, &self.material, false).ok()
+            })
+            .as_ref()
+    }
+}
+
+pub(crate) struct Tablebase {
+    entries: HashMap<String, TableEntry>,
+    max_pieces: usize,
+}
+
+impl Tablebase {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_pieces: 0,
+        }
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut tablebase = Self::new();
+        tablebase.add_directory(path)?;
+        Ok(tablebase)
+    }
+
+    // Registers every .rtbw/.rtbz file in the directory, tables are only read on first probe
+    pub(crate) fn add_directory(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let mut added = 0;
+        for dir_entry in std::fs::read_dir(path)? {
+            let path = dir_entry?.path();
+            let (Some(stem), Some(extension)) = (
+                path.file_stem().and_then(|s| s.to_str()),
+                path.extension().and_then(|s| s.to_str()),
+            ) else {
+                continue;
+            };
+            let is_wdl = match extension {
+                "rtbw" => true,
+                "rtbz" => false,
+                _ => continue,
+            };
+            let Some(material) = Material::from_name(stem) else {
+                continue;
+            };
+            if material.count() > MAX_PIECES {
+                continue;
+            }
+
+            self.max_pieces = self.max_pieces.max(material.count());
+            let entry = self
+                .entries
+                .entry(material.name())
+                .or_insert_with(|| TableEntry {
+                    material,
+                    wdl_path: None,
+                    dtz_path: None,
+                    wdl: OnceLock::new(),
+                    dtz: OnceLock::new(),
+                });
+            if is_wdl {
+                entry.wdl_path = Some(path);
+            } else {
+                entry.dtz_path = Some(path);
+            }
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    pub(crate) fn can_probe(&self, state: &State) -> bool {
+        state.castling_rights.0 == 0
+            && state.board.occupancy.count() as usize <= self.max_pieces.max(2)
+    }
+
+    // ------------------------------------------------------------------------
+    // Table Lookup
+    // ------------------------------------------------------------------------
+
+    // Returns the entry for the position's material and whether its colors are swapped
+    fn entry(&self, state: &State) -> Option<(&TableEntry, bool)> {
+        let material = Material::from_state(state);
+        if let Some(entry) = self.entries.get(&material.name()) {
+            return Some((entry, false));
+        }
+        self.entries
+            .get(&material.flipped().name())
+            .map(|entry| (entry, true))
+    }
+
+    // Side stored in the table, whether colors are swapped and the matching square mirror
+    fn orientation(table: &Table, flipped: bool, turn: Color) -> (usize, bool, u8) {
+        if table.symmetric {
+            let flip = turn == Color::Black;
+            (0, flip, if flip { 0x38 } else { 0 })
+        } else if flipped {
+            ((turn == Color::White) as usize, true, 0x38)
+        } else {
+            ((turn == Color::Black) as usize, false, 0)
+        }
+    }
+
+    fn probe_wdl_table(&self, state: &State) -> Option<i32> {
+        if state.board.occupancy.count() == 2 {
+            return Some(0);
+        }
+
+        let (entry, flipped) = self.entry(state)?;
+        let table = entry.wdl()?;
+        let (side, flip_colors, mirror) = Self::orientation(table, flipped, state.turn);
+        let (t, mut squares) = table.squares(state, side, flip_colors, mirror)?;
+        let info = table.enc[t][side].as_ref()?;
+        let idx = table.encode(info, &mut squares);
+        let value = info.pairs.decompress(&table.file, idx).ok()?;
+        Some(value as i32 - 2)
+    }
+
+    // The outer `None` is a failed probe, the inner one means the table stores the other side
+    fn probe_dtz_table(&self, state: &State, wdl: i32) -> Option<Option<i32>> {
+        const WDL_TO_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+        const PA_FLAGS: [u8; 5] = [8, 0, 0, 0, 4];
+
+        let (entry, flipped) = self.entry(state)?;
+        let table = entry.dtz()?;
+        let (side, flip_colors, mirror) = Self::orientation(table, flipped, state.turn);
+        let (t, mut squares) = table.squares(state, 0, flip_colors, mirror)?;
+
+        let flags = table.dtz_flags(t);
+        if (flags & 1) as usize != side && (table.has_pawns || !table.symmetric) {
+            return Some(None);
+        }
+
+        let info = table.enc[t][0].as_ref()?;
+        let idx = table.encode(info, &mut squares);
+        let mut value = info.pairs.decompress(&table.file, idx).ok()? as i32;
+
+        if flags & 2 != 0 {
+            let map_idx = table.dtz_map_idx[t][WDL_TO_MAP[(wdl + 2) as usize]];
+            value = if flags & 16 == 0 {
+                table
+                    .file
+                    .read_u8(table.dtz_map + map_idx + value as u64)
+                    .ok()? as i32
+            } else {
+                table
+                    .file
+                    .read_u16(table.dtz_map + map_idx + 2 * value as u64)
+                    .ok()? as i32
+            };
+        }
+
+        if flags & PA_FLAGS[(wdl + 2) as usize] == 0 || wdl & 1 != 0 {
+            value *= 2;
+        }
+
+        Some(Some(value))
+    }
+
+    // ------------------------------------------------------------------------
+    // Probing
+    // ------------------------------------------------------------------------
+
+    // Resolves captures first since tables assume the side to move has no winning capture,
+    // the flag reports that the value was reached through a capture
+    fn probe_ab(&self, state: &mut State, mut alpha: i32, beta: i32) -> Option<(i32, bool)> {
+        for mv in state.generate_moves() {
+            if mv.move_type() == MoveType::EnPassant || state.board.mailbox[mv.to()].is_none() {
+                continue;
+            }
+            state.make_move(mv);
+            let v = self.probe_ab(state, -beta, -alpha);
+            state.unmake_move();
+            let v = -v?.0;
+            if v > alpha {
+                if v >= beta {
+                    return Some((v, true));
+                }
+                alpha = v;
+            }
+        }
+
+        let v = self.probe_wdl_table(state)?;
+        if alpha >= v {
+            Some((alpha, alpha > 0))
+        } else {
+            Some((v, false))
+        }
+    }
+
+    // Best value over en passant captures, or -3 if there are none
+    fn probe_en_passant(&self, state: &mut State, moves: &[Move]) -> Option<i32> {
+        let mut best = -3;
+        for &mv in moves
+            .iter()
+            .filter(|mv| mv.move_type() == MoveType::EnPassant)
+        {
+            state.make_move(mv);
+            let v = self.probe_ab(state, -2, 2);
+            state.unmake_move();
+            best = best.max(-v?.0);
+        }
+        Some(best)
+    }
+
+    pub(crate) fn probe_wdl(&self, state: &mut State) -> Option<Wdl> {
+        if !self.can_probe(state) {
+            return None;
+        }
+
+        let (mut v, _) = self.probe_ab(state, -2, 2)?;
+        if state.en_passant.is_none() {
+            return Some(Wdl::from_value(v));
+        }
+
+        let moves = state.generate_moves();
+        let v1 = self.probe_en_passant(state, &moves)?;
+        if v1 > -3 {
+            if v1 >= v {
+                v = v1;
+            } else if v == 0 && moves.iter().all(|mv| mv.move_type() == MoveType::EnPassant) {
+                // The losing en passant capture is the only legal move
+                v = v1;
+            }
+        }
+
+        Some(Wdl::from_value(v))
+    }
+
+    fn probe_dtz_no_ep(&self, state: &mut State) -> Option<i32> {
+        let (wdl, from_capture) = self.probe_ab(state, -2, 2)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if from_capture {
+            return Some(if wdl == 2 { 1 } else { 101 });
+        }
+
+        let moves = state.generate_moves();
+        let is_capture = |state: &State, mv: &Move| {
+            mv.move_type() == MoveType::EnPassant || state.board.mailbox[mv.to()].is_some()
+        };
+        let is_pawn_move = |state: &State, mv: &Move| {
+            state.board.mailbox[mv.from()] == Some(Piece::pawn(state.turn))
+        };
+
+        if wdl > 0 {
+            // A winning pawn move resets the 50-move counter and is as short as it gets
+            for &mv in moves.iter() {
+                if !is_pawn_move(state, &mv) || is_capture(state, &mv) {
+                    continue;
+                }
+                state.make_move(mv);
+                let v = self.probe_ab(state, -2, -wdl + 1);
+                state.unmake_move();
+                if -v?.0 == wdl {
+                    return Some(if wdl == 2 { 1 } else { 101 });
+                }
+            }
+        }
+
+        if let Some(dtz) = self.probe_dtz_table(state, wdl)? {
+            let dtz = 1 + dtz + if wdl & 1 != 0 { 100 } else { 0 };
+            return Some(if wdl >= 0 { dtz } else { -dtz });
+        }
+
+        // The table only stores the other side to move, so search one ply
+        if wdl > 0 {
+            let mut best = 0xFFFF;
+            for &mv in moves.iter() {
+                if is_pawn_move(state, &mv) || is_capture(state, &mv) {
+                    continue;
+                }
+                state.make_move(mv);
+                let v = self.probe_dtz(state);
+                state.unmake_move();
+                let v = -v?;
+                if v > 0 && v + 1 < best {
+                    best = v + 1;
+                }
+            }
+            Some(best)
+        } else {
+            let mut best = -1;
+            for &mv in moves.iter() {
+                state.make_move(mv);
+                let v = if state.halfmove_clock == 0 {
+                    if wdl == -2 {
+                        Some(-1)
+                    } else {
+                        self.probe_ab(state, 1, 2)
+                            .map(|(v, _)| if v == 2 { 0 } else { -101 })
+                    }
+                } else {
+                    self.probe_dtz(state).map(|v| -v - 1)
+                };
+                state.unmake_move();
+                best = best.min(v?);
+            }
+            Some(best)
+        }
+    }
+
+    // Distance to zeroing the 50-move counter, positive when winning. Values beyond 100 are
+    // cursed wins or blessed losses that the 50-move rule turns into draws.
+    pub(crate) fn probe_dtz(&self, state: &mut State) -> Option<i32> {
+        const WDL_TO_DTZ: [i32; 5] = [-1, -101, 0, 101, 1];
+
+        if !self.can_probe(state) {
+            return None;
+        }
+
+        let mut v = self.probe_dtz_no_ep(state)?;
+        if state.en_passant.is_none() {
+            return Some(v);
+        }
+
+        let moves = state.generate_moves();
+        let v1 = self.probe_en_passant(state, &moves)?;
+        if v1 > -3 {
+            let v1 = WDL_TO_DTZ[(v1 + 2) as usize];
+            if v < -100 {
+                if v1 >= 0 {
+                    v = v1;
+                }
+            } else if v < 0 {
+                if !(-100..0).contains(&v1) {
+                    v = v1;
+                }
+            } else if v > 100 {
+                if v1 > 0 {
+                    v = v1;
+                }
+            } else if v > 0 {
+                if v1 == 1 {
+                    v = v1;
+                }
+            } else if v1 >= 0 || moves.iter().all(|mv| mv.move_type() == MoveType::EnPassant) {
+                v = v1;
+            }
+        }
+
+        Some(v)
+    }
+
+    // ------------------------------------------------------------------------
+    // Integration
+    // ------------------------------------------------------------------------
+
+    // Keeps only the root moves that preserve the tablebase result, preferring the fastest
+    // conversion for a win. Returns `None` when the position cannot be probed.
+    pub(crate) fn filter_root_moves(&self, state: &mut State) -> Option<ArrayVec<Move, 218>> {
+        const WDL_TO_DTZ: [i32; 5] = [-1, -101, 0, 101, 1];
+
+        let dtz = self.probe_dtz(state)?;
+        let moves = state.generate_moves();
+        let mut scored = ArrayVec::<(Move, i32), 218>::new();
+
+        for &mv in moves.iter() {
+            state.make_move(mv);
+            let v = if dtz > 0 && state.is_check() && state.generate_moves().is_empty() {
+                Some(1)
+            } else if state.halfmove_clock != 0 {
+                // Step back one ply from the opponent's distance
+                self.probe_dtz(state).map(|v| match -v {
+                    v if v > 0 => v + 1,
+                    v if v < 0 => v - 1,
+                    _ => 0,
+                })
+            } else {
+                self.probe_wdl(state)
+                    .map(|wdl| WDL_TO_DTZ[(2 - wdl as i32) as usize])
+            };
+            state.unmake_move();
+            scored.push((mv, v?));
+        }
+
+        let halfmove_clock = state.halfmove_clock as i32;
+        let (min, max) = if dtz > 0 {
+            let best = scored
+                .iter()
+                .map(|&(_, v)| v)
+                .filter(|&v| v > 0)
+                .min()
+                .unwrap_or(0xFFFF);
+            // Any move that still converts within the 50-move budget is acceptable
+            let max = if best + halfmove_clock <= 99 {
+                99 - halfmove_clock
+            } else {
+                best
+            };
+            (1, max)
+        } else if dtz < 0 {
+            let best = scored.iter().map(|&(_, v)| v).min().unwrap_or(0);
+            if -best * 2 + halfmove_clock < 100 {
+                return Some(moves);
+            }
+            (best, best)
+        } else {
+            (0, 0)
+        };
+
+        Some(
+            scored
+                .into_iter()
+                .filter(|&(_, v)| (min..=max).contains(&v))
+                .map(|(mv, _)| mv)
+                .collect(),
+        )
+    }
+
+    // Final result of the game if play continues perfectly under the 50-move rule
+    pub(crate) fn adjudicate(&self, state: &mut State) -> Option<GameResult> {
+        let wdl = self.probe_wdl(state)?;
+        let decisive = match wdl {
+            Wdl::Win | Wdl::Loss => self
+                .probe_dtz(state)
+                .is_none_or(|dtz| dtz.abs() + state.halfmove_clock as i32 <= 100),
+            _ => false,
+        };
+
+        if !decisive {
+            return Some(GameResult::Draw);
+        }
+        let winner = if wdl == Wdl::Win {
+            state.turn
+        } else {
+            state.turn.flip()
+        };
+        Some(match winner {
+            Color::White => GameResult::WhiteWin,
+            Color::Black => GameResult::BlackWin,
+        })
+    }
+
+    // Exact value target from the side to move's perspective, matching the value head's range
+    pub(crate) fn value_target(&self, state: &mut State) -> Option<f32> {
+        let result = self.adjudicate(state)?;
+        Some(match result.winner() {
+            Some(color) if color == state.turn => 1.0,
+            Some(_) => -1.0,
+            None => 0.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::types::Position;
+
+    #[test]
+    fn test_index_tables() {
+        let kk_pairs = KK_IDX.iter().flatten().filter(|&&idx| idx >= 0).count();
+        assert_eq!(kk_pairs, 462);
+        let sq = |name| Position::from_fen(name).unwrap().0 as usize;
+        // First king on b1, the second on d1 is the first legal pair
+        assert_eq!(KK_IDX[0][sq("b1")], -1);
+        assert_eq!(KK_IDX[0][sq("d1")], 0);
+        assert_eq!(KK_IDX[5][sq("h8")], 338);
+        // With the first king on the diagonal, pairs with both kings on it are numbered last
+        assert_eq!(KK_IDX[6][sq("c1")], 339);
+        assert_eq!(KK_IDX[6][sq("a3")], -1);
+        assert_eq!(KK_IDX[9][sq("h6")], 439);
+        assert_eq!(KK_IDX[6][sq("c3")], 441);
+        assert_eq!(KK_IDX[6][sq("h8")], 446);
+        assert_eq!(KK_IDX[7][sq("d4")], 447);
+        assert_eq!(KK_IDX[9][sq("a1")], 457);
+        assert_eq!(KK_IDX[9][sq("h8")], 461);
+
+        assert_eq!(LOWER.iter().filter(|&&l| l < 28).count(), 56);
+        assert_eq!(FLIP_DIAG[1], 8);
+        assert_eq!(DIAG[63], 7);
+        assert_eq!(DIAG[7], 8);
+
+        // One leading pawn leaves 6 ranks per file, more pawns share them
+        assert_eq!(PAWN_FACTOR[0], [6, 6, 6, 6]);
+    }
+
+    #[test]
+    fn test_material_names() {
+        let state = State::from_fen("8/8/8/4k3/8/8/1R6/K1Q5 b - - 0 1").unwrap();
+        let material = Material::from_state(&state);
+        assert_eq!(material.name(), "KQRvK");
+        assert_eq!(material.flipped().name(), "KvKQR");
+        assert!(Material::from_name("KQRvK") == Some(material));
+        assert!(Material::from_name("KQvQ").is_none());
+    }
+
+    #[test]
+    fn test_probe_without_tables() {
+        let tablebase = Tablebase::new();
+
+        let mut bare_kings = State::from_fen("8/8/8/4k3/8/8/8/K7 w - - 0 1").unwrap();
+        assert_eq!(tablebase.probe_wdl(&mut bare_kings), Some(Wdl::Draw));
+        assert_eq!(tablebase.probe_dtz(&mut bare_kings), Some(0));
+        assert_eq!(
+            tablebase.adjudicate(&mut bare_kings),
+            Some(GameResult::Draw)
+        );
+
+        let mut missing = State::from_fen("8/8/8/4k3/8/8/8/KQ6 w - - 0 1").unwrap();
+        assert_eq!(tablebase.probe_wdl(&mut missing), None);
+
+        let mut castling = State::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        assert_eq!(tablebase.probe_wdl(&mut castling), None);
+    }
+
+    // ------------------------------------------------------------------------
+    // Generated tables
+    // ------------------------------------------------------------------------
+
+    const TEST_BLOCK_SIZE: u32 = 6;
+    const TEST_IDX_BITS: u32 = 8;
+
+    // One side of a table in the layout `PairsData::parse` reads
+    struct Stream {
+        header: Vec<u8>,
+        index_table: Vec<u8>,
+        size_table: Vec<u8>,
+        data: Vec<u8>,
+    }
+
+    enum Symbol {
+        Leaf(u8),
+        Pair(usize, usize),
+    }
+
+    // Compresses the values with a leaf symbol per value and pair symbols for runs of two and four
+    // of the most common one. Code lengths are 1, 2, 3, ... by frequency, longer codes take the
+    // lower symbol numbers as in the tables.
+    fn compress(values: &[u8], flags: u8) -> Stream {
+        let mut counts = [0usize; 256];
+        values.iter().for_each(|&v| counts[v as usize] += 1);
+        let common = (0..=255u8).max_by_key(|&v| counts[v as usize]).unwrap();
+        let mut leaves: Vec<u8> = (0..=255u8).filter(|&v| counts[v as usize] > 0).collect();
+        if leaves.len() == 1 {
+            leaves.push(common.wrapping_add(1));
+        }
+        let leaf = |v: u8| leaves.iter().position(|&l| l == v).unwrap();
+        let mut symbols: Vec<Symbol> = leaves.iter().map(|&v| Symbol::Leaf(v)).collect();
+        let (pair2, pair4) = (symbols.len(), symbols.len() + 1);
+        symbols.push(Symbol::Pair(leaf(common), leaf(common)));
+        symbols.push(Symbol::Pair(pair2, pair2));
+
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < values.len() {
+            let run = values[i..]
+                .iter()
+                .take(4)
+                .take_while(|&&v| v == common)
+                .count();
+            let (symbol, len) = match run {
+                4 => (pair4, 4),
+                2 | 3 => (pair2, 2),
+                _ => (leaf(values[i]), 1),
+            };
+            tokens.push((symbol, len));
+            i += len;
+        }
+
+        let k = symbols.len();
+        let mut frequency = vec![0usize; k];
+        tokens
+            .iter()
+            .for_each(|&(symbol, _)| frequency[symbol] += 1);
+        let mut by_frequency: Vec<usize> = (0..k).collect();
+        by_frequency.sort_by_key(|&symbol| std::cmp::Reverse(frequency[symbol]));
+        let mut length = vec![0u32; k];
+        for (rank, &symbol) in by_frequency.iter().enumerate() {
+            length[symbol] = (rank as u32 + 1).min(k as u32 - 1);
+        }
+        let mut order: Vec<usize> = (0..k).collect();
+        order.sort_by_key(|&symbol| std::cmp::Reverse(length[symbol]));
+        let mut number = vec![0usize; k];
+        for (n, &symbol) in order.iter().enumerate() {
+            number[symbol] = n;
+        }
+
+        let (min_len, max_len) = (1, k as u32 - 1);
+        let h = (max_len - min_len + 1) as usize;
+        let offsets: Vec<u16> = (0..h)
+            .map(|i| {
+                let first = order.iter().position(|&s| length[s] == min_len + i as u32);
+                first.unwrap() as u16
+            })
+            .collect();
+        let mut base = vec![0u64; h];
+        for i in (0..h - 1).rev() {
+            base[i] = (base[i + 1] + offsets[i] as u64 - offsets[i + 1] as u64) / 2;
+        }
+
+        // Whole symbols per block, each block's bits written from the most significant down
+        let block_bits = 8usize << TEST_BLOCK_SIZE;
+        let mut blocks: Vec<(Vec<u8>, usize)> = Vec::new();
+        let mut bits = block_bits;
+        for &(symbol, len) in &tokens {
+            let l = length[symbol] as usize;
+            if bits + l > block_bits {
+                blocks.push((vec![0u8; block_bits / 8], 0));
+                bits = 0;
+            }
+            let i = l - min_len as usize;
+            let code = base[i] + (number[symbol] - offsets[i] as usize) as u64;
+            let (data, count) = blocks.last_mut().unwrap();
+            for b in (0..l).rev() {
+                if code >> b & 1 != 0 {
+                    data[bits / 8] |= 0x80 >> (bits % 8);
+                }
+                bits += 1;
+            }
+            *count += len;
+        }
+
+        let mut starts = vec![0usize];
+        for (_, count) in &blocks {
+            starts.push(starts.last().unwrap() + count);
+        }
+        starts.pop();
+        let mut index_table = Vec::new();
+        let num_indices = values.len().div_ceil(1 << TEST_IDX_BITS);
+        for i in 0..num_indices {
+            let middle = (i << TEST_IDX_BITS) + (1 << (TEST_IDX_BITS - 1));
+            let block = starts.iter().rposition(|&start| start <= middle).unwrap();
+            index_table.extend((block as u32).to_le_bytes());
+            index_table.extend(((middle - starts[block]) as u16).to_le_bytes());
+        }
+        let size_table = blocks
+            .iter()
+            .flat_map(|(_, count)| (*count as u16 - 1).to_le_bytes())
+            .collect();
+
+        let mut header = vec![flags, TEST_BLOCK_SIZE as u8, TEST_IDX_BITS as u8, 0];
+        header.extend((blocks.len() as u32).to_le_bytes());
+        header.extend([max_len as u8, min_len as u8]);
+        header.extend(offsets.iter().flat_map(|offset| offset.to_le_bytes()));
+        header.extend((k as u16).to_le_bytes());
+        let mut sympat = vec![0u8; 3 * k];
+        for (symbol, entry) in symbols.iter().enumerate() {
+            let (left, right) = match *entry {
+                Symbol::Leaf(value) => (value as usize, 0xFFF),
+                Symbol::Pair(left, right) => (number[left], number[right]),
+            };
+            let w = &mut sympat[3 * number[symbol]..3 * number[symbol] + 3];
+            w[0] = left as u8;
+            w[1] = (left >> 8) as u8 & 0x0F | ((right & 0x0F) << 4) as u8;
+            w[2] = (right >> 4) as u8;
+        }
+        header.extend(sympat);
+        header.resize(header.len() + (k & 1), 0);
+
+        Stream {
+            header,
+            index_table,
+            size_table,
+            data: blocks.into_iter().flat_map(|(data, _)| data).collect(),
+        }
+    }
+
+    // A side whose every position has the same value, with no data to read
+    fn constant() -> Stream {
+        Stream {
+            header: vec![0x80, 0],
+            index_table: Vec::new(),
+            size_table: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    fn pad(file: &mut Vec<u8>, alignment: usize) {
+        file.resize(file.len().next_multiple_of(alignment), 0);
+    }
+
+    // A pawnless table with the same piece order for both sides, a WDL table when there are two
+    // streams and a DTZ table storing white to move when there is one
+    fn write_table(path: &Path, pieces: &[u8], streams: &[Stream]) {
+        let is_wdl = streams.len() == 2;
+        let mut file = Vec::new();
+        file.extend(if is_wdl { WDL_MAGIC } else { DTZ_MAGIC });
+        file.push(is_wdl as u8);
+        file.push(0);
+        file.extend(pieces.iter().map(|&piece| piece | piece << 4));
+        pad(&mut file, 2);
+        streams.iter().for_each(|s| file.extend(&s.header));
+        if !is_wdl {
+            pad(&mut file, 2);
+        }
+        streams.iter().for_each(|s| file.extend(&s.index_table));
+        for s in streams {
+            pad(&mut file, 2);
+            file.extend(&s.size_table);
+        }
+        for s in streams {
+            pad(&mut file, 64);
+            file.extend(&s.data);
+        }
+        std::fs::write(path, file).unwrap();
+    }
+
+    // A split WDL table with pawns, constant on every leading pawn file, with the same piece order
+    // for each file and both sides
+    fn write_pawn_table(path: &Path, pieces: &[u8]) {
+        let mut file = Vec::new();
+        file.extend(WDL_MAGIC);
+        file.push(0x03);
+        for _ in 0..4 {
+            file.push(0);
+            file.extend(pieces.iter().map(|&piece| piece | piece << 4));
+        }
+        pad(&mut file, 2);
+        for _ in 0..8 {
+            file.extend(constant().header);
+        }
+        std::fs::write(path, file).unwrap();
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Known {
+        Unknown,
+        Draw,
+        // Plies until White mates
+        Mate(u8),
+    }
+
+    struct Node {
+        fen: String,
+        side: usize,
+        idx: usize,
+    }
+
+    fn fen(white_king: u8, white_queen: u8, black_king: u8, side: usize) -> String {
+        let mut board = String::new();
+        for rank in (0..8u8).rev() {
+            let mut empty = 0;
+            for file in 0..8u8 {
+                let piece = match rank * 8 + file {
+                    sq if sq == white_king => 'K',
+                    sq if sq == white_queen => 'Q',
+                    sq if sq == black_king => 'k',
+                    _ => {
+                        empty += 1;
+                        continue;
+                    }
+                };
+                if empty > 0 {
+                    board.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                board.push(piece);
+            }
+            if empty > 0 {
+                board.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                board.push('/');
+            }
+        }
+        format!("{} {} - - 0 1", board, if side == 0 { "w" } else { "b" })
+    }
+
+    // Plies to mate in KQvK by retrograde analysis over the table indices of `table`. The white
+    // king only visits the a1-d1-d4 triangle, the index folds every other placement onto it.
+    fn solve_kqvk(table: &Table) -> (Vec<Node>, [Vec<Known>; 2]) {
+        let index = |side: usize, mut squares: [u8; 3]| {
+            table.encode(table.enc[0][side].as_ref().unwrap(), &mut squares) as usize
+        };
+        // Three unique pieces index on the first one's triangle
+        let size = 31332;
+        let mut known = [vec![Known::Unknown; size], vec![Known::Unknown; size]];
+        let mut seen = [vec![false; size], vec![false; size]];
+        let mut nodes = Vec::new();
+        let mut children = Vec::new();
+
+        for white_king in (0..64u8).filter(|sq| sq % 8 < 4 && sq / 8 <= sq % 8) {
+            for white_queen in (0..64u8).filter(|&sq| sq != white_king) {
+                for black_king in 0..64u8 {
+                    let (file_dist, rank_dist) = (
+                        (white_king % 8).abs_diff(black_king % 8),
+                        (white_king / 8).abs_diff(black_king / 8),
+                    );
+                    if black_king == white_queen || (file_dist <= 1 && rank_dist <= 1) {
+                        continue;
+                    }
+                    for (side, seen) in seen.iter_mut().enumerate() {
+                        let squares = [white_king, white_queen, black_king];
+                        let idx = index(side, squares);
+                        let fen = fen(white_king, white_queen, black_king, side);
+                        // White to move with Black in check cannot happen
+                        let Ok(state) = State::from_fen(&fen) else {
+                            continue;
+                        };
+                        if std::mem::replace(&mut seen[idx], true) {
+                            continue;
+                        }
+                        let moves = state.generate_moves();
+                        if side == 1 && moves.is_empty() {
+                            known[1][idx] = match state.is_check() {
+                                true => Known::Mate(0),
+                                false => Known::Draw,
+                            };
+                        } else if side == 1 && moves.iter().any(|mv| mv.to().0 == white_queen) {
+                            known[1][idx] = Known::Draw;
+                        }
+                        let next: Vec<usize> = moves
+                            .iter()
+                            .map(|mv| {
+                                let mut next = squares;
+                                let moved = next.iter().position(|&sq| sq == mv.from().0);
+                                next[moved.unwrap()] = mv.to().0;
+                                index(1 - side, next)
+                            })
+                            .collect();
+                        nodes.push(Node { fen, side, idx });
+                        children.push(next);
+                    }
+                }
+            }
+        }
+
+        // White mates as soon as one move reaches a mated position, Black lasts as long as its
+        // longest move does
+        for ply in 1.. {
+            let mut resolved = Vec::new();
+            for (node, next) in nodes.iter().zip(&children) {
+                if known[node.side][node.idx] != Known::Unknown {
+                    continue;
+                }
+                let replies = next.iter().map(|&idx| known[1 - node.side][idx]);
+                let mate = match node.side {
+                    0 => replies
+                        .into_iter()
+                        .any(|reply| reply == Known::Mate(ply - 1)),
+                    _ => replies
+                        .map(|reply| match reply {
+                            Known::Mate(plies) => Some(plies),
+                            _ => None,
+                        })
+                        .collect::<Option<Vec<_>>>()
+                        .is_some_and(|plies| plies.into_iter().max() == Some(ply - 1)),
+                };
+                if mate {
+                    resolved.push((node.side, node.idx));
+                }
+            }
+            if resolved.is_empty() {
+                break;
+            }
+            for (side, idx) in resolved {
+                known[side][idx] = Known::Mate(ply);
+            }
+        }
+        (nodes, known)
+    }
+
+    #[test]
+    fn test_reference_indices() {
+        let dir = std::env::temp_dir().join(format!("syzygy-index-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("KQvK.rtbw");
+        write_table(&path, &[6, 5, 14], &[constant(), constant()]);
+        let table = Table::open(&path, &Material::from_name("KQvK").unwrap(), true).unwrap();
+        let sq = |name: &str| Position::from_fen(name).unwrap().0;
+        let index = |squares: [&str; 3]| {
+            let mut squares = squares.map(sq);
+            table.encode(table.enc[0][0].as_ref().unwrap(), &mut squares)
+        };
+
+        // Worked out by hand from the reference encoder. Off the diagonal the first king's
+        // triangle slot is followed by the two other squares, each skipping the ones before it:
+        // 0 * 63 * 62 + (35 - 1) * 62 + (63 - 2)
+        assert_eq!(index(["b1", "d5", "h8"]), 2169);
+        // d3 is slot 5 of b1, c1, d1, c2, d2, d3: 5 * 63 * 62 + 0 * 62 + (7 - 1)
+        assert_eq!(index(["d3", "a1", "h1"]), 19536);
+        // h7 turns into a2 by the 180 degree rotation and b1 by the diagonal flip, taking the
+        // others to h8 and e4: (63 - 1) * 62 + (28 - 1)
+        assert_eq!(index(["h7", "a1", "e4"]), 3871);
+        // On the diagonal the first king is numbered after every off-diagonal one:
+        // 6 * 63 * 62 + DIAG(b2) 1 * 28 * 62 + LOWER(c1) 1 * 62 + (56 - 2)
+        assert_eq!(index(["b2", "c1", "a8"]), 25288);
+        // All three on the long diagonal, the last of the reference table's 31332 positions:
+        // 6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + DIAG(d4) 3 * 7 * 6 + (7 - 1) * 6 + (6 - 1)
+        assert_eq!(index(["d4", "h8", "g7"]), 31331);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reference_pawn_indices() {
+        let dir = std::env::temp_dir().join(format!("syzygy-pawns-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let open = |name: &str, pieces: &[u8]| {
+            let path = dir.join(format!("{}.rtbw", name));
+            write_pawn_table(&path, pieces);
+            Table::open(&path, &Material::from_name(name).unwrap(), true).unwrap()
+        };
+        let index = |table: &Table, fen: &str| {
+            let state = State::from_fen(fen).unwrap();
+            let (t, mut squares) = table.squares(&state, 0, false, 0).unwrap();
+            (
+                t,
+                table.encode(table.enc[t][0].as_ref().unwrap(), &mut squares),
+            )
+        };
+
+        // Worked out by hand from the reference encoder. With the pawn order first its factor is 1
+        // and each file has 6 pawn ranks, so the kings are counted in units of 6 and 6 * 63.
+        let kpvk = open("KPvK", &[1, 6, 14]);
+        // a2 is the first pawn square of the a-file: 0 + 4 * 6 + (63 - 2) * 378
+        assert_eq!(index(&kpvk, "7k/8/8/8/8/8/P7/4K3 w - - 0 1"), (0, 23082));
+        // d7 is the last pawn square of the d-file: 5 + 0 * 6 + (18 - 1) * 378
+        assert_eq!(index(&kpvk, "8/3P4/8/8/8/2k5/8/K7 w - - 0 1"), (3, 6431));
+        // On the g-file the board is mirrored, the pawn on b5 and the kings on a1 and h8:
+        // 3 + 0 * 6 + (63 - 2) * 378
+        assert_eq!(index(&kpvk, "k7/8/8/6P1/8/8/8/7K w - - 0 1"), (1, 23061));
+        // e2 mirrors to d2, the kings to e4 and c6: 0 + (28 - 1) * 6 + (42 - 2) * 378
+        assert_eq!(index(&kpvk, "8/8/5k2/8/3K4/8/4P3/8 w - - 0 1"), (3, 15282));
+
+        // The pawn nearest the a-file and the second rank leads, the other is numbered by its
+        // PAWN_TWIST value. The kings are counted in units of the file's pawn factor, 180 for the
+        // b-file and 252 for the a-file, and 62 times that.
+        let kppvk = open("KPPvK", &[1, 1, 6, 14]);
+        // g3 leads over b5 and mirrors to b3, its pawn index 35 is the twist of b2. b5 turns into
+        // g5 with twist 28 and the kings into d1 and d8: 35 + 28 + 3 * 180 + (59 - 3) * 180 * 62
+        assert_eq!(
+            index(&kppvk, "4k3/8/8/1P6/8/6P1/8/4K3 w - - 0 1"),
+            (1, 625563)
+        );
+        // a6 leads over c2, after the twists of a2 to a5: 176 + 23 + 6 * 252 + (57 - 3) * 252 * 62
+        assert_eq!(
+            index(&kppvk, "1k6/8/P7/8/8/8/2P5/6K1 w - - 0 1"),
+            (0, 845407)
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_probe_generated_tables() {
+        let dir = std::env::temp_dir().join(format!("syzygy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let material = Material::from_name("KQvK").unwrap();
+        // King, queen, black king
+        let pieces = [6, 5, 14];
+
+        // The indices only depend on the piece order, constant tables give them
+        let wdl_path = dir.join("KQvK.rtbw");
+        write_table(&wdl_path, &pieces, &[constant(), constant()]);
+        let table = Table::open(&wdl_path, &material, true).unwrap();
+        let (nodes, known) = solve_kqvk(&table);
+        assert!(nodes
+            .iter()
+            .all(|node| known[node.side][node.idx] != Known::Unknown));
+        // The longest KQvK win is mate in 10
+        let longest = known[0].iter().filter_map(|&k| match k {
+            Known::Mate(plies) => Some(plies),
+            _ => None,
+        });
+        assert_eq!(longest.max(), Some(19));
+
+        // Tables store WDL + 2, and DTZ halved for white to move, whose wins take an odd number
+        // of plies
+        let wdl = |side: usize| -> Vec<u8> {
+            let values = known[side].iter().map(|&k| match (side, k) {
+                (0, _) => 4,
+                (_, Known::Draw) => 2,
+                _ => 0,
+            });
+            values.collect()
+        };
+        let dtz: Vec<u8> = known[0]
+            .iter()
+            .map(|&k| match k {
+                Known::Mate(plies) => (plies - 1) / 2,
+                _ => 0,
+            })
+            .collect();
+        write_table(
+            &wdl_path,
+            &pieces,
+            &[compress(&wdl(0), 0), compress(&wdl(1), 0)],
+        );
+        write_table(&dir.join("KQvK.rtbz"), &pieces, &[compress(&dtz, 0)]);
+
+        let mut tablebase = Tablebase::new();
+        assert_eq!(tablebase.add_directory(&dir).unwrap(), 2);
+        let probe = |fen: &str| {
+            let mut state = State::from_fen(fen).unwrap();
+            (
+                tablebase.probe_wdl(&mut state).unwrap(),
+                tablebase.probe_dtz(&mut state).unwrap(),
+            )
+        };
+
+        // Mate in one, and Black's only move walks into it
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"), (Wdl::Win, 1));
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/6Q1 b - - 0 1"), (Wdl::Loss, -2));
+        // Stalemate, and the queen taken
+        assert_eq!(probe("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe("8/8/8/8/8/8/1kQ5/7K b - - 0 1"), (Wdl::Draw, 0));
+        // The same table with the colors swapped
+        assert_eq!(probe("K7/8/1k6/8/8/8/8/6q1 w - - 0 1"), (Wdl::Loss, -2));
+        assert_eq!(probe("8/8/8/8/8/8/1Kq5/7k w - - 0 1"), (Wdl::Draw, 0));
+
+        for node in nodes.iter().step_by(97) {
+            let expected = match (node.side, known[node.side][node.idx]) {
+                (0, Known::Mate(plies)) => (Wdl::Win, plies as i32),
+                // A mated side is a ply from the reset
+                (_, Known::Mate(plies)) => (Wdl::Loss, -(plies.max(1) as i32)),
+                _ => (Wdl::Draw, 0),
+            };
+            assert_eq!(probe(&node.fen), expected, "{}", node.fen);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,23 +1,24 @@
 use crate::chess::bitmask::Bitmask;
-use arrayvec::ArrayVec;
 use std::ops::{Index, IndexMut};
 
 #[repr(u8)]
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub(crate) enum Color {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Color {
     White = 0,
     Black = 1,
 }
 
 impl Color {
-    pub(crate) fn from_fen(fen: &str) -> Self {
+    pub(crate) fn from_fen(fen: &str) -> Result<Self, FenError> {
         match fen {
-            "w" => Color::White,
-            _ => Color::Black,
+            "w" => Ok(Color::White),
+            "b" => Ok(Color::Black),
+            _ => Err(FenError::Turn(fen.to_string())),
         }
     }
 
-    pub(crate) const fn flip(self) -> Self {
+    pub const fn flip(self) -> Self {
         unsafe { std::mem::transmute(1 - (self as u8)) }
     }
 }
@@ -55,7 +56,8 @@ impl std::fmt::Debug for Color {
 
 #[repr(u8)]
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub(crate) enum Piece {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Piece {
     WhitePawn = 0,
     WhiteRook = 1,
     WhiteKnight = 2,
@@ -71,31 +73,35 @@ pub(crate) enum Piece {
 }
 
 impl Piece {
-    pub(crate) const fn pawn(color: Color) -> Self {
+    pub const fn pawn(color: Color) -> Self {
         unsafe { std::mem::transmute((color as u8) * 6) }
     }
 
-    pub(crate) const fn rook(color: Color) -> Self {
+    pub const fn rook(color: Color) -> Self {
         unsafe { std::mem::transmute((color as u8) * 6 + 1) }
     }
 
-    pub(crate) const fn knight(color: Color) -> Self {
+    pub const fn knight(color: Color) -> Self {
         unsafe { std::mem::transmute((color as u8) * 6 + 2) }
     }
 
-    pub(crate) const fn bishop(color: Color) -> Self {
+    pub const fn bishop(color: Color) -> Self {
         unsafe { std::mem::transmute((color as u8) * 6 + 3) }
     }
 
-    pub(crate) const fn queen(color: Color) -> Self {
+    pub const fn queen(color: Color) -> Self {
         unsafe { std::mem::transmute((color as u8) * 6 + 4) }
     }
 
-    pub(crate) const fn king(color: Color) -> Self {
+    pub const fn king(color: Color) -> Self {
         unsafe { std::mem::transmute((color as u8) * 6 + 5) }
     }
 
-    pub(crate) const fn color(self) -> Color {
+    pub const fn flip_color(self) -> Self {
+        unsafe { std::mem::transmute((self as u8 + 6) % 12) }
+    }
+
+    pub const fn color(self) -> Color {
         if (self as u8) < 6 {
             Color::White
         } else {
@@ -145,11 +151,42 @@ impl std::fmt::Debug for Piece {
     }
 }
 
+/// A square, indexed from a1 = 0 along the ranks to h8 = 63. Displays as its name.
 #[repr(transparent)]
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub(crate) struct Position(pub(crate) u8);
+pub struct Position(pub(crate) u8);
 
 impl Position {
+    /// Files and ranks count from 0, `None` past the edge of the board.
+    ///
+    /// ```
+    /// use chess_ai::Position;
+    ///
+    /// let e4 = Position::new(4, 3).unwrap();
+    /// assert_eq!(e4.to_string(), "e4");
+    /// assert_eq!(e4.index(), 28);
+    /// assert!(Position::new(8, 0).is_none());
+    /// ```
+    pub const fn new(file: u8, rank: u8) -> Option<Self> {
+        if file < 8 && rank < 8 {
+            Some(Self(rank * 8 + file))
+        } else {
+            None
+        }
+    }
+
+    pub const fn from_index(index: u8) -> Option<Self> {
+        if index < 64 {
+            Some(Self(index))
+        } else {
+            None
+        }
+    }
+
+    pub const fn index(self) -> u8 {
+        self.0
+    }
+
     pub(crate) const A1: Self = Self(0);
     pub(crate) const C1: Self = Self(2);
     pub(crate) const D1: Self = Self(3);
@@ -169,17 +206,11 @@ impl Position {
     pub(crate) const KS_CASTLE_ROOK: [(Self, Self); 2] =
         [(Self::H1, Self::F1), (Self::H8, Self::F8)];
 
+    // Matched on bytes, a multi-byte character is never mistaken for a file or a rank
     pub(crate) fn from_fen(fen: &str) -> Option<Self> {
-        if fen.len() != 2 {
-            return None;
-        }
-        let chars: ArrayVec<char, 2> = fen.chars().collect();
-        let file = chars[0] as u8 - b'a';
-        let rank = chars[1] as u8 - b'1';
-        if file >= 8 || rank >= 8 {
-            None
-        } else {
-            Some(Self(rank * 8 + file))
+        match fen.as_bytes() {
+            &[file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Self::new(file - b'a', rank - b'1'),
+            _ => None,
         }
     }
 
@@ -191,7 +222,7 @@ impl Position {
         Self(((from.0 >> 3) << 3) + (to.0 & 0b111)) // = (from/8)*8 + (to%8)
     }
 
-    pub(crate) const fn mask(self) -> Bitmask {
+    pub const fn mask(self) -> Bitmask {
         Bitmask(1u64 << self.0)
     }
 
@@ -199,11 +230,16 @@ impl Position {
         Self((self.0 as i8 + delta) as u8)
     }
 
-    pub(crate) const fn rank(self) -> u8 {
+    // Same file, opposite rank: a1 <-> a8
+    pub(crate) const fn mirrored(self) -> Self {
+        Self(self.0 ^ 56)
+    }
+
+    pub const fn rank(self) -> u8 {
         self.0 / 8
     }
 
-    pub(crate) const fn file(self) -> u8 {
+    pub const fn file(self) -> u8 {
         self.0 % 8
     }
 }
@@ -263,3 +299,108 @@ impl<T> IndexMut<Direction> for [T; 8] {
         &mut self[index as usize]
     }
 }
+
+#[cfg(feature = "cli")]
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) enum GameResult {
+    WhiteWin = 0,
+    BlackWin = 1,
+    Draw = 2,
+}
+
+#[cfg(feature = "cli")]
+impl GameResult {
+    pub(crate) fn from_pgn(pgn: &str) -> Option<Self> {
+        match pgn {
+            "1-0" => Some(Self::WhiteWin),
+            "0-1" => Some(Self::BlackWin),
+            "1/2-1/2" => Some(Self::Draw),
+            _ => None,
+        }
+    }
+
+    pub(crate) const fn winner(self) -> Option<Color> {
+        match self {
+            Self::WhiteWin => Some(Color::White),
+            Self::BlackWin => Some(Color::Black),
+            Self::Draw => None,
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let r = match self {
+            Self::WhiteWin => "1-0",
+            Self::BlackWin => "0-1",
+            Self::Draw => "1/2-1/2",
+        };
+        write!(f, "{}", r)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl std::fmt::Debug for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Why a FEN string was rejected, with the offending field.
+#[derive(Clone, PartialEq, Eq)]
+pub enum FenError {
+    /// FEN has six fields, EPD-style positions may leave out the last two clocks.
+    FieldCount(usize),
+    Board(String),
+    /// A rank of the piece placement that does not cover exactly eight files.
+    RankWidth(String),
+    /// Material no game can reach: more than 16 pieces, more than 8 pawns, or more promoted
+    /// pieces than missing pawns.
+    Material(Color),
+    PawnOnBackRank(Position),
+    /// The side that just moved left its king in check.
+    OpponentInCheck,
+    Turn(String),
+    CastlingRights(String),
+    /// Castling rights for a king or rook that is not on its home square.
+    CastlingWithoutPieces(String),
+    /// An en passant square with no pawn that just moved two squares past it.
+    EnPassant(String),
+    HalfmoveClock(String),
+    FullmoveNumber(String),
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::FieldCount(found) => write!(f, "expected 4 or 6 FEN fields, found {}", found),
+            FenError::Board(board) => write!(f, "invalid piece placement '{}'", board),
+            FenError::RankWidth(rank) => write!(f, "rank '{}' does not cover 8 files", rank),
+            FenError::Material(color) => write!(f, "impossible material for {}", color),
+            FenError::PawnOnBackRank(position) => {
+                write!(f, "pawn on the back rank at {}", position)
+            }
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+            FenError::Turn(turn) => write!(f, "invalid side to move '{}'", turn),
+            FenError::CastlingRights(rights) => write!(f, "invalid castling rights '{}'", rights),
+            FenError::CastlingWithoutPieces(rights) => write!(
+                f,
+                "castling rights '{}' without king and rook on their home squares",
+                rights
+            ),
+            FenError::EnPassant(square) => write!(f, "invalid en passant square '{}'", square),
+            FenError::HalfmoveClock(clock) => write!(f, "invalid halfmove clock '{}'", clock),
+            FenError::FullmoveNumber(number) => write!(f, "invalid fullmove number '{}'", number),
+        }
+    }
+}
+
+impl std::fmt::Debug for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for FenError {}
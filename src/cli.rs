@@ -0,0 +1,1074 @@
+pub(crate) mod args;
+
+use crate::{
+    chess::{moves::Move, pgn::PgnReader, polyglot::PolyglotBookBuilder, State},
+    cli::args::{at_least, existing_path, load_config, parse_fen, Args, USAGE},
+    engine::{
+        arena::MatchConfig,
+        backend::{BackendConfig, InferenceTask, TrainingTask},
+        benchmark::{read_puzzle_file, run_benchmark},
+        checkpoint::{
+            check_model_name, load_checkpoint, load_checkpoint_config, save_checkpoint,
+            CheckpointMetadata, ModelRegistry, Precision,
+        },
+        distill::{into_inference, DistillationConfig},
+        eval::EvalParams,
+        model::{TransformerModel, TransformerModelConfig},
+        play::{describe_evaluation, render, PlaySession, RenderStyle},
+        pretrain::PretrainingConfig,
+        quantize::{read_labelled_positions, read_positions, QuantizationConfig},
+        replay::{read_chunks, ReplayBuffer},
+        search::SearchLimits,
+        selfplay::{Engine, SelfPlayConfig},
+        server::{InferenceClient, InferenceServerConfig},
+        training::{TrainingConfig, TrainingPosition},
+        tuning::{save_rust_source, EvolutionConfig, TexelTuner},
+        uci::UciEngine,
+    },
+};
+use burn::tensor::backend::{AutodiffBackend, Backend};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
+
+// Budgets when none is given: fixed node counts where results should be reproducible, a time per
+// move where a person is waiting
+const DEFAULT_BENCH_BUDGET: &str = "2000";
+const DEFAULT_INTERACTIVE_BUDGET: &str = "1000ms";
+
+// `handcrafted`, `server:<host:port>` or a checkpoint, checked when parsed and loaded once the
+// backend is known
+#[derive(Clone)]
+enum EngineSpec {
+    Handcrafted,
+    Checkpoint(PathBuf),
+    Server(String),
+}
+
+impl EngineSpec {
+    fn parse(spec: &str) -> Result<Self, String> {
+        match spec {
+            "handcrafted" => Ok(Self::Handcrafted),
+            _ if spec.starts_with("server:") => {
+                Ok(Self::Server(spec["server:".len()..].to_string()))
+            }
+            path => checkpoint(path).map(Self::Checkpoint),
+        }
+    }
+
+    fn from_checkpoint(checkpoint: Option<PathBuf>) -> Self {
+        checkpoint.map_or(Self::Handcrafted, Self::Checkpoint)
+    }
+
+    fn load<B: Backend>(&self, device: &B::Device) -> Result<Engine<B>, String> {
+        match self {
+            Self::Handcrafted => Ok(Engine::Handcrafted),
+            Self::Checkpoint(path) => {
+                load_model(path, device).map(|model| Engine::Network(Box::new(model)))
+            }
+            // Reached once here so a wrong address fails before any game starts
+            Self::Server(address) => InferenceClient::connect(address)
+                .map(|_| Engine::Remote(address.clone()))
+                .map_err(|err| format!("{}: {}", address, err)),
+        }
+    }
+
+    fn model<B: Backend>(&self, device: &B::Device) -> Result<Option<TransformerModel<B>>, String> {
+        match self.load::<B>(device)? {
+            Engine::Handcrafted => Ok(None),
+            Engine::Network(model) => Ok(Some(*model)),
+            Engine::Remote(address) => {
+                Err(format!("{}: needs a checkpoint, not a server", address))
+            }
+        }
+    }
+}
+
+// A checkpoint directory whose config can be read
+fn checkpoint(path: impl Into<PathBuf>) -> Result<PathBuf, String> {
+    let path = existing_path(path)?;
+    load_checkpoint_config(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(path)
+}
+
+fn optional_checkpoint(args: &mut Args) -> Result<Option<PathBuf>, String> {
+    args.value::<PathBuf>("checkpoint")?
+        .map(checkpoint)
+        .transpose()
+        .map_err(|err| args.error(err))
+}
+
+fn budget(args: &mut Args, default: &str) -> Result<SearchLimits, String> {
+    let budget = args.value::<String>("budget")?;
+    budget
+        .as_deref()
+        .unwrap_or(default)
+        .parse()
+        .map_err(|err| args.error(err))
+}
+
+// `--model <config>` over the default architecture
+fn model_config(args: &mut Args) -> Result<TransformerModelConfig, String> {
+    let config = match args.value::<PathBuf>("model")? {
+        Some(path) => load_config(&path, TransformerModelConfig::new())?,
+        None => TransformerModelConfig::new(),
+    };
+    config.validate().map_err(|err| args.error(err))?;
+    Ok(config)
+}
+
+fn load_model<B: Backend>(
+    checkpoint: &Path,
+    device: &B::Device,
+) -> Result<TransformerModel<B>, String> {
+    let config = load_checkpoint_config(checkpoint).map_err(|err| err.to_string())?;
+    load_checkpoint::<B>(checkpoint, &config, device).map_err(|err| err.to_string())
+}
+
+// Counts the leaf nodes of the move tree, the move generator's correctness and speed test
+struct Perft {
+    state: State,
+    depth: u8,
+    divide: bool,
+}
+
+impl Perft {
+    fn run(mut self) {
+        let start = Instant::now();
+        let nodes = match self.divide {
+            true => {
+                self.state.divide(self.depth);
+                None
+            }
+            false => Some(self.state.perft(self.depth)),
+        };
+        let elapsed = start.elapsed();
+        if let Some(nodes) = nodes {
+            println!(
+                "perft({}) = {} in {:?}, {:.0} nodes/s",
+                self.depth,
+                nodes,
+                elapsed,
+                nodes as f64 / elapsed.as_secs_f64().max(1e-9)
+            );
+        }
+    }
+}
+
+// Speaks UCI on stdin and stdout
+struct Uci {
+    engine: EngineSpec,
+    limits: SearchLimits,
+}
+
+impl InferenceTask for Uci {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let engine = self.engine.load::<B>(&device)?;
+        UciEngine::new(&engine, &device, self.limits)
+            .run(std::io::stdin().lock(), &mut std::io::stdout())
+            .map_err(|err| err.to_string())
+    }
+}
+
+// Plays against the network policy of a checkpoint, or the handcrafted search without one, on
+// stdin and stdout
+struct Play {
+    engine: EngineSpec,
+    style: RenderStyle,
+}
+
+impl InferenceTask for Play {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let model = self.engine.model::<B>(&device)?;
+        let mut session = PlaySession::new(model, device, self.style);
+        session
+            .run(std::io::stdin().lock(), &mut std::io::stdout())
+            .map_err(|err| err.to_string())
+    }
+}
+
+enum AnalysisTarget {
+    Position(Box<State>),
+    // The first game of a PGN file
+    Game(PathBuf),
+}
+
+// Evaluates a position and searches it, or compares every move of a game with the engine's
+struct Analyse {
+    target: AnalysisTarget,
+    engine: EngineSpec,
+    limits: SearchLimits,
+}
+
+impl InferenceTask for Analyse {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let engine = self.engine.load::<B>(&device)?;
+        let model = match &engine {
+            Engine::Handcrafted | Engine::Remote(_) => None,
+            Engine::Network(model) => Some(&**model),
+        };
+        let mut searcher = engine.searcher(&device, None);
+
+        match self.target {
+            AnalysisTarget::Position(state) => {
+                print!("{}", render(&state, false, RenderStyle::detect()));
+                println!("{}", describe_evaluation(&state, model, &device));
+                match searcher.best_move(&state, &self.limits) {
+                    Some(mv) => println!("best move {}", mv.to_uci()),
+                    None => println!("no legal moves"),
+                }
+            }
+            AnalysisTarget::Game(path) => {
+                let file =
+                    File::open(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                let game = PgnReader::new(BufReader::new(file))
+                    .next()
+                    .ok_or_else(|| format!("{}: no games", path.display()))?
+                    .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+                let mut state = game
+                    .initial_state()
+                    .map_err(|err| format!("{}: {}", path.display(), err))?;
+                let mut agreed = 0;
+                for (ply, &played) in game.moves.iter().enumerate() {
+                    let best = searcher.best_move(&state, &self.limits);
+                    let marker = if best == Some(played) {
+                        agreed += 1;
+                        ""
+                    } else {
+                        "  *"
+                    };
+                    println!(
+                        "ply {:>3} played {:<6} engine {:<6}{}",
+                        ply + 1,
+                        played.to_uci(),
+                        best.map_or("none".to_string(), |mv| mv.to_uci()),
+                        marker
+                    );
+                    state.make_move(played);
+                }
+                println!("engine agreed with {}/{} moves", agreed, game.moves.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs a puzzle suite with the network policy of a checkpoint, or the handcrafted alpha-beta
+// search without one, and optionally writes the per-puzzle report
+struct Bench {
+    puzzles: PathBuf,
+    report: Option<PathBuf>,
+    limits: SearchLimits,
+    engine: EngineSpec,
+}
+
+impl InferenceTask for Bench {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let puzzles = read_puzzle_file(&self.puzzles)
+            .map_err(|err| format!("{}: {}", self.puzzles.display(), err))?;
+        let mut searcher = self.engine.load::<B>(&device)?.searcher(&device, None);
+        let report = run_benchmark(&mut *searcher, &puzzles, &self.limits);
+
+        for line in report
+            .to_string()
+            .lines()
+            .take_while(|line| !line.starts_with("puzzle "))
+        {
+            println!("{}", line);
+        }
+        match &self.report {
+            Some(path) => report.save(path).map_err(|err| err.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+// Writes games of the engine against itself as replay chunks
+struct SelfPlay {
+    dir: PathBuf,
+    engine: EngineSpec,
+    config: SelfPlayConfig,
+}
+
+impl InferenceTask for SelfPlay {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let engine = self.engine.load::<B>(&device)?;
+        let start = Instant::now();
+        let games = self
+            .config
+            .generate(&engine, &self.dir, &device)
+            .map_err(|err| format!("{}: {}", self.dir.display(), err))?;
+        println!(
+            "Wrote {} games to {} in {:?}",
+            games,
+            self.dir.display(),
+            start.elapsed()
+        );
+        Ok(())
+    }
+}
+
+// Plays two engines against each other and reports the score of the first
+struct Match {
+    engines: [EngineSpec; 2],
+    openings: Vec<State>,
+    config: MatchConfig,
+}
+
+impl InferenceTask for Match {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let first = self.engines[0].load::<B>(&device)?;
+        let second = self.engines[1].load::<B>(&device)?;
+        let report = self
+            .config
+            .play([&first, &second], &self.openings, &device)?;
+        println!("{}", report);
+        Ok(())
+    }
+}
+
+// Trains on the replay chunks in a directory, from a checkpoint or from scratch, and saves a
+// checkpoint every `checkpoint_every` steps. With a registry the output is a model name and every
+// save becomes its next version, otherwise it is a checkpoint directory overwritten each time.
+struct Train {
+    dir: PathBuf,
+    output: String,
+    registry: Option<PathBuf>,
+    checkpoint: Option<PathBuf>,
+    precision: Precision,
+    config: TrainingConfig,
+}
+
+impl TrainingTask for Train {
+    type Output = Result<(), String>;
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> Result<(), String> {
+        let (model, model_config) = match &self.checkpoint {
+            Some(checkpoint) => {
+                let config = load_checkpoint_config(checkpoint).map_err(|err| err.to_string())?;
+                let model = load_checkpoint::<B>(checkpoint, &config, &device)
+                    .map_err(|err| err.to_string())?;
+                (model, config)
+            }
+            None => (
+                self.config.model.init::<B>(&device),
+                self.config.model.clone(),
+            ),
+        };
+
+        let mut buffer = ReplayBuffer::open(&self.dir, self.config.replay.clone())
+            .map_err(|err| format!("{}: {}", self.dir.display(), err))?;
+        if buffer.is_empty() {
+            return Err(format!("{}: no self-play positions", self.dir.display()));
+        }
+        println!(
+            "Replay buffer holds {} positions of {} games, {} minibatches served so far",
+            buffer.len(),
+            buffer.games(),
+            buffer.batches()
+        );
+        let registry = match &self.registry {
+            Some(root) => Some(
+                ModelRegistry::open(root).map_err(|err| format!("{}: {}", root.display(), err))?,
+            ),
+            None => None,
+        };
+        // "name/version" when the checkpoint trained from is in the same registry
+        let parent = self.checkpoint.as_ref().map(|checkpoint| {
+            let root = self.registry.as_deref().unwrap_or(Path::new(""));
+            let parent = checkpoint.strip_prefix(root).unwrap_or(checkpoint);
+            parent.display().to_string()
+        });
+
+        self.config
+            .train(
+                model,
+                &mut buffer,
+                &device,
+                |step, loss| println!("Step {}: loss {:.4}", step, loss),
+                |model, step| {
+                    let model = into_inference(model.clone());
+                    let saved = match &registry {
+                        Some(registry) => {
+                            let metadata = CheckpointMetadata::new(self.output.clone())
+                                .with_training_steps(step)
+                                .with_parent(parent.clone());
+                            registry
+                                .save(&model, &model_config, metadata, self.precision)
+                                .map(|saved| format!("{}/{:04}", saved.name, saved.version))
+                        }
+                        None => {
+                            save_checkpoint(&model, &model_config, &self.output, self.precision)
+                                .map(|()| self.output.clone())
+                        }
+                    };
+                    let saved = saved.map_err(|err| std::io::Error::other(err.to_string()))?;
+                    println!("Step {}: saved {}", step, saved);
+                    Ok(())
+                },
+            )
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+}
+
+// Lists the checkpoints of a model registry with their metadata
+struct Models {
+    registry: PathBuf,
+}
+
+impl Models {
+    fn run(self) -> Result<(), String> {
+        let registry = ModelRegistry::open(&self.registry)
+            .map_err(|err| format!("{}: {}", self.registry.display(), err))?;
+        let checkpoints = registry.list().map_err(|err| err.to_string())?;
+        if checkpoints.is_empty() {
+            println!("no checkpoints in {}", self.registry.display());
+        }
+        for metadata in checkpoints {
+            println!(
+                "{}/{:04}  steps {:<8} elo {:<8} parent {}",
+                metadata.name,
+                metadata.version,
+                metadata.training_steps,
+                metadata
+                    .elo
+                    .map_or("-".to_string(), |elo| format!("{:.0}", elo)),
+                metadata.parent.as_deref().unwrap_or("-")
+            );
+        }
+        Ok(())
+    }
+}
+
+fn open_tuner(positions: &Path) -> Result<TexelTuner, String> {
+    let tuner =
+        TexelTuner::open(positions).map_err(|err| format!("{}: {}", positions.display(), err))?;
+    match tuner.is_empty() {
+        true => Err(format!("{}: no labelled positions", positions.display())),
+        false => Ok(tuner),
+    }
+}
+
+// Tunes the handcrafted evaluation weights on labelled positions by local search and writes them
+// as Rust source
+struct Tune {
+    positions: PathBuf,
+    output: PathBuf,
+    step: i32,
+    passes: usize,
+}
+
+impl Tune {
+    fn run(self) -> Result<(), String> {
+        let tuner = open_tuner(&self.positions)?;
+        println!("Tuning on {} positions", tuner.len());
+        let params = tuner.tune(&EvalParams::new(), self.step, self.passes, |pass, error| {
+            println!("Pass {}: error {:.6}", pass, error)
+        });
+        save_rust_source(&params, &self.output).map_err(|err| err.to_string())
+    }
+}
+
+// Evolves the handcrafted evaluation weights on labelled positions and writes them as Rust source
+struct Evolve {
+    positions: PathBuf,
+    output: PathBuf,
+    config: EvolutionConfig,
+}
+
+impl Evolve {
+    fn run(self) -> Result<(), String> {
+        let tuner = open_tuner(&self.positions)?;
+        println!("Evolving on {} positions", tuner.len());
+        let params = self
+            .config
+            .evolve(&tuner, &EvalParams::new(), |generation, error| {
+                println!("Generation {}: error {:.6}", generation, error)
+            });
+        save_rust_source(&params, &self.output).map_err(|err| err.to_string())
+    }
+}
+
+// Builds a Polyglot book from a PGN database or from the games in a directory of self-play chunks
+struct Book {
+    source: PathBuf,
+    output: PathBuf,
+    builder: PolyglotBookBuilder,
+}
+
+impl Book {
+    fn run(mut self) -> Result<(), String> {
+        let error = |err: std::io::Error| format!("{}: {}", self.source.display(), err);
+        let games = match self.source.is_dir() {
+            true => {
+                let games = read_chunks(&self.source).map_err(error)?;
+                for game in &games {
+                    let moves: Vec<Move> = game.plies.iter().map(|(mv, _)| *mv).collect();
+                    self.builder
+                        .add_game(game.root.clone(), &moves, game.result);
+                }
+                games.len()
+            }
+            false => {
+                let file = File::open(&self.source).map_err(error)?;
+                self.builder.add_pgn(BufReader::new(file)).map_err(error)?
+            }
+        };
+
+        let book = self.builder.build();
+        if book.is_empty() {
+            return Err(format!(
+                "{}: no moves to put in the book",
+                self.source.display()
+            ));
+        }
+        book.save(&self.output)
+            .map_err(|err| format!("{}: {}", self.output.display(), err))?;
+        println!(
+            "Wrote {} entries from {} games to {}",
+            book.len(),
+            games,
+            self.output.display()
+        );
+        Ok(())
+    }
+}
+
+// Makes the inference server at `address` swap to the checkpoint, its clients keep their
+// connections
+struct Reload {
+    address: String,
+    checkpoint: PathBuf,
+}
+
+impl Reload {
+    fn run(self) -> Result<(), String> {
+        let error = |err: std::io::Error| format!("{}: {}", self.address, err);
+        let mut client = InferenceClient::connect(&self.address).map_err(error)?;
+        // The server resolves the path from its own working directory
+        let checkpoint = self.checkpoint.canonicalize().map_err(error)?;
+        client.reload(&checkpoint).map_err(error)?;
+        println!("{} now serves {}", self.address, checkpoint.display());
+        Ok(())
+    }
+}
+
+// Serves the checkpoint to self-play workers and match processes until killed
+struct Serve {
+    checkpoint: PathBuf,
+    server: InferenceServerConfig,
+}
+
+impl InferenceTask for Serve {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let model = load_model::<B>(&self.checkpoint, &device)?;
+        let server = self
+            .server
+            .start(model, device)
+            .map_err(|err| err.to_string())?;
+        eprintln!(
+            "Serving {} on {}",
+            self.checkpoint.display(),
+            server.address()
+        );
+        server.wait();
+        Ok(())
+    }
+}
+
+// Prints the attention each layer and head pays from the CLS token to the board, and optionally
+// writes every activation to a JSON file and the same heatmaps as SVG images into a directory
+struct Inspect {
+    checkpoint: PathBuf,
+    state: State,
+    json: Option<PathBuf>,
+    svg: Option<PathBuf>,
+}
+
+impl InferenceTask for Inspect {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let model = load_model::<B>(&self.checkpoint, &device)?;
+        let inspection = model.inspect(&self.state, &device);
+        for layer in 0..inspection.n_layers() {
+            for head in 0..inspection.n_heads {
+                println!("{}", inspection.ascii_heatmap(layer, head, 0));
+            }
+        }
+        if let Some(path) = self.json {
+            inspection.save_json(&path).map_err(|err| err.to_string())?;
+        }
+        if let Some(dir) = self.svg {
+            let error = |err: std::io::Error| format!("{}: {}", dir.display(), err);
+            std::fs::create_dir_all(&dir).map_err(error)?;
+            for layer in 0..inspection.n_layers() {
+                for head in 0..inspection.n_heads {
+                    let path = dir.join(format!("layer{}-head{}.svg", layer, head));
+                    std::fs::write(path, inspection.svg_heatmap(layer, head, 0)).map_err(error)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Calibrates int8 weights on the positions, reports the accuracy against the f32 model and saves
+// the quantized model as a checkpoint every command that takes one can load
+struct Quantize {
+    checkpoint: PathBuf,
+    positions: PathBuf,
+    output: PathBuf,
+    config: QuantizationConfig,
+}
+
+impl InferenceTask for Quantize {
+    type Output = Result<(), String>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Result<(), String> {
+        let config = load_checkpoint_config(&self.checkpoint).map_err(|err| err.to_string())?;
+        let model = load_checkpoint::<B>(&self.checkpoint, &config, &device)
+            .map_err(|err| err.to_string())?;
+        let positions = read_positions(&self.positions, 4096)
+            .map_err(|err| format!("{}: {}", self.positions.display(), err))?;
+        let (quantized, report) = self
+            .config
+            .calibrate(&model, &positions, &device, |report| println!("{}", report))?;
+        println!("Selected {}", report);
+        save_checkpoint(&quantized, &config, &self.output, Precision::Int8)
+            .map_err(|err| err.to_string())?;
+        println!("Saved the quantized model to {}", self.output.display());
+        Ok(())
+    }
+}
+
+// Trains a fresh student with the given architecture from the teacher checkpoint
+struct Distill {
+    teacher: PathBuf,
+    student: TransformerModelConfig,
+    positions: PathBuf,
+    output: PathBuf,
+    config: DistillationConfig,
+}
+
+impl TrainingTask for Distill {
+    type Output = Result<(), String>;
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> Result<(), String> {
+        let teacher = load_model::<B::InnerBackend>(&self.teacher, &device)?;
+        let positions: Vec<TrainingPosition> = read_labelled_positions(&self.positions, usize::MAX)
+            .map_err(|err| format!("{}: {}", self.positions.display(), err))?
+            .into_iter()
+            .map(|(state, result)| {
+                let position = TrainingPosition::new(state);
+                // From a White score in [0, 1] to the value range
+                match result {
+                    Some(result) => position.with_result(result as f32 * 2.0 - 1.0),
+                    None => position,
+                }
+            })
+            .collect();
+
+        let student = self.config.distill(
+            &teacher,
+            self.student.init::<B>(&device),
+            &positions,
+            &device,
+            |epoch, loss| println!("Epoch {}: distillation loss {:.6}", epoch, loss),
+        );
+        save_checkpoint(
+            &into_inference(student),
+            &self.student,
+            &self.output,
+            Precision::Full,
+        )
+        .map_err(|err| err.to_string())
+    }
+}
+
+// Trains a fresh model with the given architecture on the games of a PGN database
+struct Pretrain {
+    games: PathBuf,
+    model: TransformerModelConfig,
+    output: PathBuf,
+    config: PretrainingConfig,
+}
+
+impl TrainingTask for Pretrain {
+    type Output = Result<(), String>;
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> Result<(), String> {
+        let (model, _) = self
+            .config
+            .pretrain(
+                self.model.init::<B>(&device),
+                || File::open(&self.games).map(BufReader::new),
+                &device,
+                |progress| println!("{}", progress),
+            )
+            .map_err(|err| format!("{}: {}", self.games.display(), err))?;
+        save_checkpoint(
+            &into_inference(model),
+            &self.model,
+            &self.output,
+            Precision::Full,
+        )
+        .map_err(|err| err.to_string())
+    }
+}
+
+enum Command {
+    Help,
+    Perft(Perft),
+    Uci(Uci),
+    Play(Play),
+    Analyse(Analyse),
+    Bench(Bench),
+    SelfPlay(SelfPlay),
+    Match(Match),
+    Train(Train),
+    Models(Models),
+    Tune(Tune),
+    Evolve(Evolve),
+    Book(Book),
+    Pretrain(Pretrain),
+    Distill(Distill),
+    Quantize(Quantize),
+    Inspect(Inspect),
+    Serve(Serve),
+    Reload(Reload),
+}
+
+impl Command {
+    // Reads and checks every argument and config file, so mistakes surface before any work starts
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        let Some((name, rest)) = args.split_first() else {
+            return Err("missing command".to_string());
+        };
+        let mut args = Args::new(name, rest.to_vec());
+        let command = match name.as_str() {
+            "help" | "--help" | "-h" => Self::Help,
+            "perft" => {
+                let fen = args.value::<String>("fen")?;
+                let state = fen.map_or(Ok(State::new()), |fen| parse_fen(&fen));
+                let depth = args.value::<u8>("depth")?.unwrap_or(5);
+                Self::Perft(Perft {
+                    state: state.map_err(|err| args.error(err))?,
+                    depth: at_least("depth", depth, 1).map_err(|err| args.error(err))?,
+                    divide: args.flag("divide"),
+                })
+            }
+            "uci" => Self::Uci(Uci {
+                engine: EngineSpec::from_checkpoint(optional_checkpoint(&mut args)?),
+                limits: budget(&mut args, DEFAULT_INTERACTIVE_BUDGET)?,
+            }),
+            "play" => Self::Play(Play {
+                engine: EngineSpec::from_checkpoint(optional_checkpoint(&mut args)?),
+                style: match args.flag("plain") {
+                    true => RenderStyle::PLAIN,
+                    false => RenderStyle::detect(),
+                },
+            }),
+            "analyse" | "analyze" => {
+                let engine = EngineSpec::from_checkpoint(optional_checkpoint(&mut args)?);
+                let limits = budget(&mut args, DEFAULT_INTERACTIVE_BUDGET)?;
+                let target = args.positional("fen or pgn file")?;
+                let target = match Path::new(&target).is_file() {
+                    true => AnalysisTarget::Game(PathBuf::from(target)),
+                    false => AnalysisTarget::Position(Box::new(
+                        parse_fen(&target).map_err(|err| args.error(err))?,
+                    )),
+                };
+                Self::Analyse(Analyse {
+                    target,
+                    engine,
+                    limits,
+                })
+            }
+            "bench" => Self::Bench(Bench {
+                report: args.value("report")?,
+                limits: budget(&mut args, DEFAULT_BENCH_BUDGET)?,
+                engine: EngineSpec::from_checkpoint(optional_checkpoint(&mut args)?),
+                puzzles: existing_path(args.positional("puzzles")?)
+                    .map_err(|err| args.error(err))?,
+            }),
+            "selfplay" => {
+                let mut config = args.config(SelfPlayConfig::new())?;
+                let mut engine = EngineSpec::from_checkpoint(optional_checkpoint(&mut args)?);
+                if let Some(address) = args.value("server")? {
+                    if !matches!(engine, EngineSpec::Handcrafted) {
+                        return Err(args.error("--server and --checkpoint exclude each other"));
+                    }
+                    engine = EngineSpec::Server(address);
+                }
+                if let Some(games) = args.value("games")? {
+                    config.games = games;
+                }
+                if let Some(threads) = args.value("threads")? {
+                    config.threads = threads;
+                }
+                if let Some(budget) = args.value("budget")? {
+                    config.budget = budget;
+                }
+                if let Some(book) = args.value::<PathBuf>("book")? {
+                    let book = existing_path(book).map_err(|err| args.error(err))?;
+                    config.book = Some(book.display().to_string());
+                }
+                config.validate().map_err(|err| args.error(err))?;
+                Self::SelfPlay(SelfPlay {
+                    dir: PathBuf::from(args.positional("chunk dir")?),
+                    engine,
+                    config,
+                })
+            }
+            "match" => {
+                let mut config = args.config(MatchConfig::new())?;
+                if let Some(games) = args.value("games")? {
+                    config.games = games;
+                }
+                if let Some(threads) = args.value("threads")? {
+                    config.threads = threads;
+                }
+                if let Some(budget) = args.value("budget")? {
+                    config.budget = budget;
+                }
+                if let Some(book) = args.value::<PathBuf>("book")? {
+                    let book = existing_path(book).map_err(|err| args.error(err))?;
+                    config.book = Some(book.display().to_string());
+                }
+                config.validate().map_err(|err| args.error(err))?;
+                let openings = match args.value::<PathBuf>("openings")? {
+                    Some(path) => {
+                        let path = existing_path(path).map_err(|err| args.error(err))?;
+                        read_positions(&path, usize::MAX)
+                            .map_err(|err| args.error(format!("{}: {}", path.display(), err)))?
+                    }
+                    None => Vec::new(),
+                };
+                let first = EngineSpec::parse(&args.positional("engine")?);
+                let second = EngineSpec::parse(&args.positional("engine")?);
+                Self::Match(Match {
+                    engines: [
+                        first.map_err(|err| args.error(err))?,
+                        second.map_err(|err| args.error(err))?,
+                    ],
+                    openings,
+                    config,
+                })
+            }
+            "train" => {
+                let mut config = args.config(TrainingConfig::new())?;
+                let checkpoint = optional_checkpoint(&mut args)?;
+                if let Some(path) = args.value::<PathBuf>("model")? {
+                    if checkpoint.is_some() {
+                        return Err(args.error("--model and --checkpoint exclude each other"));
+                    }
+                    config.model = load_config(&path, config.model)?;
+                }
+                if let Some(steps) = args.value("steps")? {
+                    config.steps = steps;
+                }
+                config.validate().map_err(|err| args.error(err))?;
+                let precision = match args.flag("half") {
+                    true => Precision::Half,
+                    false => Precision::Full,
+                };
+                let registry = args.value("registry")?;
+                let dir =
+                    existing_path(args.positional("chunk dir")?).map_err(|err| args.error(err))?;
+                let output: String = args.positional("output")?;
+                if registry.is_some() {
+                    check_model_name(&output).map_err(|err| args.error(err))?;
+                }
+                Self::Train(Train {
+                    dir,
+                    output,
+                    registry,
+                    checkpoint,
+                    precision,
+                    config,
+                })
+            }
+            "models" => Self::Models(Models {
+                registry: existing_path(args.positional("registry")?)
+                    .map_err(|err| args.error(err))?,
+            }),
+            "tune" => {
+                let step = args.value("step")?.unwrap_or(1);
+                let passes = args.value("passes")?.unwrap_or(10);
+                Self::Tune(Tune {
+                    positions: existing_path(args.positional("labelled positions")?)
+                        .map_err(|err| args.error(err))?,
+                    output: PathBuf::from(args.positional("output.rs")?),
+                    step: at_least("step", step, 1).map_err(|err| args.error(err))?,
+                    passes: at_least("passes", passes, 1).map_err(|err| args.error(err))?,
+                })
+            }
+            "evolve" => {
+                let mut config = args.config(EvolutionConfig::new())?;
+                if let Some(generations) = args.value("generations")? {
+                    config.generations = generations;
+                }
+                if let Some(population) = args.value("population")? {
+                    config.population = population;
+                }
+                config.validate().map_err(|err| args.error(err))?;
+                Self::Evolve(Evolve {
+                    positions: existing_path(args.positional("labelled positions")?)
+                        .map_err(|err| args.error(err))?,
+                    output: PathBuf::from(args.positional("output.rs")?),
+                    config,
+                })
+            }
+            "book" => {
+                let mut builder = PolyglotBookBuilder::new();
+                if let Some(max_ply) = args.value("max-ply")? {
+                    builder = builder.with_max_ply(max_ply);
+                }
+                if let Some(min_games) = args.value("min-games")? {
+                    let min_games = at_least("min-games", min_games, 1);
+                    builder = builder.with_min_games(min_games.map_err(|err| args.error(err))?);
+                }
+                Self::Book(Book {
+                    source: existing_path(args.positional("pgn or chunk dir")?)
+                        .map_err(|err| args.error(err))?,
+                    output: PathBuf::from(args.positional("output.bin")?),
+                    builder,
+                })
+            }
+            "pretrain" => {
+                let config = args.config(PretrainingConfig::new())?;
+                config.validate().map_err(|err| args.error(err))?;
+                Self::Pretrain(Pretrain {
+                    config,
+                    model: model_config(&mut args)?,
+                    games: existing_path(args.positional("pgn")?).map_err(|err| args.error(err))?,
+                    output: PathBuf::from(args.positional("output")?),
+                })
+            }
+            "distill" => {
+                let config = args.config(DistillationConfig::new())?;
+                config.validate().map_err(|err| args.error(err))?;
+                Self::Distill(Distill {
+                    config,
+                    student: model_config(&mut args)?,
+                    teacher: checkpoint(args.positional("teacher")?)
+                        .map_err(|err| args.error(err))?,
+                    positions: existing_path(args.positional("positions")?)
+                        .map_err(|err| args.error(err))?,
+                    output: PathBuf::from(args.positional("output")?),
+                })
+            }
+            "quantize" => {
+                let config = args.config(QuantizationConfig::new())?;
+                config.validate().map_err(|err| args.error(err))?;
+                Self::Quantize(Quantize {
+                    checkpoint: checkpoint(args.positional("checkpoint")?)
+                        .map_err(|err| args.error(err))?,
+                    positions: existing_path(args.positional("positions")?)
+                        .map_err(|err| args.error(err))?,
+                    output: PathBuf::from(args.positional("output")?),
+                    config,
+                })
+            }
+            "inspect" => Self::Inspect(Inspect {
+                json: args.value("json")?,
+                svg: args.value("svg")?,
+                checkpoint: checkpoint(args.positional("checkpoint")?)
+                    .map_err(|err| args.error(err))?,
+                state: parse_fen(&args.positional("fen")?).map_err(|err| args.error(err))?,
+            }),
+            "serve" => {
+                let mut server = args.config(InferenceServerConfig::new())?;
+                if let Some(address) = args.value("address")? {
+                    server.address = address;
+                }
+                server.allow_remote |= args.flag("allow-remote");
+                Self::Serve(Serve {
+                    checkpoint: checkpoint(args.positional("checkpoint")?)
+                        .map_err(|err| args.error(err))?,
+                    server,
+                })
+            }
+            "reload" => Self::Reload(Reload {
+                address: args.positional("host:port")?,
+                checkpoint: checkpoint(args.positional("checkpoint")?)
+                    .map_err(|err| args.error(err))?,
+            }),
+            other => return Err(format!("unknown command '{}'", other)),
+        };
+        args.finish()?;
+        Ok(command)
+    }
+
+    fn run(self, backend: &BackendConfig) -> Result<(), String> {
+        match self {
+            Self::Help => {
+                println!("{}", USAGE);
+                Ok(())
+            }
+            Self::Perft(task) => {
+                task.run();
+                Ok(())
+            }
+            Self::Models(task) => task.run(),
+            Self::Tune(task) => task.run(),
+            Self::Evolve(task) => task.run(),
+            Self::Book(task) => task.run(),
+            Self::Uci(task) => backend.run(task)?,
+            Self::Play(task) => backend.run(task)?,
+            Self::Analyse(task) => backend.run(task)?,
+            Self::Bench(task) => backend.run(task)?,
+            Self::SelfPlay(task) => backend.run(task)?,
+            Self::Match(task) => backend.run(task)?,
+            Self::Train(task) => backend.train(task)?,
+            Self::Pretrain(task) => backend.train(task)?,
+            Self::Distill(task) => backend.train(task)?,
+            Self::Quantize(task) => backend.run(task)?,
+            Self::Inspect(task) => backend.run(task)?,
+            Self::Serve(task) => backend.run(task)?,
+            Self::Reload(task) => task.run(),
+        }
+    }
+}
+
+/// Runs the `chess-ai` command line on `args`, the program name left out. Mistakes in the
+/// arguments exit with code 2 after printing the usage, failures while running with code 1.
+pub fn run(args: impl IntoIterator<Item = String>) -> ExitCode {
+    let parsed = BackendConfig::from_args(args)
+        .and_then(|(backend, args)| Ok((backend, Command::parse(args)?)));
+    let (backend, command) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match command.run(&backend) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
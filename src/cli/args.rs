@@ -0,0 +1,276 @@
+use crate::chess::State;
+use burn::config::Config;
+use serde_json::Value;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+pub(crate) const USAGE: &str = "\
+usage: chess-ai [--backend ndarray|wgpu] [--device <spec>] [--backend-config <file>] <command>
+
+Commands:
+  perft [--depth <n>] [--fen <fen>] [--divide]
+  uci [--checkpoint <dir>] [--budget <budget>]
+  play [--checkpoint <dir>] [--plain]
+  analyse <fen|pgn file> [--checkpoint <dir>] [--budget <budget>]
+  bench <puzzles> [--report <file>] [--budget <budget>] [--checkpoint <dir>]
+  selfplay <chunk dir> [--checkpoint <dir>] [--games <n>] [--threads <n>] [--budget <budget>]
+           [--book <file.bin>] [--server <host:port>]
+  match <engine> <engine> [--games <n>] [--threads <n>] [--budget <budget>] [--openings <file>]
+        [--book <file.bin>]
+  train <chunk dir> <output> [--checkpoint <dir>] [--model <config>] [--steps <n>]
+        [--registry <dir>] [--half]
+  models <registry>
+  tune <labelled positions> <output.rs> [--step <n>] [--passes <n>]
+  evolve <labelled positions> <output.rs> [--generations <n>] [--population <n>]
+  book <pgn|chunk dir> <output.bin> [--max-ply <n>] [--min-games <n>]
+  pretrain <pgn> <output> [--model <config>]
+  distill <teacher> <positions> <output> [--model <config>]
+  quantize <checkpoint> <positions> <output>
+  inspect <checkpoint> <fen> [--json <file>] [--svg <dir>]
+  serve <checkpoint> [--address <host:port>] [--allow-remote]
+  reload <host:port> <checkpoint>
+
+Every command with parameters beyond its flags also takes --config <file.toml|file.json>, flags
+override the file. A budget is a node count like 2000 or a time per move like 500ms. An engine is
+a checkpoint, `server:<host:port>` for the network a serve process holds, or `handcrafted` for the
+alpha-beta search over the handcrafted evaluation. With --registry, train saves every checkpoint as
+the next version of the model named <output>. serve only listens on loopback addresses unless given
+--allow-remote, its clients are not authenticated and can make it load any checkpoint it can read.
+Distillation positions may be followed by their game result as in tune's labelled positions, the
+config's label_weight mixes those results into the teacher's value targets. train reads only the
+newest chunks that fill its replay window, older ones stay on disk unless replay.prune_chunks is
+set.";
+
+// The arguments of one subcommand. Flags are taken out by name wherever they appear, whatever is
+// left over has to be the positional arguments, in order.
+pub(crate) struct Args {
+    command: String,
+    args: Vec<String>,
+}
+
+impl Args {
+    pub(crate) fn new(command: &str, args: Vec<String>) -> Self {
+        Self {
+            command: command.to_string(),
+            args,
+        }
+    }
+
+    // `--name`
+    pub(crate) fn flag(&mut self, name: &str) -> bool {
+        let flag = format!("--{}", name);
+        match self.args.iter().position(|arg| *arg == flag) {
+            Some(i) => {
+                self.args.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // `--name <value>` or `--name=<value>`
+    pub(crate) fn value<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, String>
+    where
+        T::Err: Display,
+    {
+        let flag = format!("--{}", name);
+        let prefix = format!("{}=", flag);
+        let Some(i) = self
+            .args
+            .iter()
+            .position(|arg| *arg == flag || arg.starts_with(&prefix))
+        else {
+            return Ok(None);
+        };
+
+        let arg = self.args.remove(i);
+        let value = match arg.strip_prefix(&prefix) {
+            Some(value) => value.to_string(),
+            None if i < self.args.len() => self.args.remove(i),
+            None => return Err(self.error(format!("missing value for {}", flag))),
+        };
+        value
+            .parse()
+            .map(Some)
+            .map_err(|err| self.error(format!("invalid value '{}' for {}: {}", value, flag, err)))
+    }
+
+    // `--config <file>` loaded over `defaults`
+    pub(crate) fn config<C: Config>(&mut self, defaults: C) -> Result<C, String> {
+        match self.value::<PathBuf>("config")? {
+            Some(path) => load_config(&path, defaults),
+            None => Ok(defaults),
+        }
+    }
+
+    pub(crate) fn positional(&mut self, name: &str) -> Result<String, String> {
+        self.optional_positional()
+            .ok_or_else(|| self.error(format!("missing <{}>", name)))
+    }
+
+    // The first argument that is not a flag
+    pub(crate) fn optional_positional(&mut self) -> Option<String> {
+        let i = self.args.iter().position(|arg| !arg.starts_with("--"))?;
+        Some(self.args.remove(i))
+    }
+
+    // Call once every flag and positional argument has been taken
+    pub(crate) fn finish(self) -> Result<(), String> {
+        match self.args.first() {
+            None => Ok(()),
+            Some(arg) if arg.starts_with("--") => Err(self.error(format!("unknown flag {}", arg))),
+            Some(arg) => Err(self.error(format!("unexpected argument '{}'", arg))),
+        }
+    }
+
+    pub(crate) fn error(&self, message: impl Display) -> String {
+        format!("{}: {}", self.command, message)
+    }
+}
+
+// A config file only needs the fields it changes, the rest keep the values of `defaults`. Unknown
+// fields are rejected, a misspelt one would otherwise be ignored without a word.
+pub(crate) fn load_config<C: Config>(path: &Path, defaults: C) -> Result<C, String> {
+    let error = |message: String| format!("{}: {}", path.display(), message);
+    let text = std::fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+    let overrides: Value = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(|err| error(err.to_string()))?,
+        Some("json") => serde_json::from_str(&text).map_err(|err| error(err.to_string()))?,
+        _ => return Err(error("expected a .toml or .json config file".to_string())),
+    };
+
+    let mut merged = serde_json::to_value(&defaults).map_err(|err| error(err.to_string()))?;
+    merge(&mut merged, overrides.clone());
+    let config: C = serde_json::from_value(merged).map_err(|err| error(err.to_string()))?;
+    // Fields that default to None are missing from `defaults` but present once set
+    let resolved = serde_json::to_value(&config).map_err(|err| error(err.to_string()))?;
+    check_fields(&overrides, &resolved, "").map_err(error)?;
+    Ok(config)
+}
+
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(field) => merge(field, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+fn check_fields(overrides: &Value, resolved: &Value, prefix: &str) -> Result<(), String> {
+    let (Value::Object(overrides), Value::Object(resolved)) = (overrides, resolved) else {
+        return Ok(());
+    };
+    for (key, value) in overrides {
+        let name = format!("{}{}", prefix, key);
+        let field = resolved
+            .get(key)
+            .ok_or_else(|| format!("unknown field '{}'", name))?;
+        check_fields(value, field, &format!("{}.", name))?;
+    }
+    Ok(())
+}
+
+// Validation helpers shared by the subcommands, each error names what was wrong
+
+pub(crate) fn existing_path(path: impl Into<PathBuf>) -> Result<PathBuf, String> {
+    let path = path.into();
+    match path.exists() {
+        true => Ok(path),
+        false => Err(format!("{}: no such file or directory", path.display())),
+    }
+}
+
+pub(crate) fn parse_fen(fen: &str) -> Result<State, String> {
+    State::from_fen(fen).map_err(|err| format!("invalid FEN: {}", err))
+}
+
+pub(crate) fn at_least<T: PartialOrd + Display>(name: &str, value: T, min: T) -> Result<T, String> {
+    match value >= min {
+        true => Ok(value),
+        false => Err(format!("{} must be at least {}, got {}", name, min, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{model::TransformerModelConfig, replay::ReplayBufferConfig};
+
+    fn args(command: &str, args: &[&str]) -> Args {
+        Args::new(command, args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn test_args() {
+        let mut parsed = args(
+            "bench",
+            &["--budget", "500ms", "wac.epd", "--divide", "--games=4"],
+        );
+        assert!(parsed.flag("divide"));
+        assert!(!parsed.flag("divide"));
+        assert_eq!(parsed.value::<usize>("games").unwrap(), Some(4));
+        assert_eq!(parsed.value::<String>("budget").unwrap().unwrap(), "500ms");
+        assert_eq!(parsed.value::<usize>("threads").unwrap(), None);
+        assert_eq!(parsed.positional("puzzles").unwrap(), "wac.epd");
+        assert_eq!(
+            parsed.positional("report").unwrap_err(),
+            "bench: missing <report>"
+        );
+        assert!(parsed.finish().is_ok());
+
+        let mut parsed = args("selfplay", &["--games", "many", "--threads"]);
+        assert_eq!(
+            parsed.value::<usize>("games").unwrap_err(),
+            "selfplay: invalid value 'many' for --games: invalid digit found in string"
+        );
+        assert!(parsed.value::<usize>("threads").is_err());
+        assert!(args("perft", &["--dpeth", "3"])
+            .finish()
+            .is_err_and(|err| err == "perft: unknown flag --dpeth"));
+        assert!(args("perft", &["extra"]).finish().is_err());
+    }
+
+    #[test]
+    fn test_load_config() {
+        let dir = std::env::temp_dir().join(format!("config-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Only the changed fields, nested ones included
+        let path = dir.join("model.toml");
+        std::fs::write(&path, "n_blocks = 2\nwdl_head = true\n").unwrap();
+        let config = load_config(&path, TransformerModelConfig::new()).unwrap();
+        let expected = TransformerModelConfig::new()
+            .with_n_blocks(2)
+            .with_wdl_head(true);
+        assert_eq!(config.to_string(), expected.to_string());
+
+        let path = dir.join("replay.json");
+        std::fs::write(&path, r#"{"window_games": 50, "recency_half_life": 10.0}"#).unwrap();
+        let config = load_config(&path, ReplayBufferConfig::new()).unwrap();
+        let expected = ReplayBufferConfig::new()
+            .with_window_games(50)
+            .with_recency_half_life(Some(10.0));
+        assert_eq!(config.to_string(), expected.to_string());
+
+        let path = dir.join("typo.toml");
+        std::fs::write(&path, "n_blocks = 2\nn_heds = 4\n").unwrap();
+        let error = load_config(&path, TransformerModelConfig::new()).unwrap_err();
+        assert!(error.ends_with("unknown field 'n_heds'"), "{}", error);
+        std::fs::write(&path, "n_blocks = \"two\"\n").unwrap();
+        assert!(load_config(&path, TransformerModelConfig::new()).is_err());
+        assert!(load_config(&dir.join("missing.toml"), TransformerModelConfig::new()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,2 +1,20 @@
+pub(crate) mod arena;
+pub(crate) mod backend;
+pub(crate) mod benchmark;
+pub(crate) mod checkpoint;
+pub(crate) mod distill;
+pub(crate) mod eval;
+pub(crate) mod inspect;
 pub(crate) mod model;
+pub(crate) mod play;
 pub(crate) mod policy;
+pub(crate) mod pretrain;
+pub(crate) mod quantize;
+pub(crate) mod replay;
+pub(crate) mod search;
+pub(crate) mod selfplay;
+pub(crate) mod server;
+pub(crate) mod time;
+pub(crate) mod training;
+pub(crate) mod tuning;
+pub(crate) mod uci;
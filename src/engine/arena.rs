@@ -0,0 +1,194 @@
+use crate::{
+    chess::{prng::PseudoRng, types::Color, State},
+    engine::{
+        search::{SearchLimits, Searcher},
+        selfplay::{game_seed, open_book, play_game, random_opening, Engine},
+    },
+};
+use burn::{config::Config, tensor::backend::Backend};
+
+#[derive(Config, Debug)]
+pub(crate) struct MatchConfig {
+    // Rounded up to an even number, every opening is played once with each engine as White
+    #[config(default = 100)]
+    pub(crate) games: usize,
+    #[config(default = 1)]
+    pub(crate) threads: usize,
+    // Per move and engine, a node count like "2000" or a time like "100ms"
+    #[config(default = "String::from(\"2000\")")]
+    pub(crate) budget: String,
+    #[config(default = 300)]
+    pub(crate) max_plies: usize,
+    // Random moves from the initial position when no openings are given
+    #[config(default = 6)]
+    pub(crate) random_plies: usize,
+    // Polyglot book the random openings are drawn from while it has them
+    pub(crate) book: Option<String>,
+    #[config(default = 0x2545f4914f6cdd1d)]
+    pub(crate) seed: u64,
+}
+
+// Games from the first engine's point of view
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct MatchReport {
+    pub(crate) wins: usize,
+    pub(crate) draws: usize,
+    pub(crate) losses: usize,
+}
+
+impl MatchReport {
+    pub(crate) const fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    pub(crate) fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    // Logistic Elo difference matching the score, infinite after a clean sweep either way
+    pub(crate) fn elo_difference(&self) -> f64 {
+        400.0 * (self.score() / (1.0 - self.score())).log10()
+    }
+
+    fn add(&mut self, other: Self) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+    }
+}
+
+impl std::fmt::Display for MatchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "+{} ={} -{} score {:.1}% elo {:+.0}",
+            self.wins,
+            self.draws,
+            self.losses,
+            self.score() * 100.0,
+            self.elo_difference()
+        )
+    }
+}
+
+impl MatchConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.games == 0 || self.threads == 0 {
+            return Err("games and threads must be at least 1".to_string());
+        }
+        self.budget.parse::<SearchLimits>()?;
+        Ok(())
+    }
+
+    // Plays `engines[0]` against `engines[1]`, game pairs spread over the threads. Each pair starts
+    // from the next of `openings`, or from a random opening when there are none.
+    pub(crate) fn play<B: Backend>(
+        &self,
+        engines: [&Engine<B>; 2],
+        openings: &[State],
+        device: &B::Device,
+    ) -> Result<MatchReport, String> {
+        self.validate()?;
+        let limits: SearchLimits = self.budget.parse()?;
+        let pairs = self.games.div_ceil(2);
+        let book = self
+            .book
+            .as_ref()
+            .map(open_book)
+            .transpose()
+            .map_err(|err| err.to_string())?;
+
+        let report = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads)
+                .map(|thread| {
+                    let engines = [engines[0].clone(), engines[1].clone()];
+                    let (device, book) = (device.clone(), book.as_ref());
+                    scope.spawn(move || {
+                        let mut report = MatchReport::default();
+                        for pair in (thread..pairs).step_by(self.threads) {
+                            let root = match openings.is_empty() {
+                                true => {
+                                    let mut rng = PseudoRng::new(game_seed(self.seed, pair));
+                                    random_opening(&mut rng, self.random_plies, book)
+                                }
+                                false => openings[pair % openings.len()].clone(),
+                            };
+                            for first_plays in [Color::White, Color::Black] {
+                                // Searchers are recreated so no state carries over between games
+                                let mut players: [Box<dyn Searcher>; 2] = [
+                                    engines[0].searcher(&device, None),
+                                    engines[1].searcher(&device, None),
+                                ];
+                                if first_plays == Color::Black {
+                                    players.swap(0, 1);
+                                }
+                                let game =
+                                    play_game(&mut players, &root, &limits, self.max_plies, None);
+                                let winner = game.result.winner();
+                                report.add(match winner.map(|winner| winner == first_plays) {
+                                    Some(true) => MatchReport {
+                                        wins: 1,
+                                        ..Default::default()
+                                    },
+                                    Some(false) => MatchReport {
+                                        losses: 1,
+                                        ..Default::default()
+                                    },
+                                    None => MatchReport {
+                                        draws: 1,
+                                        ..Default::default()
+                                    },
+                                });
+                            }
+                        }
+                        report
+                    })
+                })
+                .collect();
+
+            let mut report = MatchReport::default();
+            for worker in workers {
+                report.add(worker.join().expect("match thread panicked"));
+            }
+            report
+        });
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::backend::NdArray;
+
+    #[test]
+    fn test_match() {
+        let engine = Engine::<NdArray>::Handcrafted;
+        let config = MatchConfig::new()
+            .with_games(3)
+            .with_threads(2)
+            .with_budget("50".to_string())
+            .with_max_plies(16);
+        let report = config
+            .play([&engine, &engine], &[], &Default::default())
+            .unwrap();
+        assert_eq!(report.games(), 4);
+
+        // A won position for White, whoever has White wins
+        let openings = [State::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap()];
+        let report = config
+            .with_games(2)
+            .play([&engine, &engine], &openings, &Default::default())
+            .unwrap();
+        assert_eq!(
+            report,
+            MatchReport {
+                wins: 1,
+                draws: 0,
+                losses: 1
+            }
+        );
+        assert_eq!(report.to_string(), "+1 =0 -1 score 50.0% elo +0");
+        assert!(MatchConfig::new().with_games(0).validate().is_err());
+    }
+}
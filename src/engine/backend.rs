@@ -0,0 +1,252 @@
+use crate::cli::args::load_config;
+use burn::{
+    backend::{ndarray::NdArrayDevice, wgpu::WgpuDevice, Autodiff, NdArray, Wgpu},
+    config::Config,
+    tensor::backend::{AutodiffBackend, Backend},
+};
+use std::path::Path;
+
+pub(crate) type CpuBackend = NdArray;
+pub(crate) type CpuTrainingBackend = Autodiff<NdArray>;
+// Fused wgpu, `fusion` is enabled in Cargo.toml
+pub(crate) type GpuBackend = Wgpu;
+pub(crate) type GpuTrainingBackend = Autodiff<Wgpu>;
+
+#[derive(Config, Debug, Copy, PartialEq, Eq)]
+pub(crate) enum BackendKind {
+    NdArray,
+    Wgpu,
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ndarray" | "cpu" => Ok(BackendKind::NdArray),
+            "wgpu" | "gpu" => Ok(BackendKind::Wgpu),
+            _ => Err(format!("unknown backend '{}', expected ndarray or wgpu", s)),
+        }
+    }
+}
+
+// Generic code that needs a concrete backend, dispatched by `BackendConfig::run`
+pub(crate) trait InferenceTask {
+    type Output;
+
+    fn run<B: Backend>(self, device: B::Device) -> Self::Output;
+}
+
+// Same as `InferenceTask` for code that needs gradients, dispatched by `BackendConfig::train`
+pub(crate) trait TrainingTask {
+    type Output;
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> Self::Output;
+}
+
+#[derive(Config, Debug)]
+pub(crate) struct BackendConfig {
+    // NdArray needs no GPU, so it is the safe default on headless machines
+    #[config(default = "BackendKind::NdArray")]
+    pub(crate) backend: BackendKind,
+    // "default", "cpu", or "discrete", "integrated" and "virtual" with an optional ":index"
+    #[config(default = "String::from(\"default\")")]
+    pub(crate) device: String,
+}
+
+impl BackendConfig {
+    // Consumes `--backend <name>`, `--device <spec>` and `--backend-config <file.toml|file.json>`
+    // from the arguments and returns the config with the remaining arguments. Flags override the
+    // file.
+    pub(crate) fn from_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(Self, Vec<String>), String> {
+        let mut config = Self::new();
+        let (mut backend, mut device) = (None, None);
+        let mut rest = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", flag))
+            };
+            match arg.as_str() {
+                "--backend" => backend = Some(value("--backend")?.parse()?),
+                "--device" => device = Some(value("--device")?),
+                "--backend-config" => {
+                    let path = value("--backend-config")?;
+                    config = load_config(Path::new(&path), Self::new())?;
+                }
+                _ => rest.push(arg),
+            }
+        }
+
+        if let Some(backend) = backend {
+            config.backend = backend;
+        }
+        if let Some(device) = device {
+            config.device = device;
+        }
+        // Reject a bad device before any work is started
+        config.check_device()?;
+        Ok((config, rest))
+    }
+
+    fn check_device(&self) -> Result<(), String> {
+        match self.backend {
+            BackendKind::NdArray => self.ndarray_device().map(|_| ()),
+            BackendKind::Wgpu => self.wgpu_device().map(|_| ()),
+        }
+    }
+
+    fn ndarray_device(&self) -> Result<NdArrayDevice, String> {
+        match self.device.to_ascii_lowercase().as_str() {
+            "default" | "cpu" => Ok(NdArrayDevice::Cpu),
+            other => Err(format!(
+                "the ndarray backend only runs on the cpu, not '{}'",
+                other
+            )),
+        }
+    }
+
+    fn wgpu_device(&self) -> Result<WgpuDevice, String> {
+        let device = self.device.to_ascii_lowercase();
+        let (kind, index) = match device.split_once(':') {
+            Some((kind, index)) => (
+                kind,
+                index
+                    .parse()
+                    .map_err(|_| format!("invalid device index in '{}'", self.device))?,
+            ),
+            None => (device.as_str(), 0),
+        };
+        match kind {
+            "default" => Ok(WgpuDevice::DefaultDevice),
+            "cpu" => Ok(WgpuDevice::Cpu),
+            "discrete" => Ok(WgpuDevice::DiscreteGpu(index)),
+            "integrated" => Ok(WgpuDevice::IntegratedGpu(index)),
+            "virtual" => Ok(WgpuDevice::VirtualGpu(index)),
+            _ => Err(format!("unknown wgpu device '{}'", self.device)),
+        }
+    }
+
+    // Runs the task on the plain backend, for inference
+    pub(crate) fn run<T: InferenceTask>(&self, task: T) -> Result<T::Output, String> {
+        match self.backend {
+            BackendKind::NdArray => {
+                let device = self.ndarray_device()?;
+                report::<CpuBackend>(&device);
+                Ok(task.run::<CpuBackend>(device))
+            }
+            BackendKind::Wgpu => {
+                let device = self.wgpu_device()?;
+                report::<GpuBackend>(&device);
+                Ok(task.run::<GpuBackend>(device))
+            }
+        }
+    }
+
+    // Runs the task on the autodiff variant of the backend, for training
+    pub(crate) fn train<T: TrainingTask>(&self, task: T) -> Result<T::Output, String> {
+        match self.backend {
+            BackendKind::NdArray => {
+                let device = self.ndarray_device()?;
+                report::<CpuTrainingBackend>(&device);
+                Ok(task.run::<CpuTrainingBackend>(device))
+            }
+            BackendKind::Wgpu => {
+                let device = self.wgpu_device()?;
+                report::<GpuTrainingBackend>(&device);
+                Ok(task.run::<GpuTrainingBackend>(device))
+            }
+        }
+    }
+}
+
+// Written to stderr so it never mixes with protocol output on stdout
+fn report<B: Backend>(device: &B::Device) {
+    eprintln!("Using backend {} on {:?}", B::name(device), device);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess::State,
+        engine::{model::TransformerModelConfig, policy::legal_move_masks},
+    };
+    use burn::tensor::ElementConversion;
+
+    struct Evaluate;
+
+    impl InferenceTask for Evaluate {
+        type Output = f32;
+
+        fn run<B: Backend>(self, device: B::Device) -> f32 {
+            let model = TransformerModelConfig::new()
+                .with_n_blocks(1)
+                .with_n_heads(2)
+                .with_head_dimension(8)
+                .init::<B>(&device);
+            let state = State::new();
+            let mask = legal_move_masks::<B>(&[&state], &device);
+            model
+                .forward(&[&state], mask, &device)
+                .value
+                .into_scalar()
+                .elem()
+        }
+    }
+
+    struct IsAutodiff;
+
+    impl TrainingTask for IsAutodiff {
+        type Output = bool;
+
+        fn run<B: AutodiffBackend>(self, _device: B::Device) -> bool {
+            B::ad_enabled()
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_backend_args() {
+        let (config, rest) = BackendConfig::from_args(args(&["perft", "5"])).unwrap();
+        assert_eq!(config.backend, BackendKind::NdArray);
+        assert_eq!(rest, args(&["perft", "5"]));
+
+        let (config, rest) =
+            BackendConfig::from_args(args(&["--backend", "wgpu", "--device", "discrete:1", "x"]))
+                .unwrap();
+        assert_eq!(config.backend, BackendKind::Wgpu);
+        assert_eq!(config.wgpu_device(), Ok(WgpuDevice::DiscreteGpu(1)));
+        assert_eq!(rest, args(&["x"]));
+
+        assert!(BackendConfig::from_args(args(&["--backend", "tpu"])).is_err());
+        assert!(BackendConfig::from_args(args(&["--device", "discrete"])).is_err());
+        assert!(BackendConfig::from_args(args(&["--backend"])).is_err());
+
+        // A TOML file only needs the fields it changes
+        let path =
+            std::env::temp_dir().join(format!("chess-ai-backend-{}.toml", std::process::id()));
+        std::fs::write(&path, "device = \"cpu\"\n").unwrap();
+        let file = path.display().to_string();
+        let (config, rest) =
+            BackendConfig::from_args(args(&["--backend-config", &file, "perft"])).unwrap();
+        assert_eq!(config.backend, BackendKind::NdArray);
+        assert_eq!(config.device, "cpu");
+        assert_eq!(rest, args(&["perft"]));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_default_backend_runs_without_gpu() {
+        let config = BackendConfig::new();
+        assert!(config.run(Evaluate).unwrap().is_finite());
+        assert!(config.train(IsAutodiff).unwrap());
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    chess::{moves::Move, State},
+    engine::search::{SearchLimits, Searcher},
+};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+// A test position with the moves that solve it, or that must be avoided
+pub(crate) struct Puzzle {
+    pub(crate) id: String,
+    pub(crate) state: State,
+    pub(crate) best: Vec<Move>,
+    pub(crate) avoid: Vec<Move>,
+    // The suite name for EPD ids like "WAC.001", the themes for Lichess puzzles
+    pub(crate) categories: Vec<String>,
+}
+
+impl Puzzle {
+    pub(crate) fn is_solved_by(&self, mv: Move) -> bool {
+        (self.best.is_empty() || self.best.contains(&mv)) && !self.avoid.contains(&mv)
+    }
+}
+
+// EPD records with `bm`, `am` and `id` opcodes, e.g. WAC or STS, and the Lichess puzzle CSV
+// export, one per line in any mix. Blank lines, '#' comments and the CSV header are skipped.
+pub(crate) fn read_puzzles(reader: impl BufRead) -> io::Result<Vec<Puzzle>> {
+    let mut puzzles = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("PuzzleId,") {
+            continue;
+        }
+
+        let puzzle = match line.split(',').nth(1) {
+            Some(fen) if fen.contains('/') => parse_lichess_csv(line),
+            _ => parse_epd(line, number + 1),
+        };
+        puzzles.push(puzzle.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: invalid puzzle '{}'", number + 1, line),
+            )
+        })?);
+    }
+    Ok(puzzles)
+}
+
+pub(crate) fn read_puzzle_file(path: impl AsRef<Path>) -> io::Result<Vec<Puzzle>> {
+    read_puzzles(BufReader::new(File::open(path)?))
+}
+
+// <board> <side> <castling> <en passant> <opcode> <operand>; ...
+fn parse_epd(line: &str, number: usize) -> Option<Puzzle> {
+    let mut fields = line.splitn(5, ' ');
+    let position: Vec<&str> = fields.by_ref().take(4).collect();
+    if position.len() < 4 {
+        return None;
+    }
+    let state = State::from_fen(&position.join(" ")).ok()?;
+
+    let mut puzzle = Puzzle {
+        id: format!("line.{}", number),
+        state,
+        best: Vec::new(),
+        avoid: Vec::new(),
+        categories: Vec::new(),
+    };
+    for operation in fields.next().unwrap_or("").split(';') {
+        let (opcode, operand) = operation
+            .trim()
+            .split_once(' ')
+            .unwrap_or((operation.trim(), ""));
+        match opcode {
+            "bm" | "am" => {
+                let moves = operand
+                    .split_whitespace()
+                    .map(|token| resolve_move(&puzzle.state, token))
+                    .collect::<Option<Vec<_>>>()?;
+                match opcode {
+                    "bm" => puzzle.best.extend(moves),
+                    _ => puzzle.avoid.extend(moves),
+                }
+            }
+            "id" => puzzle.id = operand.trim().trim_matches('"').to_string(),
+            _ => {}
+        }
+    }
+    if puzzle.best.is_empty() && puzzle.avoid.is_empty() {
+        return None;
+    }
+
+    // "WAC.001" belongs to "WAC", "STS(v1.0) Undermining.012" to "STS(v1.0) Undermining"
+    let category = match puzzle.id.rsplit_once('.') {
+        Some((suite, index)) if index.chars().all(|c| c.is_ascii_digit()) => suite,
+        _ => "EPD",
+    };
+    puzzle.categories.push(category.to_string());
+    Some(puzzle)
+}
+
+// PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,... where the FEN is the
+// position before the opponent's move, the first of `Moves`, and the second move is the answer
+fn parse_lichess_csv(line: &str) -> Option<Puzzle> {
+    let fields: Vec<&str> = line.split(',').collect();
+    let fen = fields.get(1)?;
+    let mut state = State::from_fen(fen).ok()?;
+    let mut moves = fields.get(2)?.split_whitespace();
+    let setup = state.parse_uci(moves.next()?)?;
+    state.make_move(setup);
+    let solution = state.parse_uci(moves.next()?)?;
+
+    let mut categories: Vec<String> = fields
+        .get(7)
+        .map(|themes| themes.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default();
+    if categories.is_empty() {
+        categories.push("lichess".to_string());
+    }
+    Some(Puzzle {
+        id: fields[0].to_string(),
+        state,
+        best: vec![solution],
+        avoid: Vec::new(),
+        categories,
+    })
+}
+
+// SAN as written in EPD files, or UCI
+fn resolve_move(state: &State, token: &str) -> Option<Move> {
+    state.parse_san(token).or_else(|| state.parse_uci(token))
+}
+
+pub(crate) struct PuzzleResult {
+    pub(crate) id: String,
+    pub(crate) categories: Vec<String>,
+    pub(crate) played: Option<Move>,
+    pub(crate) solved: bool,
+}
+
+pub(crate) struct BenchmarkReport {
+    pub(crate) results: Vec<PuzzleResult>,
+}
+
+pub(crate) fn run_benchmark(
+    searcher: &mut (impl Searcher + ?Sized),
+    puzzles: &[Puzzle],
+    limits: &SearchLimits,
+) -> BenchmarkReport {
+    let results = puzzles
+        .iter()
+        .map(|puzzle| {
+            let played = searcher.best_move(&puzzle.state, limits);
+            PuzzleResult {
+                id: puzzle.id.clone(),
+                categories: puzzle.categories.clone(),
+                played,
+                solved: played.is_some_and(|mv| puzzle.is_solved_by(mv)),
+            }
+        })
+        .collect();
+    BenchmarkReport { results }
+}
+
+impl BenchmarkReport {
+    pub(crate) fn solved(&self) -> usize {
+        self.results.iter().filter(|result| result.solved).count()
+    }
+
+    // (solved, total) per category in name order, a puzzle counts towards each of its categories
+    pub(crate) fn categories(&self) -> BTreeMap<&str, (usize, usize)> {
+        let mut categories = BTreeMap::new();
+        for result in &self.results {
+            for category in &result.categories {
+                let (solved, total) = categories.entry(category.as_str()).or_insert((0, 0));
+                *solved += result.solved as usize;
+                *total += 1;
+            }
+        }
+        categories
+    }
+
+    pub(crate) fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+}
+
+// Nothing run-specific like timings goes in, so reports of two checkpoints diff cleanly: the
+// summary lines show the rates and the puzzle lines which positions changed
+impl std::fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "total {}/{} {:.1}%",
+            self.solved(),
+            self.results.len(),
+            percentage(self.solved(), self.results.len())
+        )?;
+        for (category, (solved, total)) in self.categories() {
+            writeln!(
+                f,
+                "category {} {}/{} {:.1}%",
+                category,
+                solved,
+                total,
+                percentage(solved, total)
+            )?;
+        }
+        for result in &self.results {
+            writeln!(
+                f,
+                "puzzle {} {} {}",
+                result.id,
+                if result.solved { "solved" } else { "failed" },
+                result.played.map_or("none".to_string(), Move::to_uci)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn percentage(solved: usize, total: usize) -> f64 {
+    solved as f64 * 100.0 / total.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{eval::HandcraftedEval, search::AlphaBeta};
+
+    const SUITE: &str = r#"# Made up positions in the formats of the common suites
+6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - bm Ra8#; id "Mate.001";
+4k3/8/8/3q4/8/8/3R4/4K3 w - - bm Rxd5; id "Tactic.001";
+4k3/8/8/3q4/8/8/3R4/4K3 w - - am Kf1 Ke2; id "Tactic.002"; c0 "anything but the king";
+PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags
+00001,7k/6pp/8/8/8/2q5/3R4/4K3 b - - 0 1,c3c4 d2d8,1200,80,90,100,advantage short,https://lichess.org/x,
+"#;
+
+    #[test]
+    fn test_parse_suites() {
+        let puzzles = read_puzzles(SUITE.as_bytes()).unwrap();
+        assert_eq!(puzzles.len(), 4);
+
+        assert_eq!(puzzles[0].id, "Mate.001");
+        assert_eq!(puzzles[0].categories, vec!["Mate"]);
+        assert_eq!(puzzles[0].best[0].to_uci(), "a1a8");
+        assert_eq!(puzzles[2].avoid.len(), 2);
+        assert!(puzzles[2].best.is_empty());
+        assert!(puzzles[2].is_solved_by(puzzles[1].best[0]));
+        assert!(!puzzles[2].is_solved_by(puzzles[2].avoid[0]));
+
+        // The opponent's move is played first, the answer comes from the position after it
+        assert_eq!(puzzles[3].id, "00001");
+        assert_eq!(puzzles[3].categories, vec!["advantage", "short"]);
+        assert_eq!(puzzles[3].best[0].to_uci(), "d2d8");
+        assert_eq!(
+            puzzles[3].state.to_fen(),
+            "7k/6pp/8/8/2q5/8/3R4/4K3 w - - 1 2"
+        );
+
+        let error = read_puzzles("8/8/8/8/8/8/8/8 w - - id \"x\";\n".as_bytes());
+        assert!(error.is_err());
+        let error = read_puzzles("4k3/8/8/8/8/8/8/4K3 w - - bm Qh5;\n".as_bytes());
+        assert!(error.is_err_and(|err| err.to_string().starts_with("line 1")));
+    }
+
+    #[test]
+    fn test_benchmark_report() {
+        let puzzles = read_puzzles(SUITE.as_bytes()).unwrap();
+        let mut search = AlphaBeta::new(HandcraftedEval::new());
+        let limits = SearchLimits::new().with_nodes(20_000);
+        let report = run_benchmark(&mut search, &puzzles, &limits);
+        assert_eq!(report.solved(), 4);
+        assert_eq!(report.categories()["Tactic"], (2, 2));
+
+        let text = report.to_string();
+        assert!(text.starts_with("total 4/4 100.0%\n"));
+        assert!(text.contains("category short 1/1 100.0%\n"));
+        assert!(text.contains("puzzle Tactic.001 solved d2d5\n"));
+        // Same searcher and budget, same report
+        assert_eq!(
+            run_benchmark(&mut search, &puzzles, &limits).to_string(),
+            text
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use crate::engine::model::{TransformerModel, TransformerModelConfig};
+use burn::{
+    config::{Config, ConfigError},
+    module::Module,
+    record::{FullPrecisionSettings, HalfPrecisionSettings, NamedMpkFileRecorder, RecorderError},
+    tensor::backend::Backend,
+};
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+const CONFIG_FILE: &str = "config.json";
+const METADATA_FILE: &str = "metadata.json";
+// The recorders append the `.mpk` extension themselves
+const FULL_WEIGHTS: &str = "weights-full";
+const HALF_WEIGHTS: &str = "weights-half";
+const INT8_WEIGHTS: &str = "weights-int8";
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Precision {
+    Full,
+    Half,
+    // A model already quantized by `quantize_linear_weights`, its int8 weights are stored as they
+    // are and the rest in full precision
+    Int8,
+}
+
+impl Precision {
+    // In the order `load_checkpoint` looks for them
+    const ALL: [Self; 3] = [Precision::Full, Precision::Half, Precision::Int8];
+
+    const fn weights_file(self) -> &'static str {
+        match self {
+            Precision::Full => FULL_WEIGHTS,
+            Precision::Half => HALF_WEIGHTS,
+            Precision::Int8 => INT8_WEIGHTS,
+        }
+    }
+}
+
+pub(crate) enum CheckpointError {
+    Io(io::Error),
+    Config(ConfigError),
+    Record(RecorderError),
+    // The checkpoint was trained with a different architecture than the caller expects
+    ConfigMismatch { expected: String, found: String },
+    NotFound(PathBuf),
+}
+
+impl std::fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "checkpoint I/O error: {}", err),
+            CheckpointError::Config(err) => write!(f, "invalid checkpoint config: {}", err),
+            CheckpointError::Record(err) => write!(f, "invalid checkpoint weights: {}", err),
+            CheckpointError::ConfigMismatch { expected, found } => write!(
+                f,
+                "checkpoint config does not match the model\nexpected: {}\nfound: {}",
+                expected, found
+            ),
+            CheckpointError::NotFound(path) => {
+                write!(f, "no checkpoint weights in {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for CheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+impl From<ConfigError> for CheckpointError {
+    fn from(err: ConfigError) -> Self {
+        CheckpointError::Config(err)
+    }
+}
+
+impl From<RecorderError> for CheckpointError {
+    fn from(err: RecorderError) -> Self {
+        CheckpointError::Record(err)
+    }
+}
+
+// Writes the config next to the weights, so a checkpoint directory is self-describing. Weights of
+// other precisions left by an earlier save are removed, otherwise `load_checkpoint` could pick
+// them over the ones just written.
+pub(crate) fn save_checkpoint<B: Backend>(
+    model: &TransformerModel<B>,
+    config: &TransformerModelConfig,
+    dir: impl AsRef<Path>,
+    precision: Precision,
+) -> Result<(), CheckpointError> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    config.save(dir.join(CONFIG_FILE))?;
+
+    let path = dir.join(precision.weights_file());
+    match precision {
+        Precision::Full | Precision::Int8 => model
+            .clone()
+            .save_file(path, &NamedMpkFileRecorder::<FullPrecisionSettings>::new())?,
+        Precision::Half => model
+            .clone()
+            .save_file(path, &NamedMpkFileRecorder::<HalfPrecisionSettings>::new())?,
+    }
+
+    for other in Precision::ALL
+        .into_iter()
+        .filter(|&other| other != precision)
+    {
+        let stale = dir.join(other.weights_file()).with_extension("mpk");
+        match std::fs::remove_file(stale) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn load_checkpoint_config(
+    dir: impl AsRef<Path>,
+) -> Result<TransformerModelConfig, CheckpointError> {
+    Ok(TransformerModelConfig::load(
+        dir.as_ref().join(CONFIG_FILE),
+    )?)
+}
+
+// Fails with `ConfigMismatch` instead of silently loading weights into the wrong architecture.
+// Full-precision weights are preferred when several are present.
+pub(crate) fn load_checkpoint<B: Backend>(
+    dir: impl AsRef<Path>,
+    config: &TransformerModelConfig,
+    device: &B::Device,
+) -> Result<TransformerModel<B>, CheckpointError> {
+    let dir = dir.as_ref();
+    let stored = load_checkpoint_config(dir)?;
+    // Configs compare through their JSON form, which covers every field
+    let (expected, found) = (config.to_string(), stored.to_string());
+    if expected != found {
+        return Err(CheckpointError::ConfigMismatch { expected, found });
+    }
+
+    let model = config.init::<B>(device);
+    let Some(precision) = Precision::ALL.into_iter().find(|precision| {
+        dir.join(precision.weights_file())
+            .with_extension("mpk")
+            .exists()
+    }) else {
+        return Err(CheckpointError::NotFound(dir.to_path_buf()));
+    };
+    let path = dir.join(precision.weights_file());
+    match precision {
+        // Quantized weights come back quantized, whatever the model was initialised with
+        Precision::Full | Precision::Int8 => Ok(model.load_file(
+            path,
+            &NamedMpkFileRecorder::<FullPrecisionSettings>::new(),
+            device,
+        )?),
+        Precision::Half => Ok(model.load_file(
+            path,
+            &NamedMpkFileRecorder::<HalfPrecisionSettings>::new(),
+            device,
+        )?),
+    }
+}
+
+#[derive(Config, Debug)]
+pub(crate) struct CheckpointMetadata {
+    pub(crate) name: String,
+    // Assigned by the registry when the checkpoint is saved
+    #[config(default = 0)]
+    pub(crate) version: usize,
+    #[config(default = 0)]
+    pub(crate) training_steps: usize,
+    pub(crate) elo: Option<f64>,
+    // "name/version" of the checkpoint this one was trained from
+    pub(crate) parent: Option<String>,
+}
+
+// A model name has to be a single directory below the registry root, so it cannot be empty,
+// absolute, `.` or `..`, or hold a path separator
+pub(crate) fn check_model_name(name: &str) -> io::Result<()> {
+    let single = matches!(
+        Path::new(name).components().collect::<Vec<_>>()[..],
+        [Component::Normal(_)]
+    );
+    match single && !name.contains(['/', '\\']) {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid model name '{}'", name),
+        )),
+    }
+}
+
+// Checkpoints live in `root/name/0001`, `root/name/0002`, ... each with its config, metadata and
+// weights
+pub(crate) struct ModelRegistry {
+    root: PathBuf,
+}
+
+impl ModelRegistry {
+    pub(crate) fn open(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    // Fails on a name `check_model_name` rejects
+    pub(crate) fn path(&self, name: &str, version: usize) -> io::Result<PathBuf> {
+        Ok(self.dir(name)?.join(format!("{:04}", version)))
+    }
+
+    fn dir(&self, name: &str) -> io::Result<PathBuf> {
+        check_model_name(name)?;
+        Ok(self.root.join(name))
+    }
+
+    pub(crate) fn names(&self) -> io::Result<Vec<String>> {
+        let mut names: Vec<String> = std::fs::read_dir(&self.root)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    // Ascending version numbers of every checkpoint saved under `name`
+    pub(crate) fn versions(&self, name: &str) -> io::Result<Vec<usize>> {
+        let dir = self.dir(name)?;
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut versions: Vec<usize> = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().join(METADATA_FILE).exists())
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect();
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    pub(crate) fn latest(&self, name: &str) -> io::Result<Option<usize>> {
+        Ok(self.versions(name)?.last().copied())
+    }
+
+    // Saves the model as the next version of `metadata.name` and returns the stored metadata
+    pub(crate) fn save<B: Backend>(
+        &self,
+        model: &TransformerModel<B>,
+        config: &TransformerModelConfig,
+        mut metadata: CheckpointMetadata,
+        precision: Precision,
+    ) -> Result<CheckpointMetadata, CheckpointError> {
+        metadata.version = self
+            .latest(&metadata.name)?
+            .map_or(1, |version| version + 1);
+        let dir = self.path(&metadata.name, metadata.version)?;
+        save_checkpoint(model, config, &dir, precision)?;
+        // Written last, a version only counts once its weights are on disk
+        metadata.save(dir.join(METADATA_FILE))?;
+        Ok(metadata)
+    }
+
+    pub(crate) fn metadata(
+        &self,
+        name: &str,
+        version: usize,
+    ) -> Result<CheckpointMetadata, CheckpointError> {
+        Ok(CheckpointMetadata::load(
+            self.path(name, version)?.join(METADATA_FILE),
+        )?)
+    }
+
+    // Metadata of every checkpoint, grouped by name and ordered by version
+    pub(crate) fn list(&self) -> Result<Vec<CheckpointMetadata>, CheckpointError> {
+        let mut checkpoints = Vec::new();
+        for name in self.names()? {
+            for version in self.versions(&name)? {
+                checkpoints.push(self.metadata(&name, version)?);
+            }
+        }
+        Ok(checkpoints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chess::State, engine::policy::legal_move_masks};
+    use burn::backend::NdArray;
+
+    fn tiny_config() -> TransformerModelConfig {
+        TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+    }
+
+    fn value(model: &TransformerModel<NdArray>) -> f32 {
+        let device = Default::default();
+        let state = State::new();
+        let mask = legal_move_masks::<NdArray>(&[&state], &device);
+        model.forward(&[&state], mask, &device).value.into_scalar()
+    }
+
+    #[test]
+    fn test_registry_round_trip() {
+        let root = std::env::temp_dir().join(format!("chess-ai-registry-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let registry = ModelRegistry::open(&root).unwrap();
+        let device = Default::default();
+        let config = tiny_config();
+        let model = config.init::<NdArray>(&device);
+
+        let first = registry
+            .save(
+                &model,
+                &config,
+                CheckpointMetadata::new("tiny".to_string()),
+                Precision::Full,
+            )
+            .unwrap();
+        let second = registry
+            .save(
+                &model,
+                &config,
+                CheckpointMetadata::new("tiny".to_string())
+                    .with_training_steps(1000)
+                    .with_elo(Some(1500.0))
+                    .with_parent(Some("tiny/0001".to_string())),
+                Precision::Half,
+            )
+            .unwrap();
+        assert_eq!((first.version, second.version), (1, 2));
+        assert_eq!(registry.latest("tiny").unwrap(), Some(2));
+
+        let listed = registry.list().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[1].elo, Some(1500.0));
+        assert_eq!(listed[1].parent.as_deref(), Some("tiny/0001"));
+
+        let full = load_checkpoint::<NdArray>(registry.path("tiny", 1).unwrap(), &config, &device)
+            .unwrap();
+        assert_eq!(value(&full), value(&model));
+        let half = load_checkpoint::<NdArray>(registry.path("tiny", 2).unwrap(), &config, &device)
+            .unwrap();
+        assert!((value(&half) - value(&model)).abs() < 1e-2);
+
+        // A different architecture is rejected before any weights are read
+        let wider = tiny_config().with_head_dimension(16);
+        let err = load_checkpoint::<NdArray>(registry.path("tiny", 1).unwrap(), &wider, &device)
+            .unwrap_err();
+        assert!(matches!(err, CheckpointError::ConfigMismatch { .. }));
+        assert!(
+            load_checkpoint::<NdArray>(registry.path("tiny", 3).unwrap(), &config, &device)
+                .is_err()
+        );
+
+        // Names outside a single directory below the root
+        for name in ["", ".", "..", "../tiny", "tiny/0001", "a\\b", "/tmp/tiny"] {
+            let metadata = CheckpointMetadata::new(name.to_string());
+            let err = registry.save(&model, &config, metadata, Precision::Full);
+            assert!(err.unwrap_err().to_string().contains("invalid model name"));
+            assert!(registry.versions(name).is_err());
+        }
+        assert!(check_model_name("tiny-v2.1").is_ok());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_resave_replaces_other_precision() {
+        let dir = std::env::temp_dir().join(format!("chess-ai-resave-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let device = Default::default();
+        let config = tiny_config();
+        let old = config.init::<NdArray>(&device);
+        let new = config.init::<NdArray>(&device);
+        assert_ne!(value(&old), value(&new));
+
+        // The half-precision save must not be shadowed by the full-precision one before it
+        save_checkpoint(&old, &config, &dir, Precision::Full).unwrap();
+        save_checkpoint(&new, &config, &dir, Precision::Half).unwrap();
+        assert!(!dir.join(FULL_WEIGHTS).with_extension("mpk").exists());
+        let loaded = load_checkpoint::<NdArray>(&dir, &config, &device).unwrap();
+        let error = |model| (value(&loaded) - value(model)).abs();
+        assert!(error(&new) < 1e-2 && error(&new) < error(&old));
+
+        save_checkpoint(&old, &config, &dir, Precision::Full).unwrap();
+        assert!(!dir.join(HALF_WEIGHTS).with_extension("mpk").exists());
+        let loaded = load_checkpoint::<NdArray>(&dir, &config, &device).unwrap();
+        assert_eq!(value(&loaded), value(&old));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{
+    chess::State,
+    engine::{
+        model::TransformerModel,
+        policy::legal_move_masks,
+        training::{kl_divergence, label_targets, policy_loss, value_loss, TrainingPosition},
+    },
+};
+use burn::{
+    config::Config,
+    module::AutodiffModule,
+    optim::{AdamConfig, GradientsParams, Optimizer},
+    tensor::{
+        backend::{AutodiffBackend, Backend},
+        ElementConversion,
+    },
+    Tensor,
+};
+
+// Trains a small student to reproduce a large teacher: KL divergence to the teacher's policy plus
+// regression on its value, optionally mixed with the played moves and game results
+#[derive(Config, Debug)]
+pub(crate) struct DistillationConfig {
+    #[config(default = 1.0)]
+    policy_weight: f32,
+    #[config(default = 1.0)]
+    value_weight: f32,
+    // Share of the loss taken from the ground-truth labels instead of the teacher
+    #[config(default = 0.0)]
+    label_weight: f32,
+    // Softens (> 1) or sharpens (< 1) the teacher's policy before it is used as the target
+    #[config(default = 1.0)]
+    temperature: f32,
+    #[config(default = 64)]
+    batch_size: usize,
+    #[config(default = 1e-3)]
+    learning_rate: f64,
+    #[config(default = 1)]
+    epochs: usize,
+    #[config(default = "AdamConfig::new()")]
+    optimizer: AdamConfig,
+}
+
+impl DistillationConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.batch_size == 0 || self.epochs == 0 {
+            return Err("batch_size and epochs must be at least 1".to_string());
+        }
+        if !(self.temperature > 0.0 && self.temperature.is_finite()) {
+            return Err(format!(
+                "temperature must be positive, got {}",
+                self.temperature
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.label_weight) {
+            return Err(format!(
+                "label_weight must be in [0, 1], got {}",
+                self.label_weight
+            ));
+        }
+        match self.learning_rate > 0.0 {
+            true => Ok(()),
+            false => Err(format!(
+                "learning_rate must be positive, got {}",
+                self.learning_rate
+            )),
+        }
+    }
+
+    // `progress` hears the epoch number and the mean loss over its batches after each epoch
+    pub(crate) fn distill<B: AutodiffBackend>(
+        &self,
+        teacher: &TransformerModel<B::InnerBackend>,
+        mut student: TransformerModel<B>,
+        positions: &[TrainingPosition],
+        device: &B::Device,
+        mut progress: impl FnMut(usize, f64),
+    ) -> TransformerModel<B> {
+        let mut optimizer = self.optimizer.init::<B, TransformerModel<B>>();
+
+        for epoch in 0..self.epochs {
+            let (mut total, mut batches) = (0.0, 0);
+            for chunk in positions.chunks(self.batch_size.max(1)) {
+                let batch: Vec<&TrainingPosition> = chunk.iter().collect();
+                let loss = self.loss(teacher, &student, &batch, device);
+                total += loss.clone().into_scalar().elem::<f64>();
+                batches += 1;
+
+                let grads = GradientsParams::from_grads(loss.backward(), &student);
+                student = optimizer.step(self.learning_rate, student, grads);
+            }
+            progress(epoch + 1, total / batches.max(1) as f64);
+        }
+
+        student
+    }
+
+    pub(crate) fn loss<B: AutodiffBackend>(
+        &self,
+        teacher: &TransformerModel<B::InnerBackend>,
+        student: &TransformerModel<B>,
+        batch: &[&TrainingPosition],
+        device: &B::Device,
+    ) -> Tensor<B, 1> {
+        let states: Vec<&State> = batch.iter().map(|position| &position.state).collect();
+        let mask = legal_move_masks::<B>(&states, device);
+
+        // The teacher runs without autodiff, its outputs are constants for the student
+        let target = teacher.forward(&states, mask.clone().inner(), device);
+        let teacher_policy = Tensor::<B, 2>::from_inner(self.soften(target.policy));
+        let teacher_value = Tensor::<B, 2>::from_inner(target.value);
+
+        let output = student.forward(&states, mask, device);
+        let mut loss = (kl_divergence(output.policy.clone(), teacher_policy.clone())
+            .mul_scalar(self.policy_weight)
+            + value_loss(output.value.clone(), teacher_value.clone())
+                .mul_scalar(self.value_weight))
+        .mul_scalar(1.0 - self.label_weight);
+
+        if self.label_weight > 0.0 {
+            let (policy, value) = label_targets(batch, (teacher_policy, teacher_value), device);
+            loss = loss
+                + (policy_loss(output.policy, policy).mul_scalar(self.policy_weight)
+                    + value_loss(output.value, value).mul_scalar(self.value_weight))
+                .mul_scalar(self.label_weight);
+        }
+
+        loss
+    }
+
+    fn soften<B: Backend>(&self, policy: Tensor<B, 2>) -> Tensor<B, 2> {
+        if self.temperature == 1.0 {
+            return policy;
+        }
+        let policy = policy.powf_scalar(1.0 / self.temperature);
+        let total = policy.clone().sum_dim(1);
+        policy / total
+    }
+}
+
+// The student as a plain inference model, e.g. to save it as a checkpoint
+pub(crate) fn into_inference<B: AutodiffBackend>(
+    student: TransformerModel<B>,
+) -> TransformerModel<B::InnerBackend> {
+    student.valid()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::model::TransformerModelConfig;
+    use burn::{
+        backend::{Autodiff, NdArray},
+        module::Module,
+    };
+
+    type B = Autodiff<NdArray>;
+
+    #[test]
+    fn test_student_moves_towards_teacher() {
+        let device = Default::default();
+        let teacher = TransformerModelConfig::new()
+            .with_n_blocks(2)
+            .with_n_heads(2)
+            .with_head_dimension(16)
+            .init::<NdArray>(&device);
+        let student = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .with_d_ff_scale(1.0)
+            .init::<B>(&device);
+        assert!(student.num_params() < teacher.num_params());
+
+        let mut positions = vec![TrainingPosition::new(State::new())];
+        for uci in ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6"] {
+            let mut state = positions.last().unwrap().state.clone();
+            let played = state.parse_uci(uci).unwrap();
+            let previous = positions.pop().unwrap();
+            positions.push(previous.with_played(played).with_result(0.0));
+            state.make_move(played);
+            positions.push(TrainingPosition::new(state));
+        }
+        let batch: Vec<&TrainingPosition> = positions.iter().collect();
+
+        let config = DistillationConfig::new()
+            .with_epochs(5)
+            .with_learning_rate(3e-3);
+        let before: f32 = config
+            .loss(&teacher, &student, &batch, &device)
+            .into_scalar()
+            .elem();
+        let mut epochs = 0;
+        let student = config.distill(&teacher, student, &positions, &device, |_, _| epochs += 1);
+        assert_eq!(epochs, 5);
+        let after: f32 = config
+            .loss(&teacher, &student, &batch, &device)
+            .into_scalar()
+            .elem();
+        assert!(after < before, "{} -> {}", before, after);
+        assert!(after >= 0.0);
+
+        // Temperatures that would divide by zero and label weights outside [0, 1] are refused
+        assert!(config.validate().is_ok());
+        assert!(config.clone().with_temperature(0.0).validate().is_err());
+        assert!(config
+            .clone()
+            .with_temperature(f32::NAN)
+            .validate()
+            .is_err());
+        assert!(config.clone().with_label_weight(1.5).validate().is_err());
+        assert!(config.clone().with_label_weight(-0.5).validate().is_err());
+
+        // Mixing in the labels keeps the loss finite with unlabelled positions in the batch
+        let mixed = config
+            .with_label_weight(0.5)
+            .with_temperature(2.0)
+            .loss(&teacher, &student, &batch, &device);
+        assert!(mixed.into_scalar().elem::<f32>().is_finite());
+
+        let inference = into_inference(student);
+        let state = &positions[0].state;
+        let output = inference.forward(&[state], legal_move_masks(&[state], &device), &device);
+        assert!(output.value.into_scalar().is_finite());
+    }
+}
@@ -0,0 +1,583 @@
+use crate::chess::{
+    bitmask::Bitmask,
+    types::{Color, Direction, Piece, Position},
+    State,
+};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+// Piece kinds follow the `Piece` discriminant order: pawn, rook, knight, bishop, queen, king
+const N_PIECE_KINDS: usize = 6;
+const ROOK: usize = 1;
+const KNIGHT: usize = 2;
+const BISHOP: usize = 3;
+const QUEEN: usize = 4;
+
+const PHASE_WEIGHTS: [i32; N_PIECE_KINDS] = [0, 2, 1, 1, 4, 0];
+const MAX_PHASE: i32 = 24;
+
+// Centipawn scale mapping evaluations onto the value head's [-1, 1] range
+const VALUE_SCALE: f32 = 400.0;
+
+pub(crate) trait Evaluate {
+    // Centipawns from the side to move's perspective
+    fn evaluate(&self, state: &State) -> i32;
+
+    fn value(&self, state: &State) -> f32 {
+        (self.evaluate(state) as f32 / VALUE_SCALE).tanh()
+    }
+}
+
+// Middlegame and endgame halves of a term, blended by game phase
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Score {
+    pub(crate) mg: i32,
+    pub(crate) eg: i32,
+}
+
+impl Score {
+    pub(crate) const ZERO: Self = Self::new(0, 0);
+
+    pub(crate) const fn new(mg: i32, eg: i32) -> Self {
+        Self { mg, eg }
+    }
+
+    pub(crate) const fn taper(self, phase: i32) -> i32 {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score {
+    type Output = Self;
+    #[inline]
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.mg + rhs.mg, self.eg + rhs.eg)
+    }
+}
+
+impl AddAssign for Score {
+    #[inline]
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Score {
+    type Output = Self;
+    #[inline]
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.mg - rhs.mg, self.eg - rhs.eg)
+    }
+}
+
+impl SubAssign for Score {
+    #[inline]
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl Neg for Score {
+    type Output = Self;
+    #[inline]
+    fn neg(self) -> Self {
+        Self::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Self;
+    #[inline]
+    fn mul(self, rhs: i32) -> Self {
+        Self::new(self.mg * rhs, self.eg * rhs)
+    }
+}
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "S({}, {})", self.mg, self.eg)
+    }
+}
+
+impl std::fmt::Debug for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+// Every weight of the evaluation, so the tuner can treat them as one flat parameter vector
+#[derive(Clone)]
+pub(crate) struct EvalParams {
+    pub(crate) material: [Score; N_PIECE_KINDS],
+    // Indexed from White's point of view with A1 = 0, Black reads the vertically mirrored square
+    pub(crate) pst: [[Score; 64]; N_PIECE_KINDS],
+    // Per reachable square not covered by enemy pawns
+    pub(crate) mobility: [Score; N_PIECE_KINDS],
+    pub(crate) bishop_pair: Score,
+    pub(crate) doubled_pawn: Score,
+    pub(crate) isolated_pawn: Score,
+    // Indexed by rank relative to the pawn's owner
+    pub(crate) passed_pawn: [Score; 8],
+    pub(crate) king_shield: Score,
+    pub(crate) king_open_file: Score,
+    // Per attacked square around the enemy king
+    pub(crate) king_attack: [Score; N_PIECE_KINDS],
+    pub(crate) tempo: Score,
+}
+
+impl EvalParams {
+    pub(crate) fn new() -> Self {
+        let mut pst = [[Score::ZERO; 64]; N_PIECE_KINDS];
+        for (kind, table) in pst.iter_mut().enumerate() {
+            for (i, score) in table.iter_mut().enumerate() {
+                // The source tables are laid out rank 8 first
+                *score = Score::new(MG_PST[kind][i ^ 56], EG_PST[kind][i ^ 56]);
+            }
+        }
+
+        Self {
+            material: [
+                Score::new(82, 94),
+                Score::new(477, 512),
+                Score::new(337, 281),
+                Score::new(365, 297),
+                Score::new(1025, 936),
+                Score::ZERO,
+            ],
+            pst,
+            mobility: [
+                Score::ZERO,
+                Score::new(2, 4),
+                Score::new(4, 4),
+                Score::new(5, 5),
+                Score::new(1, 2),
+                Score::ZERO,
+            ],
+            bishop_pair: Score::new(30, 50),
+            doubled_pawn: Score::new(-10, -20),
+            isolated_pawn: Score::new(-12, -8),
+            passed_pawn: [
+                Score::ZERO,
+                Score::new(0, 10),
+                Score::new(5, 15),
+                Score::new(10, 25),
+                Score::new(20, 45),
+                Score::new(35, 75),
+                Score::new(60, 120),
+                Score::ZERO,
+            ],
+            king_shield: Score::new(12, 0),
+            king_open_file: Score::new(-20, 0),
+            king_attack: [
+                Score::ZERO,
+                Score::new(8, 0),
+                Score::new(6, 0),
+                Score::new(5, 0),
+                Score::new(10, 0),
+                Score::ZERO,
+            ],
+            tempo: Score::new(15, 5),
+        }
+    }
+
+    pub(crate) fn scores_mut(&mut self) -> impl Iterator<Item = &mut Score> {
+        self.material
+            .iter_mut()
+            .chain(self.pst.iter_mut().flatten())
+            .chain(self.mobility.iter_mut())
+            .chain([
+                &mut self.bishop_pair,
+                &mut self.doubled_pawn,
+                &mut self.isolated_pawn,
+            ])
+            .chain(self.passed_pawn.iter_mut())
+            .chain([&mut self.king_shield, &mut self.king_open_file])
+            .chain(self.king_attack.iter_mut())
+            .chain([&mut self.tempo])
+    }
+
+    // Flattened as [mg, eg] pairs in `scores_mut` order
+    pub(crate) fn to_vec(&self) -> Vec<i32> {
+        self.clone()
+            .scores_mut()
+            .flat_map(|score| [score.mg, score.eg])
+            .collect()
+    }
+
+    pub(crate) fn from_slice(values: &[i32]) -> Self {
+        let mut params = Self::new();
+        for (score, pair) in params.scores_mut().zip(values.chunks_exact(2)) {
+            *score = Score::new(pair[0], pair[1]);
+        }
+        params
+    }
+}
+
+pub(crate) struct HandcraftedEval {
+    pub(crate) params: EvalParams,
+}
+
+impl HandcraftedEval {
+    pub(crate) fn new() -> Self {
+        Self::with_params(EvalParams::new())
+    }
+
+    pub(crate) const fn with_params(params: EvalParams) -> Self {
+        Self { params }
+    }
+
+    // Tapered score from White's point of view, before the side to move is applied
+    pub(crate) fn evaluate_white(&self, state: &State) -> i32 {
+        let board = &state.board;
+        let mut score = Score::ZERO;
+        let mut phase = 0;
+
+        for color in [Color::White, Color::Black] {
+            let side = self.evaluate_side(state, color);
+            if color == Color::White {
+                score += side;
+            } else {
+                score -= side;
+            }
+
+            for (kind, weight) in PHASE_WEIGHTS.iter().enumerate() {
+                phase += weight * board.pieces[kind + color as usize * 6].count() as i32;
+            }
+        }
+
+        score += if state.turn == Color::White {
+            self.params.tempo
+        } else {
+            -self.params.tempo
+        };
+        score.taper(phase.min(MAX_PHASE))
+    }
+
+    fn evaluate_side(&self, state: &State, color: Color) -> Score {
+        let params = &self.params;
+        let board = &state.board;
+        let opponent = color.flip();
+        let own = board.colors[color];
+        let pawns = board.pieces[Piece::pawn(color)];
+        let opponent_pawns = board.pieces[Piece::pawn(opponent)];
+        let opponent_king = board.pieces[Piece::king(opponent)].lsb();
+        let king_zone = Bitmask::KING_ATTACK_MASKS[opponent_king] | opponent_king.mask();
+
+        let opponent_pawn_attacks = opponent_pawns
+            .map(|p| Bitmask::PAWN_ATTACK_MASKS[opponent][p])
+            .fold(Bitmask::EMPTY, |acc, attack| acc | attack);
+        let mobility_area = !own & !opponent_pawn_attacks;
+
+        let mut score = Score::ZERO;
+
+        // Material, placement, mobility and pressure on the enemy king
+        for kind in 0..N_PIECE_KINDS {
+            for position in board.pieces[kind + color as usize * 6] {
+                let square = relative_square(color, position);
+                score += params.material[kind] + params.pst[kind][square.0 as usize];
+
+                let attacks = match kind {
+                    KNIGHT => Bitmask::KNIGHT_ATTACK_MASKS[position],
+                    BISHOP => Bitmask::bishop_attack_mask(position, board.occupancy),
+                    ROOK => Bitmask::rook_attack_mask(position, board.occupancy),
+                    QUEEN => Bitmask::queen_attack_mask(position, board.occupancy),
+                    _ => continue,
+                };
+                score += params.mobility[kind] * (attacks & mobility_area).count() as i32;
+                score += params.king_attack[kind] * (attacks & king_zone).count() as i32;
+            }
+        }
+
+        if board.pieces[Piece::bishop(color)].count() >= 2 {
+            score += params.bishop_pair;
+        }
+
+        // Pawn structure
+        for file in 0..8 {
+            let on_file = (pawns & Bitmask::FILES[file]).count() as i32;
+            if on_file == 0 {
+                continue;
+            }
+            if on_file > 1 {
+                score += params.doubled_pawn * (on_file - 1);
+            }
+            if pawns & adjacent_files(file) == Bitmask::EMPTY {
+                score += params.isolated_pawn * on_file;
+            }
+        }
+        for position in pawns {
+            if forward_span(color, position) & opponent_pawns == Bitmask::EMPTY {
+                score += params.passed_pawn[relative_square(color, position).rank() as usize];
+            }
+        }
+
+        // King shelter
+        let king = board.pieces[Piece::king(color)].lsb();
+        let shield_ranks = match relative_square(color, king).rank() {
+            rank @ 0..=5 => {
+                let next = relative_rank(color, rank + 1);
+                let after = relative_rank(color, rank + 2);
+                Bitmask::RANKS[next] | Bitmask::RANKS[after]
+            }
+            rank @ 6 => Bitmask::RANKS[relative_rank(color, rank + 1)],
+            _ => Bitmask::EMPTY,
+        };
+        score +=
+            params.king_shield * (forward_span(color, king) & shield_ranks & pawns).count() as i32;
+        let king_files =
+            Bitmask::FILES[king.file() as usize] | adjacent_files(king.file() as usize);
+        for file in 0..8 {
+            if king_files & Bitmask::FILES[file] != Bitmask::EMPTY
+                && pawns & Bitmask::FILES[file] == Bitmask::EMPTY
+            {
+                score += params.king_open_file;
+            }
+        }
+
+        score
+    }
+}
+
+impl Evaluate for HandcraftedEval {
+    fn evaluate(&self, state: &State) -> i32 {
+        let score = self.evaluate_white(state);
+        match state.turn {
+            Color::White => score,
+            Color::Black => -score,
+        }
+    }
+}
+
+const fn relative_square(color: Color, position: Position) -> Position {
+    match color {
+        Color::White => position,
+        Color::Black => Position(position.0 ^ 56),
+    }
+}
+
+const fn relative_rank(color: Color, rank: u8) -> usize {
+    match color {
+        Color::White => rank as usize,
+        Color::Black => 7 - rank as usize,
+    }
+}
+
+fn adjacent_files(file: usize) -> Bitmask {
+    let west = if file > 0 {
+        Bitmask::FILES[file - 1]
+    } else {
+        Bitmask::EMPTY
+    };
+    let east = if file < 7 {
+        Bitmask::FILES[file + 1]
+    } else {
+        Bitmask::EMPTY
+    };
+    west | east
+}
+
+// Squares ahead of `position` on its own and both neighbouring files
+fn forward_span(color: Color, position: Position) -> Bitmask {
+    let direction = match color {
+        Color::White => Direction::North,
+        Color::Black => Direction::South,
+    };
+    let ahead = Bitmask::RAYS[direction][position];
+    let mut span = ahead;
+    if position.file() > 0 {
+        span |= Bitmask::RAYS[direction][position.offset_unchecked(-1)];
+    }
+    if position.file() < 7 {
+        span |= Bitmask::RAYS[direction][position.offset_unchecked(1)];
+    }
+    span
+}
+
+// PeSTO piece-square tables, rank 8 first, in `Piece` kind order
+#[rustfmt::skip]
+const MG_PST: [[i32; 64]; N_PIECE_KINDS] = [
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+         98, 134,  61,  95,  68, 126,  34, -11,
+         -6,   7,  26,  31,  65,  56,  25, -20,
+        -14,  13,   6,  21,  23,  12,  17, -23,
+        -27,  -2,  -5,  12,  17,   6,  10, -25,
+        -26,  -4,  -4, -10,   3,   3,  33, -12,
+        -35,  -1, -20, -23, -15,  24,  38, -22,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [
+         32,  42,  32,  51,  63,   9,  31,  43,
+         27,  32,  58,  62,  80,  67,  26,  44,
+         -5,  19,  26,  36,  17,  45,  61,  16,
+        -24, -11,   7,  26,  24,  35,  -8, -20,
+        -36, -26, -12,  -1,   9,  -7,   6, -23,
+        -45, -25, -16, -17,   3,   0,  -5, -33,
+        -44, -16, -20,  -9,  -1,  11,  -6, -71,
+        -19, -13,   1,  17,  16,   7, -37, -26,
+    ],
+    [
+       -167, -89, -34, -49,  61, -97, -15,-107,
+        -73, -41,  72,  36,  23,  62,   7, -17,
+        -47,  60,  37,  65,  84, 129,  73,  44,
+         -9,  17,  19,  53,  37,  69,  18,  22,
+        -13,   4,  16,  13,  28,  19,  21,  -8,
+        -23,  -9,  12,  10,  19,  17,  25, -16,
+        -29, -53, -12,  -3,  -1,  18, -14, -19,
+       -105, -21, -58, -33, -17, -28, -19, -23,
+    ],
+    [
+        -29,   4, -82, -37, -25, -42,   7,  -8,
+        -26,  16, -18, -13,  30,  59,  18, -47,
+        -16,  37,  43,  40,  35,  50,  37,  -2,
+         -4,   5,  19,  50,  37,  37,   7,  -2,
+         -6,  13,  13,  26,  34,  12,  10,   4,
+          0,  15,  15,  15,  14,  27,  18,  10,
+          4,  15,  16,   0,   7,  21,  33,   1,
+        -33,  -3, -14, -21, -13, -12, -39, -21,
+    ],
+    [
+        -28,   0,  29,  12,  59,  44,  43,  45,
+        -24, -39,  -5,   1, -16,  57,  28,  54,
+        -13, -17,   7,   8,  29,  56,  47,  57,
+        -27, -27, -16, -16,  -1,  17,  -2,   1,
+         -9, -26,  -9, -10,  -2,  -4,   3,  -3,
+        -14,   2, -11,  -2,  -5,   2,  14,   5,
+        -35,  -8,  11,   2,   8,  15,  -3,   1,
+         -1, -18,  -9,  10, -15, -25, -31, -50,
+    ],
+    [
+        -65,  23,  16, -15, -56, -34,   2,  13,
+         29,  -1, -20,  -7,  -8,  -4, -38, -29,
+         -9,  24,   2, -16, -20,   6,  22, -22,
+        -17, -20, -12, -27, -30, -25, -14, -36,
+        -49,  -1, -27, -39, -46, -44, -33, -51,
+        -14, -14, -22, -46, -44, -30, -15, -27,
+          1,   7,  -8, -64, -43, -16,   9,   8,
+        -15,  36,  12, -54,   8, -28,  24,  14,
+    ],
+];
+
+#[rustfmt::skip]
+const EG_PST: [[i32; 64]; N_PIECE_KINDS] = [
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+        178, 173, 158, 134, 147, 132, 165, 187,
+         94, 100,  85,  67,  56,  53,  82,  84,
+         32,  24,  13,   5,  -2,   4,  17,  17,
+         13,   9,  -3,  -7,  -7,  -8,   3,  -1,
+          4,   7,  -6,   1,   0,  -5,  -1,  -8,
+         13,   8,   8,  10,  13,   0,   2,  -7,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [
+         13,  10,  18,  15,  12,  12,   8,   5,
+         11,  13,  13,  11,  -3,   3,   8,   3,
+          7,   7,   7,   5,   4,  -3,  -5,  -3,
+          4,   3,  13,   1,   2,   1,  -1,   2,
+          3,   5,   8,   4,  -5,  -6,  -8, -11,
+         -4,   0,  -5,  -1,  -7, -12,  -8, -16,
+         -6,  -6,   0,   2,  -9,  -9, -11,  -3,
+         -9,   2,   3,  -1,  -5, -13,   4, -20,
+    ],
+    [
+        -58, -38, -13, -28, -31, -27, -63, -99,
+        -25,  -8, -25,  -2,  -9, -25, -24, -52,
+        -24, -20,  10,   9,  -1,  -9, -19, -41,
+        -17,   3,  22,  22,  22,  11,   8, -18,
+        -18,  -6,  16,  25,  16,  17,   4, -18,
+        -23,  -3,  -1,  15,  10,  -3, -20, -22,
+        -42, -20, -10,  -5,  -2, -20, -23, -44,
+        -29, -51, -23, -15, -22, -18, -50, -64,
+    ],
+    [
+        -14, -21, -11,  -8,  -7,  -9, -17, -24,
+         -8,  -4,   7, -12,  -3, -13,  -4, -14,
+          2,  -8,   0,  -1,  -2,   6,   0,   4,
+         -3,   9,  12,   9,  14,  10,   3,   2,
+         -6,   3,  13,  19,   7,  10,  -3,  -9,
+        -12,  -3,   8,  10,  13,   3,  -7, -15,
+        -14, -18,  -7,  -1,   4,  -9, -15, -27,
+        -23,  -9, -23,  -5,  -9, -16,  -5, -17,
+    ],
+    [
+         -9,  22,  22,  27,  27,  19,  10,  20,
+        -17,  20,  32,  41,  58,  25,  30,   0,
+        -20,   6,   9,  49,  47,  35,  19,   9,
+          3,  22,  24,  45,  57,  40,  57,  36,
+        -18,  28,  19,  47,  31,  34,  39,  23,
+        -16, -27,  15,   6,   9,  17,  10,   5,
+        -22, -23, -30, -16, -16, -23, -36, -32,
+        -33, -28, -22, -43,  -5, -32, -20, -41,
+    ],
+    [
+        -74, -35, -18, -18, -11,  15,   4, -17,
+        -12,  17,  14,  17,  17,  38,  23,  11,
+         10,  17,  23,  15,  20,  45,  44,  13,
+         -8,  22,  24,  27,  26,  33,  26,   3,
+        -18,  -4,  21,  24,  27,  23,   9, -11,
+        -19,  -3,  11,  21,  23,  16,   7,  -9,
+        -27, -11,   4,  13,  14,   4,  -5, -17,
+        -53, -34, -21, -11, -28, -14, -24, -43,
+    ],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluation_is_color_symmetric() {
+        let pairs = [
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b KQkq - 0 1",
+            ),
+            (
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                "8/4p1p1/8/1r3P1K/kp5R/3P4/2P5/8 b - - 0 1",
+            ),
+        ];
+
+        let eval = HandcraftedEval::new();
+        for (fen, mirrored) in pairs {
+            let state = State::from_fen(fen).unwrap();
+            let mirrored = State::from_fen(mirrored).unwrap();
+            assert_eq!(eval.evaluate(&state), eval.evaluate(&mirrored), "{}", fen);
+        }
+
+        let start = State::new();
+        assert_eq!(eval.evaluate(&start), eval.params.tempo.taper(MAX_PHASE));
+    }
+
+    #[test]
+    fn test_evaluation_terms() {
+        let eval = HandcraftedEval::new();
+
+        let up_a_queen = State::from_fen("3qk3/8/8/8/8/8/8/3QK1Q1 w - - 0 1").unwrap();
+        assert!(eval.evaluate(&up_a_queen) > 800);
+        assert!(eval.value(&up_a_queen) > 0.9);
+
+        // Same material, but the passed pawn is further advanced
+        let passed = State::from_fen("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let behind = State::from_fen("4k3/8/8/8/8/1P6/8/4K3 w - - 0 1").unwrap();
+        assert!(eval.evaluate(&passed) > eval.evaluate(&behind));
+
+        let healthy = State::from_fen("4k3/8/8/8/8/8/PP6/4K3 w - - 0 1").unwrap();
+        let doubled = State::from_fen("4k3/8/8/8/8/P7/P7/4K3 w - - 0 1").unwrap();
+        assert!(eval.evaluate(&healthy) > eval.evaluate(&doubled));
+    }
+
+    #[test]
+    fn test_params_round_trip() {
+        let params = EvalParams::new();
+        let values = params.to_vec();
+        assert_eq!(values.len(), 2 * (6 + 6 * 64 + 6 + 3 + 8 + 2 + 6 + 1));
+
+        let restored = EvalParams::from_slice(&values);
+        assert_eq!(restored.to_vec(), values);
+        assert_eq!(
+            restored.pst[KNIGHT][Position::E1.0 as usize],
+            Score::new(-17, -22)
+        );
+    }
+}
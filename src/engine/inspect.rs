@@ -0,0 +1,266 @@
+use crate::chess::{types::Position, State};
+use serde_json::json;
+use std::{io, path::Path};
+
+// CLS token followed by the 64 squares
+const N_TOKENS: usize = 65;
+const SHADES: &[u8] = b" .:-=+*#%@";
+const SVG_SQUARE_SIZE: usize = 40;
+
+// Activations of one position, see `TransformerModel::inspect`
+pub(crate) struct Inspection {
+    pub(crate) fen: String,
+    pub(crate) n_heads: usize,
+    // Per layer [n_heads, 65, 65], indexed by head, query token and key token. Token 0 is the CLS
+    // token and token 1 + square the square with A1 = 0.
+    pub(crate) attention: Vec<Vec<f32>>,
+    pub(crate) cls_embedding: Vec<f32>,
+    // [64 * 73] before the legal move mask and the softmax
+    pub(crate) policy_logits: Vec<f32>,
+}
+
+impl Inspection {
+    // `mirrored` attention comes from the canonical board and is mapped back to absolute squares
+    pub(crate) fn new(
+        fen: String,
+        n_heads: usize,
+        attention: Vec<Vec<f32>>,
+        cls_embedding: Vec<f32>,
+        policy_logits: Vec<f32>,
+        mirrored: bool,
+    ) -> Self {
+        let attention = if mirrored {
+            attention
+                .into_iter()
+                .map(|layer| unmirror_tokens(&layer, n_heads))
+                .collect()
+        } else {
+            attention
+        };
+
+        Self {
+            fen,
+            n_heads,
+            attention,
+            cls_embedding,
+            policy_logits,
+        }
+    }
+
+    pub(crate) fn n_layers(&self) -> usize {
+        self.attention.len()
+    }
+
+    // How much `query` attends to each of the 65 tokens, sums to one
+    pub(crate) fn attention_row(&self, layer: usize, head: usize, query: usize) -> &[f32] {
+        let start = (head * N_TOKENS + query) * N_TOKENS;
+        &self.attention[layer][start..start + N_TOKENS]
+    }
+
+    // Values that are not finite have no JSON form and are written as null
+    pub(crate) fn to_json(&self) -> String {
+        let attention: Vec<Vec<Vec<&[f32]>>> = self
+            .attention
+            .iter()
+            .map(|layer| {
+                layer
+                    .chunks(N_TOKENS * N_TOKENS)
+                    .map(|head| head.chunks(N_TOKENS).collect())
+                    .collect()
+            })
+            .collect();
+
+        json!({
+            "fen": self.fen,
+            "n_layers": self.n_layers(),
+            "n_heads": self.n_heads,
+            "attention": attention,
+            "cls_embedding": self.cls_embedding,
+            "policy_logits": self.policy_logits,
+        })
+        .to_string()
+    }
+
+    pub(crate) fn save_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+
+    // Board from White's side with each square shaded by the attention it receives from `query`,
+    // relative to the most attended square
+    pub(crate) fn ascii_heatmap(&self, layer: usize, head: usize, query: usize) -> String {
+        let row = self.attention_row(layer, head, query);
+        let max = row[1..].iter().copied().fold(f32::MIN_POSITIVE, f32::max);
+        let state = State::from_fen(&self.fen).expect("inspections are taken of valid positions");
+
+        let mut out = format!(
+            "Layer {} head {} query {}, CLS weight {:.3}\n",
+            layer,
+            head,
+            token_name(query),
+            row[0]
+        );
+        for rank in (0..8).rev() {
+            out.push_str(&format!("{} ", rank + 1));
+            for file in 0..8 {
+                let square = rank * 8 + file;
+                let level = ((row[1 + square] / max) * (SHADES.len() - 1) as f32).round() as usize;
+                let shade = SHADES[level.min(SHADES.len() - 1)] as char;
+                let piece = state.board.mailbox[square].map_or(shade, |piece| {
+                    piece.to_string().chars().next().unwrap_or(shade)
+                });
+                out.push_str(&format!("{}{}{}", shade, piece, shade));
+            }
+            out.push('\n');
+        }
+        out.push_str("   a  b  c  d  e  f  g  h\n");
+        out
+    }
+
+    // Same heatmap as `ascii_heatmap` as a standalone SVG image, the query square is outlined
+    pub(crate) fn svg_heatmap(&self, layer: usize, head: usize, query: usize) -> String {
+        let row = self.attention_row(layer, head, query);
+        let max = row[1..].iter().copied().fold(f32::MIN_POSITIVE, f32::max);
+        let state = State::from_fen(&self.fen).expect("inspections are taken of valid positions");
+        let size = 8 * SVG_SQUARE_SIZE;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" \
+             viewBox=\"0 0 {0} {0}\">\n",
+            size
+        );
+        svg.push_str(&format!(
+            "<title>Layer {} head {} query {}</title>\n",
+            layer,
+            head,
+            token_name(query)
+        ));
+        for square in 0..64 {
+            let (x, y) = (
+                (square % 8) * SVG_SQUARE_SIZE,
+                (7 - square / 8) * SVG_SQUARE_SIZE,
+            );
+            let base = if (square / 8 + square % 8) % 2 == 0 {
+                "#b58863"
+            } else {
+                "#f0d9b5"
+            };
+            svg.push_str(&format!(
+                "<rect x=\"{x}\" y=\"{y}\" width=\"{s}\" height=\"{s}\" fill=\"{base}\"/>\n\
+                 <rect x=\"{x}\" y=\"{y}\" width=\"{s}\" height=\"{s}\" fill=\"#d00000\" \
+                 fill-opacity=\"{:.3}\"/>\n",
+                row[1 + square] / max,
+                s = SVG_SQUARE_SIZE,
+            ));
+            if query == 1 + square {
+                svg.push_str(&format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{s}\" height=\"{s}\" fill=\"none\" \
+                     stroke=\"#0050ff\" stroke-width=\"3\"/>\n",
+                    x + 1,
+                    y + 1,
+                    s = SVG_SQUARE_SIZE - 2,
+                ));
+            }
+            if let Some(piece) = state.board.mailbox[square] {
+                svg.push_str(&format!(
+                    "<text x=\"{}\" y=\"{}\" font-family=\"monospace\" font-size=\"24\" \
+                     text-anchor=\"middle\">{}</text>\n",
+                    x + SVG_SQUARE_SIZE / 2,
+                    y + SVG_SQUARE_SIZE * 7 / 10,
+                    piece
+                ));
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+// "cls" for token 0, otherwise the square name
+pub(crate) fn token_name(token: usize) -> String {
+    match token {
+        0 => "cls".to_string(),
+        _ => Position((token - 1) as u8).to_string(),
+    }
+}
+
+// Token of an absolute square on the mirrored board, the CLS token stays in place
+fn mirrored_token(token: usize) -> usize {
+    match token {
+        0 => 0,
+        _ => 1 + Position((token - 1) as u8).mirrored().0 as usize,
+    }
+}
+
+fn unmirror_tokens(layer: &[f32], n_heads: usize) -> Vec<f32> {
+    let mut absolute = vec![0f32; layer.len()];
+    for head in 0..n_heads {
+        let offset = head * N_TOKENS * N_TOKENS;
+        for query in 0..N_TOKENS {
+            for key in 0..N_TOKENS {
+                absolute[offset + query * N_TOKENS + key] =
+                    layer[offset + mirrored_token(query) * N_TOKENS + mirrored_token(key)];
+            }
+        }
+    }
+    absolute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::model::TransformerModelConfig;
+    use burn::backend::NdArray;
+
+    #[test]
+    fn test_inspect_attention_and_render() {
+        let device = Default::default();
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(2)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .init::<NdArray>(&device);
+
+        let state = State::from_fen("4k3/8/8/3q4/4P3/8/8/4K3 b - - 0 1").unwrap();
+        let inspection = model.inspect(&state, &device);
+        assert_eq!(inspection.n_layers(), 2);
+        assert_eq!(inspection.n_heads, 2);
+        assert_eq!(inspection.cls_embedding.len(), 16);
+        assert_eq!(inspection.policy_logits.len(), 64 * 73);
+        for query in [0, 1, 64] {
+            let total: f32 = inspection.attention_row(1, 1, query).iter().sum();
+            assert!((total - 1.0).abs() < 1e-4);
+        }
+
+        // The same position seen from White gives the same maps on the mirrored squares
+        let mirrored = model.inspect(&state.mirrored(), &device);
+        let (d5, d4) = (1 + 35, 1 + 27);
+        for key in 0..N_TOKENS {
+            let expected = mirrored.attention_row(0, 1, d4)[mirrored_token(key)];
+            assert!((inspection.attention_row(0, 1, d5)[key] - expected).abs() < 1e-5);
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&inspection.to_json()).unwrap();
+        assert_eq!(json["fen"], "4k3/8/8/3q4/4P3/8/8/4K3 b - - 0 1");
+        assert_eq!(
+            json["attention"][1][1][64].as_array().unwrap().len(),
+            N_TOKENS
+        );
+        assert_eq!(json["cls_embedding"].as_array().unwrap().len(), 16);
+
+        let ascii = inspection.ascii_heatmap(0, 0, d5);
+        assert_eq!(ascii.lines().count(), 10);
+        assert!(ascii.contains("query d5"));
+        assert!(ascii.contains('q') && ascii.contains('K'));
+
+        let svg = inspection.svg_heatmap(0, 0, 0);
+        assert!(svg.starts_with("<svg") && svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<text").count(), 4);
+
+        // Values without a JSON form still leave a valid document
+        let mut broken = inspection;
+        broken.policy_logits[0] = f32::NAN;
+        broken.cls_embedding[0] = f32::INFINITY;
+        let json: serde_json::Value = serde_json::from_str(&broken.to_json()).unwrap();
+        assert!(json["policy_logits"][0].is_null() && json["cls_embedding"][0].is_null());
+    }
+}
@@ -1,18 +1,27 @@
 use crate::{
-    chess::{board::CastlingRights, State},
-    engine::policy::N_MOVE_PLANES,
+    chess::{
+        board::CastlingRights,
+        state::PreviousPosition,
+        types::{Color, Position},
+        State,
+    },
+    engine::{
+        inspect::Inspection,
+        policy::{mirror_policy_rows, N_MOVE_PLANES},
+    },
 };
 use burn::{
     config::Config,
-    module::Module,
+    constant,
+    module::{Module, Param},
     nn::{
         attention::{MhaInput, MultiHeadAttention, MultiHeadAttentionConfig},
         Embedding, EmbeddingConfig, Linear, LinearConfig, RmsNorm, RmsNormConfig,
     },
     tensor::{
-        activation::{gelu, softmax, tanh},
+        activation::{gelu, softmax, softplus, tanh},
         backend::Backend,
-        TensorData,
+        Int, TensorData,
     },
     Tensor,
 };
@@ -20,8 +29,13 @@ use burn::{
 #[derive(Module, Debug)]
 struct ChessEmbedding<B: Backend> {
     e_piece: Embedding<B>,
-    e_pos: Embedding<B>,
+    // Absent when the attention blocks encode square geometry with relative biases
+    e_pos: Option<Embedding<B>>,
     ff: Linear<B>,
+    // One-hot pieces of the previous positions per square, projected onto each square token
+    e_history: Option<Linear<B>>,
+    history_length: usize,
+    repetition_features: bool,
 }
 
 impl<B: Backend> ChessEmbedding<B> {
@@ -37,8 +51,14 @@ impl<B: Backend> ChessEmbedding<B> {
                 .map(|sqr| sqr.map_or(0i32, |p| p as i32 + 1))
         }));
 
-        let mut metadata = Vec::with_capacity(batch_size * 15);
-        metadata.extend(states.iter().flat_map(|state| {
+        let history: Vec<Vec<PreviousPosition>> = states
+            .iter()
+            .map(|state| state.previous_positions(self.history_length))
+            .collect();
+
+        let metadata_size = self.metadata_size();
+        let mut metadata = Vec::with_capacity(batch_size * metadata_size);
+        metadata.extend(states.iter().zip(&history).flat_map(|(state, history)| {
             let en_passant_idx = state.en_passant.map_or(0, |ep| ep.file() + 1);
             [(state.turn as i8 * -2 + 1) as f32]
                 .into_iter()
@@ -54,33 +74,165 @@ impl<B: Backend> ChessEmbedding<B> {
                 // One-hot en passant file: index 0 = none, 1..=8 = files a..h
                 .chain((0..9).map(move |i| (i == en_passant_idx) as i8 as f32))
                 .chain([state.halfmove_clock as f32 / 100.0])
+                // Repetitions of the current and each previous position, zero when missing
+                .chain(
+                    (0..=self.history_length)
+                        .filter(|_| self.repetition_features)
+                        .map(move |i| {
+                            let count = match i {
+                                0 => state.repetition_count(),
+                                _ => history.get(i - 1).map_or(0, |&(_, count)| count),
+                            };
+                            count.min(2) as f32 / 2.0
+                        }),
+                )
         }));
 
         let piece_input = Tensor::from_data(TensorData::new(piece_data, [batch_size, 64]), device);
-        let pos_input = Tensor::from_data(TensorData::new((0i32..64).collect(), [1, 64]), device);
-        let metadata_input: Tensor<B, 2> =
-            Tensor::from_data(TensorData::new(metadata, [batch_size, 15]), device);
+        let metadata_input: Tensor<B, 2> = Tensor::from_data(
+            TensorData::new(metadata, [batch_size, metadata_size]),
+            device,
+        );
 
-        let encoded_piece = self.e_piece.forward(piece_input);
-        let encoded_pos = self.e_pos.forward(pos_input);
+        let mut squares = self.e_piece.forward(piece_input);
         let cls_token = self.ff.forward(metadata_input).unsqueeze_dim(1);
 
-        Tensor::cat(vec![cls_token, encoded_piece + encoded_pos], 1)
+        if let Some(e_pos) = &self.e_pos {
+            let pos_input =
+                Tensor::from_data(TensorData::new((0i32..64).collect(), [1, 64]), device);
+            squares = squares + e_pos.forward(pos_input);
+        }
+        if let Some(e_history) = &self.e_history {
+            squares = squares + e_history.forward(self.history_input(&history, device));
+        }
+
+        Tensor::cat(vec![cls_token, squares], 1)
+    }
+
+    const fn metadata_size(&self) -> usize {
+        metadata_size(self.history_length, self.repetition_features)
+    }
+
+    // [batch_size, 64, history_length * 12], all zeros before the game start or FEN root
+    fn history_input(&self, history: &[Vec<PreviousPosition>], device: &B::Device) -> Tensor<B, 3> {
+        let channels = self.history_length * 12;
+        let mut data = vec![0f32; history.len() * 64 * channels];
+        for (b, positions) in history.iter().enumerate() {
+            for (k, (mailbox, _)) in positions.iter().enumerate() {
+                for (square, piece) in mailbox.iter().enumerate() {
+                    if let Some(piece) = piece {
+                        data[(b * 64 + square) * channels + k * 12 + *piece as usize] = 1.0;
+                    }
+                }
+            }
+        }
+        Tensor::from_data(TensorData::new(data, [history.len(), 64, channels]), device)
+    }
+}
+
+// Turn, castling rights, en passant file and halfmove clock, then the optional repetition counts
+const fn metadata_size(history_length: usize, repetition_features: bool) -> usize {
+    if repetition_features {
+        15 + history_length + 1
+    } else {
+        15
     }
 }
 
+// How the model learns where squares are. `Absolute` adds a learned embedding per square, the
+// relative schemes instead add a learned per-head bias to the attention scores of every pair of
+// tokens, keyed on their (rank delta, file delta) or on the piece movement connecting them.
+#[derive(Config, Debug, Copy, PartialEq, Eq)]
+pub(crate) enum PositionEncoding {
+    Absolute,
+    RankFileDelta,
+    Movement,
+}
+
 #[derive(Config, Debug)]
 struct ChessEmbeddingConfig {
     #[config(default = 384)]
     d_model: usize,
+    #[config(default = "PositionEncoding::Absolute")]
+    position_encoding: PositionEncoding,
+    // Number of previous positions taken from the state's undo history
+    #[config(default = 0)]
+    history_length: usize,
+    #[config(default = false)]
+    repetition_features: bool,
 }
 
 impl ChessEmbeddingConfig {
     fn init<B: Backend>(&self, device: &B::Device) -> ChessEmbedding<B> {
+        let metadata_size = metadata_size(self.history_length, self.repetition_features);
+
         ChessEmbedding {
             e_piece: EmbeddingConfig::new(13, self.d_model).init(device),
-            e_pos: EmbeddingConfig::new(64, self.d_model).init(device),
-            ff: LinearConfig::new(15, self.d_model).init(device),
+            e_pos: (self.position_encoding == PositionEncoding::Absolute)
+                .then(|| EmbeddingConfig::new(64, self.d_model).init(device)),
+            ff: LinearConfig::new(metadata_size, self.d_model).init(device),
+            e_history: (self.history_length > 0)
+                .then(|| LinearConfig::new(self.history_length * 12, self.d_model).init(device)),
+            history_length: self.history_length,
+            repetition_features: self.repetition_features,
+        }
+    }
+}
+
+// Relation tables for the relative encodings. Pairs of squares come first, then square to CLS,
+// CLS to square and CLS to itself.
+const RANK_FILE_DELTA_RELATIONS: usize = 15 * 15 + 3;
+// Same square, knight hop, rook ray and bishop ray by distance, unrelated squares
+const MOVEMENT_RELATIONS: usize = 1 + 1 + 7 + 7 + 1 + 3;
+
+#[derive(Module, Debug)]
+struct RelativeBias<B: Backend> {
+    // [n_heads, n_relations]
+    bias: Param<Tensor<B, 2>>,
+    movement: bool,
+}
+
+impl<B: Backend> RelativeBias<B> {
+    // [1, n_heads, 65, 65], added to the attention scores
+    fn forward(&self) -> Tensor<B, 4> {
+        let bias = self.bias.val();
+        let [n_heads, _] = bias.dims();
+        let relations: Vec<i32> = (0..65 * 65)
+            .map(|i| token_relation(i / 65, i % 65, self.movement) as i32)
+            .collect();
+        let relations =
+            Tensor::<B, 1, Int>::from_data(TensorData::new(relations, [65 * 65]), &bias.device());
+        bias.select(1, relations).reshape([1, n_heads, 65, 65])
+    }
+}
+
+// Token 0 is the CLS token, token 1 + square is the square with A1 = 0
+fn token_relation(query: usize, key: usize, movement: bool) -> usize {
+    let n_relations = if movement {
+        MOVEMENT_RELATIONS
+    } else {
+        RANK_FILE_DELTA_RELATIONS
+    };
+    match (query, key) {
+        (0, 0) => n_relations - 1,
+        (0, _) => n_relations - 2,
+        (_, 0) => n_relations - 3,
+        _ => {
+            let (from, to) = (Position((query - 1) as u8), Position((key - 1) as u8));
+            let rank_delta = to.rank() as i32 - from.rank() as i32;
+            let file_delta = to.file() as i32 - from.file() as i32;
+            if !movement {
+                return ((rank_delta + 7) * 15 + file_delta + 7) as usize;
+            }
+
+            let (ranks, files) = (rank_delta.unsigned_abs(), file_delta.unsigned_abs());
+            match (ranks, files) {
+                (0, 0) => 0,
+                (1, 2) | (2, 1) => 1,
+                (0, distance) | (distance, 0) => 1 + distance as usize,
+                _ if ranks == files => 8 + ranks as usize,
+                _ => 16,
+            }
         }
     }
 }
@@ -88,6 +240,7 @@ impl ChessEmbeddingConfig {
 #[derive(Module, Debug)]
 struct AttentionBlock<B: Backend> {
     attention: MultiHeadAttention<B>,
+    relative_bias: Option<RelativeBias<B>>,
     norm1: RmsNorm<B>,
     norm2: RmsNorm<B>,
     ff1: Linear<B>,
@@ -97,14 +250,56 @@ struct AttentionBlock<B: Backend> {
 impl<B: Backend> AttentionBlock<B> {
     // [batch_size, 65, d_model]
     fn forward(&self, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        self.forward_with_weights(input).0
+    }
+
+    // Also returns the attention weights [batch_size, n_heads, 65, 65]
+    fn forward_with_weights(&self, input: Tensor<B, 3>) -> (Tensor<B, 3>, Tensor<B, 4>) {
         let x = self.norm1.forward(input.clone());
-        let x = self.attention.forward(MhaInput::self_attn(x)).context;
-        let middle = input + x;
+        let (context, weights) = match &self.relative_bias {
+            Some(bias) => self.biased_attention(x, bias.forward()),
+            None => {
+                let output = self.attention.forward(MhaInput::self_attn(x));
+                (output.context, output.weights)
+            }
+        };
+        let middle = input + context;
         let x = self.norm2.forward(middle.clone());
         let x = self.ff1.forward(x);
         let x = gelu(x);
         let x = self.ff2.forward(x);
-        middle + x
+        (middle + x, weights)
+    }
+
+    // Same computation as `MultiHeadAttention::forward` with the bias added to the scores, reusing
+    // its projections so both encodings share one parameter layout
+    fn biased_attention(
+        &self,
+        x: Tensor<B, 3>,
+        bias: Tensor<B, 4>,
+    ) -> (Tensor<B, 3>, Tensor<B, 4>) {
+        let mha = &self.attention;
+        let [batch_size, seq_len, d_model] = x.dims();
+        let heads = |linear: &Linear<B>| {
+            linear
+                .forward(x.clone())
+                .reshape([batch_size, seq_len, mha.n_heads, mha.d_k])
+                .swap_dims(1, 2)
+        };
+        let (query, key, value) = (heads(&mha.query), heads(&mha.key), heads(&mha.value));
+
+        let scores = query
+            .matmul(key.transpose())
+            .div_scalar((mha.d_k as f32).sqrt());
+        let scores = mha.dropout.forward(scores) + bias;
+        let weights = softmax(scores, 3);
+
+        let context = weights
+            .clone()
+            .matmul(value)
+            .swap_dims(1, 2)
+            .reshape([batch_size, seq_len, d_model]);
+        (mha.output.forward(context), weights)
     }
 }
 
@@ -116,12 +311,25 @@ struct AttentionBlockConfig {
     d_model: usize,
     #[config(default = 768)]
     d_ff: usize,
+    #[config(default = "PositionEncoding::Absolute")]
+    position_encoding: PositionEncoding,
 }
 
 impl AttentionBlockConfig {
     fn init<B: Backend>(&self, device: &B::Device) -> AttentionBlock<B> {
+        let relations = match self.position_encoding {
+            PositionEncoding::Absolute => None,
+            PositionEncoding::RankFileDelta => Some(RANK_FILE_DELTA_RELATIONS),
+            PositionEncoding::Movement => Some(MOVEMENT_RELATIONS),
+        };
+
         AttentionBlock {
             attention: MultiHeadAttentionConfig::new(self.d_model, self.n_heads).init(device),
+            // Starts at zero, so training begins from plain content attention
+            relative_bias: relations.map(|relations| RelativeBias {
+                bias: Param::from_tensor(Tensor::zeros([self.n_heads, relations], device)),
+                movement: self.position_encoding == PositionEncoding::Movement,
+            }),
             norm1: RmsNormConfig::new(self.d_model).init(device),
             norm2: RmsNormConfig::new(self.d_model).init(device),
             ff1: LinearConfig::new(self.d_model, self.d_ff).init(device),
@@ -138,10 +346,12 @@ struct PolicyHead<B: Backend> {
 impl<B: Backend> PolicyHead<B> {
     // [batch_size, 64, d_model]
     fn forward(&self, input: Tensor<B, 3>, legal_mask: Tensor<B, 2>) -> Tensor<B, 2> {
-        let x = self.ff.forward(input);
-        let x = x.flatten(1, 2);
-        let x = x + legal_mask;
-        softmax(x, 1)
+        softmax(self.logits(input) + legal_mask, 1)
+    }
+
+    // [batch_size, 64 * 73] before masking
+    fn logits(&self, input: Tensor<B, 3>) -> Tensor<B, 2> {
+        self.ff.forward(input).flatten(1, 2)
     }
 }
 
@@ -190,22 +400,148 @@ impl ValueHeadConfig {
     }
 }
 
+#[derive(Module, Debug)]
+struct WdlHead<B: Backend> {
+    ff1: Linear<B>,
+    ff2: Linear<B>,
+}
+
+impl<B: Backend> WdlHead<B> {
+    // [batch_size, d_model] — CLS token, returns win/draw/loss probabilities
+    fn forward(&self, input: Tensor<B, 2>) -> Tensor<B, 2> {
+        let x = self.ff1.forward(input);
+        let x = gelu(x);
+        let x = self.ff2.forward(x);
+        softmax(x, 1)
+    }
+}
+
+#[derive(Config, Debug)]
+struct WdlHeadConfig {
+    #[config(default = 384)]
+    d_model: usize,
+}
+
+impl WdlHeadConfig {
+    fn init<B: Backend>(&self, device: &B::Device) -> WdlHead<B> {
+        WdlHead {
+            ff1: LinearConfig::new(self.d_model, self.d_model).init(device),
+            ff2: LinearConfig::new(self.d_model, 3).init(device),
+        }
+    }
+}
+
+#[derive(Module, Debug)]
+struct MovesLeftHead<B: Backend> {
+    ff1: Linear<B>,
+    ff2: Linear<B>,
+}
+
+impl<B: Backend> MovesLeftHead<B> {
+    // [batch_size, d_model] — CLS token, returns the expected number of plies left
+    fn forward(&self, input: Tensor<B, 2>) -> Tensor<B, 2> {
+        let x = self.ff1.forward(input);
+        let x = gelu(x);
+        let x = self.ff2.forward(x);
+        softplus(x, 1.0)
+    }
+}
+
+#[derive(Config, Debug)]
+struct MovesLeftHeadConfig {
+    #[config(default = 384)]
+    d_model: usize,
+}
+
+impl MovesLeftHeadConfig {
+    fn init<B: Backend>(&self, device: &B::Device) -> MovesLeftHead<B> {
+        MovesLeftHead {
+            ff1: LinearConfig::new(self.d_model, self.d_model).init(device),
+            ff2: LinearConfig::new(self.d_model, 1).init(device),
+        }
+    }
+}
+
+// Expected score of a win/draw/loss distribution, a draw score below zero plays for a win
+#[derive(Config, Debug)]
+pub(crate) struct WdlMapping {
+    #[config(default = 1.0)]
+    win: f32,
+    #[config(default = 0.0)]
+    draw: f32,
+    #[config(default = -1.0)]
+    loss: f32,
+}
+
+// Part of the model as a setting rather than a weight, checkpoints take it from the config
+constant!(WdlMapping);
+
+impl WdlMapping {
+    // [batch_size, 3] -> [batch_size, 1]
+    pub(crate) fn expected_scores<B: Backend>(&self, wdl: Tensor<B, 2>) -> Tensor<B, 2> {
+        let device = wdl.device();
+        let weights = Tensor::<B, 1>::from_data(
+            TensorData::new(vec![self.win, self.draw, self.loss], [3]),
+            &device,
+        );
+        (wdl * weights.unsqueeze_dim(0)).sum_dim(1)
+    }
+}
+
+pub(crate) struct ModelOutput<B: Backend> {
+    // [batch_size, 64 * 73]
+    pub(crate) policy: Tensor<B, 2>,
+    // [batch_size, 1], from the value head or derived from the WDL head when it is the only one
+    pub(crate) value: Tensor<B, 2>,
+    // [batch_size, 1], what searching and evaluating go by: the expected score of the WDL head
+    // under the model's `WdlMapping`, from the side to move's point of view and then turned to
+    // White's, or the value head's output without a WDL head
+    pub(crate) score: Tensor<B, 2>,
+    // [batch_size, 3]
+    pub(crate) wdl: Option<Tensor<B, 2>>,
+    // [batch_size, 1]
+    pub(crate) moves_left: Option<Tensor<B, 2>>,
+}
+
 #[derive(Module, Debug)]
 pub(crate) struct TransformerModel<B: Backend> {
     embedding: ChessEmbedding<B>,
     attention_blocks: Vec<AttentionBlock<B>>,
     policy_head: PolicyHead<B>,
-    value_head: ValueHead<B>,
+    value_head: Option<ValueHead<B>>,
+    wdl_head: Option<WdlHead<B>>,
+    moves_left_head: Option<MovesLeftHead<B>>,
+    canonicalize: bool,
+    wdl_mapping: WdlMapping,
 }
 
 impl<B: Backend> TransformerModel<B> {
+    // Takes and returns policies in absolute squares, values and WDL from White's point of view.
+    // With `canonicalize` black-to-move positions are mirrored first, so the network always sees
+    // the side to move as White.
     pub(crate) fn forward(
         &self,
         states: &[&State],
         legal_mask: Tensor<B, 2>,
         device: &B::Device,
-    ) -> (Tensor<B, 2>, Tensor<B, 2>) {
-        let attn_input = self.embedding.forward(states, device);
+    ) -> ModelOutput<B> {
+        let mirrored: Vec<bool> = states
+            .iter()
+            .map(|state| self.canonicalize && state.turn == Color::Black)
+            .collect();
+        let flipped: Vec<Option<State>> = states
+            .iter()
+            .zip(&mirrored)
+            .map(|(state, &mirror)| mirror.then(|| state.mirrored()))
+            .collect();
+        let canonical: Vec<&State> = states
+            .iter()
+            .zip(&flipped)
+            .map(|(&state, flipped)| flipped.as_ref().unwrap_or(state))
+            .collect();
+        let legal_mask = mirror_policy_rows(legal_mask, &mirrored);
+
+        let attn_input = self.embedding.forward(&canonical, device);
 
         let attn_output = self
             .attention_blocks
@@ -217,12 +553,114 @@ impl<B: Backend> TransformerModel<B> {
         let squares = attn_output.narrow(1, 1, seq_len - 1); // [B, 64, d_model]
 
         let policy = self.policy_head.forward(squares, legal_mask);
-        let value = self.value_head.forward(cls_token);
+        let wdl = self
+            .wdl_head
+            .as_ref()
+            .map(|head| head.forward(cls_token.clone()));
+        let value = match (&self.value_head, &wdl) {
+            (Some(head), _) => head.forward(cls_token.clone()),
+            (None, Some(wdl)) => self.wdl_mapping.expected_scores(wdl.clone()),
+            (None, None) => unreachable!("at least one value head is active"),
+        };
+        let moves_left = self
+            .moves_left_head
+            .as_ref()
+            .map(|head| head.forward(cls_token));
+
+        let signs: Vec<f32> = mirrored
+            .iter()
+            .map(|&mirror| if mirror { -1.0 } else { 1.0 })
+            .collect();
+        let signs = Tensor::from_data(TensorData::new(signs, [states.len(), 1]), device);
+        let value = value * signs;
+        let wdl = wdl.map(|wdl| swap_wdl_rows(wdl, &mirrored));
+
+        // Without canonicalization the WDL head speaks for White, a draw score is still the side
+        // to move's
+        let black_to_move: Vec<bool> = states
+            .iter()
+            .map(|state| state.turn == Color::Black)
+            .collect();
+        let score = match &wdl {
+            Some(wdl) => {
+                let to_move = swap_wdl_rows(wdl.clone(), &black_to_move);
+                let turns: Vec<f32> = black_to_move
+                    .iter()
+                    .map(|&black| if black { -1.0 } else { 1.0 })
+                    .collect();
+                let turns = Tensor::from_data(TensorData::new(turns, [states.len(), 1]), device);
+                self.wdl_mapping.expected_scores(to_move) * turns
+            }
+            None => value.clone(),
+        };
 
-        (policy, value)
+        ModelOutput {
+            policy: mirror_policy_rows(policy, &mirrored),
+            value,
+            score,
+            wdl,
+            moves_left,
+        }
     }
 }
 
+impl<B: Backend> TransformerModel<B> {
+    // Attention weights of every layer, the final CLS embedding and the unmasked policy logits for
+    // one position, mapped back to absolute squares when the position was mirrored
+    pub(crate) fn inspect(&self, state: &State, device: &B::Device) -> Inspection {
+        let mirrored = self.canonicalize && state.turn == Color::Black;
+        let flipped = mirrored.then(|| state.mirrored());
+        let canonical = flipped.as_ref().unwrap_or(state);
+
+        let mut x = self.embedding.forward(&[canonical], device);
+        let mut attention = Vec::with_capacity(self.attention_blocks.len());
+        let mut n_heads = 0;
+        for block in &self.attention_blocks {
+            let (output, weights) = block.forward_with_weights(x);
+            n_heads = weights.dims()[1];
+            attention.push(weights.into_data().to_vec::<f32>().unwrap());
+            x = output;
+        }
+
+        let seq_len = x.dims()[1];
+        let cls_embedding = x.clone().narrow(1, 0, 1).flatten::<1>(0, 2);
+        let logits = self.policy_head.logits(x.narrow(1, 1, seq_len - 1));
+        let logits = mirror_policy_rows(logits, &[mirrored]);
+
+        Inspection::new(
+            state.to_fen(),
+            n_heads,
+            attention,
+            cls_embedding.into_data().to_vec::<f32>().unwrap(),
+            logits.into_data().to_vec::<f32>().unwrap(),
+            mirrored,
+        )
+    }
+}
+
+// Win and loss trade places for the rows evaluated from Black's side
+fn swap_wdl_rows<B: Backend>(wdl: Tensor<B, 2>, mirrored: &[bool]) -> Tensor<B, 2> {
+    if !mirrored.contains(&true) {
+        return wdl;
+    }
+
+    let device = wdl.device();
+    let swapped = Tensor::<B, 1, Int>::from_data(TensorData::new(vec![2i32, 1, 0], [3]), &device);
+    let rows = mirrored
+        .iter()
+        .enumerate()
+        .map(|(i, &mirror)| {
+            let row = wdl.clone().narrow(0, i, 1);
+            if mirror {
+                row.select(1, swapped.clone())
+            } else {
+                row
+            }
+        })
+        .collect();
+    Tensor::cat(rows, 0)
+}
+
 #[derive(Config, Debug)]
 pub(crate) struct TransformerModelConfig {
     #[config(default = 8)]
@@ -233,16 +671,57 @@ pub(crate) struct TransformerModelConfig {
     d_ff_scale: f64,
     #[config(default = 64)]
     head_dimension: usize,
+    #[config(default = true)]
+    canonicalize: bool,
+    #[config(default = true)]
+    value_head: bool,
+    #[config(default = false)]
+    wdl_head: bool,
+    #[config(default = false)]
+    moves_left_head: bool,
+    #[config(default = 0)]
+    history_length: usize,
+    #[config(default = false)]
+    repetition_features: bool,
+    #[config(default = "PositionEncoding::Absolute")]
+    position_encoding: PositionEncoding,
+    // How WDL predictions are scored, see `ModelOutput::score`
+    #[config(default = "WdlMapping::new()")]
+    wdl_mapping: WdlMapping,
 }
 
 impl TransformerModelConfig {
+    // Catches the architectures `init` would panic on, or build without any weights to train
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.n_blocks == 0 || self.n_heads == 0 || self.head_dimension == 0 {
+            return Err("n_blocks, n_heads and head_dimension must be at least 1".to_string());
+        }
+        if !(self.d_ff_scale > 0.0 && self.d_ff_scale.is_finite()) {
+            return Err(format!(
+                "d_ff_scale must be positive, got {}",
+                self.d_ff_scale
+            ));
+        }
+        match self.value_head || self.wdl_head {
+            true => Ok(()),
+            false => Err("the model needs the value head or the WDL head".to_string()),
+        }
+    }
+
     pub(crate) fn init<B: Backend>(&self, device: &B::Device) -> TransformerModel<B> {
         let d_model = self.n_heads * self.head_dimension;
         let d_ff = (self.d_ff_scale * d_model as f64) as usize;
+        assert!(
+            self.value_head || self.wdl_head,
+            "TransformerModelConfig needs the value head or the WDL head"
+        );
 
         TransformerModel {
             embedding: ChessEmbeddingConfig::new()
                 .with_d_model(d_model)
+                .with_history_length(self.history_length)
+                .with_repetition_features(self.repetition_features)
+                .with_position_encoding(self.position_encoding)
                 .init(device),
             attention_blocks: (0..self.n_blocks)
                 .map(|_| {
@@ -250,11 +729,234 @@ impl TransformerModelConfig {
                         .with_n_heads(self.n_heads)
                         .with_d_model(d_model)
                         .with_d_ff(d_ff)
+                        .with_position_encoding(self.position_encoding)
                         .init(device)
                 })
                 .collect(),
             policy_head: PolicyHeadConfig::new().with_d_model(d_model).init(device),
-            value_head: ValueHeadConfig::new().with_d_model(d_model).init(device),
+            value_head: self
+                .value_head
+                .then(|| ValueHeadConfig::new().with_d_model(d_model).init(device)),
+            wdl_head: self
+                .wdl_head
+                .then(|| WdlHeadConfig::new().with_d_model(d_model).init(device)),
+            moves_left_head: self.moves_left_head.then(|| {
+                MovesLeftHeadConfig::new()
+                    .with_d_model(d_model)
+                    .init(device)
+            }),
+            canonicalize: self.canonicalize,
+            wdl_mapping: self.wdl_mapping.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::policy::legal_move_masks;
+    use burn::backend::NdArray;
+
+    #[test]
+    fn test_mirrored_positions_share_predictions() {
+        let device = Default::default();
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .with_wdl_head(true)
+            .with_moves_left_head(true)
+            .init::<NdArray>(&device);
+
+        let state =
+            State::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let mirrored = state.mirrored();
+        let mask = legal_move_masks::<NdArray>(&[&state, &mirrored], &device);
+
+        let output = model.forward(&[&state, &mirrored], mask, &device);
+        let policy = mirror_policy_rows(output.policy, &[false, true]);
+        let policy = policy.into_data().to_vec::<f32>().unwrap();
+        let value = output.value.into_data().to_vec::<f32>().unwrap();
+        let wdl = output.wdl.unwrap().into_data().to_vec::<f32>().unwrap();
+        let moves_left = output
+            .moves_left
+            .unwrap()
+            .into_data()
+            .to_vec::<f32>()
+            .unwrap();
+
+        let n = 64 * N_MOVE_PLANES;
+        for i in 0..n {
+            assert!((policy[i] - policy[n + i]).abs() < 1e-5);
+        }
+        assert!((value[0] + value[1]).abs() < 1e-5);
+
+        assert!((wdl[0..3].iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!((wdl[0] - wdl[5]).abs() < 1e-5 && (wdl[2] - wdl[3]).abs() < 1e-5);
+        assert!(moves_left[0] >= 0.0 && (moves_left[0] - moves_left[1]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_wdl_only_model() {
+        let device = Default::default();
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .with_value_head(false)
+            .with_wdl_head(true)
+            .init::<NdArray>(&device);
+
+        let state = State::new();
+        let mask = legal_move_masks::<NdArray>(&[&state], &device);
+        let output = model.forward(&[&state], mask, &device);
+        assert!(output.moves_left.is_none());
+
+        let wdl = output.wdl.unwrap();
+        let mapping = WdlMapping::new();
+        let expected = mapping
+            .expected_scores(wdl.clone())
+            .into_data()
+            .to_vec::<f32>()
+            .unwrap();
+        let value = output.value.into_data().to_vec::<f32>().unwrap();
+        assert!((expected[0] - value[0]).abs() < 1e-5);
+
+        let contempt = WdlMapping::new().with_draw(-0.2).expected_scores(wdl);
+        assert!(contempt.into_data().to_vec::<f32>().unwrap()[0] < expected[0]);
+
+        // Without either head there is no value to predict
+        let config = TransformerModelConfig::new().with_value_head(false);
+        assert!(config.clone().with_wdl_head(true).validate().is_ok());
+        assert!(config.validate().is_err());
+        assert!(TransformerModelConfig::new()
+            .with_n_heads(0)
+            .validate()
+            .is_err());
+        assert!(TransformerModelConfig::new()
+            .with_d_ff_scale(0.0)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_wdl_mapping_scores() {
+        let device = Default::default();
+        let config = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .with_wdl_head(true);
+        let neutral = config.init::<NdArray>(&device);
+        // Same weights, both sides count a draw as a quarter of a loss
+        let mut contempt = neutral.clone();
+        contempt.wdl_mapping = WdlMapping::new().with_draw(-0.25);
+
+        let state = State::from_fen("4k3/8/8/8/8/8/8/4K2R b - - 0 1").unwrap();
+        let states = [&state, &state.mirrored()];
+        let mask = legal_move_masks::<NdArray>(&states, &device);
+        let scores = |model: &TransformerModel<NdArray>| {
+            let output = model.forward(&states, mask.clone(), &device);
+            let wdl: Vec<f32> = output.wdl.unwrap().into_data().to_vec().unwrap();
+            let score: Vec<f32> = output.score.into_data().to_vec().unwrap();
+            (wdl, score)
+        };
+
+        let (wdl, score) = scores(&neutral);
+        assert!((score[0] - (wdl[0] - wdl[2])).abs() < 1e-5);
+        let (_, with_contempt) = scores(&contempt);
+        // Scores are from White's side, Black to move in the first position dislikes the draw
+        let draw = wdl[1] * 0.25;
+        assert!((with_contempt[0] - (score[0] + draw)).abs() < 1e-5);
+        assert!((with_contempt[1] - (score[1] - wdl[4] * 0.25)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_history_features_shape() {
+        let device = Default::default();
+        let embedding = ChessEmbeddingConfig::new()
+            .with_d_model(16)
+            .with_history_length(4)
+            .with_repetition_features(true)
+            .init::<NdArray>(&device);
+
+        let root = State::new();
+        let mut played = State::new();
+        for san in ["Nf3", "Nf6", "Ng1", "Ng8"] {
+            let mv = played.parse_san(san).unwrap();
+            played.make_move(mv);
+        }
+        assert_eq!(embedding.metadata_size(), 20);
+
+        let history = vec![root.previous_positions(4), played.previous_positions(4)];
+        let input = embedding.history_input(&history, &device);
+        assert_eq!(input.dims(), [2, 64, 48]);
+        // Nothing before the root is padded with zeros, the played game fills every slot
+        let sums = input
+            .sum_dim(2)
+            .sum_dim(1)
+            .into_data()
+            .to_vec::<f32>()
+            .unwrap();
+        assert_eq!(sums, vec![0.0, 4.0 * 32.0]);
+
+        let output = embedding.forward(&[&root, &played], &device);
+        assert_eq!(output.dims(), [2, 65, 16]);
+    }
+
+    #[test]
+    fn test_relative_position_encodings() {
+        // a1 -> b3 is a knight hop, a1 -> h8 the long diagonal, a1 -> a8 a file ray
+        let (a1, b3, h8, a8) = (1, 1 + 17, 1 + 63, 1 + 56);
+        assert_eq!(token_relation(a1, b3, true), 1);
+        assert_eq!(token_relation(a1, h8, true), 15);
+        assert_eq!(token_relation(a1, a8, true), 8);
+        assert_eq!(token_relation(a1, a1, false), 7 * 15 + 7);
+        assert_eq!(token_relation(h8, a1, false), 0);
+        for movement in [false, true] {
+            let n_relations = (0..65 * 65)
+                .map(|i| token_relation(i / 65, i % 65, movement))
+                .max()
+                .unwrap()
+                + 1;
+            let expected = if movement {
+                MOVEMENT_RELATIONS
+            } else {
+                RANK_FILE_DELTA_RELATIONS
+            };
+            assert_eq!(n_relations, expected);
+        }
+
+        // A zero bias leaves the attention identical to the plain multi-head attention
+        let device = Default::default();
+        let block = AttentionBlockConfig::new()
+            .with_n_heads(2)
+            .with_d_model(16)
+            .with_d_ff(32)
+            .with_position_encoding(PositionEncoding::Movement)
+            .init::<NdArray>(&device);
+        let input =
+            Tensor::<NdArray, 3>::random([2, 65, 16], burn::tensor::Distribution::Default, &device);
+        let bias = block.relative_bias.as_ref().unwrap().forward();
+        assert_eq!(bias.dims(), [1, 2, 65, 65]);
+        let (context, weights) = block.biased_attention(input.clone(), bias);
+        let plain = block.attention.forward(MhaInput::self_attn(input));
+        let difference = (context - plain.context).abs().max().into_scalar()
+            + (weights - plain.weights).abs().max().into_scalar();
+        assert!(difference < 1e-5);
+
+        let state = State::new();
+        for encoding in [PositionEncoding::RankFileDelta, PositionEncoding::Movement] {
+            let model = TransformerModelConfig::new()
+                .with_n_blocks(1)
+                .with_n_heads(2)
+                .with_head_dimension(8)
+                .with_position_encoding(encoding)
+                .init::<NdArray>(&device);
+            assert!(model.embedding.e_pos.is_none());
+            let output = model.forward(&[&state], legal_move_masks(&[&state], &device), &device);
+            assert!(output.value.into_scalar().is_finite());
         }
     }
 }
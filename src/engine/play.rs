@@ -0,0 +1,444 @@
+use crate::{
+    chess::{moves::Move, types::Color, State},
+    engine::{
+        eval::{Evaluate, HandcraftedEval},
+        model::TransformerModel,
+        policy::legal_move_masks,
+        search::{AlphaBeta, PolicySearcher, SearchLimits, Searcher},
+    },
+};
+use burn::tensor::backend::Backend;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+// Outline glyphs for White and solid ones for Black, in `Piece` order: pawn, rook, knight,
+// bishop, queen, king
+const WHITE_GLYPHS: [char; 6] = ['♙', '♖', '♘', '♗', '♕', '♔'];
+const BLACK_GLYPHS: [char; 6] = ['♟', '♜', '♞', '♝', '♛', '♚'];
+// Plain 16-color ANSI codes, which even the Linux console understands
+const LIGHT_SQUARE: &str = "\x1b[46m";
+const DARK_SQUARE: &str = "\x1b[44m";
+const LAST_MOVE_SQUARE: &str = "\x1b[43m";
+const WHITE_PIECE: &str = "\x1b[1;97m";
+const BLACK_PIECE: &str = "\x1b[1;30m";
+const RESET: &str = "\x1b[0m";
+const TOP_MOVES: usize = 5;
+
+const HELP: &str = "\
+<move>          play a move in SAN (Nf3, exd5, e8=Q) or UCI (g1f3)
+undo [n]        take back the last n moves, 1 by default
+go [budget]     let the engine reply, e.g. go 500ms or go 100000 (nodes)
+eval            value and most likely moves of the network, or the static eval without one
+moves           list the legal moves
+fen             print the position as FEN
+load <fen>      set up a position, `load startpos` for the initial one
+flip            view the board from the other side
+board           print the board again
+help            show this help
+quit            leave";
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct RenderStyle {
+    pub(crate) color: bool,
+    pub(crate) unicode: bool,
+}
+
+impl RenderStyle {
+    pub(crate) const PLAIN: Self = Self {
+        color: false,
+        unicode: false,
+    };
+
+    // Colors only on an interactive terminal that allows them, and letters instead of glyphs on
+    // the Linux console, whose fonts have no chess pieces
+    pub(crate) fn detect() -> Self {
+        let term = std::env::var("TERM").unwrap_or_default();
+        Self {
+            color: io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none()
+                && term != "dumb",
+            unicode: term != "linux" && term != "dumb",
+        }
+    }
+}
+
+// The board with rank and file labels, White at the bottom unless `flipped`
+pub(crate) fn render(state: &State, flipped: bool, style: RenderStyle) -> String {
+    let last_move = state.last_move();
+    let ranks: Vec<usize> = match flipped {
+        false => (0..8).rev().collect(),
+        true => (0..8).collect(),
+    };
+    let files: Vec<usize> = match flipped {
+        false => (0..8).collect(),
+        true => (0..8).rev().collect(),
+    };
+
+    let mut out = String::new();
+    for &rank in &ranks {
+        out.push_str(&format!(" {} ", rank + 1));
+        for &file in &files {
+            let square = rank * 8 + file;
+            let piece = state.board.mailbox[square];
+            let symbol = match piece {
+                None => {
+                    if style.color {
+                        ' '
+                    } else {
+                        '.'
+                    }
+                }
+                Some(piece) if style.unicode => {
+                    let kind = piece as usize % 6;
+                    match (piece as usize) < 6 && !style.color {
+                        true => WHITE_GLYPHS[kind],
+                        // On a colored board solid glyphs in the piece's color read best
+                        false => BLACK_GLYPHS[kind],
+                    }
+                }
+                Some(piece) => piece.to_string().chars().next().unwrap_or('?'),
+            };
+
+            if !style.color {
+                out.push_str(&format!(" {} ", symbol));
+                continue;
+            }
+            let highlighted = last_move
+                .is_some_and(|mv| mv.from().0 as usize == square || mv.to().0 as usize == square);
+            let background = if highlighted {
+                LAST_MOVE_SQUARE
+            } else if (rank + file) % 2 == 0 {
+                DARK_SQUARE
+            } else {
+                LIGHT_SQUARE
+            };
+            let foreground = match piece {
+                Some(piece) if (piece as usize) < 6 => WHITE_PIECE,
+                _ => BLACK_PIECE,
+            };
+            out.push_str(&format!(
+                "{}{} {} {}",
+                background, foreground, symbol, RESET
+            ));
+        }
+        out.push('\n');
+    }
+
+    out.push_str("   ");
+    for &file in &files {
+        out.push_str(&format!(" {} ", (b'a' + file as u8) as char));
+    }
+    out.push('\n');
+    out
+}
+
+// Checkmate, stalemate and the draws a game would be adjudicated on
+pub(crate) fn game_status(state: &State) -> Option<String> {
+    if state.generate_moves().is_empty() {
+        return Some(match (state.is_check(), state.turn) {
+            (true, Color::White) => "Checkmate, Black wins".to_string(),
+            (true, Color::Black) => "Checkmate, White wins".to_string(),
+            (false, _) => "Stalemate".to_string(),
+        });
+    }
+    if state.halfmove_clock >= 100 {
+        return Some("Draw by the fifty-move rule".to_string());
+    }
+    if state.repetition_count() >= 2 {
+        return Some("Draw by threefold repetition".to_string());
+    }
+    None
+}
+
+// A position to play moves into, with an engine to answer them: the network's policy when a
+// model is loaded, else the handcrafted alpha-beta search
+pub(crate) struct PlaySession<B: Backend> {
+    state: State,
+    flipped: bool,
+    style: RenderStyle,
+    model: Option<TransformerModel<B>>,
+    device: B::Device,
+    limits: SearchLimits,
+}
+
+impl<B: Backend> PlaySession<B> {
+    pub(crate) fn new(
+        model: Option<TransformerModel<B>>,
+        device: B::Device,
+        style: RenderStyle,
+    ) -> Self {
+        Self {
+            state: State::new(),
+            flipped: false,
+            style,
+            model,
+            device,
+            limits: SearchLimits::new().with_time(std::time::Duration::from_secs(1)),
+        }
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut out = render(&self.state, self.flipped, self.style);
+        let side = match self.state.turn {
+            Color::White => "White",
+            Color::Black => "Black",
+        };
+        match game_status(&self.state) {
+            Some(status) => out.push_str(&format!("{}\n", status)),
+            None => out.push_str(&format!("{} to move\n", side)),
+        }
+        out
+    }
+
+    // Reads commands until `quit` or the end of the input
+    pub(crate) fn run(&mut self, input: impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "{}", self.render())?;
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if matches!(line.trim(), "quit" | "exit") {
+                break;
+            }
+            match self.execute(&line) {
+                Ok(response) if response.is_empty() => {}
+                Ok(response) => writeln!(output, "{}", response)?,
+                Err(err) => writeln!(output, "error: {}", err)?,
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+
+    // One command, returns what to print
+    pub(crate) fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        match command {
+            "" => Ok(String::new()),
+            "help" => Ok(HELP.to_string()),
+            "board" => Ok(self.render()),
+            "fen" => Ok(self.state.to_fen()),
+            "flip" => {
+                self.flipped = !self.flipped;
+                Ok(self.render())
+            }
+            "load" => {
+                self.state = match args {
+                    "startpos" | "" => State::new(),
+                    fen => State::from_fen(fen).map_err(|err| format!("invalid FEN: {}", err))?,
+                };
+                Ok(self.render())
+            }
+            "undo" => {
+                let count = match args {
+                    "" => 1,
+                    count => count
+                        .parse()
+                        .map_err(|_| format!("invalid move count '{}'", count))?,
+                };
+                for _ in 0..count {
+                    if self.state.last_move().is_none() {
+                        break;
+                    }
+                    self.state.unmake_move();
+                }
+                Ok(self.render())
+            }
+            "moves" => {
+                let mut moves: Vec<String> = self
+                    .state
+                    .generate_moves()
+                    .iter()
+                    .map(|mv| mv.to_uci())
+                    .collect();
+                moves.sort();
+                Ok(moves.join(" "))
+            }
+            "go" => {
+                let limits = match args {
+                    "" => self.limits,
+                    budget => budget.parse()?,
+                };
+                let mv = self.engine_move(&limits).ok_or("no legal moves")?;
+                self.state.make_move(mv);
+                Ok(format!("Engine plays {}\n{}", mv.to_uci(), self.render()))
+            }
+            "eval" => Ok(self.evaluate()),
+            _ => {
+                let mv = self
+                    .state
+                    .parse_san(line)
+                    .or_else(|| self.state.parse_uci(line))
+                    .ok_or_else(|| {
+                        format!("unknown command or illegal move '{}', try help", line)
+                    })?;
+                self.state.make_move(mv);
+                Ok(self.render())
+            }
+        }
+    }
+
+    fn engine_move(&self, limits: &SearchLimits) -> Option<Move> {
+        match &self.model {
+            Some(model) => PolicySearcher::new(model.clone(), self.device.clone())
+                .best_move(&self.state, limits),
+            None => AlphaBeta::new(HandcraftedEval::new()).best_move(&self.state, limits),
+        }
+    }
+
+    fn evaluate(&self) -> String {
+        describe_evaluation(&self.state, self.model.as_ref(), &self.device)
+    }
+}
+
+// The network's value and most likely moves, or the handcrafted evaluation without a network
+pub(crate) fn describe_evaluation<B: Backend>(
+    state: &State,
+    model: Option<&TransformerModel<B>>,
+    device: &B::Device,
+) -> String {
+    let Some(model) = model else {
+        // Also as the value the head is bootstrapped towards, to compare with a network's
+        let eval = HandcraftedEval::new();
+        let value = match state.turn {
+            Color::White => eval.value(state),
+            Color::Black => -eval.value(state),
+        };
+        return format!(
+            "static eval {:+.2} pawns, value {:+.3} for White (no network loaded)",
+            eval.evaluate_white(state) as f64 / 100.0,
+            value
+        );
+    };
+
+    let output = model.forward(&[state], legal_move_masks(&[state], device), device);
+    let value: f32 = output.score.into_data().to_vec::<f32>().unwrap()[0];
+
+    let mut out = format!("value {:+.3} for White", value);
+    let moves = state.policy_to_moves(output.policy.squeeze::<1>());
+    for (mv, probability) in moves.iter().take(TOP_MOVES) {
+        out.push_str(&format!(
+            "\n  {:<6} {:5.1}%",
+            mv.to_uci(),
+            probability * 100.0
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::model::TransformerModelConfig;
+    use burn::backend::NdArray;
+
+    fn new_session(model: bool) -> PlaySession<NdArray> {
+        let device = Default::default();
+        let model = model.then(|| {
+            TransformerModelConfig::new()
+                .with_n_blocks(1)
+                .with_n_heads(2)
+                .with_head_dimension(8)
+                .init::<NdArray>(&device)
+        });
+        PlaySession::new(model, device, RenderStyle::PLAIN)
+    }
+
+    #[test]
+    fn test_moves_undo_and_load() {
+        let mut session = new_session(false);
+        for mv in ["e4", "e7e5", "Nf3", "Nc6"] {
+            session.execute(mv).unwrap();
+        }
+        assert_eq!(
+            session.execute("fen").unwrap(),
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"
+        );
+        assert!(session.execute("Ke3").is_err());
+        assert!(session.execute("undo x").is_err());
+
+        session.execute("undo 2").unwrap();
+        assert_eq!(session.state.last_move().unwrap().to_uci(), "e7e5");
+        // Undoing past the start stops there
+        session.execute("undo 10").unwrap();
+        assert_eq!(session.state.to_fen(), State::new().to_fen());
+
+        let board = session
+            .execute("load 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1")
+            .unwrap();
+        assert!(board.ends_with("White to move\n"));
+        assert!(session.execute("load not a fen").is_err());
+        let board = session.execute("Ra8#").unwrap();
+        assert!(board.ends_with("Checkmate, White wins\n"));
+
+        let moves = session
+            .execute("load startpos")
+            .and_then(|_| session.execute("moves"));
+        assert_eq!(moves.unwrap().split(' ').count(), 20);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut state = State::new();
+        state.make_move(state.parse_uci("e2e4").unwrap());
+
+        let plain = render(&state, false, RenderStyle::PLAIN);
+        let lines: Vec<&str> = plain.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], " 8  r  n  b  q  k  b  n  r ");
+        assert_eq!(lines[4], " 4  .  .  .  .  P  .  .  . ");
+        assert_eq!(lines[8], "    a  b  c  d  e  f  g  h ");
+
+        let flipped = render(&state, true, RenderStyle::PLAIN);
+        assert!(flipped.starts_with(" 1  R  N  B  K  Q  B  N  R "));
+        assert!(flipped.ends_with("    h  g  f  e  d  c  b  a \n"));
+
+        let style = RenderStyle {
+            color: true,
+            unicode: true,
+        };
+        let colored = render(&state, false, style);
+        // Both squares of the last move are highlighted
+        assert_eq!(colored.matches(LAST_MOVE_SQUARE).count(), 2);
+        assert!(colored.contains('♚') && !colored.contains('♔'));
+        let outlined = render(
+            &state,
+            false,
+            RenderStyle {
+                color: false,
+                unicode: true,
+            },
+        );
+        assert!(outlined.contains('♔') && outlined.contains('♚'));
+    }
+
+    #[test]
+    fn test_engine_commands() {
+        let mut session = new_session(false);
+        session
+            .execute("load 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1")
+            .unwrap();
+        let eval = session.execute("eval").unwrap();
+        assert!(eval.starts_with("static eval +") && eval.contains("value +"));
+        let reply = session.execute("go 20000").unwrap();
+        assert!(reply.starts_with("Engine plays a1a8"));
+        assert!(session.execute("go 1s").is_err());
+
+        let mut session = new_session(true);
+        let eval = session.execute("eval").unwrap();
+        assert!(eval.starts_with("value "));
+        assert_eq!(eval.lines().count(), 1 + TOP_MOVES);
+        session.execute("go").unwrap();
+        assert_eq!(session.state.turn, Color::Black);
+
+        let mut output = Vec::new();
+        session
+            .run("e5\nbogus\nquit\ne4\n".as_bytes(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("error: unknown command or illegal move 'bogus'"));
+        assert_eq!(session.state.turn, Color::White);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::chess::{
     moves::{Move, MoveType},
-    types::Direction,
+    symmetry::Symmetry,
+    types::{Direction, Position},
     State,
 };
 use burn::{
@@ -15,6 +16,28 @@ const KNIGHT_PROMO_CENTER_OFFSET: i8 = 64;
 const BISHOP_PROMO_CENTER_OFFSET: i8 = 67;
 const ROOK_PROMO_CENTER_OFFSET: i8 = 70;
 
+// (rank, file) offset of each ray direction plane group, in `Direction` order
+const RAY_DELTAS: [(i8, i8); 8] = [
+    (1, 0),   // North
+    (-1, 0),  // South
+    (0, 1),   // East
+    (0, -1),  // West
+    (1, 1),   // North East
+    (1, -1),  // North West
+    (-1, 1),  // South East
+    (-1, -1), // South West
+];
+const KNIGHT_DELTAS: [(i8, i8); 8] = [
+    (2, 1),
+    (1, 2),
+    (-1, 2),
+    (-2, 1),
+    (-2, -1),
+    (-1, -2),
+    (1, -2),
+    (2, -1),
+];
+
 pub(crate) trait PolicyEncoding {
     fn policy_index(&self) -> usize;
 }
@@ -70,6 +93,154 @@ impl PolicyEncoding for Move {
     }
 }
 
+// Inverse of `policy_index`, purely geometric: returns the from/to squares and the
+// under-promotion piece if the plane encodes one. `None` for planes that leave the board.
+pub(crate) fn decode_policy_index(index: usize) -> Option<(Position, Position, Option<MoveType>)> {
+    if index >= 64 * N_MOVE_PLANES {
+        return None;
+    }
+    let from = Position((index / N_MOVE_PLANES) as u8);
+    let plane = index % N_MOVE_PLANES;
+
+    let ((rank_diff, file_diff), promotion) = if plane < KNIGHT_MOVE_PLANE_START {
+        let (dr, df) = RAY_DELTAS[plane / MAX_RAY_MOVE_DIST];
+        let dist = (plane % MAX_RAY_MOVE_DIST) as i8 + 1;
+        ((dr * dist, df * dist), None)
+    } else if plane < KNIGHT_PROMO_CENTER_OFFSET as usize {
+        (KNIGHT_DELTAS[plane - KNIGHT_MOVE_PLANE_START], None)
+    } else {
+        let offset = plane as i8 - KNIGHT_PROMO_CENTER_OFFSET;
+        let promotion = match offset / 3 {
+            0 => MoveType::PromotionKnight,
+            1 => MoveType::PromotionBishop,
+            _ => MoveType::PromotionRook,
+        };
+        // Under-promotions only ever start from the seventh rank of the side to move
+        let rank_diff = match from.rank() {
+            6 => 1,
+            1 => -1,
+            _ => return None,
+        };
+        ((rank_diff, offset % 3 - 1), Some(promotion))
+    };
+
+    let rank = from.rank() as i8 + rank_diff;
+    let file = from.file() as i8 + file_diff;
+    if !(0..8).contains(&rank) || !(0..8).contains(&file) {
+        return None;
+    }
+    Some((from, Position((rank * 8 + file) as u8), promotion))
+}
+
+// Plane of each move seen from the other side of the board: north and south swap, east and west
+// stay, knights flip their rank offset and under-promotions keep their file offset
+const MIRRORED_PLANES: [usize; N_MOVE_PLANES] = {
+    const RAY_DIRECTIONS: [usize; 8] = [1, 0, 2, 3, 6, 7, 4, 5];
+    const KNIGHT_PLANES: [usize; 8] = [3, 2, 1, 0, 7, 6, 5, 4];
+
+    let mut planes = [0; N_MOVE_PLANES];
+    let mut plane = 0;
+    while plane < N_MOVE_PLANES {
+        planes[plane] = if plane < KNIGHT_MOVE_PLANE_START {
+            RAY_DIRECTIONS[plane / MAX_RAY_MOVE_DIST] * MAX_RAY_MOVE_DIST
+                + plane % MAX_RAY_MOVE_DIST
+        } else if plane < KNIGHT_PROMO_CENTER_OFFSET as usize {
+            KNIGHT_MOVE_PLANE_START + KNIGHT_PLANES[plane - KNIGHT_MOVE_PLANE_START]
+        } else {
+            plane
+        };
+        plane += 1;
+    }
+    planes
+};
+
+// Index of `Move::mirrored` for the move at `index`, an involution over the whole policy
+pub(crate) const fn mirror_policy_index(index: usize) -> usize {
+    let from = Position((index / N_MOVE_PLANES) as u8).mirrored();
+    from.0 as usize * N_MOVE_PLANES + MIRRORED_PLANES[index % N_MOVE_PLANES]
+}
+
+// Index of `Move::transformed` for the move at `index`. Rays and knight hops turn with the board:
+// a file flip swaps the East and West planes, a rotation takes North to East. Under-promotions
+// only ever meet the file flip, the one symmetry that keeps pawns, and otherwise stay put so that
+// every symmetry is a permutation of the whole policy.
+pub(crate) fn transform_policy_index(index: usize, symmetry: Symmetry) -> usize {
+    let from = Position((index / N_MOVE_PLANES) as u8).transformed(symmetry);
+    let plane = index % N_MOVE_PLANES;
+
+    let plane = if plane < KNIGHT_MOVE_PLANE_START {
+        let delta = symmetry.transform_delta(RAY_DELTAS[plane / MAX_RAY_MOVE_DIST]);
+        let direction = RAY_DELTAS.iter().position(|&d| d == delta).unwrap();
+        direction * MAX_RAY_MOVE_DIST + plane % MAX_RAY_MOVE_DIST
+    } else if plane < KNIGHT_PROMO_CENTER_OFFSET as usize {
+        let delta = symmetry.transform_delta(KNIGHT_DELTAS[plane - KNIGHT_MOVE_PLANE_START]);
+        KNIGHT_MOVE_PLANE_START + KNIGHT_DELTAS.iter().position(|&d| d == delta).unwrap()
+    } else if symmetry == Symmetry::FlipFiles {
+        let offset = plane - KNIGHT_PROMO_CENTER_OFFSET as usize;
+        KNIGHT_PROMO_CENTER_OFFSET as usize + offset / 3 * 3 + (2 - offset % 3)
+    } else {
+        plane
+    };
+    from.0 as usize * N_MOVE_PLANES + plane
+}
+
+// Maps the selected rows of a [batch, 64 * 73] policy or mask between the absolute and the
+// mirrored frame, the transform is its own inverse
+pub(crate) fn mirror_policy_rows<B: Backend>(
+    policy: Tensor<B, 2>,
+    mirrored: &[bool],
+) -> Tensor<B, 2> {
+    if !mirrored.contains(&true) {
+        return policy;
+    }
+
+    let device = policy.device();
+    let n = 64 * N_MOVE_PLANES;
+    let indices: Vec<i32> = (0..n).map(|i| mirror_policy_index(i) as i32).collect();
+    let indices = Tensor::from_data(TensorData::new(indices, [n]), &device);
+
+    let rows = mirrored
+        .iter()
+        .enumerate()
+        .map(|(i, &mirror)| {
+            let row = policy.clone().narrow(0, i, 1);
+            if mirror {
+                row.select(1, indices.clone())
+            } else {
+                row
+            }
+        })
+        .collect();
+    Tensor::cat(rows, 0)
+}
+
+impl State {
+    // Resolves a policy index to the legal move it encodes, including its exact `MoveType`
+    pub(crate) fn policy_index_to_move(&self, index: usize) -> Option<Move> {
+        let (from, to, _) = decode_policy_index(index)?;
+        self.generate_moves()
+            .into_iter()
+            .find(|mv| mv.from() == from && mv.to() == to && mv.policy_index() == index)
+    }
+
+    // Legal moves paired with their probability in a single-position policy, most likely first
+    pub(crate) fn policy_to_moves<B: Backend>(&self, policy: Tensor<B, 1>) -> Vec<(Move, f32)> {
+        let data = policy.into_data();
+        self.probabilities_to_moves(data.as_slice::<f32>().unwrap())
+    }
+
+    // Same as `policy_to_moves` for one row of a policy already copied off the device
+    pub(crate) fn probabilities_to_moves(&self, probabilities: &[f32]) -> Vec<(Move, f32)> {
+        let mut moves: Vec<(Move, f32)> = self
+            .generate_moves()
+            .into_iter()
+            .map(|mv| (mv, probabilities[mv.policy_index()]))
+            .collect();
+        moves.sort_by(|a, b| b.1.total_cmp(&a.1));
+        moves
+    }
+}
+
 pub(crate) trait LegalMoveMask<B: Backend> {
     fn legal_move_mask(&self, device: &B::Device) -> Tensor<B, 1>;
 }
@@ -78,15 +249,31 @@ impl<B: Backend> LegalMoveMask<B> for State {
     fn legal_move_mask(&self, device: &<B as Backend>::Device) -> Tensor<B, 1> {
         let n = 64 * N_MOVE_PLANES;
         let mut data: Vec<f32> = vec![f32::NEG_INFINITY; n];
-
-        self.generate_moves()
-            .iter()
-            .for_each(|mv| data[mv.policy_index()] = 0f32);
+        fill_legal_move_mask(self, &mut data);
 
         Tensor::from_data(TensorData::new(data, [n]), device)
     }
 }
 
+// Masks for a whole batch, filled into one contiguous buffer and uploaded as a single
+// [batch_size, 64 * 73] tensor, ready for `TransformerModel::forward`
+pub(crate) fn legal_move_masks<B: Backend>(states: &[&State], device: &B::Device) -> Tensor<B, 2> {
+    let n = 64 * N_MOVE_PLANES;
+    let mut data: Vec<f32> = vec![f32::NEG_INFINITY; states.len() * n];
+    for (state, row) in states.iter().zip(data.chunks_exact_mut(n)) {
+        fill_legal_move_mask(state, row);
+    }
+
+    Tensor::from_data(TensorData::new(data, [states.len(), n]), device)
+}
+
+// Expects a row initialised to -inf
+fn fill_legal_move_mask(state: &State, row: &mut [f32]) {
+    for mv in state.generate_moves() {
+        row[mv.policy_index()] = 0f32;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -102,7 +289,7 @@ mod tests {
         ];
 
         for fen in fens {
-            let state = State::from_fen(fen);
+            let state = State::from_fen(fen).unwrap();
             let moves = state.generate_moves();
             let mut indices = Vec::new();
             for mv in moves {
@@ -138,7 +325,7 @@ mod tests {
         let device = Default::default();
 
         for fen in fens {
-            let state = State::from_fen(fen);
+            let state = State::from_fen(fen).unwrap();
             let mask = LegalMoveMask::<NdArray>::legal_move_mask(&state, &device);
             let data = mask.into_data();
             assert_eq!(data.shape[0], 64 * N_MOVE_PLANES);
@@ -167,4 +354,173 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_decode_policy_index_round_trips() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ];
+
+        for fen in fens {
+            let state = State::from_fen(fen).unwrap();
+            for mv in state.generate_moves() {
+                let idx = mv.policy_index();
+                let (from, to, promotion) = decode_policy_index(idx).unwrap();
+                assert_eq!((from, to), (mv.from(), mv.to()), "{:?} in {}", mv, fen);
+                if let Some(promotion) = promotion {
+                    assert!(promotion == mv.move_type(), "{:?} in {}", mv, fen);
+                }
+                assert_eq!(state.policy_index_to_move(idx), Some(mv), "{}", fen);
+            }
+        }
+
+        // Every on-board plane decodes to a move that encodes back to the same index
+        for idx in 0..64 * N_MOVE_PLANES {
+            if let Some((from, to, promotion)) = decode_policy_index(idx) {
+                let move_type = promotion.unwrap_or(MoveType::Standard);
+                assert_eq!(Move::new(from, to, move_type).policy_index(), idx);
+            }
+        }
+    }
+
+    #[test]
+    fn test_policy_to_moves_sorts_legal_moves() {
+        use burn::backend::NdArray;
+        let device = Default::default();
+        let state = State::new();
+        let e4 = state.parse_san("e4").unwrap();
+        let nf3 = state.parse_san("Nf3").unwrap();
+
+        let mut data = vec![0.0f32; 64 * N_MOVE_PLANES];
+        data[e4.policy_index()] = 0.6;
+        data[nf3.policy_index()] = 0.3;
+        // Probability mass on an illegal move is ignored
+        data[0] = 0.1;
+        let policy =
+            Tensor::<NdArray, 1>::from_data(TensorData::new(data, [64 * N_MOVE_PLANES]), &device);
+
+        let moves = state.policy_to_moves(policy);
+        assert_eq!(moves.len(), 20);
+        assert_eq!(moves[0], (e4, 0.6));
+        assert_eq!(moves[1], (nf3, 0.3));
+    }
+
+    #[test]
+    fn test_mirrored_legal_masks_correspond() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ];
+
+        for idx in 0..64 * N_MOVE_PLANES {
+            assert_eq!(mirror_policy_index(mirror_policy_index(idx)), idx);
+        }
+
+        for fen in fens {
+            let state = State::from_fen(fen).unwrap();
+            let mirrored = state.mirrored();
+            for mv in state.generate_moves() {
+                assert_eq!(
+                    mirror_policy_index(mv.policy_index()),
+                    mv.mirrored().policy_index()
+                );
+            }
+
+            use burn::backend::NdArray;
+            let device = Default::default();
+            let mask = LegalMoveMask::<NdArray>::legal_move_mask(&state, &device).unsqueeze_dim(0);
+            let mirrored_mask =
+                LegalMoveMask::<NdArray>::legal_move_mask(&mirrored, &device).unsqueeze_dim::<2>(0);
+            let mask = mirror_policy_rows(mask, &[true]);
+            assert_eq!(
+                mask.into_data().as_slice::<f32>().unwrap(),
+                mirrored_mask.into_data().as_slice::<f32>().unwrap(),
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn test_symmetric_legal_masks_correspond() {
+        // Pawnless without castling rights, and a file flip with under-promotions and en passant
+        let cases = [
+            ("1k6/8/3q4/8/4N3/2B5/6R1/4K3 w - - 0 1", &Symmetry::ALL[..]),
+            (
+                "1n2k3/P7/8/3pP3/8/8/8/4K3 w - d6 0 2",
+                &[Symmetry::FlipFiles][..],
+            ),
+        ];
+
+        for symmetry in Symmetry::ALL {
+            let mut seen = vec![false; 64 * N_MOVE_PLANES];
+            for idx in 0..64 * N_MOVE_PLANES {
+                seen[transform_policy_index(idx, symmetry)] = true;
+            }
+            assert!(seen.iter().all(|&seen| seen), "{:?}", symmetry);
+        }
+        for idx in 0..64 * N_MOVE_PLANES {
+            assert_eq!(
+                transform_policy_index(idx, Symmetry::FlipRanks),
+                mirror_policy_index(idx)
+            );
+        }
+
+        for (fen, symmetries) in cases {
+            let state = State::from_fen(fen).unwrap();
+            let mut mask = vec![f32::NEG_INFINITY; 64 * N_MOVE_PLANES];
+            fill_legal_move_mask(&state, &mut mask);
+            for &symmetry in symmetries {
+                let transformed = state.transformed(symmetry);
+                for mv in state.generate_moves() {
+                    assert_eq!(
+                        transform_policy_index(mv.policy_index(), symmetry),
+                        mv.transformed(symmetry).policy_index()
+                    );
+                }
+
+                let mut expected = vec![f32::NEG_INFINITY; 64 * N_MOVE_PLANES];
+                fill_legal_move_mask(&transformed, &mut expected);
+                let mut permuted = vec![f32::NEG_INFINITY; 64 * N_MOVE_PLANES];
+                for (idx, &value) in mask.iter().enumerate() {
+                    permuted[transform_policy_index(idx, symmetry)] = value;
+                }
+                assert_eq!(permuted, expected, "{} {:?}", fen, symmetry);
+            }
+        }
+    }
+
+    #[test]
+    fn test_batched_legal_move_masks() {
+        use burn::backend::NdArray;
+        let states = [
+            State::new(),
+            State::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap(),
+            State::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1")
+                .unwrap(),
+        ];
+        let refs: Vec<&State> = states.iter().collect();
+
+        let device = Default::default();
+        let batch = legal_move_masks::<NdArray>(&refs, &device);
+        assert_eq!(batch.dims(), [states.len(), 64 * N_MOVE_PLANES]);
+
+        let stacked = Tensor::stack::<2>(
+            states
+                .iter()
+                .map(|state| LegalMoveMask::<NdArray>::legal_move_mask(state, &device))
+                .collect(),
+            0,
+        );
+        assert_eq!(
+            batch.into_data().as_slice::<f32>().unwrap(),
+            stacked.into_data().as_slice::<f32>().unwrap()
+        );
+        assert_eq!(legal_move_masks::<NdArray>(&[], &device).dims()[0], 0);
+    }
 }
@@ -0,0 +1,736 @@
+use crate::{
+    chess::{
+        pgn::{PgnGame, PgnReader},
+        prng::PseudoRng,
+        types::GameResult,
+        State,
+    },
+    engine::{
+        model::TransformerModel,
+        policy::{legal_move_masks, PolicyEncoding},
+        training::{
+            label_targets, policy_loss, result_wdl, value_loss, LossConfig, TrainingPosition,
+            TrainingTargets,
+        },
+    },
+};
+use burn::{
+    config::Config,
+    module::AutodiffModule,
+    optim::{AdamConfig, GradientsParams, Optimizer},
+    tensor::{
+        backend::{AutodiffBackend, Backend},
+        ElementConversion, TensorData,
+    },
+    Tensor,
+};
+use std::{collections::VecDeque, io, io::BufRead};
+
+// Which games and positions of a PGN database are worth learning from. Bounds that are set skip
+// games missing the tag they look at.
+#[derive(Config, Debug)]
+pub(crate) struct GameFilter {
+    // Applied to both the WhiteElo and BlackElo tags
+    min_elo: Option<u32>,
+    max_elo: Option<u32>,
+    // Base time of the TimeControl tag in seconds, 180 for "180+2" and 7200 for "40/7200:3600"
+    min_base_seconds: Option<u32>,
+    max_base_seconds: Option<u32>,
+    // Positions are kept from ply `min_ply` up to, but excluding, ply `max_ply`
+    #[config(default = 0)]
+    min_ply: usize,
+    max_ply: Option<usize>,
+}
+
+impl GameFilter {
+    // Unfinished games have no result to learn the value from and are always skipped
+    pub(crate) fn accepts(&self, game: &PgnGame) -> bool {
+        let in_range = |value: Option<u32>, min: Option<u32>, max: Option<u32>| {
+            (min.is_none() && max.is_none())
+                || value.is_some_and(|value| {
+                    min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+                })
+        };
+        let elo = |tag| game.tag(tag).and_then(|elo| elo.parse().ok());
+
+        game.result.is_some()
+            && in_range(elo("WhiteElo"), self.min_elo, self.max_elo)
+            && in_range(elo("BlackElo"), self.min_elo, self.max_elo)
+            && in_range(
+                game.tag("TimeControl").and_then(base_seconds),
+                self.min_base_seconds,
+                self.max_base_seconds,
+            )
+    }
+
+    fn accepts_ply(&self, ply: usize) -> bool {
+        ply >= self.min_ply && self.max_ply.is_none_or(|max| ply < max)
+    }
+}
+
+// "180+2" -> 180, "40/7200:3600" -> 7200, "-" and "?" -> None
+fn base_seconds(time_control: &str) -> Option<u32> {
+    let period = time_control.split(':').next()?;
+    let period = period
+        .split_once('/')
+        .map_or(period, |(_, seconds)| seconds);
+    period.split('+').next()?.parse().ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Split {
+    Training,
+    Validation,
+}
+
+// Streams (position, played move, result) samples out of a PGN database, one game at a time. A
+// read error from the database is passed on and ends the stream.
+// Every `validation_every`-th accepted game goes to the validation split, so the split is the
+// same on every pass over the same file and no game contributes to both.
+pub(crate) struct PgnSamples<R: BufRead> {
+    games: PgnReader<R>,
+    filter: GameFilter,
+    validation_every: usize,
+    pending: VecDeque<(TrainingPosition, Split)>,
+    pub(crate) games_read: usize,
+    pub(crate) games_accepted: usize,
+}
+
+impl<R: BufRead> PgnSamples<R> {
+    // A `validation_every` of 0 keeps every game for training
+    pub(crate) fn new(reader: R, filter: GameFilter, validation_every: usize) -> Self {
+        Self {
+            games: PgnReader::new(reader),
+            filter,
+            validation_every,
+            pending: VecDeque::new(),
+            games_read: 0,
+            games_accepted: 0,
+        }
+    }
+
+    fn replay(&mut self, game: &PgnGame, split: Split) {
+        let result = match game.result {
+            Some(GameResult::WhiteWin) => 1.0,
+            Some(GameResult::BlackWin) => -1.0,
+            Some(GameResult::Draw) | None => 0.0,
+        };
+
+        let Ok(mut state) = game.initial_state() else {
+            return;
+        };
+        for (ply, &mv) in game.moves.iter().enumerate() {
+            if self.filter.accepts_ply(ply) {
+                let position = TrainingPosition::new(state.clone())
+                    .with_played(mv)
+                    .with_result(result)
+                    .with_moves_left(game.moves.len() - ply);
+                self.pending.push_back((position, split));
+            }
+            state.make_move(mv);
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnSamples<R> {
+    type Item = io::Result<(TrainingPosition, Split)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let game = match self.games.next()? {
+                Ok(game) => game,
+                Err(err) => return Some(Err(err)),
+            };
+            self.games_read += 1;
+            if !self.filter.accepts(&game) {
+                continue;
+            }
+
+            let split = match self.validation_every {
+                0 => Split::Training,
+                every if self.games_accepted % every == every - 1 => Split::Validation,
+                _ => Split::Training,
+            };
+            self.games_accepted += 1;
+            self.replay(&game, split);
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+// Supervised bootstrapping from human or engine games before self-play takes over
+#[derive(Config, Debug)]
+pub(crate) struct PretrainingConfig {
+    #[config(default = "GameFilter::new()")]
+    filter: GameFilter,
+    #[config(default = 20)]
+    validation_every: usize,
+    #[config(default = 10000)]
+    max_validation_positions: usize,
+    #[config(default = 256)]
+    batch_size: usize,
+    // Training positions are drawn at random from a buffer of this many, so a batch mixes many
+    // games instead of holding consecutive plies of a few. 1 keeps the order of the database.
+    #[config(default = 100000)]
+    shuffle_buffer: usize,
+    #[config(default = 1)]
+    seed: u64,
+    #[config(default = 1e-3)]
+    learning_rate: f64,
+    #[config(default = 1)]
+    epochs: usize,
+    // Training loss and accuracy are reported every this many batches, 0 only at the end of epochs
+    #[config(default = 500)]
+    report_every: usize,
+    #[config(default = "LossConfig::new()")]
+    loss: LossConfig,
+    #[config(default = "AdamConfig::new()")]
+    optimizer: AdamConfig,
+}
+
+impl PretrainingConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.batch_size == 0 || self.epochs == 0 {
+            return Err("batch_size and epochs must be at least 1".to_string());
+        }
+        if let Some(max_ply) = self
+            .filter
+            .max_ply
+            .filter(|&max| max <= self.filter.min_ply)
+        {
+            return Err(format!(
+                "filter.max_ply must be above filter.min_ply, got {} and {}",
+                max_ply, self.filter.min_ply
+            ));
+        }
+        match self.learning_rate > 0.0 {
+            true => Ok(()),
+            false => Err(format!(
+                "learning_rate must be positive, got {}",
+                self.learning_rate
+            )),
+        }
+    }
+
+    // `open` is called once per epoch and streams the games from the start. The validation
+    // positions are collected on the first pass and the model is evaluated on them after every
+    // epoch, the metrics of the last one are returned. `progress` hears of each step on the way.
+    pub(crate) fn pretrain<B: AutodiffBackend, R: BufRead>(
+        &self,
+        mut model: TransformerModel<B>,
+        open: impl Fn() -> io::Result<R>,
+        device: &B::Device,
+        mut progress: impl FnMut(PretrainingProgress),
+    ) -> io::Result<(TransformerModel<B>, PretrainingMetrics)> {
+        let mut optimizer = self.optimizer.init::<B, TransformerModel<B>>();
+        let mut rng = PseudoRng::new(self.seed.max(1));
+        let mut validation = Vec::new();
+        let mut metrics = PretrainingMetrics::default();
+
+        for epoch in 0..self.epochs {
+            let mut samples = PgnSamples::new(open()?, self.filter.clone(), self.validation_every);
+            let mut shuffle = Vec::new();
+            let mut running = RunningMetrics::default();
+            let mut batches = 0;
+
+            let keep_validation = if epoch == 0 {
+                self.max_validation_positions
+            } else {
+                0
+            };
+            while let Some(batch) = self.next_batch(
+                &mut samples,
+                (&mut shuffle, &mut rng),
+                &mut validation,
+                keep_validation,
+            )? {
+                let positions: Vec<&TrainingPosition> = batch.iter().collect();
+                let states: Vec<&State> =
+                    positions.iter().map(|position| &position.state).collect();
+                let output = model.forward(&states, legal_move_masks::<B>(&states, device), device);
+                let heads = (output.wdl.is_some(), output.moves_left.is_some());
+                let targets = training_targets(&positions, heads, device);
+                let loss = self.loss.loss(&output, &targets);
+
+                running.add(
+                    loss.clone().into_scalar().elem::<f64>(),
+                    &positions,
+                    &output.policy.into_data().to_vec::<f32>().unwrap(),
+                );
+                let grads = GradientsParams::from_grads(loss.backward(), &model);
+                model = optimizer.step(self.learning_rate, model, grads);
+
+                batches += 1;
+                if self.report_every > 0 && batches % self.report_every == 0 {
+                    progress(PretrainingProgress::Training {
+                        epoch: epoch + 1,
+                        batches,
+                        metrics: &running,
+                    });
+                    running = RunningMetrics::default();
+                }
+            }
+
+            if epoch == 0 {
+                progress(PretrainingProgress::Read {
+                    games: samples.games_read,
+                    kept: samples.games_accepted,
+                    validation: validation.len(),
+                });
+            }
+            if running.positions > 0 {
+                progress(PretrainingProgress::Training {
+                    epoch: epoch + 1,
+                    batches,
+                    metrics: &running,
+                });
+            }
+            metrics = evaluate(&model.valid(), &validation, self.batch_size, device);
+            progress(PretrainingProgress::Validation {
+                epoch: epoch + 1,
+                metrics: &metrics,
+            });
+        }
+
+        Ok((model, metrics))
+    }
+
+    // The next training batch, or None once the games run out. Training positions pass through
+    // `shuffle`, validation positions met on the way are kept up to `keep_validation` in total.
+    fn next_batch<R: BufRead>(
+        &self,
+        samples: &mut PgnSamples<R>,
+        (shuffle, rng): (&mut Vec<TrainingPosition>, &mut PseudoRng),
+        validation: &mut Vec<TrainingPosition>,
+        keep_validation: usize,
+    ) -> io::Result<Option<Vec<TrainingPosition>>> {
+        let mut batch = Vec::with_capacity(self.batch_size);
+        while batch.len() < self.batch_size.max(1) {
+            // Topped up before every draw, it only runs low once the games run out
+            while shuffle.len() < self.shuffle_buffer.max(1) {
+                match samples.next().transpose()? {
+                    Some((position, Split::Training)) => shuffle.push(position),
+                    Some((position, Split::Validation)) => {
+                        if validation.len() < keep_validation {
+                            validation.push(position);
+                        }
+                    }
+                    None => break,
+                }
+            }
+            if shuffle.is_empty() {
+                break;
+            }
+            let i = (rng.next() % shuffle.len() as u64) as usize;
+            batch.push(shuffle.swap_remove(i));
+        }
+        Ok((!batch.is_empty()).then_some(batch))
+    }
+}
+
+// One-hot played moves and game results, with win/draw/loss and moves-left targets for models
+// that have those heads. Moves left are only targeted when every position in the batch knows them.
+fn training_targets<B: Backend>(
+    positions: &[&TrainingPosition],
+    (wdl, moves_left): (bool, bool),
+    device: &B::Device,
+) -> TrainingTargets<B> {
+    let batch_size = positions.len();
+    let fallback = (
+        Tensor::zeros([batch_size, 64 * 73], device),
+        Tensor::zeros([batch_size, 1], device),
+    );
+    let (policy, value) = label_targets(positions, fallback, device);
+
+    let wdl = wdl.then(|| {
+        let rows: Vec<f32> = positions
+            .iter()
+            .flat_map(|position| result_wdl(position.result.unwrap_or(0.0)))
+            .collect();
+        Tensor::from_data(TensorData::new(rows, [batch_size, 3]), device)
+    });
+    let moves_left = positions
+        .iter()
+        .map(|position| position.moves_left)
+        .collect::<Option<Vec<f32>>>()
+        .filter(|_| moves_left)
+        .map(|rows| Tensor::from_data(TensorData::new(rows, [batch_size, 1]), device));
+
+    TrainingTargets {
+        policy,
+        value,
+        wdl,
+        moves_left,
+    }
+}
+
+// Move prediction quality on labelled positions
+#[derive(Default)]
+pub(crate) struct PretrainingMetrics {
+    pub(crate) positions: usize,
+    pub(crate) policy_loss: f64,
+    pub(crate) value_mse: f64,
+    // Share of positions where the played move is the most likely one, or among the three most
+    pub(crate) top1_accuracy: f64,
+    pub(crate) top3_accuracy: f64,
+}
+
+impl std::fmt::Display for PretrainingMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "policy loss {:.4}, value MSE {:.4}, top-1 accuracy {:.2}%, top-3 accuracy {:.2}% \
+             over {} positions",
+            self.policy_loss,
+            self.value_mse,
+            self.top1_accuracy * 100.0,
+            self.top3_accuracy * 100.0,
+            self.positions
+        )
+    }
+}
+
+pub(crate) fn evaluate<B: Backend>(
+    model: &TransformerModel<B>,
+    positions: &[TrainingPosition],
+    batch_size: usize,
+    device: &B::Device,
+) -> PretrainingMetrics {
+    let (mut policy_total, mut value_total) = (0.0, 0.0);
+    let (mut top1, mut top3) = (0, 0);
+    for chunk in positions.chunks(batch_size.max(1)) {
+        let batch: Vec<&TrainingPosition> = chunk.iter().collect();
+        let states: Vec<&State> = batch.iter().map(|position| &position.state).collect();
+        let output = model.forward(&states, legal_move_masks::<B>(&states, device), device);
+        let targets = training_targets::<B>(&batch, (false, false), device);
+
+        // Batch means weighted by the batch size, so a short last batch counts for what it holds
+        let n = batch.len() as f64;
+        policy_total += policy_loss(output.policy.clone(), targets.policy)
+            .into_scalar()
+            .elem::<f64>()
+            * n;
+        value_total += value_loss(output.value, targets.value)
+            .into_scalar()
+            .elem::<f64>()
+            * n;
+
+        let policy = output.policy.into_data().to_vec::<f32>().unwrap();
+        for (position, row) in batch.iter().zip(policy.chunks(64 * 73)) {
+            match played_rank(position, row) {
+                Some(0) => (top1, top3) = (top1 + 1, top3 + 1),
+                Some(1 | 2) => top3 += 1,
+                _ => {}
+            }
+        }
+    }
+
+    let n = positions.len().max(1) as f64;
+    PretrainingMetrics {
+        positions: positions.len(),
+        policy_loss: policy_total / n,
+        value_mse: value_total / n,
+        top1_accuracy: top1 as f64 / n,
+        top3_accuracy: top3 as f64 / n,
+    }
+}
+
+// How many moves the policy prefers over the played one, 0 when it is the top choice
+fn played_rank(position: &TrainingPosition, probabilities: &[f32]) -> Option<usize> {
+    let played = probabilities[position.played?.policy_index()];
+    Some(probabilities.iter().filter(|&&p| p > played).count())
+}
+
+// What `PretrainingConfig::pretrain` has done so far
+pub(crate) enum PretrainingProgress<'a> {
+    // Metrics of the training batches since the last report, `batches` into the epoch
+    Training {
+        epoch: usize,
+        batches: usize,
+        metrics: &'a RunningMetrics,
+    },
+    // The end of the first pass over the games
+    Read {
+        games: usize,
+        kept: usize,
+        validation: usize,
+    },
+    Validation {
+        epoch: usize,
+        metrics: &'a PretrainingMetrics,
+    },
+}
+
+impl std::fmt::Display for PretrainingProgress<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Training {
+                epoch,
+                batches,
+                metrics,
+            } => write!(f, "Epoch {} batch {}: {}", epoch, batches, metrics),
+            Self::Read {
+                games,
+                kept,
+                validation,
+            } => write!(
+                f,
+                "Read {} games, kept {}, {} validation positions",
+                games, kept, validation
+            ),
+            Self::Validation { epoch, metrics } => {
+                write!(f, "Epoch {} validation: {}", epoch, metrics)
+            }
+        }
+    }
+}
+
+// Training metrics averaged over the batches since the last report
+#[derive(Default)]
+pub(crate) struct RunningMetrics {
+    loss: f64,
+    batches: usize,
+    positions: usize,
+    top1: usize,
+}
+
+impl RunningMetrics {
+    fn add(&mut self, loss: f64, positions: &[&TrainingPosition], policy: &[f32]) {
+        self.loss += loss;
+        self.batches += 1;
+        self.positions += positions.len();
+        self.top1 += positions
+            .iter()
+            .zip(policy.chunks(64 * 73))
+            .filter(|(position, row)| played_rank(position, row) == Some(0))
+            .count();
+    }
+}
+
+impl std::fmt::Display for RunningMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "training loss {:.4}, top-1 accuracy {:.2}%",
+            self.loss / self.batches.max(1) as f64,
+            self.top1 as f64 / self.positions.max(1) as f64 * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::model::TransformerModelConfig;
+    use burn::backend::{Autodiff, NdArray};
+
+    type B = Autodiff<NdArray>;
+
+    const GAMES: &str = r#"[Event "Blitz"]
+[WhiteElo "2400"]
+[BlackElo "2350"]
+[TimeControl "180+2"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 1-0
+
+[Event "Bullet"]
+[WhiteElo "2500"]
+[BlackElo "2450"]
+[TimeControl "60+0"]
+[Result "0-1"]
+
+1. d4 d5 2. c4 e6 0-1
+
+[Event "Unrated"]
+[TimeControl "600+5"]
+[Result "1/2-1/2"]
+
+1. c4 c5 2. Nc3 Nc6 1/2-1/2
+
+[Event "Classical"]
+[WhiteElo "2200"]
+[BlackElo "2210"]
+[TimeControl "40/7200:3600"]
+[Result "1/2-1/2"]
+
+1. Nf3 d5 2. g3 Nf6 3. Bg2 e6 1/2-1/2
+
+[Event "Unfinished"]
+[WhiteElo "2300"]
+[BlackElo "2300"]
+
+1. e4 c5 *
+"#;
+
+    #[test]
+    fn test_filters_and_split() {
+        assert_eq!(base_seconds("180+2"), Some(180));
+        assert_eq!(base_seconds("40/7200:3600"), Some(7200));
+        assert_eq!(base_seconds("-"), None);
+
+        // Everything with a result, the classical game is the validation game
+        let samples: Vec<_> = PgnSamples::new(GAMES.as_bytes(), GameFilter::new(), 4)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(samples.len(), 8 + 4 + 4 + 6);
+        let validation: Vec<_> = samples
+            .iter()
+            .filter(|(_, split)| *split == Split::Validation)
+            .collect();
+        assert_eq!(validation.len(), 6);
+        assert_eq!(validation[0].0.result, Some(0.0));
+
+        // The played move is legal in its position and the result is from White's side
+        let (position, _) = &samples[8];
+        assert_eq!(position.result, Some(-1.0));
+        assert_eq!(position.played.unwrap().to_uci(), "d2d4");
+        assert!(position
+            .state
+            .generate_moves()
+            .contains(&position.played.unwrap()));
+        let (position, _) = &samples[9];
+        assert_eq!(position.played.unwrap().to_uci(), "d7d5");
+
+        let filter = GameFilter::new()
+            .with_min_elo(Some(2300))
+            .with_min_base_seconds(Some(120))
+            .with_min_ply(2)
+            .with_max_ply(Some(6));
+        let mut samples = PgnSamples::new(GAMES.as_bytes(), filter, 0);
+        let positions: Vec<_> = samples.by_ref().collect::<io::Result<_>>().unwrap();
+        assert_eq!((samples.games_read, samples.games_accepted), (5, 1));
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[0].0.played.unwrap().to_uci(), "g1f3");
+        assert!(positions.iter().all(|(_, split)| *split == Split::Training));
+
+        // A read error is passed on after the samples of the games before it
+        let mut broken = GAMES.as_bytes().to_vec();
+        broken.extend(b"[Event \"Broken\"]\n\n1. e4 \xff *\n");
+        let samples: Vec<_> = PgnSamples::new(broken.as_slice(), GameFilter::new(), 0).collect();
+        assert_eq!(samples.len(), 8 + 4 + 4 + 6 + 1);
+        assert!(samples.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_shuffle_buffer_mixes_games() {
+        let first_batch = |shuffle_buffer| {
+            let config = PretrainingConfig::new()
+                .with_batch_size(8)
+                .with_shuffle_buffer(shuffle_buffer);
+            let mut samples = PgnSamples::new(GAMES.as_bytes(), GameFilter::new(), 0);
+            let (mut shuffle, mut rng) = (Vec::new(), PseudoRng::new(1));
+            let batch = config
+                .next_batch(&mut samples, (&mut shuffle, &mut rng), &mut Vec::new(), 0)
+                .unwrap()
+                .unwrap();
+            let mut moves: Vec<String> = batch
+                .iter()
+                .map(|position| position.played.unwrap().to_uci())
+                .collect();
+            moves.sort();
+            moves
+        };
+
+        // Without shuffling the first batch is the Ruy Lopez game, ply by ply
+        let ruy_lopez = [
+            "a7a6", "b5a4", "b8c6", "e2e4", "e7e5", "f1b5", "g1f3", "g8f6",
+        ];
+        assert_eq!(first_batch(1), ruy_lopez);
+        assert_ne!(first_batch(1000), ruy_lopez);
+    }
+
+    #[test]
+    fn test_moves_left_targets() {
+        let device = Default::default();
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .with_moves_left_head(true)
+            .init::<NdArray>(&device);
+
+        // The Ruy Lopez game is 8 plies long
+        let positions: Vec<TrainingPosition> =
+            PgnSamples::new(GAMES.as_bytes(), GameFilter::new(), 0)
+                .take(8)
+                .map(|sample| sample.unwrap().0)
+                .collect();
+        let batch: Vec<&TrainingPosition> = positions.iter().collect();
+        let states: Vec<&State> = batch.iter().map(|position| &position.state).collect();
+        let output = model.forward(&states, legal_move_masks(&states, &device), &device);
+
+        let targets = training_targets::<NdArray>(&batch, (false, true), &device);
+        let moves_left = targets.moves_left.clone().unwrap();
+        let moves_left = moves_left.into_data().to_vec::<f32>().unwrap();
+        assert_eq!(moves_left, [8.0, 7.0, 6.0, 5.0, 4.0, 3.0, 2.0, 1.0]);
+        let with = LossConfig::new().loss(&output, &targets).into_scalar();
+        let without = LossConfig::new()
+            .with_moves_left_weight(0.0)
+            .loss(&output, &targets)
+            .into_scalar();
+        assert!(with > without, "{} <= {}", with, without);
+
+        let unlabelled = TrainingPosition::new(State::new());
+        let targets = training_targets::<NdArray>(&[&unlabelled], (false, true), &device);
+        assert!(targets.moves_left.is_none());
+    }
+
+    #[test]
+    fn test_pretraining_learns_played_moves() {
+        let device = Default::default();
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .init::<B>(&device);
+
+        // Validate on the training games themselves to see the moves being memorised
+        let training: Vec<TrainingPosition> =
+            PgnSamples::new(GAMES.as_bytes(), GameFilter::new(), 0)
+                .map(|sample| sample.unwrap().0)
+                .collect();
+        let before = evaluate(&model.valid(), &training, 8, &device);
+
+        assert!(PretrainingConfig::new().validate().is_ok());
+        assert!(PretrainingConfig::new().with_epochs(0).validate().is_err());
+        let empty_plies = GameFilter::new().with_min_ply(10).with_max_ply(Some(10));
+        assert!(PretrainingConfig::new()
+            .with_filter(empty_plies)
+            .validate()
+            .is_err());
+
+        let config = PretrainingConfig::new()
+            .with_validation_every(2)
+            .with_batch_size(8)
+            .with_epochs(5)
+            .with_learning_rate(3e-3)
+            .with_report_every(0);
+        let mut reports = Vec::new();
+        let (model, metrics) = config
+            .pretrain(
+                model,
+                || Ok(GAMES.as_bytes()),
+                &device,
+                |progress| reports.push(progress.to_string()),
+            )
+            .unwrap();
+        assert_eq!(metrics.positions, 4 + 6);
+        assert!(metrics.policy_loss.is_finite());
+        assert!(metrics.top1_accuracy <= metrics.top3_accuracy);
+        // The games read, then the training and validation metrics of every epoch
+        assert_eq!(reports.len(), 1 + 2 * 5);
+        assert!(reports[0].starts_with("Read "));
+        assert!(reports[1].starts_with("Epoch 1 batch "));
+        assert!(reports[10].starts_with("Epoch 5 validation: "));
+
+        let after = evaluate(&model.valid(), &training, 8, &device);
+        assert_eq!(after.positions, training.len());
+        assert!(
+            after.policy_loss < before.policy_loss,
+            "{} -> {}",
+            before,
+            after
+        );
+    }
+}
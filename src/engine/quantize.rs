@@ -0,0 +1,497 @@
+use crate::{
+    chess::State,
+    engine::{model::TransformerModel, policy::legal_move_masks, tuning::parse_labelled_line},
+};
+use burn::{
+    config::Config,
+    module::{Module, ModuleMapper, ModuleVisitor, Param},
+    tensor::{
+        backend::Backend,
+        ops::QuantizedTensor,
+        quantization::{
+            compute_q_params, compute_range, Calibration, QTensorPrimitive, QuantLevel, QuantParam,
+            QuantStore, QuantValue,
+        },
+    },
+    Tensor,
+};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+// Weight-only int8 quantization of the linear layers that dominate CPU inference: the
+// feed-forwards of every attention block and the policy head. Activations, embeddings, norms and
+// the attention projections stay in f32.
+#[derive(Config, Debug)]
+pub(crate) struct QuantizationConfig {
+    // Candidates tried by `calibrate`, coarsest first. 0 means one scale per weight matrix, any
+    // other value, at most 255, one scale per block of that many weights along the output
+    // dimension. Layers whose output the block size does not divide get one scale per matrix.
+    #[config(default = "vec![0, 128, 64, 32]")]
+    block_sizes: Vec<usize>,
+    #[config(default = 0.98)]
+    min_top1_agreement: f64,
+    #[config(default = 1e-4)]
+    max_value_mse: f64,
+    #[config(default = 64)]
+    batch_size: usize,
+}
+
+impl QuantizationConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.block_sizes.is_empty() {
+            return Err("at least one block size is needed".to_string());
+        }
+        match self
+            .block_sizes
+            .iter()
+            .find(|&&size| size > u8::MAX as usize)
+        {
+            Some(size) => Err(format!(
+                "block size {} is above the largest supported, {}",
+                size,
+                u8::MAX
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Quantizes with each candidate block size in turn and keeps the first, cheapest, one whose
+    // predictions on the calibration positions stay within the targets, or else the most accurate.
+    // A block size that divides the output of none of the quantized layers would only repeat the
+    // per-matrix candidate, so it is rejected with the invalid ones. `progress` sees the report of
+    // every candidate tried.
+    pub(crate) fn calibrate<B: Backend>(
+        &self,
+        model: &TransformerModel<B>,
+        positions: &[State],
+        device: &B::Device,
+        mut progress: impl FnMut(&QuantizationReport),
+    ) -> Result<(TransformerModel<B>, QuantizationReport), String> {
+        self.validate()?;
+        let mut sizes = OutputSizes::default();
+        model.visit(&mut sizes);
+        for &block_size in &self.block_sizes {
+            if block_size > 0 && sizes.sizes.iter().all(|size| size % block_size != 0) {
+                return Err(format!(
+                    "block size {} divides none of the quantized layer outputs {:?}",
+                    block_size, sizes.sizes
+                ));
+            }
+        }
+
+        let mut best: Option<(TransformerModel<B>, QuantizationReport)> = None;
+        for &block_size in &self.block_sizes {
+            let (quantized, layers) = quantize_linear_weights(model.clone(), block_size);
+            let report =
+                compare(model, &quantized, positions, self.batch_size, device).with_layers(layers);
+            progress(&report);
+
+            if report.top1_agreement >= self.min_top1_agreement
+                && report.value_mse <= self.max_value_mse
+            {
+                return Ok((quantized, report));
+            }
+            if best
+                .as_ref()
+                .is_none_or(|(_, best)| report.is_more_accurate_than(best))
+            {
+                best = Some((quantized, report));
+            }
+        }
+        Ok(best.unwrap())
+    }
+}
+
+// Quantizes with one scale per `block_size` weights where the block size tiles the layer output,
+// and returns the block size each layer actually got, 0 for one scale per matrix
+pub(crate) fn quantize_linear_weights<B: Backend>(
+    model: TransformerModel<B>,
+    block_size: usize,
+) -> (TransformerModel<B>, Vec<(String, usize)>) {
+    let mut quantizer = LinearQuantizer {
+        block_size,
+        path: Vec::new(),
+        applied: Vec::new(),
+    };
+    let model = model.map(&mut quantizer);
+    (model, quantizer.applied)
+}
+
+// attention_blocks.<i>.ff1.weight, attention_blocks.<i>.ff2.weight and policy_head.ff.weight, by
+// the field names from the model root down to the parameter
+fn is_target(path: &[String]) -> bool {
+    match path {
+        [blocks, _, linear, weight] => {
+            blocks == "attention_blocks"
+                && (linear == "ff1" || linear == "ff2")
+                && weight == "weight"
+        }
+        [head, linear, weight] => head == "policy_head" && linear == "ff" && weight == "weight",
+        _ => false,
+    }
+}
+
+// Output dimensions of the layers `quantize_linear_weights` quantizes
+#[derive(Default)]
+struct OutputSizes {
+    path: Vec<String>,
+    sizes: Vec<usize>,
+}
+
+impl<B: Backend> ModuleVisitor<B> for OutputSizes {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        if is_target(&self.path) {
+            self.sizes.push(param.shape().dims[D - 1]);
+        }
+    }
+}
+
+struct LinearQuantizer {
+    block_size: usize,
+    path: Vec<String>,
+    // Layer path and the block size it was quantized with, 0 for one scale per matrix
+    applied: Vec<(String, usize)>,
+}
+
+impl<B: Backend> ModuleMapper<B> for LinearQuantizer {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<B, D>>) -> Param<Tensor<B, D>> {
+        let (id, tensor, mapper) = param.consume();
+        if !is_target(&self.path) {
+            return Param::from_mapped_value(id, tensor, mapper);
+        }
+
+        // Blocks have to tile the output dimension, the policy head's 73 planes fall back to one
+        // scale per matrix
+        let output_size = tensor.dims()[D - 1];
+        let (level, applied) = match u8::try_from(self.block_size) {
+            Ok(block_size) if block_size > 0 && output_size % self.block_size == 0 => {
+                (QuantLevel::block([block_size]), self.block_size)
+            }
+            _ => (QuantLevel::Tensor, 0),
+        };
+        let layer = self.path[..self.path.len() - 1].join(".");
+        self.applied.push((layer, applied));
+        let scheme = <QuantizedTensor<B> as QTensorPrimitive>::default_scheme()
+            .with_value(QuantValue::Q8S)
+            .with_level(level)
+            .with_param(QuantParam::F32)
+            .with_store(QuantStore::Native);
+
+        let range = compute_range(&scheme, &tensor, &Calibration::MinMax);
+        let qparams = compute_q_params(&scheme, range);
+        Param::from_mapped_value(id, tensor.quantize(&scheme, qparams), mapper)
+    }
+}
+
+pub(crate) struct QuantizationReport {
+    pub(crate) positions: usize,
+    // Layer path and the block size it was quantized with, 0 for one scale per matrix
+    pub(crate) layers: Vec<(String, usize)>,
+    // Share of positions where both models put the most probability on the same move
+    pub(crate) top1_agreement: f64,
+    pub(crate) value_mse: f64,
+    pub(crate) max_value_error: f64,
+}
+
+impl QuantizationReport {
+    fn with_layers(mut self, layers: Vec<(String, usize)>) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    fn is_more_accurate_than(&self, other: &Self) -> bool {
+        (self.top1_agreement, -self.value_mse) > (other.top1_agreement, -other.value_mse)
+    }
+}
+
+impl std::fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Layers grouped by the block size they got, in the order the sizes first appear
+        let mut counts: Vec<(usize, usize)> = Vec::new();
+        for &(_, size) in &self.layers {
+            match counts.iter_mut().find(|(counted, _)| *counted == size) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((size, 1)),
+            }
+        }
+        let granularity = counts
+            .iter()
+            .map(|&(size, count)| {
+                let layers = if count == 1 { "layer" } else { "layers" };
+                match size {
+                    0 => format!("per tensor ({} {})", count, layers),
+                    size => format!("blocks of {} ({} {})", size, count, layers),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            f,
+            "int8 {}: top-1 agreement {:.2}%, value MSE {:.3e}, max value error {:.4} over {} \
+             positions",
+            granularity,
+            self.top1_agreement * 100.0,
+            self.value_mse,
+            self.max_value_error,
+            self.positions
+        )
+    }
+}
+
+// Accuracy of the quantized model measured against the f32 one
+pub(crate) fn compare<B: Backend>(
+    reference: &TransformerModel<B>,
+    quantized: &TransformerModel<B>,
+    positions: &[State],
+    batch_size: usize,
+    device: &B::Device,
+) -> QuantizationReport {
+    let (mut agreements, mut squared_error, mut max_error) = (0, 0.0, 0.0f64);
+    for chunk in positions.chunks(batch_size.max(1)) {
+        let states: Vec<&State> = chunk.iter().collect();
+        let mask = legal_move_masks::<B>(&states, device);
+        let expected = reference.forward(&states, mask.clone(), device);
+        let actual = quantized.forward(&states, mask, device);
+
+        let expected_moves = expected.policy.argmax(1).into_data().convert::<i64>();
+        let actual_moves = actual.policy.argmax(1).into_data().convert::<i64>();
+        agreements += expected_moves
+            .as_slice::<i64>()
+            .unwrap()
+            .iter()
+            .zip(actual_moves.as_slice::<i64>().unwrap())
+            .filter(|(a, b)| a == b)
+            .count();
+
+        let expected_values = expected.value.into_data().to_vec::<f32>().unwrap();
+        let actual_values = actual.value.into_data().to_vec::<f32>().unwrap();
+        for (a, b) in expected_values.iter().zip(&actual_values) {
+            let error = (a - b) as f64;
+            squared_error += error * error;
+            max_error = max_error.max(error.abs());
+        }
+    }
+
+    let n = positions.len().max(1) as f64;
+    QuantizationReport {
+        positions: positions.len(),
+        layers: Vec::new(),
+        top1_agreement: agreements as f64 / n,
+        value_mse: squared_error / n,
+        max_value_error: max_error,
+    }
+}
+
+// Calibration positions, one FEN or EPD record per line. Anything after the board, side to move,
+// castling rights and en passant square other than the two move counters is ignored. Blank lines
+// and `#` comments are skipped, while a malformed line or a file without a single position is an
+// error rather than a shorter list.
+pub(crate) fn read_positions(path: impl AsRef<Path>, limit: usize) -> io::Result<Vec<State>> {
+    let positions = read_labelled_positions(path, limit)?;
+    Ok(positions.into_iter().map(|(state, _)| state).collect())
+}
+
+// The positions of `read_positions`, each with the result written after it as a White score in
+// [0, 1] if there is one, in any form `TexelTuner::from_reader` takes
+pub(crate) fn read_labelled_positions(
+    path: impl AsRef<Path>,
+    limit: usize,
+) -> io::Result<Vec<(State, Option<f64>)>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut positions = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        if positions.len() == limit {
+            break;
+        }
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let invalid = |message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, message),
+            )
+        };
+        match tokens.first() {
+            None => continue,
+            Some(token) if token.starts_with('#') => continue,
+            Some(_) if tokens.len() < 4 => {
+                return Err(invalid(format!("no position in '{}'", line.trim())))
+            }
+            Some(_) => {}
+        }
+        let fen = match tokens.get(4..6) {
+            Some([halfmove, fullmove])
+                if halfmove.parse::<u32>().is_ok() && fullmove.parse::<u32>().is_ok() =>
+            {
+                tokens[..6].join(" ")
+            }
+            _ => tokens[..4].join(" "),
+        };
+        let state = State::from_fen(&fen).map_err(|err| invalid(err.to_string()))?;
+        let result = parse_labelled_line(&line).map(|(_, result)| result);
+        positions.push((state, result));
+    }
+    match positions.is_empty() && limit > 0 {
+        true => Err(io::Error::new(io::ErrorKind::InvalidData, "no positions")),
+        false => Ok(positions),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::model::TransformerModelConfig;
+    use burn::{backend::NdArray, module::ModuleVisitor};
+
+    struct CountQuantized(usize);
+
+    impl<B: Backend> ModuleVisitor<B> for CountQuantized {
+        fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+            if matches!(param.val().dtype(), burn::tensor::DType::QFloat(_)) {
+                self.0 += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantized_model_tracks_f32_model() {
+        let device = Default::default();
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(2)
+            .with_n_heads(2)
+            .with_head_dimension(16)
+            .init::<NdArray>(&device);
+
+        // Two feed-forward layers per block and the policy head, whose 73 planes no block tiles
+        let (quantized, layers) = quantize_linear_weights(model.clone(), 32);
+        let mut count = CountQuantized(0);
+        quantized.visit(&mut count);
+        assert_eq!(count.0, 2 * 2 + 1);
+        assert_eq!(layers.len(), 2 * 2 + 1);
+        assert_eq!(layers[0], ("attention_blocks.0.ff1".to_string(), 32));
+        assert_eq!(layers[4], ("policy_head.ff".to_string(), 0));
+
+        let mut positions = vec![State::new()];
+        for uci in ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"] {
+            let mut state = positions.last().unwrap().clone();
+            state.make_move(state.parse_uci(uci).unwrap());
+            positions.push(state);
+        }
+
+        let report = compare(&model, &quantized, &positions, 4, &device);
+        assert_eq!(report.positions, positions.len());
+        assert!(report.top1_agreement > 0.5);
+        assert!(report.value_mse < 1e-3);
+        assert!(report.max_value_error < 0.1);
+
+        let mut tried = 0;
+        let (_, report) = QuantizationConfig::new()
+            .with_block_sizes(vec![0, 32])
+            .with_min_top1_agreement(0.0)
+            .with_max_value_mse(1.0)
+            .calibrate(&model, &positions, &device, |_| tried += 1)
+            .unwrap();
+        // The coarsest candidate already meets such loose targets
+        assert_eq!(tried, 1);
+        assert!(report.layers.iter().all(|(_, size)| *size == 0));
+        assert!(report.to_string().starts_with("int8 per tensor (5 layers)"));
+
+        let (_, report) = QuantizationConfig::new()
+            .with_block_sizes(vec![32])
+            .with_min_top1_agreement(1.1)
+            .calibrate(&model, &positions, &device, |_| {})
+            .unwrap();
+        assert!(report
+            .to_string()
+            .starts_with("int8 blocks of 32 (4 layers), per tensor (1 layer)"));
+
+        // Above the u8 limit, or tiling no layer of this 32 wide model
+        for block_sizes in [vec![0, 256], vec![0, 128], vec![]] {
+            let config = QuantizationConfig::new().with_block_sizes(block_sizes);
+            assert!(config
+                .calibrate(&model, &positions, &device, |_| {})
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_quantized_checkpoint_round_trip() {
+        use crate::engine::checkpoint::{load_checkpoint, save_checkpoint, Precision};
+
+        let dir = std::env::temp_dir().join(format!("chess-ai-int8-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let device = Default::default();
+        let config = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(16);
+        let model = config.init::<NdArray>(&device);
+        let (quantized, _) = quantize_linear_weights(model.clone(), 32);
+
+        // Saved over full-precision weights, which would otherwise be loaded instead
+        save_checkpoint(&model, &config, &dir, Precision::Full).unwrap();
+        save_checkpoint(&quantized, &config, &dir, Precision::Int8).unwrap();
+        let loaded = load_checkpoint::<NdArray>(&dir, &config, &device).unwrap();
+        let mut count = CountQuantized(0);
+        loaded.visit(&mut count);
+        assert_eq!(count.0, 2 + 1);
+
+        let state = State::new();
+        let value = |model: &TransformerModel<NdArray>| {
+            let mask = legal_move_masks::<NdArray>(&[&state], &device);
+            model.forward(&[&state], mask, &device).value.into_scalar()
+        };
+        assert_eq!(value(&loaded), value(&quantized));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_labelled_positions() {
+        let path = std::env::temp_dir().join(format!("chess-ai-labelled-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "# comment\n\
+             rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\n\
+             4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 \"1-0\"\n\
+             4k3/8/8/8/8/8/8/4K3 b - - 1/2-1/2\n",
+        )
+        .unwrap();
+
+        let positions = read_labelled_positions(&path, usize::MAX).unwrap();
+        let results: Vec<Option<f64>> = positions.iter().map(|(_, result)| *result).collect();
+        assert_eq!(results, [None, Some(1.0), Some(0.5)]);
+        assert_eq!(positions[2].0.to_fen(), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(read_positions(&path, 2).unwrap().len(), 2);
+
+        std::fs::write(&path, "# nothing but a comment\n\n").unwrap();
+        let err = read_positions(&path, usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::write(&path, "4k3/8/8/8/8/8/8/4K3 w - -\n\n4k3/8/8 w\n").unwrap();
+        let err = read_positions(&path, usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 3:"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
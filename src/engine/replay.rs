@@ -0,0 +1,832 @@
+use crate::{
+    chess::{
+        moves::Move,
+        prng::PseudoRng,
+        symmetry::Symmetry,
+        syzygy::Tablebase,
+        types::{Color, GameResult},
+        State,
+    },
+    engine::{
+        policy::{transform_policy_index, PolicyEncoding},
+        training::{result_wdl, TrainingTargets},
+    },
+};
+use burn::{
+    config::Config,
+    tensor::{backend::Backend, TensorData},
+    Tensor,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const CHUNK_EXTENSION: &str = "chunk";
+const STATE_FILE: &str = "replay-state.json";
+const POLICY_SIZE: usize = 64 * 73;
+
+// Move played at a ply with the search's visit distribution over the legal moves
+type Ply = (Move, Vec<(Move, f32)>);
+
+// A finished self-play game. In a chunk file it is written as
+//   fen <root fen>
+//   ply <played uci> <uci>:<visit share>...    (once per move)
+//   result <1-0 | 0-1 | 1/2-1/2>
+// followed by a blank line.
+pub(crate) struct SelfPlayGame {
+    pub(crate) root: State,
+    pub(crate) plies: Vec<Ply>,
+    pub(crate) result: GameResult,
+}
+
+impl SelfPlayGame {
+    pub(crate) fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "fen {}", self.root.to_fen())?;
+        for (played, visits) in &self.plies {
+            write!(out, "ply {}", played.to_uci())?;
+            for (mv, share) in visits {
+                write!(out, " {}:{}", mv.to_uci(), share)?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out, "result {}\n", self.result)
+    }
+}
+
+pub(crate) fn read_games(reader: impl BufRead) -> io::Result<Vec<SelfPlayGame>> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid self-play line '{}'", line),
+        )
+    };
+
+    let mut games = Vec::new();
+    // Root, position reached so far and plies of the game being read
+    let mut current: Option<(State, State, Vec<Ply>)> = None;
+    for line in reader.lines() {
+        let line = line?;
+        let (tag, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        match (tag, &mut current) {
+            ("", _) => {}
+            ("fen", None) => {
+                let root = State::from_fen(rest).map_err(|_| invalid(&line))?;
+                current = Some((root.clone(), root, Vec::new()));
+            }
+            ("ply", Some((_, state, plies))) => {
+                let mut tokens = rest.split_whitespace();
+                let played = tokens
+                    .next()
+                    .and_then(|uci| state.parse_uci(uci))
+                    .ok_or_else(|| invalid(&line))?;
+                let visits = tokens
+                    .map(|token| {
+                        let (uci, share) = token.split_once(':')?;
+                        Some((state.parse_uci(uci)?, share.parse().ok()?))
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| invalid(&line))?;
+                state.make_move(played);
+                plies.push((played, visits));
+            }
+            ("result", Some(_)) => {
+                let result = GameResult::from_pgn(rest.trim()).ok_or_else(|| invalid(&line))?;
+                let (root, _, plies) = current.take().unwrap();
+                games.push(SelfPlayGame {
+                    root,
+                    plies,
+                    result,
+                });
+            }
+            _ => return Err(invalid(&line)),
+        }
+    }
+
+    match current {
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "self-play game without a result",
+        )),
+        None => Ok(games),
+    }
+}
+
+// The chunks in `dir`, oldest first since their names start with the time they were written
+fn chunk_paths(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut chunks: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    chunks.retain(|path| path.extension().is_some_and(|ext| ext == CHUNK_EXTENSION));
+    chunks.sort();
+    Ok(chunks)
+}
+
+fn read_chunk(path: &Path) -> io::Result<Vec<SelfPlayGame>> {
+    let error = |err: io::Error| io::Error::new(err.kind(), format!("{}: {}", path.display(), err));
+    read_games(BufReader::new(File::open(path).map_err(error)?)).map_err(error)
+}
+
+// Every game of every chunk in `dir`, oldest first
+pub(crate) fn read_chunks(dir: impl AsRef<Path>) -> io::Result<Vec<SelfPlayGame>> {
+    let mut games = Vec::new();
+    for path in chunk_paths(dir.as_ref())? {
+        games.extend(read_chunk(&path)?);
+    }
+    Ok(games)
+}
+
+// Writes the games as a new chunk in `dir`. The chunk appears under its final name only once it
+// is complete, so a buffer scanning the directory never reads half a file. Names sort by time, the
+// sequence number keeps chunks of concurrent writers in one process apart.
+pub(crate) fn write_chunk(dir: impl AsRef<Path>, games: &[SelfPlayGame]) -> io::Result<PathBuf> {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    let name = format!(
+        "{:024}-{}-{}",
+        nanos,
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let partial = dir.join(format!("{}.tmp", name));
+    let mut out = BufWriter::new(File::create(&partial)?);
+    for game in games {
+        game.write(&mut out)?;
+    }
+    out.into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+
+    let path = dir.join(format!("{}.{}", name, CHUNK_EXTENSION));
+    fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+#[derive(Config, Debug)]
+pub(crate) struct ReplayBufferConfig {
+    // Only the positions of this many most recent games are sampled from
+    #[config(default = 10000)]
+    window_games: usize,
+    // None samples the window uniformly, otherwise a position's weight halves every this many
+    // games since it was last played
+    recency_half_life: Option<f64>,
+    // Merges transpositions and repeats into one position, keyed by Zobrist hash
+    #[config(default = true)]
+    deduplicate: bool,
+    // Merged positions train on the mean of their targets instead of the most recent one
+    #[config(default = true)]
+    average_duplicates: bool,
+    // Serves each sampled position under a random board symmetry it allows, see `State::allows`
+    #[config(default = false)]
+    augment: bool,
+    // Directory of Syzygy tables, positions they cover train on the tablebase result instead of
+    // the game's
+    syzygy: Option<String>,
+    #[config(default = 0x9e3779b97f4a7c15)]
+    seed: u64,
+    // Deletes a chunk once all its games have left the window. Off by default, the chunks are the
+    // only record of the games and `book` or another trainer may still want them.
+    #[config(default = false)]
+    prune_chunks: bool,
+}
+
+// Sampling state kept next to the chunks, so a restarted trainer continues the same sequence of
+// minibatches
+#[derive(Config, Debug)]
+struct ReplayState {
+    rng_state: u64,
+    batches: u64,
+}
+
+// What one game says about a position
+struct Contribution {
+    game: u64,
+    // Sparse visit distribution by policy index
+    policy: Vec<(usize, f32)>,
+    // From White's point of view
+    result: f32,
+    // Plies until the game ended
+    moves_left: f32,
+}
+
+struct Entry {
+    key: u64,
+    state: State,
+    contributions: Vec<Contribution>,
+}
+
+impl Entry {
+    fn last_game(&self) -> u64 {
+        self.contributions.last().map_or(0, |c| c.game)
+    }
+
+    // Either every contribution, or just the most recent
+    fn targets(&self, average: bool) -> &[Contribution] {
+        match average {
+            true => &self.contributions,
+            false => &self.contributions[self.contributions.len() - 1..],
+        }
+    }
+}
+
+// Sliding window over the self-play games in a directory of chunks, see `write_chunk`. Chunks stay
+// on disk after their games leave the window unless `prune_chunks` is set, but only the newest
+// ones holding a window's worth of games are ever read.
+pub(crate) struct ReplayBuffer {
+    config: ReplayBufferConfig,
+    dir: PathBuf,
+    ingested: HashSet<PathBuf>,
+    // Ingested chunks with games still in the window and the number after their last game
+    chunks: VecDeque<(PathBuf, u64)>,
+    // Game number and position keys of every game in the window, oldest first
+    games: VecDeque<(u64, Vec<u64>)>,
+    next_game: u64,
+    entries: Vec<Entry>,
+    index: HashMap<u64, usize>,
+    next_key: u64,
+    rng: PseudoRng,
+    batches: u64,
+    tablebase: Option<Tablebase>,
+}
+
+impl ReplayBuffer {
+    // Rebuilds the window from the newest chunks in `dir` and resumes the sampling state saved by
+    // `save_state`, if any
+    pub(crate) fn open(dir: impl AsRef<Path>, config: ReplayBufferConfig) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let (rng_state, batches) = match ReplayState::load(dir.join(STATE_FILE)) {
+            Ok(state) => (state.rng_state, state.batches),
+            Err(_) => (config.seed.max(1), 0),
+        };
+        let tablebase = config.syzygy.as_ref().map(Tablebase::open).transpose()?;
+
+        let mut buffer = Self {
+            config,
+            dir,
+            ingested: HashSet::new(),
+            chunks: VecDeque::new(),
+            games: VecDeque::new(),
+            next_game: 0,
+            entries: Vec::new(),
+            index: HashMap::new(),
+            next_key: 0,
+            rng: PseudoRng::new(rng_state),
+            batches,
+            tablebase,
+        };
+        buffer.refresh()?;
+        Ok(buffer)
+    }
+
+    // Ingests the chunks written since the last call, oldest first, and returns how many games
+    // they added. Chunks that newer ones would push out of the window right away are skipped
+    // unread.
+    pub(crate) fn refresh(&mut self) -> io::Result<usize> {
+        let mut chunks = chunk_paths(&self.dir)?;
+        chunks.retain(|path| !self.ingested.contains(path));
+
+        // Newest first, until they fill the window
+        let window = self.config.window_games.max(1);
+        let (mut read, mut skipped) = (Vec::new(), Vec::new());
+        let mut newer = 0;
+        for path in chunks.into_iter().rev() {
+            match newer < window {
+                true => {
+                    let games = read_chunk(&path)?;
+                    newer += games.len();
+                    read.push((path, games));
+                }
+                false => skipped.push(path),
+            }
+        }
+
+        let mut added = 0;
+        for path in skipped.into_iter().rev() {
+            self.ingested.insert(path.clone());
+            self.chunks.push_back((path, self.next_game));
+        }
+        for (path, games) in read.into_iter().rev() {
+            for game in &games {
+                self.add_game(game);
+            }
+            added += games.len();
+            self.ingested.insert(path.clone());
+            self.chunks.push_back((path, self.next_game));
+        }
+        self.retire_chunks()?;
+        Ok(added)
+    }
+
+    // Forgets the chunks whose games have all left the window, deleting them with `prune_chunks`
+    fn retire_chunks(&mut self) -> io::Result<()> {
+        let oldest = self
+            .games
+            .front()
+            .map_or(self.next_game, |(number, _)| *number);
+        while let Some((path, _)) = self.chunks.front().filter(|(_, end)| *end <= oldest) {
+            if self.config.prune_chunks {
+                match fs::remove_file(path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {
+                        self.ingested.remove(path);
+                    }
+                }
+            }
+            self.chunks.pop_front();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_game(&mut self, game: &SelfPlayGame) {
+        let number = self.next_game;
+        self.next_game += 1;
+        let result = match game.result {
+            GameResult::WhiteWin => 1.0,
+            GameResult::BlackWin => -1.0,
+            GameResult::Draw => 0.0,
+        };
+
+        let mut state = game.root.clone();
+        let mut keys = Vec::with_capacity(game.plies.len());
+        for (ply, (played, visits)) in game.plies.iter().enumerate() {
+            let key = match self.config.deduplicate {
+                true => state.hash(),
+                false => {
+                    self.next_key += 1;
+                    self.next_key
+                }
+            };
+            let exact = self
+                .tablebase
+                .as_ref()
+                .and_then(|tablebase| tablebase.value_target(&mut state))
+                .map(|value| match state.turn {
+                    Color::White => value,
+                    Color::Black => -value,
+                });
+            let contribution = Contribution {
+                game: number,
+                policy: visits
+                    .iter()
+                    .map(|(mv, share)| (mv.policy_index(), *share))
+                    .collect(),
+                result: exact.unwrap_or(result),
+                moves_left: (game.plies.len() - ply) as f32,
+            };
+
+            match self.index.get(&key) {
+                Some(&i) => self.entries[i].contributions.push(contribution),
+                None => {
+                    self.index.insert(key, self.entries.len());
+                    self.entries.push(Entry {
+                        key,
+                        state: state.clone(),
+                        contributions: vec![contribution],
+                    });
+                }
+            }
+            // A position repeated within the game still counts once for that game
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+            state.make_move(*played);
+        }
+        self.games.push_back((number, keys));
+
+        while self.games.len() > self.config.window_games.max(1) {
+            self.evict_oldest();
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let Some((number, keys)) = self.games.pop_front() else {
+            return;
+        };
+        for key in keys {
+            let Some(&i) = self.index.get(&key) else {
+                continue;
+            };
+            let entry = &mut self.entries[i];
+            entry.contributions.retain(|c| c.game != number);
+            if entry.contributions.is_empty() {
+                self.index.remove(&key);
+                self.entries.swap_remove(i);
+                if let Some(moved) = self.entries.get(i) {
+                    self.index.insert(moved.key, i);
+                }
+            }
+        }
+    }
+
+    // Distinct positions in the window
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn games(&self) -> usize {
+        self.games.len()
+    }
+
+    // Positions drawn at random from the window, with replacement as in AlphaZero, together with
+    // their policy, value, win/draw/loss and moves-left targets. None while the buffer is empty.
+    pub(crate) fn minibatch<B: Backend>(
+        &mut self,
+        batch_size: usize,
+        device: &B::Device,
+    ) -> Option<(Vec<State>, TrainingTargets<B>)> {
+        let picks = self.sample(batch_size)?;
+
+        let mut states = Vec::with_capacity(batch_size);
+        let mut policy = vec![0f32; batch_size * POLICY_SIZE];
+        let mut value = vec![0f32; batch_size];
+        let mut wdl = vec![0f32; batch_size * 3];
+        let mut moves_left = vec![0f32; batch_size];
+        for (row, &i) in picks.iter().enumerate() {
+            let symmetry = match self.config.augment {
+                true => random_symmetry(&mut self.rng, &self.entries[i].state),
+                false => Symmetry::Identity,
+            };
+            let entry = &self.entries[i];
+            let targets = entry.targets(self.config.average_duplicates);
+            let weight = 1.0 / targets.len() as f32;
+            for contribution in targets {
+                for &(index, share) in &contribution.policy {
+                    policy[row * POLICY_SIZE + transform_policy_index(index, symmetry)] +=
+                        share * weight;
+                }
+                value[row] += contribution.result * weight;
+                moves_left[row] += contribution.moves_left * weight;
+                for (k, p) in result_wdl(contribution.result).into_iter().enumerate() {
+                    wdl[row * 3 + k] += p * weight;
+                }
+            }
+            states.push(match symmetry {
+                Symmetry::Identity => entry.state.clone(),
+                _ => entry.state.transformed(symmetry),
+            });
+        }
+        self.batches += 1;
+
+        let targets = TrainingTargets {
+            policy: Tensor::from_data(TensorData::new(policy, [batch_size, POLICY_SIZE]), device),
+            value: Tensor::from_data(TensorData::new(value, [batch_size, 1]), device),
+            wdl: Some(Tensor::from_data(
+                TensorData::new(wdl, [batch_size, 3]),
+                device,
+            )),
+            moves_left: Some(Tensor::from_data(
+                TensorData::new(moves_left, [batch_size, 1]),
+                device,
+            )),
+        };
+        Some((states, targets))
+    }
+
+    fn sample(&mut self, batch_size: usize) -> Option<Vec<usize>> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let Some(half_life) = self.config.recency_half_life else {
+            let n = self.entries.len() as u64;
+            return Some(
+                (0..batch_size)
+                    .map(|_| (self.rng.next() % n) as usize)
+                    .collect(),
+            );
+        };
+
+        let newest = self.next_game.saturating_sub(1);
+        let mut cumulative = Vec::with_capacity(self.entries.len());
+        let mut total = 0.0;
+        for entry in &self.entries {
+            let age = (newest - entry.last_game()) as f64;
+            total += 0.5f64.powf(age / half_life.max(f64::MIN_POSITIVE));
+            cumulative.push(total);
+        }
+        Some(
+            (0..batch_size)
+                .map(|_| {
+                    let target = self.uniform() * total;
+                    cumulative
+                        .partition_point(|&weight| weight <= target)
+                        .min(self.entries.len() - 1)
+                })
+                .collect(),
+        )
+    }
+
+    // In [0, 1)
+    fn uniform(&mut self) -> f64 {
+        (self.rng.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Minibatches served so far, including those before a restart
+    pub(crate) const fn batches(&self) -> u64 {
+        self.batches
+    }
+
+    pub(crate) fn save_state(&self) -> io::Result<()> {
+        ReplayState::new(self.rng.state(), self.batches).save(self.dir.join(STATE_FILE))
+    }
+}
+
+fn random_symmetry(rng: &mut PseudoRng, state: &State) -> Symmetry {
+    let allowed: Vec<Symmetry> = Symmetry::ALL
+        .into_iter()
+        .filter(|&symmetry| state.allows(symmetry))
+        .collect();
+    allowed[(rng.next() % allowed.len() as u64) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::backend::NdArray;
+
+    // Plays the moves with all the visits on the played move
+    fn game(ucis: &[&str], result: GameResult) -> SelfPlayGame {
+        game_from(State::new(), ucis, result)
+    }
+
+    fn game_from(root: State, ucis: &[&str], result: GameResult) -> SelfPlayGame {
+        let mut state = root.clone();
+        let plies = ucis
+            .iter()
+            .map(|uci| {
+                let mv = state.parse_uci(uci).unwrap();
+                state.make_move(mv);
+                (mv, vec![(mv, 1.0)])
+            })
+            .collect();
+        SelfPlayGame {
+            root,
+            plies,
+            result,
+        }
+    }
+
+    #[test]
+    fn test_chunks_round_trip() {
+        let mut out = Vec::new();
+        let mut written = game(&["e2e4", "e7e5"], GameResult::Draw);
+        let mut state = State::new();
+        let (d4, e4) = (
+            state.parse_uci("d2d4").unwrap(),
+            state.parse_uci("e2e4").unwrap(),
+        );
+        written.plies[0].1 = vec![(e4, 0.75), (d4, 0.25)];
+        written.write(&mut out).unwrap();
+        game(&["g1f3"], GameResult::WhiteWin)
+            .write(&mut out)
+            .unwrap();
+
+        let games = read_games(out.as_slice()).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].result, GameResult::Draw);
+        assert_eq!(games[0].plies.len(), 2);
+        assert_eq!(games[0].plies[0].1, vec![(e4, 0.75), (d4, 0.25)]);
+        state.make_move(e4);
+        assert_eq!(games[0].plies[1].0, state.parse_uci("e7e5").unwrap());
+        assert_eq!(games[1].result, GameResult::WhiteWin);
+
+        let fen = State::new().to_fen();
+        assert!(read_games(format!("fen {}\nply e2e5\n", fen).as_bytes()).is_err());
+        assert!(read_games(format!("fen {}\nply e2e4\n", fen).as_bytes()).is_err());
+        assert!(read_games("ply e2e4\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_window_deduplication_and_restarts() {
+        let dir = std::env::temp_dir().join(format!("chess-ai-replay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = ReplayBufferConfig::new().with_window_games(2);
+
+        // Both games open with e4, the start and the position after it are shared
+        write_chunk(
+            &dir,
+            &[
+                game(&["e2e4", "e7e5"], GameResult::WhiteWin),
+                game(&["e2e4", "c7c5"], GameResult::BlackWin),
+            ],
+        )
+        .unwrap();
+        let mut buffer = ReplayBuffer::open(&dir, config.clone()).unwrap();
+        assert_eq!((buffer.games(), buffer.len()), (2, 2));
+
+        let device = Default::default();
+        let (states, targets) = buffer.minibatch::<NdArray>(16, &device).unwrap();
+        assert_eq!(states.len(), 16);
+        let values = targets.value.into_data().to_vec::<f32>().unwrap();
+        assert!(values.iter().all(|value| value.abs() < 1e-6));
+        let wdl = targets.wdl.unwrap().into_data().to_vec::<f32>().unwrap();
+        assert_eq!(&wdl[..3], &[0.5, 0.0, 0.5]);
+        let totals = targets
+            .policy
+            .sum_dim(1)
+            .into_data()
+            .to_vec::<f32>()
+            .unwrap();
+        assert!(totals.iter().all(|total| (total - 1.0).abs() < 1e-6));
+
+        let unmerged = ReplayBuffer::open(&dir, config.clone().with_deduplicate(false)).unwrap();
+        assert_eq!(unmerged.len(), 4);
+
+        // A third game pushes the first out of the window
+        write_chunk(&dir, &[game(&["d2d4"], GameResult::Draw)]).unwrap();
+        assert_eq!(buffer.refresh().unwrap(), 1);
+        assert_eq!(buffer.refresh().unwrap(), 0);
+        assert_eq!((buffer.games(), buffer.len()), (2, 2));
+
+        // Without averaging the most recent game decides the target of the start position
+        let mut latest =
+            ReplayBuffer::open(&dir, config.clone().with_average_duplicates(false)).unwrap();
+        let start = State::new().hash();
+        let (states, targets) = latest.minibatch::<NdArray>(32, &device).unwrap();
+        let values = targets.value.into_data().to_vec::<f32>().unwrap();
+        for (state, value) in states.iter().zip(values) {
+            let expected = if state.hash() == start { 0.0 } else { -1.0 };
+            assert_eq!(value, expected);
+        }
+
+        // Recency weighting prefers the newest game's positions
+        let mut recent =
+            ReplayBuffer::open(&dir, config.clone().with_recency_half_life(Some(0.05))).unwrap();
+        let (states, _) = recent.minibatch::<NdArray>(32, &device).unwrap();
+        assert!(states.iter().all(|state| state.hash() == start));
+
+        // A restarted buffer continues with the minibatches the old one would have served
+        buffer.save_state().unwrap();
+        let (expected, _) = buffer.minibatch::<NdArray>(8, &device).unwrap();
+        let mut restarted = ReplayBuffer::open(&dir, config).unwrap();
+        assert_eq!(restarted.batches(), 1);
+        let (states, _) = restarted.minibatch::<NdArray>(8, &device).unwrap();
+        let fens = |states: &[State]| states.iter().map(State::to_fen).collect::<Vec<_>>();
+        assert_eq!(fens(&states), fens(&expected));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chunks_outside_the_window() {
+        let dir = std::env::temp_dir().join(format!("chess-ai-retention-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = ReplayBufferConfig::new().with_window_games(2);
+
+        // The oldest chunk is unreadable, but the newer ones fill the window without it
+        let oldest = write_chunk(&dir, &[game(&["e2e4"], GameResult::Draw)]).unwrap();
+        fs::write(&oldest, "not a chunk\n").unwrap();
+        let older = write_chunk(&dir, &[game(&["d2d4"], GameResult::Draw)]).unwrap();
+        let newest = write_chunk(
+            &dir,
+            &[
+                game(&["c2c4"], GameResult::WhiteWin),
+                game(&["g1f3"], GameResult::BlackWin),
+            ],
+        )
+        .unwrap();
+        let buffer = ReplayBuffer::open(&dir, config.clone()).unwrap();
+        assert_eq!(buffer.games(), 2);
+        assert!(oldest.exists() && older.exists());
+
+        let mut buffer = ReplayBuffer::open(&dir, config.with_prune_chunks(true)).unwrap();
+        assert!(!oldest.exists() && !older.exists());
+
+        // Half of the newest chunk is still in the window after one more game, none after two
+        write_chunk(&dir, &[game(&["b2b3"], GameResult::Draw)]).unwrap();
+        assert_eq!(buffer.refresh().unwrap(), 1);
+        assert!(newest.exists());
+        write_chunk(&dir, &[game(&["g2g3"], GameResult::Draw)]).unwrap();
+        assert_eq!(buffer.refresh().unwrap(), 1);
+        assert!(!newest.exists());
+        assert_eq!(chunk_paths(&dir).unwrap().len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_moves_left_targets() {
+        use crate::engine::{
+            model::TransformerModelConfig, policy::legal_move_masks, training::LossConfig,
+        };
+
+        let dir = std::env::temp_dir().join(format!("chess-ai-moves-left-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        write_chunk(&dir, &[game(&["e2e4", "e7e5", "g1f3"], GameResult::Draw)]).unwrap();
+
+        let mut buffer = ReplayBuffer::open(&dir, ReplayBufferConfig::new()).unwrap();
+        let device = Default::default();
+        let (states, targets) = buffer.minibatch::<NdArray>(16, &device).unwrap();
+        let moves_left = targets
+            .moves_left
+            .clone()
+            .unwrap()
+            .into_data()
+            .to_vec::<f32>()
+            .unwrap();
+        let mut expected = HashMap::new();
+        let mut state = State::new();
+        for (ply, uci) in ["e2e4", "e7e5", "g1f3"].into_iter().enumerate() {
+            expected.insert(state.hash(), 3.0 - ply as f32);
+            state.make_move(state.parse_uci(uci).unwrap());
+        }
+        for (state, plies) in states.iter().zip(moves_left) {
+            assert_eq!(plies, expected[&state.hash()]);
+        }
+
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .with_moves_left_head(true)
+            .init::<NdArray>(&device);
+        let refs: Vec<&State> = states.iter().collect();
+        let output = model.forward(&refs, legal_move_masks(&refs, &device), &device);
+        let with = LossConfig::new().loss(&output, &targets).into_scalar();
+        let without = LossConfig::new()
+            .with_moves_left_weight(0.0)
+            .loss(&output, &targets)
+            .into_scalar();
+        assert!(with > without, "{} <= {}", with, without);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_augmented_minibatches() {
+        let dir = std::env::temp_dir().join(format!("chess-ai-augment-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let root = State::from_fen("1k6/8/3q4/8/4N3/2B5/6R1/4K3 w - - 0 1").unwrap();
+        write_chunk(
+            &dir,
+            &[game_from(root, &["e4d6", "b8a7"], GameResult::WhiteWin)],
+        )
+        .unwrap();
+
+        let config = ReplayBufferConfig::new().with_augment(true);
+        let mut buffer = ReplayBuffer::open(&dir, config).unwrap();
+        let device = Default::default();
+        let (states, targets) = buffer.minibatch::<NdArray>(32, &device).unwrap();
+        let policy = targets.policy.into_data().to_vec::<f32>().unwrap();
+
+        // The visits follow the board, onto a legal move of the transformed position
+        let mut fens = HashSet::new();
+        for (state, row) in states.iter().zip(policy.chunks(POLICY_SIZE)) {
+            let index = row.iter().position(|&p| p == 1.0).unwrap();
+            assert!(state
+                .generate_moves()
+                .iter()
+                .any(|mv| mv.policy_index() == index));
+            fens.insert(state.to_fen());
+        }
+        assert!(fens.len() > 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tablebase_value_targets() {
+        let dir = std::env::temp_dir().join(format!("chess-ai-syzygy-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // White takes the knight, the bare kings left are a draw whatever the game's result
+        let root = State::from_fen("8/8/8/4k3/8/8/8/Kn6 w - - 0 1").unwrap();
+        let bare_kings = State::from_fen("8/8/8/4k3/8/8/8/1K6 b - - 0 1").unwrap();
+        write_chunk(
+            &dir,
+            &[game_from(root, &["a1b1", "e5e4"], GameResult::WhiteWin)],
+        )
+        .unwrap();
+
+        let device = Default::default();
+        for (syzygy, expected) in [(None, 1.0), (Some(dir.display().to_string()), 0.0)] {
+            let config = ReplayBufferConfig::new().with_syzygy(syzygy);
+            let mut buffer = ReplayBuffer::open(&dir, config).unwrap();
+            let (states, targets) = buffer.minibatch::<NdArray>(16, &device).unwrap();
+            let values = targets.value.into_data().to_vec::<f32>().unwrap();
+            for (state, value) in states.iter().zip(values) {
+                match state.hash() == bare_kings.hash() {
+                    true => assert_eq!(value, expected),
+                    false => assert_eq!(value, 1.0),
+                }
+            }
+        }
+
+        let missing = ReplayBufferConfig::new().with_syzygy(Some("/nonexistent".to_string()));
+        assert!(ReplayBuffer::open(&dir, missing).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
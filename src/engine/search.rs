@@ -0,0 +1,330 @@
+use crate::{
+    chess::{moves::Move, syzygy::Tablebase, State},
+    engine::{
+        eval::Evaluate,
+        model::TransformerModel,
+        policy::LegalMoveMask,
+        time::{TimeControl, TimeManager},
+        tuning::quiescence,
+    },
+};
+use burn::tensor::{backend::Backend, ElementConversion};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const MATE_SCORE: i32 = 100_000;
+// Mate scores are at least this far from zero
+const MATE_BOUND: i32 = MATE_SCORE - 1000;
+// How often the clock is read, in nodes
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+// Budget of a single search, whichever limit is reached first ends it
+#[derive(Copy, Clone)]
+pub(crate) struct SearchLimits {
+    pub(crate) nodes: Option<u64>,
+    pub(crate) time: Option<Duration>,
+    pub(crate) depth: usize,
+    // The clock of a game, left to a `TimeManager` for the searchers that use one
+    pub(crate) time_control: Option<TimeControl>,
+}
+
+impl SearchLimits {
+    pub(crate) const fn new() -> Self {
+        Self {
+            nodes: None,
+            time: None,
+            depth: 64,
+            time_control: None,
+        }
+    }
+
+    pub(crate) const fn with_nodes(mut self, nodes: u64) -> Self {
+        self.nodes = Some(nodes);
+        self
+    }
+
+    pub(crate) const fn with_time(mut self, time: Duration) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    pub(crate) const fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub(crate) const fn with_time_control(mut self, time_control: TimeControl) -> Self {
+        self.time_control = Some(time_control);
+        self
+    }
+}
+
+// "100000" nodes or "500ms", per search
+impl std::str::FromStr for SearchLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid search budget '{}', expected nodes or milliseconds like 500ms",
+                s
+            )
+        };
+        match s.strip_suffix("ms") {
+            Some(ms) => Ok(
+                Self::new().with_time(Duration::from_millis(ms.parse().map_err(|_| invalid())?))
+            ),
+            None => Ok(Self::new().with_nodes(s.parse().map_err(|_| invalid())?)),
+        }
+    }
+}
+
+pub(crate) trait Searcher {
+    // None only without legal moves
+    fn best_move(&mut self, state: &State, limits: &SearchLimits) -> Option<Move>;
+}
+
+// Iterative deepening negamax over an evaluation with a quiescence search at the leaves. Nodes are
+// counted in the main search, the captures resolved by the quiescence search are not. Under a time
+// control the `TimeManager` hears about every finished iteration and decides whether to start
+// another, and its hard limit cuts an iteration short. With a tablebase, positions it covers are
+// only searched among the root moves that keep the tablebase result.
+pub(crate) struct AlphaBeta<E: Evaluate> {
+    eval: E,
+    nodes: u64,
+    start: Instant,
+    limits: SearchLimits,
+    time: Option<TimeManager>,
+    tablebase: Option<Arc<Tablebase>>,
+}
+
+impl<E: Evaluate> AlphaBeta<E> {
+    pub(crate) fn new(eval: E) -> Self {
+        Self {
+            eval,
+            nodes: 0,
+            start: Instant::now(),
+            limits: SearchLimits::new(),
+            time: None,
+            tablebase: None,
+        }
+    }
+
+    pub(crate) fn with_tablebase(mut self, tablebase: Arc<Tablebase>) -> Self {
+        self.tablebase = Some(tablebase);
+        self
+    }
+
+    fn out_of_budget(&self) -> bool {
+        self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes)
+            || (self.nodes.is_multiple_of(TIME_CHECK_INTERVAL)
+                && (self
+                    .limits
+                    .time
+                    .is_some_and(|time| self.start.elapsed() >= time)
+                    || self.time.as_ref().is_some_and(TimeManager::must_stop)))
+    }
+
+    // None once the budget runs out, the unfinished result is then discarded
+    fn negamax(
+        &mut self,
+        state: &mut State,
+        depth: usize,
+        mut alpha: i32,
+        beta: i32,
+        ply: i32,
+    ) -> Option<i32> {
+        self.nodes += 1;
+        if self.out_of_budget() {
+            return None;
+        }
+        if state.repetition_count() > 0 || state.halfmove_clock >= 100 {
+            return Some(0);
+        }
+
+        let moves = state.generate_moves();
+        if moves.is_empty() {
+            return Some(if state.is_check() {
+                -MATE_SCORE + ply
+            } else {
+                0
+            });
+        }
+        if depth == 0 {
+            let mut pv = Vec::new();
+            return Some(quiescence(&self.eval, state, alpha, beta, &mut pv));
+        }
+
+        let mut best = -MATE_SCORE;
+        for mv in order_moves(state, moves.into_iter(), None) {
+            state.make_move(mv);
+            let score = self.negamax(state, depth - 1, -beta, -alpha, ply + 1);
+            state.unmake_move();
+            let score = -score?;
+
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+}
+
+impl<E: Evaluate> Searcher for AlphaBeta<E> {
+    fn best_move(&mut self, state: &State, limits: &SearchLimits) -> Option<Move> {
+        let mut state = state.clone();
+        let mut moves = state.generate_moves();
+        if let Some(tablebase) = &self.tablebase {
+            match tablebase.filter_root_moves(&mut state) {
+                Some(filtered) if !filtered.is_empty() => moves = filtered,
+                _ => {}
+            }
+        }
+        let mut best = *moves.first()?;
+        self.nodes = 0;
+        self.start = Instant::now();
+        self.limits = *limits;
+        self.time = limits
+            .time_control
+            .map(|time_control| TimeManager::new(&time_control, &state));
+
+        for depth in 1..=limits.depth.max(1) {
+            let (mut alpha, mut iteration_best) = (-MATE_SCORE, None);
+            let mut aborted = false;
+            // The previous best move goes first, so even an unfinished iteration improves on it
+            for mv in order_moves(&state, moves.iter().copied(), Some(best)) {
+                state.make_move(mv);
+                let score = self.negamax(&mut state, depth - 1, -MATE_SCORE, -alpha, 1);
+                state.unmake_move();
+                let Some(score) = score.map(|score| -score) else {
+                    aborted = true;
+                    break;
+                };
+                if score > alpha {
+                    alpha = score;
+                    iteration_best = Some(mv);
+                }
+            }
+
+            if let Some(mv) = iteration_best {
+                best = mv;
+            }
+            if aborted || alpha.abs() >= MATE_BOUND || moves.len() == 1 {
+                break;
+            }
+            if let Some(time) = &mut self.time {
+                time.update(best, alpha);
+                if time.should_stop() {
+                    break;
+                }
+            }
+        }
+        Some(best)
+    }
+}
+
+// `first`, then captures by victim, then the rest in generation order
+fn order_moves(state: &State, moves: impl Iterator<Item = Move>, first: Option<Move>) -> Vec<Move> {
+    let mut moves: Vec<(Move, i32)> = moves
+        .map(|mv| {
+            let order = if Some(mv) == first {
+                i32::MAX
+            } else {
+                state.board.mailbox[mv.to()].map_or(0, |victim| 1 + victim as i32 % 6)
+            };
+            (mv, order)
+        })
+        .collect();
+    moves.sort_by_key(|&(_, order)| -order);
+    moves.into_iter().map(|(mv, _)| mv).collect()
+}
+
+// The most likely move of the raw network policy, a single evaluation whatever the budget
+pub(crate) struct PolicySearcher<B: Backend> {
+    model: TransformerModel<B>,
+    device: B::Device,
+}
+
+impl<B: Backend> PolicySearcher<B> {
+    pub(crate) const fn new(model: TransformerModel<B>, device: B::Device) -> Self {
+        Self { model, device }
+    }
+}
+
+impl<B: Backend> Searcher for PolicySearcher<B> {
+    fn best_move(&mut self, state: &State, _limits: &SearchLimits) -> Option<Move> {
+        let mask = state.legal_move_mask(&self.device).unsqueeze_dim(0);
+        let output = self.model.forward(&[state], mask, &self.device);
+        // Illegal moves are masked to zero, so the most likely index is a legal move
+        let index = output.policy.argmax(1).into_scalar().elem::<i64>();
+        state.policy_index_to_move(index as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{eval::HandcraftedEval, model::TransformerModelConfig};
+    use burn::backend::NdArray;
+
+    #[test]
+    fn test_alpha_beta_finds_tactics_within_budget() {
+        let mut search = AlphaBeta::new(HandcraftedEval::new());
+        let limits = SearchLimits::new().with_nodes(20_000);
+
+        let state = State::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+        let mv = search.best_move(&state, &limits).unwrap();
+        assert_eq!(mv.to_uci(), "a1a8");
+        assert!(search.nodes <= 20_000);
+
+        // The hanging queen is worth more than anything else on the board
+        let state = State::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let mv = search.best_move(&state, &limits).unwrap();
+        assert_eq!(mv.to_uci(), "d2d5");
+
+        let limits = SearchLimits::new().with_time(Duration::from_millis(50));
+        let start = Instant::now();
+        assert!(search.best_move(&State::new(), &limits).is_some());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let mated = State::from_fen("R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1").unwrap();
+        assert!(search.best_move(&mated, &limits).is_none());
+
+        // A second on the clock is spent over many moves, not on this one
+        let second = Duration::from_secs(1);
+        let limits = SearchLimits::new().with_time_control(TimeControl::new(
+            second,
+            second,
+            Duration::ZERO,
+            Duration::ZERO,
+        ));
+        let start = Instant::now();
+        assert!(search.best_move(&State::new(), &limits).is_some());
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // Every king move keeps the draw of the bare kings
+        let mut search =
+            AlphaBeta::new(HandcraftedEval::new()).with_tablebase(Arc::new(Tablebase::new()));
+        let bare_kings = State::from_fen("8/8/8/4k3/8/8/8/K7 w - - 0 1").unwrap();
+        let mv = search.best_move(&bare_kings, &limits).unwrap();
+        assert!(bare_kings.generate_moves().contains(&mv));
+    }
+
+    #[test]
+    fn test_policy_searcher_plays_legal_moves() {
+        let device = Default::default();
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .init::<NdArray>(&device);
+        let mut searcher = PolicySearcher::new(model, device);
+        let state = State::new();
+        let mv = searcher.best_move(&state, &SearchLimits::new()).unwrap();
+        assert!(state.generate_moves().contains(&mv));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{
+    chess::{
+        polyglot::PolyglotBook, prng::PseudoRng, syzygy::Tablebase, types::Color,
+        types::GameResult, State,
+    },
+    engine::{
+        eval::HandcraftedEval,
+        model::TransformerModel,
+        replay::{write_chunk, SelfPlayGame},
+        search::{AlphaBeta, PolicySearcher, SearchLimits, Searcher},
+        server::RemoteSearcher,
+    },
+};
+use burn::{config::Config, tensor::backend::Backend};
+use std::{io, path::Path, sync::Arc};
+
+// The player behind a searcher, cloned into every thread that needs its own
+#[derive(Clone)]
+pub(crate) enum Engine<B: Backend> {
+    // Alpha-beta over the handcrafted evaluation
+    Handcrafted,
+    // The policy of a network
+    Network(Box<TransformerModel<B>>),
+    // The policy of the network an inference server at this address holds
+    Remote(String),
+}
+
+impl<B: Backend> Engine<B> {
+    // The tablebase, if any, narrows the root moves of the alpha-beta search
+    pub(crate) fn searcher(
+        &self,
+        device: &B::Device,
+        tablebase: Option<&Arc<Tablebase>>,
+    ) -> Box<dyn Searcher> {
+        match self {
+            Self::Handcrafted => {
+                let search = AlphaBeta::new(HandcraftedEval::new());
+                match tablebase {
+                    Some(tablebase) => Box::new(search.with_tablebase(tablebase.clone())),
+                    None => Box::new(search),
+                }
+            }
+            Self::Network(model) => Box::new(PolicySearcher::new(*model.clone(), device.clone())),
+            Self::Remote(address) => Box::new(RemoteSearcher::new(address.clone())),
+        }
+    }
+}
+
+// The result once the game is over by the rules, threefold repetition and the fifty-move rule
+// included
+pub(crate) fn outcome(state: &State) -> Option<GameResult> {
+    if state.generate_moves().is_empty() {
+        return Some(match (state.is_check(), state.turn) {
+            (true, Color::White) => GameResult::BlackWin,
+            (true, Color::Black) => GameResult::WhiteWin,
+            (false, _) => GameResult::Draw,
+        });
+    }
+    if state.halfmove_clock >= 100 || state.repetition_count() >= 2 {
+        return Some(GameResult::Draw);
+    }
+    None
+}
+
+// `plies` moves from the initial position, redrawn until the game is still on. Each is a weighted
+// pick from the book while it knows the position, uniformly random otherwise.
+pub(crate) fn random_opening(
+    rng: &mut PseudoRng,
+    plies: usize,
+    book: Option<&PolyglotBook>,
+) -> State {
+    loop {
+        let mut state = State::new();
+        for _ in 0..plies {
+            if let Some(mv) = book.and_then(|book| book.pick_move(&state, rng)) {
+                state.make_move(mv);
+                continue;
+            }
+            let moves = state.generate_moves();
+            if moves.is_empty() {
+                break;
+            }
+            state.make_move(moves[rng.next() as usize % moves.len()]);
+        }
+        if outcome(&state).is_none() {
+            return state;
+        }
+    }
+}
+
+// Plays `root` out with `players[color]` moving for that color. Games still running after
+// `max_plies` are scored as draws, and with a tablebase a game ends with its result as soon as it
+// reaches a position the tables cover. The searchers report no distribution over the moves, so
+// each ply's policy target is the move played.
+pub(crate) fn play_game(
+    players: &mut [Box<dyn Searcher>; 2],
+    root: &State,
+    limits: &SearchLimits,
+    max_plies: usize,
+    tablebase: Option<&Tablebase>,
+) -> SelfPlayGame {
+    let mut state = root.clone();
+    let mut plies = Vec::new();
+    let result = loop {
+        if let Some(result) = outcome(&state) {
+            break result;
+        }
+        if let Some(result) = tablebase.and_then(|tablebase| tablebase.adjudicate(&mut state)) {
+            break result;
+        }
+        if plies.len() >= max_plies {
+            break GameResult::Draw;
+        }
+        let Some(mv) = players[state.turn].best_move(&state, limits) else {
+            unreachable!("a game that is not over has legal moves");
+        };
+        state.make_move(mv);
+        plies.push((mv, vec![(mv, 1.0)]));
+    };
+
+    SelfPlayGame {
+        root: root.clone(),
+        plies,
+        result,
+    }
+}
+
+#[derive(Config, Debug)]
+pub(crate) struct SelfPlayConfig {
+    #[config(default = 100)]
+    pub(crate) games: usize,
+    #[config(default = 1)]
+    pub(crate) threads: usize,
+    // Per move, a node count like "2000" or a time like "100ms"
+    #[config(default = "String::from(\"2000\")")]
+    pub(crate) budget: String,
+    #[config(default = 300)]
+    pub(crate) max_plies: usize,
+    // Random moves before the engine takes over, a deterministic searcher would otherwise play
+    // the same game every time
+    #[config(default = 6)]
+    pub(crate) random_plies: usize,
+    // Polyglot book the opening moves are drawn from while it has them
+    pub(crate) book: Option<String>,
+    #[config(default = 10)]
+    pub(crate) games_per_chunk: usize,
+    #[config(default = 0x2545f4914f6cdd1d)]
+    pub(crate) seed: u64,
+    // Directory of Syzygy tables used to adjudicate games and pick root moves
+    pub(crate) syzygy: Option<String>,
+}
+
+impl SelfPlayConfig {
+    pub(crate) fn validate(&self) -> Result<SearchLimits, String> {
+        if self.games == 0 || self.threads == 0 || self.games_per_chunk == 0 {
+            return Err("games, threads and games_per_chunk must be at least 1".to_string());
+        }
+        self.budget.parse()
+    }
+
+    // Plays the games against itself on `threads` threads, each writing a chunk to `dir` every
+    // `games_per_chunk` games, and returns the number of games written
+    pub(crate) fn generate<B: Backend>(
+        &self,
+        engine: &Engine<B>,
+        dir: &Path,
+        device: &B::Device,
+    ) -> io::Result<usize> {
+        let limits = self
+            .validate()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let tablebase = self
+            .syzygy
+            .as_ref()
+            .map(Tablebase::open)
+            .transpose()?
+            .map(Arc::new);
+        let book = self.book.as_ref().map(open_book).transpose()?;
+
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads)
+                .map(|thread| {
+                    let (engine, device) = (engine.clone(), device.clone());
+                    let (tablebase, book) = (tablebase.clone(), book.as_ref());
+                    scope.spawn(move || -> io::Result<usize> {
+                        let mut players = [
+                            engine.searcher(&device, tablebase.as_ref()),
+                            engine.searcher(&device, tablebase.as_ref()),
+                        ];
+                        let mut chunk = Vec::new();
+                        let mut written = 0;
+                        for game in (thread..self.games).step_by(self.threads) {
+                            let mut rng = PseudoRng::new(game_seed(self.seed, game));
+                            let root = random_opening(&mut rng, self.random_plies, book);
+                            chunk.push(play_game(
+                                &mut players,
+                                &root,
+                                &limits,
+                                self.max_plies,
+                                tablebase.as_deref(),
+                            ));
+
+                            if chunk.len() == self.games_per_chunk {
+                                written += chunk.len();
+                                write_chunk(dir, &chunk)?;
+                                chunk.clear();
+                            }
+                        }
+                        if !chunk.is_empty() {
+                            written += chunk.len();
+                            write_chunk(dir, &chunk)?;
+                        }
+                        Ok(written)
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("self-play thread panicked"))
+                .sum()
+        })
+    }
+}
+
+// A book with no entries would silently leave every opening random
+pub(crate) fn open_book(path: impl AsRef<Path>) -> io::Result<PolyglotBook> {
+    let path = path.as_ref();
+    let error = |kind, message| io::Error::new(kind, format!("{}: {}", path.display(), message));
+    let book = PolyglotBook::open(path).map_err(|err| error(err.kind(), err.to_string()))?;
+    match book.is_empty() {
+        true => Err(error(io::ErrorKind::InvalidData, "empty book".to_string())),
+        false => Ok(book),
+    }
+}
+
+// Distinct, reproducible streams per game whatever the thread count
+pub(crate) const fn game_seed(seed: u64, game: usize) -> u64 {
+    (seed ^ (game as u64).wrapping_mul(0x9e3779b97f4a7c15)) | 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess::{moves::Move, polyglot::PolyglotBookBuilder},
+        engine::replay::read_chunks,
+    };
+    use burn::backend::NdArray;
+
+    #[test]
+    fn test_generate_games() {
+        let dir = std::env::temp_dir().join(format!("selfplay-test-{}", std::process::id()));
+        let config = SelfPlayConfig::new()
+            .with_games(3)
+            .with_threads(2)
+            .with_games_per_chunk(1)
+            .with_budget("50".to_string())
+            .with_max_plies(20);
+        let engine = Engine::<NdArray>::Handcrafted;
+        let written = config.generate(&engine, &dir, &Default::default()).unwrap();
+        assert_eq!(written, 3);
+
+        let games = read_chunks(&dir).unwrap();
+        assert_eq!(games.len(), 3);
+        for game in &games {
+            // The random opening is the root, the rest was searched
+            assert_eq!(game.root.fullmove_number, 4);
+            assert!(game.plies.len() <= 20);
+            assert!(game
+                .plies
+                .iter()
+                .all(|(mv, policy)| policy == &vec![(*mv, 1.0)]));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(SelfPlayConfig::new().with_threads(0).validate().is_err());
+        assert!(SelfPlayConfig::new()
+            .with_budget("fast".to_string())
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_book_openings() {
+        let mut builder = PolyglotBookBuilder::new();
+        builder
+            .add_pgn("[Result \"1/2-1/2\"]\n1. e4 e5 2. Nf3 1/2-1/2\n".as_bytes())
+            .unwrap();
+        let book = builder.build();
+
+        // Three moves from the book, then out of it the fourth is random
+        let mut rng = PseudoRng::new(7);
+        let state = random_opening(&mut rng, 4, Some(&book));
+        let (_, moves) = state.root_and_moves();
+        let moves: Vec<_> = moves.into_iter().map(Move::to_uci).collect();
+        assert_eq!(moves[..3], ["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(moves.len(), 4);
+
+        let path = std::env::temp_dir().join(format!("empty-book-{}.bin", std::process::id()));
+        PolyglotBookBuilder::new().build().save(&path).unwrap();
+        assert!(open_book(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_outcome() {
+        let mut players: [Box<dyn Searcher>; 2] = [
+            Box::new(AlphaBeta::new(HandcraftedEval::new())),
+            Box::new(AlphaBeta::new(HandcraftedEval::new())),
+        ];
+        let root = State::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+        let limits = SearchLimits::new().with_nodes(5000);
+        let game = play_game(&mut players, &root, &limits, 10, None);
+        assert!(game.result == GameResult::WhiteWin);
+        assert_eq!(game.plies.len(), 1);
+
+        let stalemate = State::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(outcome(&stalemate) == Some(GameResult::Draw));
+        assert!(outcome(&State::new()).is_none());
+        let game = play_game(&mut players, &State::new(), &limits, 4, None);
+        assert!(game.result == GameResult::Draw && game.plies.len() == 4);
+
+        // Bare kings play on to the ply limit unless a tablebase ends the game
+        let bare_kings = State::from_fen("8/8/8/4k3/8/8/8/K7 w - - 0 1").unwrap();
+        let game = play_game(&mut players, &bare_kings, &limits, 4, None);
+        assert!(game.result == GameResult::Draw && game.plies.len() == 4);
+        let tablebase = Tablebase::new();
+        let game = play_game(&mut players, &bare_kings, &limits, 4, Some(&tablebase));
+        assert!(game.result == GameResult::Draw && game.plies.is_empty());
+
+        // Taking the knight reaches the bare kings
+        let knight = State::from_fen("8/8/8/4k3/8/8/8/Kn6 w - - 0 1").unwrap();
+        let game = play_game(&mut players, &knight, &limits, 10, Some(&tablebase));
+        assert!(game.result == GameResult::Draw);
+        assert_eq!(game.plies.len(), 1);
+        assert_eq!(game.plies[0].0.to_uci(), "a1b1");
+    }
+}
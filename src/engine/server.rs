@@ -0,0 +1,404 @@
+use crate::{
+    chess::{moves::Move, State},
+    engine::{
+        checkpoint::{load_checkpoint, load_checkpoint_config},
+        model::TransformerModel,
+        policy::legal_move_masks,
+        search::{SearchLimits, Searcher},
+    },
+};
+use burn::{config::Config, tensor::backend::Backend};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// Line-based protocol, one request and one response per line:
+//   eval <fen> [moves <uci>...]  ->  ok <value> <uci>:<probability>...
+//   reload <checkpoint dir>      ->  ok
+//   ping                         ->  ok
+// Failures are answered with `error <message>`. The value is from White's point of view and the
+// moves are ordered by probability.
+// There is no authentication: anyone who can connect can make the server load any checkpoint
+// path it can read, so it only listens on loopback addresses unless `allow_remote` is set.
+#[derive(Config, Debug)]
+pub(crate) struct InferenceServerConfig {
+    // Port 0 picks a free port, see `InferenceServer::address`
+    #[config(default = "String::from(\"127.0.0.1:7878\")")]
+    pub(crate) address: String,
+    // Lets the server bind to addresses other hosts can reach, only for trusted networks
+    #[config(default = false)]
+    pub(crate) allow_remote: bool,
+    #[config(default = 64)]
+    pub(crate) max_batch_size: usize,
+    // How long the first request of a batch waits for others to join it
+    #[config(default = 5)]
+    pub(crate) max_wait_ms: u64,
+}
+
+pub(crate) struct Evaluation {
+    pub(crate) value: f32,
+    pub(crate) policy: Vec<(Move, f32)>,
+}
+
+enum Job {
+    Evaluate(Box<State>, Sender<Result<Evaluation, String>>),
+    Reload(PathBuf, Sender<Result<(), String>>),
+}
+
+pub(crate) struct InferenceServer {
+    address: SocketAddr,
+    acceptor: JoinHandle<()>,
+}
+
+impl InferenceServerConfig {
+    // One worker thread owns the model and batches requests from every connection
+    pub(crate) fn start<B: Backend>(
+        &self,
+        model: TransformerModel<B>,
+        device: B::Device,
+    ) -> io::Result<InferenceServer> {
+        let addresses: Vec<SocketAddr> = self.address.to_socket_addrs()?.collect();
+        if !self.allow_remote && addresses.iter().any(|address| !address.ip().is_loopback()) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} is not a loopback address, the server is unauthenticated and needs \
+                     allow_remote to listen on it",
+                    self.address
+                ),
+            ));
+        }
+        let listener = TcpListener::bind(addresses.as_slice())?;
+        let address = listener.local_addr()?;
+
+        let (jobs, receiver) = mpsc::channel();
+        let (max_batch_size, max_wait) = (
+            self.max_batch_size.max(1),
+            Duration::from_millis(self.max_wait_ms),
+        );
+        thread::spawn(move || run_worker(model, device, receiver, max_batch_size, max_wait));
+
+        let acceptor = thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                let jobs = jobs.clone();
+                thread::spawn(move || handle_connection(stream, jobs));
+            }
+        });
+
+        Ok(InferenceServer { address, acceptor })
+    }
+}
+
+impl InferenceServer {
+    pub(crate) const fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Blocks for as long as the server accepts connections
+    pub(crate) fn wait(self) {
+        let _ = self.acceptor.join();
+    }
+}
+
+fn run_worker<B: Backend>(
+    mut model: TransformerModel<B>,
+    device: B::Device,
+    jobs: Receiver<Job>,
+    max_batch_size: usize,
+    max_wait: Duration,
+) {
+    let mut batch: Vec<(State, Sender<Result<Evaluation, String>>)> = Vec::new();
+    while let Ok(job) = jobs.recv() {
+        let deadline = Instant::now() + max_wait;
+        let mut next = Some(job);
+        while let Some(job) = next.take() {
+            match job {
+                Job::Evaluate(state, reply) => batch.push((*state, reply)),
+                Job::Reload(path, reply) => {
+                    // Requests queued before the reload still see the old weights
+                    evaluate_batch(&model, &device, &mut batch);
+                    let _ = reply.send(reload(&path, &device).map(|loaded| model = loaded));
+                }
+            }
+
+            if batch.len() >= max_batch_size {
+                break;
+            }
+            match jobs.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(job) => next = Some(job),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => {}
+            }
+        }
+        evaluate_batch(&model, &device, &mut batch);
+    }
+}
+
+fn evaluate_batch<B: Backend>(
+    model: &TransformerModel<B>,
+    device: &B::Device,
+    batch: &mut Vec<(State, Sender<Result<Evaluation, String>>)>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let states: Vec<&State> = batch.iter().map(|(state, _)| state).collect();
+    let output = model.forward(&states, legal_move_masks(&states, device), device);
+    let policy = output.policy.into_data().to_vec::<f32>().unwrap();
+    let values = output.score.into_data().to_vec::<f32>().unwrap();
+
+    let n = policy.len() / batch.len();
+    for (i, (state, reply)) in batch.drain(..).enumerate() {
+        let _ = reply.send(Ok(Evaluation {
+            value: values[i],
+            policy: state.probabilities_to_moves(&policy[i * n..(i + 1) * n]),
+        }));
+    }
+}
+
+// The checkpoint may use a different architecture, it brings its own config
+fn reload<B: Backend>(path: &Path, device: &B::Device) -> Result<TransformerModel<B>, String> {
+    let config = load_checkpoint_config(path).map_err(|err| err.to_string())?;
+    load_checkpoint(path, &config, device).map_err(|err| err.to_string())
+}
+
+fn handle_connection(stream: TcpStream, jobs: Sender<Job>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let (command, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let response = match command {
+            "eval" => match parse_position(args) {
+                Some(state) => {
+                    let (reply, result) = mpsc::channel();
+                    let _ = jobs.send(Job::Evaluate(Box::new(state), reply));
+                    match result.recv() {
+                        Ok(Ok(evaluation)) => format_evaluation(&evaluation),
+                        Ok(Err(err)) => format!("error {}", err),
+                        Err(_) => "error server is shutting down".to_string(),
+                    }
+                }
+                None => format!("error invalid position '{}'", args),
+            },
+            "reload" => {
+                let (reply, result) = mpsc::channel();
+                let _ = jobs.send(Job::Reload(PathBuf::from(args.trim()), reply));
+                match result.recv() {
+                    Ok(Ok(())) => "ok".to_string(),
+                    Ok(Err(err)) => format!("error {}", err.replace('\n', " ")),
+                    Err(_) => "error server is shutting down".to_string(),
+                }
+            }
+            "ping" => "ok".to_string(),
+            "quit" => return Ok(()),
+            _ => format!("error unknown command '{}'", command),
+        };
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+// "<fen> [moves <uci>...]", the moves give history-aware models the game leading up to the FEN
+fn parse_position(args: &str) -> Option<State> {
+    let tokens: Vec<&str> = args.split_whitespace().collect();
+    if tokens.len() < 6 || tokens.get(6).is_some_and(|&token| token != "moves") {
+        return None;
+    }
+    let fen = tokens[..6].join(" ");
+    let mut state = State::from_fen(&fen).ok()?;
+    for uci in tokens.iter().skip(7) {
+        let mv = state.parse_uci(uci)?;
+        state.make_move(mv);
+    }
+    Some(state)
+}
+
+fn format_evaluation(evaluation: &Evaluation) -> String {
+    let mut response = format!("ok {}", evaluation.value);
+    for (mv, probability) in &evaluation.policy {
+        response.push_str(&format!(" {}:{}", mv.to_uci(), probability));
+    }
+    response
+}
+
+pub(crate) struct InferenceClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl InferenceClient {
+    pub(crate) fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let writer = TcpStream::connect(address)?;
+        writer.set_nodelay(true)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self { reader, writer })
+    }
+
+    fn request(&mut self, request: &str) -> io::Result<String> {
+        writeln!(self.writer, "{}", request)?;
+        let mut response = String::new();
+        if self.reader.read_line(&mut response)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        match response.trim_end().split_once(' ') {
+            Some(("error", message)) => Err(io::Error::other(message.to_string())),
+            _ if response.starts_with("ok") => Ok(response.trim_end()[2..].trim().to_string()),
+            _ => Err(io::ErrorKind::InvalidData.into()),
+        }
+    }
+
+    // Sends the whole game, so the server sees the same history as the caller
+    pub(crate) fn evaluate(&mut self, state: &State) -> io::Result<Evaluation> {
+        let (root, moves) = state.root_and_moves();
+        let mut request = format!("eval {}", root.to_fen());
+        if !moves.is_empty() {
+            request.push_str(" moves");
+            for mv in moves {
+                request.push(' ');
+                request.push_str(&mv.to_uci());
+            }
+        }
+
+        let response = self.request(&request)?;
+        let mut tokens = response.split_whitespace();
+        let value = tokens
+            .next()
+            .and_then(|value| value.parse().ok())
+            .ok_or(io::ErrorKind::InvalidData)?;
+        let policy = tokens
+            .map(|token| {
+                let (uci, probability) = token.split_once(':')?;
+                Some((state.parse_uci(uci)?, probability.parse().ok()?))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(io::ErrorKind::InvalidData)?;
+        Ok(Evaluation { value, policy })
+    }
+
+    // Swaps the server's model for the checkpoint in `dir`, for every client at once
+    pub(crate) fn reload(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        self.request(&format!("reload {}", dir.as_ref().display()))
+            .map(|_| ())
+    }
+}
+
+// The most likely move of the policy the server returns, `PolicySearcher` without a model of its
+// own. It connects on first use and again after a failure, which it reports as no move.
+pub(crate) struct RemoteSearcher {
+    address: String,
+    client: Option<InferenceClient>,
+}
+
+impl RemoteSearcher {
+    pub(crate) const fn new(address: String) -> Self {
+        Self {
+            address,
+            client: None,
+        }
+    }
+
+    fn evaluate(&mut self, state: &State) -> io::Result<Evaluation> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => self.client.insert(InferenceClient::connect(&self.address)?),
+        };
+        client.evaluate(state)
+    }
+}
+
+impl Searcher for RemoteSearcher {
+    fn best_move(&mut self, state: &State, _limits: &SearchLimits) -> Option<Move> {
+        match self.evaluate(state) {
+            Ok(evaluation) => evaluation.policy.first().map(|&(mv, _)| mv),
+            Err(err) => {
+                eprintln!("{}: {}", self.address, err);
+                self.client = None;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        checkpoint::{save_checkpoint, Precision},
+        model::TransformerModelConfig,
+    };
+    use burn::backend::NdArray;
+
+    fn tiny_config() -> TransformerModelConfig {
+        TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+    }
+
+    fn direct_value(model: &TransformerModel<NdArray>, state: &State) -> f32 {
+        let device = Default::default();
+        let output = model.forward(&[state], legal_move_masks(&[state], &device), &device);
+        output.value.into_scalar()
+    }
+
+    #[test]
+    fn test_batched_evaluation_and_reload() {
+        let device = Default::default();
+        let model = tiny_config().init::<NdArray>(&device);
+        let server = InferenceServerConfig::new()
+            .with_address("127.0.0.1:0".to_string())
+            .with_max_wait_ms(20)
+            .start(model.clone(), device)
+            .unwrap();
+        let address = server.address();
+
+        let remote = InferenceServerConfig::new().with_address("0.0.0.0:0".to_string());
+        let refused = remote.start(model.clone(), device).err().unwrap();
+        assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+
+        let mut state = State::new();
+        state.make_move(state.parse_uci("e2e4").unwrap());
+        let expected = direct_value(&model, &state);
+
+        // Concurrent clients end up in the same batches
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let state = state.clone();
+                thread::spawn(move || {
+                    let mut client = InferenceClient::connect(address).unwrap();
+                    client.evaluate(&state).unwrap()
+                })
+            })
+            .collect();
+        let mut best = None;
+        for worker in workers {
+            let evaluation = worker.join().unwrap();
+            assert!((evaluation.value - expected).abs() < 1e-5);
+            assert_eq!(evaluation.policy.len(), state.generate_moves().len());
+            let total: f32 = evaluation.policy.iter().map(|(_, p)| p).sum();
+            assert!((total - 1.0).abs() < 1e-3);
+            best = evaluation.policy.first().map(|&(mv, _)| mv);
+        }
+        let mut searcher = RemoteSearcher::new(address.to_string());
+        assert_eq!(searcher.best_move(&state, &SearchLimits::new()), best);
+
+        let mut client = InferenceClient::connect(address).unwrap();
+        let dir = std::env::temp_dir().join(format!("chess-ai-server-{}", std::process::id()));
+        let config = tiny_config().with_head_dimension(16);
+        let replacement = config.init::<NdArray>(&device);
+        save_checkpoint(&replacement, &config, &dir, Precision::Full).unwrap();
+
+        client.reload(&dir).unwrap();
+        let evaluation = client.evaluate(&state).unwrap();
+        assert!((evaluation.value - direct_value(&replacement, &state)).abs() < 1e-5);
+
+        assert!(client.reload(dir.join("missing")).is_err());
+        assert!(client.request("eval not a fen").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+use crate::chess::{moves::Move, types::Color, State};
+use std::time::{Duration, Instant};
+
+// Assumed number of moves left when the time control does not say
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+pub(crate) const DEFAULT_MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+// The hard limit may stretch the soft limit this far, but never past this share of the clock
+const HARD_LIMIT_FACTOR: u32 = 4;
+const HARD_LIMIT_SHARE: f64 = 0.8;
+// With a single legal reply there is nothing to search beyond a first iteration
+const SINGLE_REPLY_LIMIT: Duration = Duration::from_millis(10);
+// Centipawn drop below the best reported score that counts as a failing score
+const SCORE_DROP_MARGIN: i32 = 20;
+const MAX_SCORE_DROP: i32 = 100;
+
+#[derive(Copy, Clone)]
+pub(crate) struct TimeControl {
+    pub(crate) time: [Duration; 2],
+    pub(crate) increment: [Duration; 2],
+    pub(crate) moves_to_go: Option<u32>,
+    pub(crate) move_overhead: Duration,
+}
+
+impl TimeControl {
+    pub(crate) const fn new(
+        wtime: Duration,
+        btime: Duration,
+        winc: Duration,
+        binc: Duration,
+    ) -> Self {
+        Self {
+            time: [wtime, btime],
+            increment: [winc, binc],
+            moves_to_go: None,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+        }
+    }
+
+    pub(crate) const fn with_moves_to_go(mut self, moves_to_go: u32) -> Self {
+        self.moves_to_go = Some(moves_to_go);
+        self
+    }
+
+    pub(crate) const fn with_move_overhead(mut self, move_overhead: Duration) -> Self {
+        self.move_overhead = move_overhead;
+        self
+    }
+}
+
+// Budget of one move under a time control. `AlphaBeta` reports after each completed iteration and
+// stops once `should_stop`, and polls `must_stop` inside the search.
+pub(crate) struct TimeManager {
+    start: Instant,
+    base_soft: Duration,
+    soft: Duration,
+    hard: Duration,
+    best_move: Option<Move>,
+    // Consecutive reports with the same best move
+    stability: u32,
+    best_score: Option<i32>,
+}
+
+impl TimeManager {
+    pub(crate) fn new(time_control: &TimeControl, state: &State) -> Self {
+        let color = state.turn;
+        let (soft, hard) = if state.generate_moves().len() == 1 {
+            (Duration::ZERO, SINGLE_REPLY_LIMIT)
+        } else {
+            Self::limits(time_control, color)
+        };
+
+        Self {
+            start: Instant::now(),
+            base_soft: soft,
+            soft,
+            hard,
+            best_move: None,
+            stability: 0,
+            best_score: None,
+        }
+    }
+
+    fn limits(time_control: &TimeControl, color: Color) -> (Duration, Duration) {
+        let time = time_control.time[color];
+        let increment = time_control.increment[color];
+        let moves_to_go = time_control
+            .moves_to_go
+            .unwrap_or(DEFAULT_MOVES_TO_GO)
+            .max(1);
+
+        // Time we can spend without flagging, the overhead covers GUI and network latency
+        let available = time.saturating_sub(time_control.move_overhead);
+        let max_hard = available.mul_f64(HARD_LIMIT_SHARE);
+
+        let soft = available / moves_to_go + increment * 3 / 4;
+        let hard = (soft * HARD_LIMIT_FACTOR).min(max_hard);
+        (soft.min(hard), hard)
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // Checked between iterations or playout batches, starting another one is not worth it
+    pub(crate) fn should_stop(&self) -> bool {
+        self.elapsed() >= self.soft
+    }
+
+    // Checked inside the search, the move has to be played now
+    pub(crate) fn must_stop(&self) -> bool {
+        self.elapsed() >= self.hard
+    }
+
+    // `score` is in centipawns from the side to move's point of view
+    pub(crate) fn update(&mut self, best_move: Move, score: i32) {
+        if self.best_move == Some(best_move) {
+            self.stability += 1;
+        } else {
+            self.stability = 0;
+        }
+        self.best_move = Some(best_move);
+
+        // A changing best move means the search has not settled yet
+        let instability = match self.stability {
+            0 => 1.6,
+            1 => 1.3,
+            2 => 1.0,
+            3 => 0.9,
+            _ => 0.75,
+        };
+
+        let drop = self
+            .best_score
+            .map_or(0, |previous| previous - score)
+            .clamp(0, MAX_SCORE_DROP);
+        let falling = if drop > SCORE_DROP_MARGIN {
+            1.0 + drop as f64 / MAX_SCORE_DROP as f64
+        } else {
+            1.0
+        };
+        self.best_score = Some(
+            self.best_score
+                .map_or(score, |previous| previous.max(score)),
+        );
+
+        self.soft = self.base_soft.mul_f64(instability * falling).min(self.hard);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blitz() -> TimeControl {
+        TimeControl::new(
+            Duration::from_secs(10),
+            Duration::from_secs(10),
+            Duration::from_millis(100),
+            Duration::from_millis(100),
+        )
+    }
+
+    #[test]
+    fn test_limits_for_tournament_controls() {
+        let state = State::new();
+
+        let manager = TimeManager::new(&blitz(), &state);
+        assert!(manager.soft > Duration::from_millis(200));
+        assert!(manager.soft < Duration::from_secs(1));
+        assert!(manager.hard >= manager.soft);
+        assert!(manager.hard < Duration::from_secs(8));
+
+        // 40 moves in 60 seconds, with one move left the whole clock is the budget
+        let classical = TimeControl::new(
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+            Duration::ZERO,
+            Duration::ZERO,
+        );
+        let manager = TimeManager::new(&classical.with_moves_to_go(40), &state);
+        assert_eq!(
+            manager.soft,
+            (Duration::from_secs(60) - DEFAULT_MOVE_OVERHEAD) / 40
+        );
+        let manager = TimeManager::new(&classical.with_moves_to_go(1), &state);
+        assert!(manager.hard < Duration::from_secs(60) - DEFAULT_MOVE_OVERHEAD);
+
+        // Never plan to spend more than the clock minus the overhead
+        let flagging = blitz().with_move_overhead(Duration::from_secs(20));
+        let manager = TimeManager::new(&flagging, &state);
+        assert_eq!(manager.hard, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_single_reply_is_played_immediately() {
+        let state = State::from_fen("k7/8/8/8/8/8/1r6/K7 w - - 0 1").unwrap();
+        assert_eq!(state.generate_moves().len(), 1);
+
+        let manager = TimeManager::new(&blitz(), &state);
+        assert_eq!(manager.soft, Duration::ZERO);
+        assert!(manager.should_stop());
+    }
+
+    #[test]
+    fn test_instability_extends_soft_limit() {
+        let state = State::new();
+        let moves = state.generate_moves();
+        let mut manager = TimeManager::new(&blitz(), &state);
+        let base = manager.soft;
+
+        manager.update(moves[0], 20);
+        let unsettled = manager.soft;
+        assert!(unsettled > base);
+
+        for _ in 0..5 {
+            manager.update(moves[0], 20);
+        }
+        assert!(manager.soft < base);
+
+        manager.update(moves[0], -80);
+        assert!(manager.soft > base);
+        assert!(manager.soft <= manager.hard);
+    }
+}
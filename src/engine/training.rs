@@ -0,0 +1,394 @@
+use crate::{
+    chess::{moves::Move, State},
+    engine::{
+        model::{ModelOutput, TransformerModel, TransformerModelConfig},
+        policy::{legal_move_masks, PolicyEncoding},
+        replay::{ReplayBuffer, ReplayBufferConfig},
+    },
+};
+use burn::{
+    config::Config,
+    nn::loss::{HuberLossConfig, MseLoss, Reduction},
+    optim::{AdamConfig, GradientsParams, Optimizer},
+    tensor::{
+        backend::{AutodiffBackend, Backend},
+        ElementConversion, TensorData,
+    },
+    Tensor,
+};
+use std::io;
+
+// Keeps log() finite where the policy or WDL head assigns zero probability
+const LOG_EPSILON: f32 = 1e-8;
+
+pub(crate) struct TrainingTargets<B: Backend> {
+    // [batch_size, 64 * 73] visit or move distribution
+    pub(crate) policy: Tensor<B, 2>,
+    // [batch_size, 1] game result from White's point of view
+    pub(crate) value: Tensor<B, 2>,
+    // [batch_size, 3] one-hot or soft win/draw/loss from White's point of view
+    pub(crate) wdl: Option<Tensor<B, 2>>,
+    // [batch_size, 1] plies until the game ended
+    pub(crate) moves_left: Option<Tensor<B, 2>>,
+}
+
+// A position with whatever ground truth is known about it
+#[derive(Clone)]
+pub(crate) struct TrainingPosition {
+    pub(crate) state: State,
+    // Move played from this position in the game
+    pub(crate) played: Option<Move>,
+    // Final result from White's point of view: 1 win, 0 draw, -1 loss
+    pub(crate) result: Option<f32>,
+    // Plies from this position until the game ended
+    pub(crate) moves_left: Option<f32>,
+}
+
+impl TrainingPosition {
+    pub(crate) const fn new(state: State) -> Self {
+        Self {
+            state,
+            played: None,
+            result: None,
+            moves_left: None,
+        }
+    }
+
+    pub(crate) const fn with_played(mut self, played: Move) -> Self {
+        self.played = Some(played);
+        self
+    }
+
+    pub(crate) const fn with_result(mut self, result: f32) -> Self {
+        self.result = Some(result);
+        self
+    }
+
+    pub(crate) const fn with_moves_left(mut self, plies: usize) -> Self {
+        self.moves_left = Some(plies as f32);
+        self
+    }
+}
+
+// [batch_size, 64 * 73] one-hot played moves and [batch_size, 1] results. Positions missing a
+// label take the row of `fallback` instead, so labelled and unlabelled positions can share a batch.
+pub(crate) fn label_targets<B: Backend>(
+    positions: &[&TrainingPosition],
+    fallback: (Tensor<B, 2>, Tensor<B, 2>),
+    device: &B::Device,
+) -> (Tensor<B, 2>, Tensor<B, 2>) {
+    let (fallback_policy, fallback_value) = fallback;
+    let n = fallback_policy.dims()[1];
+    let batch_size = positions.len();
+
+    let mut policy = vec![0f32; batch_size * n];
+    let mut value = vec![0f32; batch_size];
+    let (mut policy_rows, mut value_rows) = (vec![0f32; batch_size], vec![0f32; batch_size]);
+    for (i, position) in positions.iter().enumerate() {
+        if let Some(played) = position.played {
+            policy[i * n + played.policy_index()] = 1.0;
+            policy_rows[i] = 1.0;
+        }
+        if let Some(result) = position.result {
+            value[i] = result;
+            value_rows[i] = 1.0;
+        }
+    }
+
+    let labelled =
+        |rows: Vec<f32>| Tensor::<B, 2>::from_data(TensorData::new(rows, [batch_size, 1]), device);
+    let (policy_rows, value_rows) = (labelled(policy_rows), labelled(value_rows));
+    let policy = Tensor::from_data(TensorData::new(policy, [batch_size, n]), device)
+        + fallback_policy * policy_rows.neg().add_scalar(1.0);
+    let value = Tensor::from_data(TensorData::new(value, [batch_size, 1]), device)
+        + fallback_value * value_rows.neg().add_scalar(1.0);
+    (policy, value)
+}
+
+// Win/draw/loss target of a 1, 0 or -1 result, soft for anything in between
+pub(crate) fn result_wdl(result: f32) -> [f32; 3] {
+    [result.max(0.0), 1.0 - result.abs(), (-result).max(0.0)]
+}
+
+#[derive(Config, Debug)]
+pub(crate) struct LossConfig {
+    #[config(default = 1.0)]
+    policy_weight: f32,
+    #[config(default = 1.0)]
+    value_weight: f32,
+    #[config(default = 1.0)]
+    wdl_weight: f32,
+    #[config(default = 0.01)]
+    moves_left_weight: f32,
+    // Moves-left errors beyond this many plies grow linearly
+    #[config(default = 10.0)]
+    moves_left_delta: f32,
+}
+
+impl LossConfig {
+    // Weighted sum of every loss whose head and target are both present
+    pub(crate) fn loss<B: Backend>(
+        &self,
+        output: &ModelOutput<B>,
+        targets: &TrainingTargets<B>,
+    ) -> Tensor<B, 1> {
+        let mut loss = policy_loss(output.policy.clone(), targets.policy.clone())
+            .mul_scalar(self.policy_weight)
+            + value_loss(output.value.clone(), targets.value.clone()).mul_scalar(self.value_weight);
+
+        if let (Some(wdl), Some(target)) = (&output.wdl, &targets.wdl) {
+            loss = loss + wdl_loss(wdl.clone(), target.clone()).mul_scalar(self.wdl_weight);
+        }
+        if let (Some(moves_left), Some(target)) = (&output.moves_left, &targets.moves_left) {
+            let moves_left = HuberLossConfig::new(self.moves_left_delta).init().forward(
+                moves_left.clone(),
+                target.clone(),
+                Reduction::Mean,
+            );
+            loss = loss + moves_left.mul_scalar(self.moves_left_weight);
+        }
+
+        loss
+    }
+}
+
+// Trains on minibatches from a replay buffer that self-play keeps filling
+#[derive(Config, Debug)]
+pub(crate) struct TrainingConfig {
+    // Architecture of a model trained from scratch
+    #[config(default = "TransformerModelConfig::new()")]
+    pub(crate) model: TransformerModelConfig,
+    #[config(default = "ReplayBufferConfig::new()")]
+    pub(crate) replay: ReplayBufferConfig,
+    #[config(default = 10000)]
+    pub(crate) steps: usize,
+    #[config(default = 256)]
+    pub(crate) batch_size: usize,
+    #[config(default = 1e-3)]
+    pub(crate) learning_rate: f64,
+    // Steps between looking for new chunks, 0 trains on what was there at the start
+    #[config(default = 100)]
+    pub(crate) refresh_every: usize,
+    #[config(default = 100)]
+    pub(crate) report_every: usize,
+    #[config(default = 1000)]
+    pub(crate) checkpoint_every: usize,
+    #[config(default = "LossConfig::new()")]
+    pub(crate) loss: LossConfig,
+    #[config(default = "AdamConfig::new()")]
+    pub(crate) optimizer: AdamConfig,
+}
+
+impl TrainingConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.steps == 0 || self.batch_size == 0 {
+            return Err("steps and batch_size must be at least 1".to_string());
+        }
+        self.model.validate()?;
+        match self.learning_rate > 0.0 {
+            true => Ok(()),
+            false => Err(format!(
+                "learning_rate must be positive, got {}",
+                self.learning_rate
+            )),
+        }
+    }
+
+    // `progress` hears the step and the mean loss since the last report every `report_every`
+    // steps. `checkpoint` is called every `checkpoint_every` steps and after the last one, the
+    // buffer's sampling state is saved alongside.
+    pub(crate) fn train<B: AutodiffBackend>(
+        &self,
+        mut model: TransformerModel<B>,
+        buffer: &mut ReplayBuffer,
+        device: &B::Device,
+        mut progress: impl FnMut(usize, f64),
+        mut checkpoint: impl FnMut(&TransformerModel<B>, usize) -> io::Result<()>,
+    ) -> io::Result<TransformerModel<B>> {
+        let mut optimizer = self.optimizer.init::<B, TransformerModel<B>>();
+        let (mut running, mut batches) = (0.0, 0);
+
+        for step in 1..=self.steps {
+            if self.refresh_every > 0 && step.is_multiple_of(self.refresh_every) {
+                buffer.refresh()?;
+            }
+            let Some((states, targets)) = buffer.minibatch::<B>(self.batch_size, device) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the replay buffer holds no games",
+                ));
+            };
+
+            let states: Vec<&State> = states.iter().collect();
+            let output = model.forward(&states, legal_move_masks::<B>(&states, device), device);
+            let loss = self.loss.loss(&output, &targets);
+            running += loss.clone().into_scalar().elem::<f64>();
+            batches += 1;
+            let grads = GradientsParams::from_grads(loss.backward(), &model);
+            model = optimizer.step(self.learning_rate, model, grads);
+
+            if self.report_every > 0 && step.is_multiple_of(self.report_every) {
+                progress(step, running / batches as f64);
+                (running, batches) = (0.0, 0);
+            }
+            if step == self.steps
+                || (self.checkpoint_every > 0 && step.is_multiple_of(self.checkpoint_every))
+            {
+                checkpoint(&model, step)?;
+                buffer.save_state()?;
+            }
+        }
+        Ok(model)
+    }
+}
+
+// Cross-entropy between the target distribution and the predicted probabilities
+pub(crate) fn policy_loss<B: Backend>(policy: Tensor<B, 2>, target: Tensor<B, 2>) -> Tensor<B, 1> {
+    cross_entropy(policy, target)
+}
+
+pub(crate) fn value_loss<B: Backend>(value: Tensor<B, 2>, target: Tensor<B, 2>) -> Tensor<B, 1> {
+    MseLoss::new().forward(value, target, Reduction::Mean)
+}
+
+pub(crate) fn wdl_loss<B: Backend>(wdl: Tensor<B, 2>, target: Tensor<B, 2>) -> Tensor<B, 1> {
+    cross_entropy(wdl, target)
+}
+
+// KL(target || predicted), zero when the distributions match. Differs from the cross-entropy only
+// by the target's entropy, so both give the same gradients.
+pub(crate) fn kl_divergence<B: Backend>(
+    probabilities: Tensor<B, 2>,
+    target: Tensor<B, 2>,
+) -> Tensor<B, 1> {
+    let target_entropy = (target.clone() * target.clone().clamp_min(LOG_EPSILON).log())
+        .sum_dim(1)
+        .mean()
+        .neg();
+    cross_entropy(probabilities, target) - target_entropy
+}
+
+fn cross_entropy<B: Backend>(probabilities: Tensor<B, 2>, target: Tensor<B, 2>) -> Tensor<B, 1> {
+    let log_probabilities = probabilities.clamp_min(LOG_EPSILON).log();
+    (target * log_probabilities).sum_dim(1).mean().neg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess::State,
+        engine::{model::TransformerModelConfig, policy::legal_move_masks},
+    };
+    use burn::{backend::NdArray, tensor::TensorData};
+
+    #[test]
+    fn test_losses_cover_active_heads() {
+        let device = Default::default();
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .with_wdl_head(true)
+            .with_moves_left_head(true)
+            .init::<NdArray>(&device);
+
+        let state = State::new();
+        let mask = legal_move_masks::<NdArray>(&[&state], &device);
+        let output = model.forward(&[&state], mask.clone(), &device);
+
+        let e4 = state.parse_san("e4").unwrap();
+        let mut policy = vec![0f32; mask.dims()[1]];
+        policy[crate::engine::policy::PolicyEncoding::policy_index(&e4)] = 1.0;
+        let targets = TrainingTargets {
+            policy: Tensor::from_data(TensorData::new(policy, [1, mask.dims()[1]]), &device),
+            value: Tensor::from_data(TensorData::new(vec![1f32], [1, 1]), &device),
+            wdl: Some(Tensor::from_data(
+                TensorData::new(vec![1f32, 0.0, 0.0], [1, 3]),
+                &device,
+            )),
+            moves_left: Some(Tensor::from_data(
+                TensorData::new(vec![80f32], [1, 1]),
+                &device,
+            )),
+        };
+
+        let config = LossConfig::new();
+        let total = config.loss(&output, &targets).into_scalar();
+        let without_aux = LossConfig::new()
+            .with_wdl_weight(0.0)
+            .with_moves_left_weight(0.0)
+            .loss(&output, &targets)
+            .into_scalar();
+        assert!(total.is_finite());
+        assert!(total > without_aux);
+
+        // A perfect prediction has no WDL loss
+        let wdl = wdl_loss(
+            Tensor::<NdArray, 2>::from_data(TensorData::new(vec![1f32, 0.0, 0.0], [1, 3]), &device),
+            targets.wdl.unwrap(),
+        );
+        assert!(wdl.into_scalar().abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_train_from_replay_buffer() {
+        use crate::{
+            chess::types::GameResult,
+            engine::replay::{write_chunk, SelfPlayGame},
+        };
+        use burn::backend::Autodiff;
+
+        let dir = std::env::temp_dir().join(format!("training-test-{}", std::process::id()));
+        let device = Default::default();
+        let config = TrainingConfig::new()
+            .with_steps(3)
+            .with_batch_size(4)
+            .with_checkpoint_every(2)
+            .with_report_every(2);
+        let model = TransformerModelConfig::new()
+            .with_n_blocks(1)
+            .with_n_heads(2)
+            .with_head_dimension(8)
+            .init::<Autodiff<NdArray>>(&device);
+
+        let mut buffer = ReplayBuffer::open(&dir, config.replay.clone()).unwrap();
+        let mut checkpoints = Vec::new();
+        let empty = config.train(
+            model.clone(),
+            &mut buffer,
+            &device,
+            |_, _| {},
+            |_, _| Ok(()),
+        );
+        assert!(empty.is_err());
+
+        let root = State::new();
+        let e4 = root.parse_uci("e2e4").unwrap();
+        let game = SelfPlayGame {
+            root,
+            plies: vec![(e4, vec![(e4, 1.0)])],
+            result: GameResult::WhiteWin,
+        };
+        write_chunk(&dir, &[game]).unwrap();
+        buffer.refresh().unwrap();
+        let mut reports = Vec::new();
+        config
+            .train(
+                model,
+                &mut buffer,
+                &device,
+                |step, _| reports.push(step),
+                |_, step| {
+                    checkpoints.push(step);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(reports, vec![2]);
+        assert_eq!(checkpoints, vec![2, 3]);
+        assert_eq!(buffer.batches(), 3);
+        assert!(dir.join("replay-state.json").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,450 @@
+use crate::{
+    chess::{
+        moves::{Move, MoveType},
+        prng::PseudoRng,
+        types::GameResult,
+        State,
+    },
+    engine::eval::{EvalParams, Evaluate, HandcraftedEval},
+};
+use arrayvec::ArrayVec;
+use burn::config::Config;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+// Victim values used only to order captures in the quiescence search
+const MVV_LVA_VALUES: [i32; 6] = [100, 500, 300, 300, 900, 0];
+const MAX_SCALE: f64 = 3.0;
+
+pub(crate) struct TexelTuner {
+    // Quiet positions with the game result from White's point of view
+    positions: Vec<(State, f64)>,
+    pub(crate) scale: f64,
+}
+
+impl TexelTuner {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    // Accepts one labelled position per line: a FEN (optionally without move counters) followed by
+    // a result written as "1-0", "0-1", "1/2-1/2" or a White score between 0 and 1. Surrounding
+    // brackets, quotes and EPD opcodes such as `c9` are ignored. Blank lines and `#` comments are
+    // skipped, any other line without a valid position and result is an error naming the line.
+    pub(crate) fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let eval = HandcraftedEval::new();
+        let mut positions = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, message),
+                )
+            };
+            let (fen, result) = parse_labelled_line(&line)
+                .ok_or_else(|| invalid(format!("no labelled position in '{}'", trimmed)))?;
+            let state = State::from_fen(&fen).map_err(|err| invalid(err.to_string()))?;
+            positions.push((quiet_position(&eval, state), result));
+        }
+
+        let mut tuner = Self {
+            positions,
+            scale: 1.0,
+        };
+        tuner.scale = tuner.fit_scale(&EvalParams::new());
+        Ok(tuner)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    // Mean squared error between the results and the sigmoid-mapped evaluations
+    pub(crate) fn error(&self, params: &EvalParams) -> f64 {
+        if self.positions.is_empty() {
+            return 0.0;
+        }
+
+        let eval = HandcraftedEval::with_params(params.clone());
+        let total: f64 = self
+            .positions
+            .iter()
+            .map(|(state, result)| {
+                let predicted = sigmoid(eval.evaluate_white(state) as f64, self.scale);
+                (result - predicted).powi(2)
+            })
+            .sum();
+        total / self.positions.len() as f64
+    }
+
+    // Higher is better, cheap enough to rank a whole population of parameter sets
+    pub(crate) fn fitness(&self, params: &EvalParams) -> f64 {
+        -self.error(params)
+    }
+
+    // Fits the sigmoid scale so the starting parameters explain the results as well as possible
+    pub(crate) fn fit_scale(&self, params: &EvalParams) -> f64 {
+        let eval = HandcraftedEval::with_params(params.clone());
+        let evals: Vec<f64> = self
+            .positions
+            .iter()
+            .map(|(state, _)| eval.evaluate_white(state) as f64)
+            .collect();
+        let error = |scale: f64| -> f64 {
+            evals
+                .iter()
+                .zip(&self.positions)
+                .map(|(&e, (_, result))| (result - sigmoid(e, scale)).powi(2))
+                .sum()
+        };
+
+        // Ternary search, the error is unimodal in the scale
+        let (mut low, mut high) = (0.0, MAX_SCALE);
+        for _ in 0..100 {
+            let a = low + (high - low) / 3.0;
+            let b = high - (high - low) / 3.0;
+            if error(a) < error(b) {
+                high = b;
+            } else {
+                low = a;
+            }
+        }
+        (low + high) / 2.0
+    }
+
+    // Local search over the flattened parameter vector: nudge each weight up or down by `step`
+    // and keep any change that lowers the error, until a full pass makes no progress. `progress`
+    // hears the pass number and the error after each pass.
+    pub(crate) fn tune(
+        &self,
+        params: &EvalParams,
+        step: i32,
+        max_passes: usize,
+        mut progress: impl FnMut(usize, f64),
+    ) -> EvalParams {
+        let mut values = params.to_vec();
+        let mut best = self.error(params);
+
+        for pass in 0..max_passes {
+            let mut improved = false;
+            for i in 0..values.len() {
+                for delta in [step, -step] {
+                    values[i] += delta;
+                    let error = self.error(&EvalParams::from_slice(&values));
+                    if error < best {
+                        best = error;
+                        improved = true;
+                        break;
+                    }
+                    values[i] -= delta;
+                }
+            }
+
+            progress(pass + 1, best);
+            if !improved {
+                break;
+            }
+        }
+
+        EvalParams::from_slice(&values)
+    }
+}
+
+// (mu + lambda) evolution strategy over the flattened parameter vector, ranked by
+// `TexelTuner::fitness`. Unlike `tune` it can step over small local bumps in the error.
+#[derive(Config, Debug)]
+pub(crate) struct EvolutionConfig {
+    #[config(default = 50)]
+    pub(crate) generations: usize,
+    // Offspring per generation
+    #[config(default = 16)]
+    pub(crate) population: usize,
+    // Best parameter sets kept as parents, out of the parents and offspring together
+    #[config(default = 4)]
+    pub(crate) survivors: usize,
+    // Share of the weights an offspring changes
+    #[config(default = 0.05)]
+    pub(crate) mutation_rate: f64,
+    // Largest change of one weight
+    #[config(default = 10)]
+    pub(crate) mutation_step: i32,
+    #[config(default = 0x2545f4914f6cdd1d)]
+    pub(crate) seed: u64,
+}
+
+impl EvolutionConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.population == 0 || self.survivors == 0 || self.mutation_step <= 0 {
+            return Err("population, survivors and mutation_step must be at least 1".to_string());
+        }
+        if !(self.mutation_rate > 0.0 && self.mutation_rate <= 1.0) {
+            return Err(format!(
+                "mutation_rate must be in (0, 1], got {}",
+                self.mutation_rate
+            ));
+        }
+        Ok(())
+    }
+
+    // Never returns anything less fit than `params`, which stays among the parents until beaten.
+    // `progress` hears the generation number and the error of the best parent after each one.
+    pub(crate) fn evolve(
+        &self,
+        tuner: &TexelTuner,
+        params: &EvalParams,
+        mut progress: impl FnMut(usize, f64),
+    ) -> EvalParams {
+        let mut rng = PseudoRng::new(self.seed.max(1));
+        let mut parents = vec![(tuner.fitness(params), params.to_vec())];
+
+        for generation in 0..self.generations {
+            let mut candidates = parents.clone();
+            for _ in 0..self.population {
+                let parent = &parents[rng.next() as usize % parents.len()].1;
+                let child = self.mutate(&mut rng, parent);
+                candidates.push((tuner.fitness(&EvalParams::from_slice(&child)), child));
+            }
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+            candidates.truncate(self.survivors);
+            parents = candidates;
+            progress(generation + 1, -parents[0].0);
+        }
+
+        EvalParams::from_slice(&parents[0].1)
+    }
+
+    fn mutate(&self, rng: &mut PseudoRng, parent: &[i32]) -> Vec<i32> {
+        let mut child = parent.to_vec();
+        let changes = ((child.len() as f64 * self.mutation_rate).ceil() as usize).max(1);
+        for _ in 0..changes {
+            let i = rng.next() as usize % child.len();
+            let step =
+                (rng.next() % (2 * self.mutation_step as u64 + 1)) as i32 - self.mutation_step;
+            child[i] += step;
+        }
+        child
+    }
+}
+
+// Rust source for the tuned weights, loadable with `EvalParams::from_slice(&TUNED_PARAMS)`
+pub(crate) fn export_rust_source(params: &EvalParams) -> String {
+    let values = params.to_vec();
+    let mut source = String::new();
+    source.push_str("// Generated by the Texel tuner, load with `EvalParams::from_slice`\n");
+    source.push_str("#[rustfmt::skip]\n");
+    source.push_str(&format!(
+        "pub(crate) const TUNED_PARAMS: [i32; {}] = [\n",
+        values.len()
+    ));
+    for chunk in values.chunks(16) {
+        let line: Vec<String> = chunk.iter().map(|v| v.to_string()).collect();
+        source.push_str(&format!("    {},\n", line.join(", ")));
+    }
+    source.push_str("];\n");
+    source
+}
+
+pub(crate) fn save_rust_source(params: &EvalParams, path: impl AsRef<Path>) -> io::Result<()> {
+    std::fs::write(path, export_rust_source(params))
+}
+
+pub(crate) fn quiescence<E: Evaluate>(
+    eval: &E,
+    state: &mut State,
+    mut alpha: i32,
+    beta: i32,
+    pv: &mut Vec<Move>,
+) -> i32 {
+    pv.clear();
+    let stand_pat = eval.evaluate(state);
+    if stand_pat >= beta {
+        return stand_pat;
+    }
+    alpha = alpha.max(stand_pat);
+
+    let mut captures: ArrayVec<(Move, i32), 218> = state
+        .generate_moves()
+        .into_iter()
+        .filter_map(|mv| {
+            let (from, to, move_type) = mv.unpack();
+            let attacker = state.board.mailbox[from].map_or(0, |p| MVV_LVA_VALUES[p as usize % 6]);
+            let victim = match (move_type, state.board.mailbox[to]) {
+                (MoveType::EnPassant, _) => MVV_LVA_VALUES[0],
+                (MoveType::PromotionQueen, victim) => {
+                    MVV_LVA_VALUES[4] + victim.map_or(0, |p| MVV_LVA_VALUES[p as usize % 6])
+                }
+                (_, Some(victim)) => MVV_LVA_VALUES[victim as usize % 6],
+                (_, None) => return None,
+            };
+            Some((mv, victim * 10 - attacker / 100))
+        })
+        .collect();
+    captures.sort_unstable_by_key(|&(_, order)| -order);
+
+    let mut child_pv = Vec::new();
+    for (mv, _) in captures {
+        state.make_move(mv);
+        let score = -quiescence(eval, state, -beta, -alpha, &mut child_pv);
+        state.unmake_move();
+
+        if score >= beta {
+            return score;
+        }
+        if score > alpha {
+            alpha = score;
+            pv.clear();
+            pv.push(mv);
+            pv.extend_from_slice(&child_pv);
+        }
+    }
+
+    alpha
+}
+
+// Plays out the capture sequence the quiescence search expects, so the static eval is meaningful
+fn quiet_position<E: Evaluate>(eval: &E, mut state: State) -> State {
+    let mut pv = Vec::new();
+    quiescence(eval, &mut state, -i32::MAX, i32::MAX, &mut pv);
+    for mv in pv {
+        state.make_move(mv);
+    }
+    state
+}
+
+fn sigmoid(eval: f64, scale: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-scale * eval / 400.0))
+}
+
+pub(crate) fn parse_labelled_line(line: &str) -> Option<(String, f64)> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.len() < 5 || tokens[0].starts_with('#') {
+        return None;
+    }
+
+    let (counters, rest) = match &tokens[4..] {
+        [halfmove, fullmove, rest @ ..]
+            if !rest.is_empty()
+                && halfmove.parse::<u32>().is_ok()
+                && fullmove.parse::<u32>().is_ok() =>
+        {
+            (format!("{} {}", halfmove, fullmove), rest)
+        }
+        // Bare move counters without a result
+        [halfmove, fullmove]
+            if halfmove.parse::<u32>().is_ok() && fullmove.parse::<u32>().is_ok() =>
+        {
+            return None
+        }
+        rest => ("0 1".to_string(), rest),
+    };
+
+    let result = rest.iter().rev().find_map(|token| {
+        let token = token.trim_matches(['[', ']', '"', ';', '|']);
+        match GameResult::from_pgn(token) {
+            Some(GameResult::WhiteWin) => Some(1.0),
+            Some(GameResult::BlackWin) => Some(0.0),
+            Some(GameResult::Draw) => Some(0.5),
+            None => token
+                .parse::<f64>()
+                .ok()
+                .filter(|r| (0.0..=1.0).contains(r)),
+        }
+    })?;
+
+    Some((format!("{} {}", tokens[..4].join(" "), counters), result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: &str = r#"
+rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 [0.5]
+3qk3/8/8/8/8/8/8/3QK1Q1 w - - 0 1 [1.0]
+3qk1q1/8/8/8/8/8/8/3QK3 b - - c9 "0-1";
+4k3/8/1P6/8/8/8/8/4K3 w - - | 1-0
+4k3/8/8/8/8/8/8/4K3 w - - 0 1 1/2-1/2
+"#;
+
+    #[test]
+    fn test_parse_labelled_lines() {
+        let (fen, result) = parse_labelled_line("4k3/8/8/8/8/8/8/4K3 w - - 12 40 [1.0]").unwrap();
+        assert_eq!(fen, "4k3/8/8/8/8/8/8/4K3 w - - 12 40");
+        assert_eq!(result, 1.0);
+
+        let (fen, result) = parse_labelled_line("4k3/8/8/8/8/8/8/4K3 b - - c9 \"0-1\";").unwrap();
+        assert_eq!(fen, "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(result, 0.0);
+
+        assert!(parse_labelled_line("4k3/8/8/8/8/8/8/4K3 w - - 0 1").is_none());
+        assert!(parse_labelled_line("# comment").is_none());
+    }
+
+    #[test]
+    fn test_malformed_lines_are_errors() {
+        let err = TexelTuner::from_reader(format!("{}\nnot a position\n", POSITIONS).as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 8:"));
+
+        // A position without a result cannot be fitted
+        let err = TexelTuner::from_reader("4k3/8/8/8/8/8/8/4K3 w - - 0 1\n".as_bytes())
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("line 1:"));
+
+        let err = TexelTuner::from_reader("4k3/8/8/8/8/8/8/4K2 w - - 1-0\n".as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_quiescence_resolves_captures() {
+        let eval = HandcraftedEval::new();
+
+        // The hanging queen on d5 is taken before the position is scored
+        let state = quiet_position(
+            &eval,
+            State::from_fen("4k3/8/8/3q4/4P3/8/8/4K3 w - - 0 1").unwrap(),
+        );
+        assert!(state.board.pieces[crate::chess::types::Piece::BlackQueen].count() == 0);
+        assert!(eval.evaluate(&state) < 0);
+    }
+
+    #[test]
+    fn test_tuning_does_not_increase_error() {
+        let tuner = TexelTuner::from_reader(POSITIONS.as_bytes()).unwrap();
+        assert_eq!(tuner.len(), 5);
+        assert!(tuner.scale > 0.0 && tuner.scale < MAX_SCALE);
+
+        let params = EvalParams::new();
+        let tuned = tuner.tune(&params, 5, 1, |_, _| {});
+        assert!(tuner.error(&tuned) <= tuner.error(&params));
+        assert!(tuner.fitness(&tuned) >= tuner.fitness(&params));
+
+        let config = EvolutionConfig::new()
+            .with_generations(3)
+            .with_population(4);
+        assert!(config.validate().is_ok());
+        let evolved = config.evolve(&tuner, &params, |_, _| {});
+        assert!(tuner.fitness(&evolved) >= tuner.fitness(&params));
+        assert!(config.with_mutation_rate(0.0).validate().is_err());
+
+        let source = export_rust_source(&tuned);
+        assert!(source.contains(&format!("[i32; {}]", params.to_vec().len())));
+    }
+}
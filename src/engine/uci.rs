@@ -0,0 +1,237 @@
+use crate::{
+    chess::State,
+    engine::{
+        search::{SearchLimits, Searcher},
+        selfplay::Engine,
+        time::{TimeControl, DEFAULT_MOVE_OVERHEAD},
+    },
+};
+use burn::tensor::backend::Backend;
+use std::{
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+// Largest `Move Overhead` a GUI may set, in milliseconds
+const MAX_MOVE_OVERHEAD_MS: u64 = 5000;
+
+// The subset of the UCI protocol a GUI or match runner needs to play games. Searches run on the
+// reading thread, so `stop` has nothing to interrupt and `go infinite` uses the default budget.
+pub(crate) struct UciEngine {
+    searcher: Box<dyn Searcher>,
+    state: State,
+    // For `go` without limits
+    default_limits: SearchLimits,
+    // Set with the `Move Overhead` option
+    move_overhead: Duration,
+}
+
+impl UciEngine {
+    pub(crate) fn new<B: Backend>(
+        engine: &Engine<B>,
+        device: &B::Device,
+        default_limits: SearchLimits,
+    ) -> Self {
+        Self {
+            searcher: engine.searcher(device, None),
+            state: State::new(),
+            default_limits,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+        }
+    }
+
+    // Answers commands until `quit` or the end of the input
+    pub(crate) fn run(&mut self, input: impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("uci") => {
+                    writeln!(output, "id name chess-ai {}", env!("CARGO_PKG_VERSION"))?;
+                    writeln!(
+                        output,
+                        "option name Move Overhead type spin default {} min 0 max {}",
+                        DEFAULT_MOVE_OVERHEAD.as_millis(),
+                        MAX_MOVE_OVERHEAD_MS
+                    )?;
+                    writeln!(output, "uciok")?;
+                }
+                Some("isready") => writeln!(output, "readyok")?,
+                Some("ucinewgame") => self.state = State::new(),
+                Some("setoption") => {
+                    if let Err(err) = self.set_option(tokens.collect()) {
+                        writeln!(output, "info string {}", err)?;
+                    }
+                }
+                Some("position") => {
+                    if let Err(err) = self.set_position(tokens.collect()) {
+                        writeln!(output, "info string {}", err)?;
+                    }
+                }
+                Some("go") => {
+                    let limits = self.limits(&tokens.collect::<Vec<_>>());
+                    let best = self.searcher.best_move(&self.state, &limits);
+                    // "0000" is the null move, sent when there is nothing to play
+                    let best = best.map_or("0000".to_string(), |mv| mv.to_uci());
+                    writeln!(output, "bestmove {}", best)?;
+                }
+                Some("quit") => break,
+                // Unknown commands are ignored, as the protocol asks
+                _ => {}
+            }
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    // setoption name <name> value <value>, `Move Overhead` being the only option
+    fn set_option(&mut self, tokens: Vec<&str>) -> Result<(), String> {
+        let value_at = tokens
+            .iter()
+            .position(|&token| token == "value")
+            .unwrap_or(tokens.len());
+        let name = tokens.get(1..value_at).unwrap_or_default().join(" ");
+        let value = tokens.get(value_at + 1..).unwrap_or_default().join(" ");
+        match name.as_str() {
+            "Move Overhead" => match value.parse::<u64>() {
+                Ok(ms) if ms <= MAX_MOVE_OVERHEAD_MS => {
+                    self.move_overhead = Duration::from_millis(ms);
+                    Ok(())
+                }
+                _ => Err(format!("invalid Move Overhead '{}'", value)),
+            },
+            _ => Err(format!("unknown option '{}'", name)),
+        }
+    }
+
+    // position [startpos | fen <fen>] [moves <uci>...]
+    fn set_position(&mut self, tokens: Vec<&str>) -> Result<(), String> {
+        let moves_at = tokens
+            .iter()
+            .position(|&token| token == "moves")
+            .unwrap_or(tokens.len());
+        let mut state = match tokens.first() {
+            Some(&"startpos") => State::new(),
+            Some(&"fen") => State::from_fen(&tokens[1..moves_at].join(" "))
+                .map_err(|err| format!("invalid FEN: {}", err))?,
+            _ => return Err("expected startpos or fen".to_string()),
+        };
+        for uci in tokens.iter().skip(moves_at + 1) {
+            let mv = state
+                .parse_uci(uci)
+                .ok_or_else(|| format!("illegal move {}", uci))?;
+            state.make_move(mv);
+        }
+        self.state = state;
+        Ok(())
+    }
+
+    // go [wtime <ms>] [btime <ms>] [winc <ms>] [binc <ms>] [movestogo <n>] [movetime <ms>]
+    //    [nodes <n>] [depth <n>] [infinite]
+    fn limits(&self, tokens: &[&str]) -> SearchLimits {
+        let value = |name: &str| -> Option<u64> {
+            let i = tokens.iter().position(|&token| token == name)?;
+            tokens.get(i + 1)?.parse().ok()
+        };
+        let millis = |name: &str| Duration::from_millis(value(name).unwrap_or(0));
+
+        let mut limits = SearchLimits::new();
+        if let Some(nodes) = value("nodes") {
+            limits = limits.with_nodes(nodes);
+        }
+        if let Some(depth) = value("depth") {
+            limits = limits.with_depth(depth as usize);
+        }
+        if value("movetime").is_some() {
+            limits = limits.with_time(millis("movetime"));
+        } else if value("wtime").is_some() || value("btime").is_some() {
+            let mut time_control = TimeControl::new(
+                millis("wtime"),
+                millis("btime"),
+                millis("winc"),
+                millis("binc"),
+            )
+            .with_move_overhead(self.move_overhead);
+            if let Some(moves_to_go) = value("movestogo") {
+                time_control = time_control.with_moves_to_go(moves_to_go as u32);
+            }
+            limits = limits.with_time_control(time_control);
+        }
+
+        let limited = ["nodes", "depth", "movetime", "wtime", "btime"]
+            .iter()
+            .any(|name| value(name).is_some());
+        match limited {
+            true => limits,
+            false => self.default_limits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::backend::NdArray;
+
+    fn session(commands: &str) -> Vec<String> {
+        let limits = SearchLimits::new().with_nodes(2000);
+        let mut engine =
+            UciEngine::new(&Engine::<NdArray>::Handcrafted, &Default::default(), limits);
+        let mut output = Vec::new();
+        engine.run(commands.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_uci_session() {
+        let output = session(
+            "uci\nisready\nucinewgame\n\
+             position fen 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1\ngo wtime 1000 btime 1000\n\
+             position startpos moves e2e4 e7e5 g1f3\ngo nodes 500\n\
+             position fen R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1\ngo\n\
+             position startpos moves e2e5\nbogus\n\
+             setoption name Move Overhead value 100\nsetoption name Hash value 16\n\
+             setoption name Move Overhead value -1\nquit\nisready\n",
+        );
+        assert_eq!(
+            output[0],
+            format!("id name chess-ai {}", env!("CARGO_PKG_VERSION"))
+        );
+        assert!(output[1].starts_with("option name Move Overhead type spin default 30 "));
+        assert_eq!(output[2..4], ["uciok", "readyok"]);
+        assert_eq!(output[4], "bestmove a1a8");
+
+        let mut state = State::new();
+        for uci in ["e2e4", "e7e5", "g1f3"] {
+            state.make_move(state.parse_uci(uci).unwrap());
+        }
+        let reply = output[5].strip_prefix("bestmove ").unwrap();
+        assert!(state.parse_uci(reply).is_some());
+
+        assert_eq!(output[6], "bestmove 0000");
+        assert_eq!(output[7], "info string illegal move e2e5");
+        assert_eq!(output[8], "info string unknown option 'Hash'");
+        assert_eq!(output[9], "info string invalid Move Overhead '-1'");
+        // Nothing after quit is answered
+        assert_eq!(output.len(), 10);
+    }
+
+    #[test]
+    fn test_move_overhead_reaches_time_control() {
+        let limits = SearchLimits::new();
+        let mut engine =
+            UciEngine::new(&Engine::<NdArray>::Handcrafted, &Default::default(), limits);
+        engine
+            .set_option(vec!["name", "Move", "Overhead", "value", "250"])
+            .unwrap();
+        let time_control = engine
+            .limits(&["wtime", "1000", "btime", "1000"])
+            .time_control
+            .unwrap();
+        assert_eq!(time_control.move_overhead, Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,31 @@
+//! Move generation, FEN and UCI handling for chess, and the engine behind the `chess-ai` binary.
+//!
+//! The types re-exported here are the stable interface. Everything else, the engine included, is
+//! internal and reached only through `cli::run`, behind the default `cli` feature. Without it the
+//! crate needs none of the engine's dependencies. The `serde` feature makes the types serializable,
+//! a `State` as the FEN it started from and the moves played since.
+//!
+//! ```
+//! use chess_ai::State;
+//!
+//! let mut state: State = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".parse()?;
+//! assert_eq!(state.legal_moves().count(), 20);
+//!
+//! let mv = state.parse_uci("e2e4").expect("a legal move");
+//! state.make_move(mv);
+//! assert_eq!(
+//!     state.to_fen(),
+//!     "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+//! );
+//! # Ok::<(), chess_ai::FenError>(())
+//! ```
+
+mod chess;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "cli")]
+mod engine;
+
+pub use chess::{
+    Bitmask, Board, CastlingRights, Color, FenError, Move, MoveType, Piece, Position, State,
+};
@@ -1,16 +1,3 @@
-mod chess;
-mod engine;
-
-use crate::chess::State;
-
-fn main() {
-    let mut state = State::new();
-    let start_time = std::time::Instant::now();
-
-    let depth = 5;
-    println!("Running perft depth {} on starting position...", depth);
-    state.divide(depth);
-
-    let elapsed = start_time.elapsed();
-    println!("Time taken: {:?}", elapsed);
+fn main() -> std::process::ExitCode {
+    chess_ai::cli::run(std::env::args().skip(1))
 }